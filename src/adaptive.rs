@@ -0,0 +1,111 @@
+//! Adaptive compaction threshold.
+//!
+//! A fixed `max_writes` compacts too often on a busy store and lets garbage pile up on a
+//! store whose values are large. With `Builder::adaptive_compaction`, every compaction
+//! measures the write rate, the garbage those writes left behind and the time compaction took,
+//! and picks the number of writes before the next compaction so that compactions are at
+//! least `min_interval` apart and take at most `max_compaction_share` of the time, while the
+//! log grows to at most `max_space_amplification` times its compacted size. When the targets
+//! conflict, the space target wins.
+
+use crate::RCask;
+use std::time::{Duration, Instant};
+
+/// Targets for the adaptive compaction threshold.
+#[derive(Debug, Clone)]
+pub struct AdaptiveCompaction {
+    /// Compactions should be at least this far apart. Defaults to 1 minute.
+    pub min_interval: Duration,
+    /// Fraction of the time compactions may run for. Defaults to 0.05.
+    pub max_compaction_share: f64,
+    /// The log may grow to this many times its size right after compaction. Defaults to 2.
+    pub max_space_amplification: f64,
+    /// Bounds of the threshold. Default to 1,000 and 10,000,000 writes.
+    pub min_writes: u64,
+    pub max_writes: u64,
+}
+
+impl Default for AdaptiveCompaction {
+    fn default() -> Self {
+        return AdaptiveCompaction {
+            min_interval: Duration::from_secs(60),
+            max_compaction_share: 0.05,
+            max_space_amplification: 2.0,
+            min_writes: 1000,
+            max_writes: 10_000_000,
+        };
+    }
+}
+
+/// What one compaction saw.
+pub(crate) struct Observation {
+    /// Writes since the previous compaction.
+    pub(crate) writes: u64,
+    /// Length of the log before and after compaction.
+    pub(crate) before: u64,
+    pub(crate) after: u64,
+    /// Time spent compacting.
+    pub(crate) busy: Duration,
+}
+
+pub(crate) struct Controller {
+    targets: AdaptiveCompaction,
+    /// When the previous compaction finished.
+    last: Instant,
+}
+
+impl Controller {
+    pub(crate) fn new(targets: AdaptiveCompaction) -> Self {
+        return Controller {
+            targets,
+            last: Instant::now(),
+        };
+    }
+
+    /// Returns the threshold to use after a compaction, given the current one.
+    pub(crate) fn observe(&mut self, threshold: u64, observation: &Observation) -> u64 {
+        let elapsed = self.last.elapsed().as_secs_f64();
+        self.last = Instant::now();
+        let targets = &self.targets;
+        if observation.writes == 0 || elapsed <= 0.0 {
+            return threshold.clamp(targets.min_writes, targets.max_writes);
+        }
+
+        // Writes that take long enough for compactions to be far enough apart.
+        let share = targets.max_compaction_share.max(f64::EPSILON);
+        let interval = targets
+            .min_interval
+            .as_secs_f64()
+            .max(observation.busy.as_secs_f64() / share);
+        let rate = observation.writes as f64 / elapsed;
+        let mut wanted = rate * interval;
+
+        // Writes that leave at most as much garbage as the space target allows.
+        let garbage = observation.before.saturating_sub(observation.after);
+        if garbage > 0 {
+            let per_write = garbage as f64 / observation.writes as f64;
+            let allowed =
+                (targets.max_space_amplification - 1.0).max(0.0) * observation.after as f64;
+            wanted = wanted.min(allowed / per_write);
+        }
+
+        // Move half way, so that one unusual compaction does not swing the threshold.
+        let next = (threshold as f64 + wanted) / 2.0;
+        return (next as u64).clamp(targets.min_writes, targets.max_writes);
+    }
+}
+
+impl RCask {
+    /// Adjusts the compaction threshold after a compaction, if it is adaptive.
+    pub(crate) fn tune_compaction(&mut self, observation: Observation) {
+        if let Some(controller) = &mut self.adaptive {
+            self.max_writes = controller.observe(self.max_writes, &observation);
+        }
+    }
+
+    /// Number of writes after a compaction that trigger the next one: `max_writes`, or the
+    /// current value of the adaptive threshold.
+    pub fn compaction_threshold(&self) -> u64 {
+        return self.max_writes;
+    }
+}
@@ -0,0 +1,355 @@
+//! Portable snapshots of a store as tar archives.
+//!
+//! `RCask::snapshot_to_archive` writes everything needed to open the store elsewhere into one
+//! tar stream, e.g. to upload it to object storage as a backup: a `MANIFEST` entry first,
+//! then the active segment up to its last complete record, its zstd dictionary and the blob
+//! and content-store directories. The store keeps a single segment, so there are no sealed
+//! segments to add. A pending incremental compaction is finished first and the segment is
+//! synced, and since the snapshot holds the store for its whole duration, no write can slip
+//! in between the files.
+//!
+//! `restore` unpacks such an archive into a directory, checking every file against the
+//! manifest, and the store is then opened there with `RCask::builder` and the same options as
+//! the original.
+//!
+//! ```text
+//! rcask-archive 1
+//! pattern log
+//! segment 3
+//! file log.3.log 73728
+//! file log.large/0.blob 2097152
+//! ```
+
+use crate::keycodec;
+use crate::value;
+use crate::{segment_number, RCask, Result};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Name of the manifest entry of an archive.
+const MANIFEST: &str = "MANIFEST";
+/// First line of every manifest.
+const MANIFEST_HEADER: &str = "rcask-archive 1";
+/// Size of tar headers and the unit data is padded to.
+const BLOCK: usize = 512;
+
+/// What a snapshot archive holds, returned by `RCask::snapshot_to_archive` and `restore`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    /// Pattern of the store's file names.
+    pub pattern: String,
+    /// Number of the archived segment.
+    pub segment: u64,
+    /// Paths relative to the store's directory and sizes of the archived files.
+    pub files: Vec<(PathBuf, u64)>,
+}
+
+impl Manifest {
+    fn encode(&self) -> io::Result<String> {
+        let mut text = format!(
+            "{}\npattern {}\nsegment {}\n",
+            MANIFEST_HEADER, self.pattern, self.segment
+        );
+        for (path, size) in &self.files {
+            text.push_str(&format!("file {} {}\n", archive_name(path)?, size));
+        }
+        return Ok(text);
+    }
+
+    fn decode(text: &str) -> io::Result<Self> {
+        let mut lines = text.lines();
+        if lines.next() != Some(MANIFEST_HEADER) {
+            return Err(invalid("not an rcask archive manifest".to_string()));
+        }
+        let mut manifest = Manifest::default();
+        for line in lines {
+            let (field, value) = line.split_once(' ').unwrap_or((line, ""));
+            match field {
+                "pattern" => manifest.pattern = value.to_string(),
+                "segment" => manifest.segment = parse_number(value)?,
+                "file" => {
+                    let Some((path, size)) = value.rsplit_once(' ') else {
+                        return Err(invalid(format!("malformed manifest line {:?}", line)));
+                    };
+                    manifest
+                        .files
+                        .push((PathBuf::from(path), parse_number(size)?));
+                }
+                _ => return Err(invalid(format!("malformed manifest line {:?}", line))),
+            }
+        }
+        return Ok(manifest);
+    }
+}
+
+impl RCask {
+    /// Writes a consistent snapshot of the store to `writer` as a tar archive and returns
+    /// its manifest, see the module docs.
+    pub fn snapshot_to_archive<W: Write>(&mut self, mut writer: W) -> Result<Manifest> {
+        self.finish_pending_migration()?;
+        self.sync()?;
+
+        let directory = PathBuf::from(&self.directory);
+        let segment = PathBuf::from(&self.store.path);
+        // Anything after the last complete record is cut off when the store is opened.
+        let mut files = vec![(segment.clone(), self.store.end())];
+        let dictionary = segment.with_extension("dict");
+        if let Ok(metadata) = fs::metadata(&dictionary) {
+            files.push((dictionary, metadata.len()));
+        }
+        let records = [
+            keycodec::record_path(&directory, &self.pattern),
+            value::format_path(&directory, &self.pattern),
+        ];
+        for record in records {
+            if let Ok(metadata) = fs::metadata(&record) {
+                files.push((record, metadata.len()));
+            }
+        }
+        for name in ["large", "blobs"] {
+            let dir = directory.join(format!("{}.{}", self.pattern, name));
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            let mut blobs = Vec::new();
+            for entry in entries {
+                let path = entry?.path();
+                if path
+                    .extension()
+                    .is_some_and(|extension| extension == "blob")
+                {
+                    blobs.push((path.clone(), fs::metadata(&path)?.len()));
+                }
+            }
+            blobs.sort();
+            files.extend(blobs);
+        }
+
+        let manifest = Manifest {
+            pattern: self.pattern.clone(),
+            segment: segment_number(&segment),
+            files: files
+                .iter()
+                .map(|(path, size)| {
+                    let relative = path.strip_prefix(&directory).unwrap_or(path);
+                    (relative.to_path_buf(), *size)
+                })
+                .collect(),
+        };
+        let text = manifest.encode()?;
+        write_entry(&mut writer, MANIFEST, text.len() as u64, text.as_bytes())?;
+        for ((path, size), (name, _)) in files.iter().zip(&manifest.files) {
+            let file = fs::File::open(path)?;
+            write_entry(&mut writer, &archive_name(name)?, *size, file.take(*size))?;
+        }
+        // A tar archive ends with two empty blocks.
+        writer.write_all(&[0; 2 * BLOCK])?;
+        writer.flush()?;
+        return Ok(manifest);
+    }
+}
+
+/// Unpacks an archive written by `RCask::snapshot_to_archive` into `directory`, creating it
+/// if needed, and returns its manifest. Never overwrites a file: restoring fails if a file of
+/// the archive already exists there, and if the archive does not hold exactly the files of
+/// its manifest.
+pub fn restore<R: Read>(mut reader: R, directory: &str) -> Result<Manifest> {
+    let directory = Path::new(directory);
+    fs::create_dir_all(directory)?;
+    let mut manifest: Option<Manifest> = None;
+    let mut restored = Vec::new();
+    while let Some((name, size)) = read_header(&mut reader)? {
+        let mut data = (&mut reader).take(size);
+        match &manifest {
+            None if name == MANIFEST => {
+                let mut text = String::new();
+                data.read_to_string(&mut text)?;
+                manifest = Some(Manifest::decode(&text)?);
+            }
+            None => return Err(invalid(format!("{} is not the first entry", MANIFEST)).into()),
+            Some(manifest) => {
+                let path = PathBuf::from(&name);
+                if !manifest.files.contains(&(path.clone(), size)) {
+                    return Err(invalid(format!("{} is not in the manifest", name)).into());
+                }
+                let target = directory.join(&path);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let mut file = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&target)?;
+                io::copy(&mut data, &mut file)?;
+                file.sync_all()?;
+                restored.push((path, size));
+            }
+        }
+        if data.limit() > 0 {
+            return Err(truncated().into());
+        }
+        skip_padding(&mut reader, size)?;
+    }
+    let Some(manifest) = manifest else {
+        return Err(invalid(format!("the archive has no {}", MANIFEST)).into());
+    };
+    if restored.len() != manifest.files.len() {
+        return Err(invalid("the archive is missing files of its manifest".to_string()).into());
+    }
+    return Ok(manifest);
+}
+
+/// Writes one regular file of `size` bytes read from `data` as a ustar entry.
+fn write_entry<W: Write, R: Read>(writer: &mut W, name: &str, size: u64, data: R) -> Result<()> {
+    let mut header = [0u8; BLOCK];
+    let (prefix, name) = split_name(name)?;
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    write_number(&mut header[124..136], size);
+    write_number(&mut header[136..148], crate::now_millis() / 1000);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    // The checksum is taken with its own field filled with spaces.
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    writer.write_all(&header)?;
+
+    let copied = io::copy(&mut data.take(size), writer)?;
+    if copied != size {
+        return Err(truncated().into());
+    }
+    let padding = (BLOCK - (size as usize % BLOCK)) % BLOCK;
+    writer.write_all(&[0; BLOCK][..padding])?;
+    return Ok(());
+}
+
+/// Reads the next entry's header and returns its name and size, or `None` at the end of the
+/// archive. Entries other than regular files are skipped.
+fn read_header<R: Read>(reader: &mut R) -> io::Result<Option<(String, u64)>> {
+    loop {
+        let mut header = [0u8; BLOCK];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(truncated()),
+            Err(e) => return Err(e),
+        }
+        if header.iter().all(|&b| b == 0) {
+            return Ok(None);
+        }
+        let mut checksum: u32 = header.iter().map(|&b| b as u32).sum();
+        checksum -= header[148..156].iter().map(|&b| b as u32).sum::<u32>();
+        checksum += 8 * b' ' as u32;
+        if read_number(&header[148..156])? != checksum as u64 {
+            return Err(invalid("tar header checksum mismatch".to_string()));
+        }
+        let size = read_number(&header[124..136])?;
+        if header[156] != b'0' && header[156] != 0 {
+            io::copy(&mut reader.take(size), &mut io::sink())?;
+            skip_padding(reader, size)?;
+            continue;
+        }
+        let name = field_str(&header[..100])?;
+        let prefix = field_str(&header[345..500])?;
+        let name = match prefix.is_empty() {
+            true => name.to_string(),
+            false => format!("{}/{}", prefix, name),
+        };
+        // Only plain relative paths, so an archive cannot write outside the directory.
+        let plain = Path::new(&name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if name.is_empty() || !plain {
+            return Err(invalid(format!("unsafe path {:?} in archive", name)));
+        }
+        return Ok(Some((name, size)));
+    }
+}
+
+fn skip_padding<R: Read>(reader: &mut R, size: u64) -> io::Result<()> {
+    let padding = (BLOCK - (size as usize % BLOCK)) % BLOCK;
+    let mut buf = [0u8; BLOCK];
+    return reader.read_exact(&mut buf[..padding]);
+}
+
+/// Returns the archive name of a relative path, with `/` separators.
+fn archive_name(path: &Path) -> io::Result<String> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component.as_os_str().to_str() {
+            Some(part) => parts.push(part),
+            None => return Err(invalid(format!("{} is not UTF-8", path.display()))),
+        }
+    }
+    return Ok(parts.join("/"));
+}
+
+/// Splits a name into the prefix and name fields of a ustar header.
+fn split_name(name: &str) -> io::Result<(&str, &str)> {
+    if name.len() <= 100 {
+        return Ok(("", name));
+    }
+    return name
+        .char_indices()
+        .filter(|&(at, c)| c == '/' && at <= 155 && name.len() - at - 1 <= 100)
+        .map(|(at, _)| (&name[..at], &name[at + 1..]))
+        .next()
+        .ok_or_else(|| invalid(format!("{} is too long for a tar archive", name)));
+}
+
+/// Writes `value` to a numeric field in octal, or in base-256 if it does not fit.
+fn write_number(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    if value < 1 << (3 * digits) {
+        let text = format!("{:0width$o}\0", value, width = digits);
+        field.copy_from_slice(text.as_bytes());
+        return;
+    }
+    field.fill(0);
+    field[0] = 0x80;
+    let bytes = value.to_be_bytes();
+    let at = field.len() - bytes.len();
+    field[at..].copy_from_slice(&bytes);
+}
+
+fn read_number(field: &[u8]) -> io::Result<u64> {
+    if field[0] & 0x80 != 0 {
+        let mut value = (field[0] & 0x7f) as u64;
+        for &b in &field[1..] {
+            value = value << 8 | b as u64;
+        }
+        return Ok(value);
+    }
+    let text = field_str(field)?.trim_matches(|c: char| c == ' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    return u64::from_str_radix(text, 8)
+        .map_err(|_| invalid(format!("malformed tar number {:?}", text)));
+}
+
+/// Returns the text of a NUL-terminated header field.
+fn field_str(field: &[u8]) -> io::Result<&str> {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    return std::str::from_utf8(&field[..end])
+        .map_err(|_| invalid("tar header field is not UTF-8".to_string()));
+}
+
+fn parse_number(text: &str) -> io::Result<u64> {
+    return text
+        .parse()
+        .map_err(|_| invalid(format!("malformed manifest number {:?}", text)));
+}
+
+fn invalid(message: String) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, message);
+}
+
+fn truncated() -> io::Error {
+    return io::Error::new(io::ErrorKind::UnexpectedEof, "truncated tar archive");
+}
@@ -0,0 +1,108 @@
+//! Export of the live dataset to Arrow and Parquet.
+//!
+//! Every live key becomes a row with the columns `key` (UTF-8), `value` (binary),
+//! `expires_at` (a millisecond timestamp, null for keys without a TTL) and `size` (the value's
+//! length in bytes). Rows are sorted by key and written in batches of `BATCH_ROWS`, so an
+//! export never holds more than one batch of values in memory. Lists, sets and hashes are
+//! skipped, as in `RCask::get_all_key_values`.
+
+use crate::{Error, RCask, ReadOptions, Result};
+use arrow_array::builder::{
+    ArrayBuilder, BinaryBuilder, StringBuilder, TimestampMillisecondBuilder, UInt64Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+pub use arrow_array;
+
+/// Rows per record batch.
+pub const BATCH_ROWS: usize = 8192;
+
+/// The schema of the exported record batches.
+pub fn schema() -> SchemaRef {
+    return Arc::new(Schema::new(vec![
+        Field::new("key", DataType::Utf8, false),
+        Field::new("value", DataType::Binary, false),
+        Field::new(
+            "expires_at",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            true,
+        ),
+        Field::new("size", DataType::UInt64, false),
+    ]));
+}
+
+/// Calls `write` with every batch of live rows and returns the number of rows.
+pub fn for_each_batch<F>(store: &mut RCask, mut write: F) -> Result<u64>
+where
+    F: FnMut(RecordBatch) -> Result<()>,
+{
+    let options = ReadOptions {
+        fill_cache: false,
+        ..Default::default()
+    };
+    let mut keys = store.store.keys();
+    keys.sort_unstable();
+    let mut rows = 0;
+    for chunk in keys.chunks(BATCH_ROWS) {
+        let mut key_column = StringBuilder::new();
+        let mut value_column = BinaryBuilder::new();
+        let mut expires_column = TimestampMillisecondBuilder::new();
+        let mut size_column = UInt64Builder::new();
+        for key in chunk {
+            let value = match store.get_opt_into(key, &options, Vec::new()) {
+                Ok(Some(value)) => value,
+                Ok(None) | Err(Error::WrongType { .. }) => continue,
+                Err(e) => return Err(e),
+            };
+            let verify = store.verify_checksums;
+            let expires_at = store
+                .read_latest(key, verify)?
+                .and_then(|(_, attributes)| attributes.expires_at);
+            key_column.append_value(key);
+            size_column.append_value(value.len() as u64);
+            value_column.append_value(value);
+            expires_column.append_option(expires_at.map(|at| at as i64));
+        }
+        if key_column.is_empty() {
+            continue;
+        }
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(key_column.finish()),
+            Arc::new(value_column.finish()),
+            Arc::new(expires_column.finish()),
+            Arc::new(size_column.finish()),
+        ];
+        let batch = RecordBatch::try_new(schema(), columns).map_err(to_io)?;
+        rows += batch.num_rows() as u64;
+        write(batch)?;
+    }
+    return Ok(rows);
+}
+
+/// Writes every live row to `writer` as an Arrow IPC stream and returns the number of rows.
+pub fn export_ipc<W: Write>(store: &mut RCask, writer: W) -> Result<u64> {
+    let mut stream = arrow_ipc::writer::StreamWriter::try_new(writer, &schema()).map_err(to_io)?;
+    let rows = for_each_batch(store, |batch| {
+        return Ok(stream.write(&batch).map_err(to_io)?);
+    })?;
+    stream.finish().map_err(to_io)?;
+    return Ok(rows);
+}
+
+/// Writes every live row to `writer` as a Parquet file and returns the number of rows.
+#[cfg(feature = "parquet")]
+pub fn export_parquet<W: Write + Send>(store: &mut RCask, writer: W) -> Result<u64> {
+    let mut file = parquet::arrow::ArrowWriter::try_new(writer, schema(), None).map_err(to_io)?;
+    let rows = for_each_batch(store, |batch| {
+        return Ok(file.write(&batch).map_err(to_io)?);
+    })?;
+    file.close().map_err(to_io)?;
+    return Ok(rows);
+}
+
+fn to_io<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    return io::Error::other(e);
+}
@@ -0,0 +1,151 @@
+//! Write backpressure.
+//!
+//! When compaction falls behind or the disk fills up, writes slow down before they fail. Two
+//! signals are watched: the compaction backlog, which is the number of writes since the last
+//! compaction divided by `max_writes` and grows past 1 while compaction is held back (by an
+//! incremental compaction in progress, the compaction scheduler or failing compactions), and
+//! the free space on the store's disk. Past a soft threshold every write is delayed, the more
+//! the closer the signal is to its hard threshold; past the hard threshold writes fail with
+//! `Error::Backpressure`. Rejected writes do not advance an incremental compaction;
+//! `RCask::compaction_tick` or `BackgroundRuntime::maintain` do. See `Builder::backpressure`.
+
+use crate::health::DEFAULT_MIN_FREE_DISK;
+use crate::{Error, RCask, Result};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often free disk space is checked.
+pub(crate) const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Thresholds for write backpressure.
+#[derive(Debug, Clone)]
+pub struct BackpressureOptions {
+    /// Compaction backlog at which writes start to slow down. Defaults to 2.
+    pub soft_backlog: f64,
+    /// Compaction backlog at which writes fail. Defaults to 4.
+    pub hard_backlog: f64,
+    /// Free disk space below which writes start to slow down. Defaults to 1 GiB.
+    pub soft_free_disk: u64,
+    /// Free disk space below which writes fail. Defaults to 64 MiB.
+    pub hard_free_disk: u64,
+    /// Delay of a write just below a hard threshold. Defaults to 10 ms.
+    pub max_delay: Duration,
+}
+
+impl Default for BackpressureOptions {
+    fn default() -> Self {
+        return BackpressureOptions {
+            soft_backlog: 2.0,
+            hard_backlog: 4.0,
+            soft_free_disk: 1 << 30,
+            hard_free_disk: DEFAULT_MIN_FREE_DISK,
+            max_delay: Duration::from_millis(10),
+        };
+    }
+}
+
+/// What writes are held back by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PressureCause {
+    CompactionBacklog,
+    DiskSpace,
+}
+
+/// How writes are admitted, passed to the `Builder::on_backpressure` callback whenever it
+/// changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PressureLevel {
+    Normal,
+    /// Writes are delayed.
+    Throttled(PressureCause),
+    /// Writes fail with `Error::Backpressure`.
+    Rejecting(PressureCause),
+}
+
+pub(crate) type PressureCallback = Box<dyn FnMut(PressureLevel) + Send>;
+
+pub(crate) struct Throttle {
+    options: BackpressureOptions,
+    callback: Option<PressureCallback>,
+    level: PressureLevel,
+    /// Free disk space when it was last checked.
+    free_disk: Option<(Instant, u64)>,
+}
+
+impl Throttle {
+    pub(crate) fn new(options: BackpressureOptions, callback: Option<PressureCallback>) -> Self {
+        return Throttle {
+            options,
+            callback,
+            level: PressureLevel::Normal,
+            free_disk: None,
+        };
+    }
+
+    fn free_disk(&mut self, directory: &Path) -> Option<u64> {
+        if let Some((checked, free)) = self.free_disk {
+            if checked.elapsed() < DISK_CHECK_INTERVAL {
+                return Some(free);
+            }
+        }
+        let free = fs2::available_space(directory).ok()?;
+        self.free_disk = Some((Instant::now(), free));
+        return Some(free);
+    }
+}
+
+/// How far `value` has got from `soft` towards `hard`, where 0 is at or before `soft` and 1
+/// is at or past `hard`.
+fn severity(value: f64, soft: f64, hard: f64) -> f64 {
+    if hard == soft {
+        return if value >= hard { 1.0 } else { 0.0 };
+    }
+    return ((value - soft) / (hard - soft)).clamp(0.0, 1.0);
+}
+
+impl RCask {
+    /// Delays or rejects a write according to the backpressure thresholds, if any, or
+    /// rejects it if it would eat into the headroom kept for compaction.
+    pub(crate) fn admit_write(&mut self) -> Result<()> {
+        self.admit_headroom()?;
+        let backlog = self.writes as f64 / self.max_writes.max(1) as f64;
+        let Some(throttle) = &mut self.throttle else {
+            return Ok(());
+        };
+        let options = &throttle.options;
+        let compaction = severity(backlog, options.soft_backlog, options.hard_backlog);
+        let (soft_free, hard_free) = (options.soft_free_disk, options.hard_free_disk);
+        let disk = match throttle.free_disk(Path::new(&self.directory)) {
+            // Less free space is worse, so the thresholds are compared in reverse.
+            Some(free) => severity(-(free as f64), -(soft_free as f64), -(hard_free as f64)),
+            None => 0.0,
+        };
+        let (severity, cause) = match compaction >= disk {
+            true => (compaction, PressureCause::CompactionBacklog),
+            false => (disk, PressureCause::DiskSpace),
+        };
+        let level = if severity >= 1.0 {
+            PressureLevel::Rejecting(cause)
+        } else if severity > 0.0 {
+            PressureLevel::Throttled(cause)
+        } else {
+            PressureLevel::Normal
+        };
+
+        if level != throttle.level {
+            throttle.level = level;
+            if let Some(callback) = &mut throttle.callback {
+                callback(level);
+            }
+        }
+        return match level {
+            PressureLevel::Normal => Ok(()),
+            PressureLevel::Throttled(_) => {
+                thread::sleep(throttle.options.max_delay.mul_f64(severity));
+                Ok(())
+            }
+            PressureLevel::Rejecting(cause) => Err(Error::Backpressure { cause }),
+        };
+    }
+}
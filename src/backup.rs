@@ -0,0 +1,377 @@
+//! Incremental backups as a chain of links.
+//!
+//! Records are only ever appended to the active segment, so everything written after a known
+//! position is the tail of the segment from there. `RCask::backup_since` adds a link to a
+//! backup chain directory: a full link holds the whole segment, its zstd dictionary and the
+//! blob and content-store files, and an incremental link only the bytes written since the
+//! previous link along with the blob files that are new since then. A nightly full backup
+//! followed by frequent incrementals then costs little more than the data written.
+//!
+//! Every link is a numbered directory, and the chain manifest `CHAIN` lists them in order
+//! with the segment range each one covers:
+//!
+//! ```text
+//! rcask-backup-chain 1
+//! pattern log
+//! link 1 full 3 0 73728
+//! link 2 incremental 3 73728 81920
+//! ```
+//!
+//! Compaction rewrites the store into a new segment, so the first link after one is always a
+//! full link. `restore` rebuilds the store from the last full link by appending the
+//! incremental links after it in the order of the manifest, checking that each one starts
+//! where the previous one ended, and `restore_to` stops at an earlier position.
+
+use crate::keycodec;
+use crate::pitr::{copy_new, cut_log};
+use crate::value;
+use crate::{RCask, Result, Version};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the chain manifest in a backup directory.
+const CHAIN: &str = "CHAIN";
+/// First line of every chain manifest.
+const CHAIN_HEADER: &str = "rcask-backup-chain 1";
+/// Directories of blob and content-store files, after the pattern.
+const BLOB_DIRS: [&str; 2] = ["large", "blobs"];
+
+/// One backup of a chain, see `RCask::backup_since`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Link {
+    /// Position of the link in the chain, from 1.
+    pub number: u64,
+    /// Whether the link holds the whole segment rather than what was written since the
+    /// previous link.
+    pub full: bool,
+    /// Position the link's records start at.
+    pub since: Version,
+    /// Position just past the link's last record, to take the next incremental backup from.
+    pub until: Version,
+}
+
+/// The chain manifest of a backup directory.
+pub(crate) struct Chain {
+    pub(crate) pattern: String,
+    pub(crate) links: Vec<Link>,
+}
+
+impl Chain {
+    fn path(dir: &Path) -> PathBuf {
+        return dir.join(CHAIN);
+    }
+
+    /// Reads the chain of `dir`, or `None` if it has none yet.
+    pub(crate) fn load(dir: &Path) -> io::Result<Option<Self>> {
+        let text = match fs::read_to_string(Chain::path(dir)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut lines = text.lines();
+        if lines.next() != Some(CHAIN_HEADER) {
+            return Err(invalid(format!("{} is not a backup chain", dir.display())));
+        }
+        let mut chain = Chain {
+            pattern: String::new(),
+            links: Vec::new(),
+        };
+        for line in lines {
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields.as_slice() {
+                ["pattern", pattern] => chain.pattern = pattern.to_string(),
+                ["link", number, kind @ ("full" | "incremental"), segment, from, to] => {
+                    let segment = parse_number(segment)?;
+                    chain.links.push(Link {
+                        number: parse_number(number)?,
+                        full: *kind == "full",
+                        since: Version::new(segment, parse_number(from)?),
+                        until: Version::new(segment, parse_number(to)?),
+                    });
+                }
+                _ => return Err(invalid(format!("malformed chain line {:?}", line))),
+            }
+        }
+        return Ok(Some(chain));
+    }
+
+    /// Replaces the chain manifest of `dir` with this one.
+    fn save(&self, dir: &Path) -> io::Result<()> {
+        let mut text = format!("{}\npattern {}\n", CHAIN_HEADER, self.pattern);
+        for link in &self.links {
+            let kind = if link.full { "full" } else { "incremental" };
+            text.push_str(&format!(
+                "link {} {} {} {} {}\n",
+                link.number,
+                kind,
+                link.since.segment(),
+                link.since.offset(),
+                link.until.offset()
+            ));
+        }
+        let tmp = Chain::path(dir).with_extension("tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        return fs::rename(&tmp, Chain::path(dir));
+    }
+
+    /// The last full link and the incremental links after it, which a restore replays, or
+    /// with `until` those of them needed to restore the records before it.
+    pub(crate) fn run(&self, until: Option<Version>) -> &[Link] {
+        let start = self
+            .links
+            .iter()
+            .rposition(|link| link.full && until.is_none_or(|until| link.since <= until))
+            .unwrap_or(0);
+        let run = &self.links[start..];
+        let Some(until) = until else {
+            return run;
+        };
+        let end = run.iter().position(|link| link.until >= until);
+        return &run[..end.map_or(run.len(), |at| at + 1)];
+    }
+}
+
+impl RCask {
+    /// Adds a backup of everything written after `since` to the backup chain in `dir`,
+    /// creating the chain if needed, and returns the new link, see the module docs. Pass the
+    /// `until` of the chain's last link for an incremental backup, or `Version::from_u64(0)`
+    /// for a full one. A full backup is also taken if the chain is empty or the store was
+    /// compacted since `since`.
+    pub fn backup_since(&mut self, since: Version, dir: &str) -> Result<Link> {
+        self.finish_pending_migration()?;
+        self.sync()?;
+        let dir = Path::new(dir);
+        fs::create_dir_all(dir)?;
+        let mut chain = Chain::load(dir)?.unwrap_or(Chain {
+            pattern: self.pattern.clone(),
+            links: Vec::new(),
+        });
+        if chain.pattern != self.pattern {
+            return Err(invalid_input(format!(
+                "the chain in {} backs up pattern {}",
+                dir.display(),
+                chain.pattern
+            ))
+            .into());
+        }
+
+        let until = self.sequence();
+        let last = chain.links.last();
+        let full = since.offset() == 0 || since.segment() != until.segment() || last.is_none();
+        if !full {
+            if since.offset() > until.offset() {
+                return Err(invalid_input(format!("{} is past the end of the log", since)).into());
+            }
+            if last.map(|link| link.until) != Some(since) {
+                return Err(invalid_input(format!(
+                    "the chain in {} does not end at {}",
+                    dir.display(),
+                    since
+                ))
+                .into());
+            }
+        }
+        let link = Link {
+            number: last.map_or(1, |link| link.number + 1),
+            full,
+            since: match full {
+                true => Version::new(until.segment(), 0),
+                false => since,
+            },
+            until,
+        };
+
+        let link_dir = dir.join(link.number.to_string());
+        // Left behind by a backup that failed before it was added to the chain.
+        if link_dir.exists() {
+            fs::remove_dir_all(&link_dir)?;
+        }
+        fs::create_dir_all(&link_dir)?;
+        let segment = PathBuf::from(&self.store.path);
+        let name = segment.file_name().unwrap_or_default();
+        let mut source = fs::File::open(&segment)?;
+        source.seek(SeekFrom::Start(link.since.offset()))?;
+        let length = link.until.offset() - link.since.offset();
+        copy_new(source.take(length), &link_dir.join(name))?;
+        let dictionary = segment.with_extension("dict");
+        if full && dictionary.exists() {
+            copy_new(
+                fs::File::open(&dictionary)?,
+                &link_dir.join(dictionary.file_name().unwrap_or_default()),
+            )?;
+        }
+        if full {
+            self.copy_key_codec(&link_dir)?;
+            self.copy_value_format(&link_dir)?;
+        }
+
+        // Blob files are never changed once written, so a chain needs each one once.
+        let mut backed_up = HashSet::new();
+        if !full {
+            for earlier in chain.run(None) {
+                backed_up.extend(blob_files(
+                    &dir.join(earlier.number.to_string()),
+                    &self.pattern,
+                )?);
+            }
+        }
+        for relative in blob_files(Path::new(&self.directory), &self.pattern)? {
+            if !backed_up.contains(&relative) {
+                let target = link_dir.join(&relative);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                copy_new(
+                    fs::File::open(Path::new(&self.directory).join(&relative))?,
+                    &target,
+                )?;
+            }
+        }
+
+        chain.links.push(link);
+        chain.save(dir)?;
+        return Ok(link);
+    }
+}
+
+/// Rebuilds the store backed up in the chain `dir` into the directory `target`, creating it
+/// if needed, and returns the last link restored. Never overwrites a file: restoring fails if
+/// a file of the store already exists in `target`.
+pub fn restore(dir: &str, target: &str) -> Result<Link> {
+    return restore_chain(Path::new(dir), Path::new(target), None);
+}
+
+/// Rebuilds the store backed up in the chain `dir` into the directory `target` as it was just
+/// before the position `until`, like `RCask::restore_to` does from the live log with a
+/// `RestorePoint::Sequence`.
+/// Restores from the last full link before `until` and the incremental links after it, and
+/// returns the last link used.
+pub fn restore_to(dir: &str, target: &str, until: Version) -> Result<Link> {
+    return restore_chain(Path::new(dir), Path::new(target), Some(until));
+}
+
+fn restore_chain(dir: &Path, target: &Path, until: Option<Version>) -> Result<Link> {
+    let Some(chain) = Chain::load(dir)? else {
+        return Err(invalid(format!("{} has no backup chain", dir.display())).into());
+    };
+    let run = chain.run(until);
+    let (Some(first), Some(last)) = (run.first(), run.last()) else {
+        return Err(invalid(format!("the chain in {} is empty", dir.display())).into());
+    };
+    if !first.full {
+        return Err(invalid(format!("the chain in {} has no full link", dir.display())).into());
+    }
+    if let Some(until) = until {
+        if until < first.since || until > last.until {
+            return Err(invalid_input(format!(
+                "the chain in {} does not hold position {}",
+                dir.display(),
+                until
+            ))
+            .into());
+        }
+    }
+
+    fs::create_dir_all(target)?;
+    let name = format!("{}.{}.log", chain.pattern, first.since.segment());
+    let mut segment = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(target.join(&name))?;
+    let mut end = Version::new(first.since.segment(), 0);
+    for link in run {
+        if link.since != end {
+            return Err(invalid(format!(
+                "link {} starts at {}, not where the previous one ended",
+                link.number, link.since
+            ))
+            .into());
+        }
+        let link_dir = dir.join(link.number.to_string());
+        let mut records = fs::File::open(link_dir.join(&name))?;
+        let length = link.until.offset() - link.since.offset();
+        let copied = io::copy(&mut (&mut records).take(length), &mut segment)?;
+        if copied != length {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("link {} is truncated", link.number),
+            )
+            .into());
+        }
+        end = link.until;
+
+        for relative in blob_files(&link_dir, &chain.pattern)? {
+            let to = target.join(&relative);
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            copy_new(fs::File::open(link_dir.join(&relative))?, &to)?;
+        }
+        let dictionary = link_dir.join(&name).with_extension("dict");
+        if dictionary.exists() {
+            copy_new(
+                fs::File::open(&dictionary)?,
+                &target.join(&name).with_extension("dict"),
+            )?;
+        }
+        let records = [
+            keycodec::record_path(&link_dir, &chain.pattern),
+            value::format_path(&link_dir, &chain.pattern),
+        ];
+        for record in records {
+            if record.exists() {
+                copy_new(
+                    fs::File::open(&record)?,
+                    &target.join(record.file_name().unwrap_or_default()),
+                )?;
+            }
+        }
+    }
+    segment.sync_all()?;
+    if let Some(until) = until {
+        cut_log(&target.join(&name), until.offset())?;
+    }
+    return Ok(*last);
+}
+
+/// Returns the blob and content-store files of the store `pattern` in `dir`, relative to it.
+fn blob_files(dir: &Path, pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for name in BLOB_DIRS {
+        let blobs = format!("{}.{}", pattern, name);
+        let entries = match fs::read_dir(dir.join(&blobs)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "blob")
+            {
+                files.push(Path::new(&blobs).join(path.file_name().unwrap_or_default()));
+            }
+        }
+    }
+    files.sort();
+    return Ok(files);
+}
+
+fn parse_number(text: &str) -> io::Result<u64> {
+    return text
+        .parse()
+        .map_err(|_| invalid(format!("malformed chain number {:?}", text)));
+}
+
+fn invalid(message: String) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, message);
+}
+
+fn invalid_input(message: String) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidInput, message);
+}
@@ -0,0 +1,182 @@
+//! Large batches: writes that become visible all together, however many there are.
+//!
+//! A `LargeBatch` from `RCask::large_batch` gathers sets and deletions and spills them to the
+//! log in chunks of `CHUNK_SIZE` bytes as it goes, keeping only their keys in memory, so a
+//! batch can hold far more data than fits in memory. Nothing of it can be read until `commit`
+//! writes the batch's commit marker, and loading indexes the records of a batch only at its
+//! marker, so after a crash the store holds the whole batch or none of it, e.g. for imports of
+//! many gigabytes. A batch dropped without `commit` is abandoned: its records stay in the log
+//! as dead records until compaction drops them.
+//!
+//! Like `bulk_load`, a batch writes plain records: no expiry, metadata or large value
+//! storage, and observers are not called. The batch borrows the handle, so nothing else
+//! writes to the store or compacts it meanwhile.
+
+use crate::kvstore::Attributes;
+use crate::{RCask, Result};
+use std::io;
+
+/// Bytes of records a batch gathers before spilling them to the log as one chunk.
+const CHUNK_SIZE: usize = 4 << 20;
+
+/// Writes that become visible together once committed, see `RCask::large_batch`.
+pub struct LargeBatch<'a> {
+    store: &'a mut RCask,
+    /// The batch in the log, once its first chunk is written.
+    batch: Option<u64>,
+    /// Stored keys, values and attributes not spilled yet.
+    buffer: Vec<(String, Vec<u8>, Attributes)>,
+    buffered_bytes: usize,
+    /// Stored key of every record and its size, or `None` for a deletion, for bounded mode
+    /// and quotas.
+    sizes: Vec<(String, Option<u64>)>,
+    track_sizes: bool,
+    user_bytes: u64,
+    records: u64,
+    /// Whether a chunk failed to write, which abandons the batch.
+    failed: bool,
+}
+
+impl RCask {
+    /// Starts a batch of writes that become visible, and survive a crash, all together once
+    /// `LargeBatch::commit` returns, see the module docs.
+    pub fn large_batch(&mut self) -> Result<LargeBatch<'_>> {
+        self.admit_write()?;
+        // Keys queued for relocation would be relocated over the batch's records.
+        self.finish_pending_migration()?;
+        let track_sizes = self.lru.is_some() || self.quotas.is_some();
+        return Ok(LargeBatch {
+            store: self,
+            batch: None,
+            buffer: Vec::new(),
+            buffered_bytes: 0,
+            sizes: Vec::new(),
+            track_sizes,
+            user_bytes: 0,
+            records: 0,
+            failed: false,
+        });
+    }
+}
+
+impl LargeBatch<'_> {
+    /// Adds setting `key` to `value` to the batch. A later write of the same key in the batch
+    /// replaces an earlier one.
+    pub fn set<T: AsRef<[u8]>>(&mut self, key: &str, value: T) -> Result<()> {
+        let value = value.as_ref();
+        self.user_bytes += (key.len() + value.len()) as u64;
+        let key = self.store.stored_key(key).into_owned();
+        let stored = self.store.values.encode(value)?.into_owned();
+        let attributes = Attributes {
+            written_at: self.store.stamp(),
+            ..Default::default()
+        };
+        if self.track_sizes {
+            let size = (key.len() + stored.len()) as u64;
+            self.sizes.push((key.clone(), Some(size)));
+        }
+        return self.add(key, stored, attributes);
+    }
+
+    /// Adds deleting `key` to the batch.
+    pub fn delete(&mut self, key: &str) -> Result<()> {
+        self.user_bytes += key.len() as u64;
+        let key = self.store.stored_key(key).into_owned();
+        let attributes = Attributes {
+            written_at: self.store.stamp(),
+            ..Attributes::tombstone()
+        };
+        if self.track_sizes {
+            self.sizes.push((key.clone(), None));
+        }
+        return self.add(key, Vec::new(), attributes);
+    }
+
+    /// Number of writes added so far.
+    pub fn len(&self) -> u64 {
+        return self.records;
+    }
+
+    /// Whether no write has been added yet.
+    pub fn is_empty(&self) -> bool {
+        return self.records == 0;
+    }
+
+    /// Writes the rest of the batch and its commit marker, after which every write of the
+    /// batch is visible and durable. Returns the number of writes. If this fails, none of
+    /// the batch is visible, now or after reopening.
+    pub fn commit(mut self) -> Result<u64> {
+        self.spill()?;
+        let Some(batch) = self.batch.take() else {
+            return Ok(0);
+        };
+        let committed = self.store.store.commit_batch(batch);
+        self.store.health.wrote(&committed);
+        let keys = match committed {
+            Ok(keys) => keys,
+            Err(e) => {
+                self.store.store.abort_batch(batch);
+                return Err(e.into());
+            }
+        };
+        for key in &keys {
+            self.store.invalidate(key);
+        }
+        for (key, size) in std::mem::take(&mut self.sizes) {
+            match size {
+                Some(size) => self.store.lru_wrote([(key.as_str(), size)], false)?,
+                None => self.store.lru_removed(&key),
+            }
+        }
+        self.store.traffic.user_bytes += self.user_bytes;
+        return self.store.finish_bulk_load(self.records);
+    }
+
+    /// Abandons the batch, like dropping it.
+    pub fn abort(self) {}
+
+    fn add(&mut self, key: String, value: Vec<u8>, attributes: Attributes) -> Result<()> {
+        if self.failed {
+            return Err(io::Error::other("the batch was abandoned after a failed write").into());
+        }
+        self.buffered_bytes += key.len() + value.len();
+        self.buffer.push((key, value, attributes));
+        self.records += 1;
+        if self.buffered_bytes >= CHUNK_SIZE {
+            self.spill()?;
+        }
+        return Ok(());
+    }
+
+    /// Writes the buffered records to the log as a chunk of the batch.
+    fn spill(&mut self) -> Result<()> {
+        if self.failed {
+            return Err(io::Error::other("the batch was abandoned after a failed write").into());
+        }
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let records = std::mem::take(&mut self.buffer);
+        self.buffered_bytes = 0;
+        let spilled = self.store.store.spill_batch(self.batch, &records);
+        self.store.health.wrote(&spilled);
+        match spilled {
+            Ok(batch) => {
+                self.batch = Some(batch);
+                return Ok(());
+            }
+            Err(e) => {
+                self.failed = true;
+                return Err(e.into());
+            }
+        }
+    }
+}
+
+impl Drop for LargeBatch<'_> {
+    fn drop(&mut self) {
+        if let Some(batch) = self.batch.take() {
+            self.store.store.abort_batch(batch);
+        }
+    }
+}
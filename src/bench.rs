@@ -0,0 +1,187 @@
+//! Benchmark harness.
+//!
+//! Runs a configurable workload against a store and reports throughput and latency
+//! percentiles, so the cost of a release or a configuration can be measured. Reads go through
+//! one reader handle per thread and writes through the shared store, as an application
+//! sharing a store between threads would.
+//!
+//! ```no_run
+//! use rcask::bench::{self, Workload};
+//! use rcask::RCask;
+//!
+//! # fn main() -> rcask::Result<()> {
+//! let store = RCask::builder("./bench".to_string(), "bench".to_string()).open()?;
+//! let report = bench::run(store, &Workload::default())?;
+//! println!("{}", report);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{RCask, Result};
+use std::fmt;
+use std::panic;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The operations a benchmark runs.
+#[derive(Debug, Clone)]
+pub struct Workload {
+    /// Number of distinct keys, all written before the measured run.
+    pub keys: u64,
+    /// Size of every written value in bytes.
+    pub value_size: usize,
+    /// Share of operations that are reads, from 0.0 to 1.0.
+    pub read_ratio: f64,
+    /// Number of threads issuing operations.
+    pub threads: usize,
+    /// Total number of measured operations across all threads.
+    pub operations: u64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        return Workload {
+            keys: 10_000,
+            value_size: 100,
+            read_ratio: 0.9,
+            threads: 4,
+            operations: 100_000,
+        };
+    }
+}
+
+/// Latency percentiles of one kind of operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Latencies {
+    pub count: u64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Latencies {
+    fn from_samples(mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return Latencies::default();
+        }
+        samples.sort_unstable();
+        let percentile = |p: usize| samples[(samples.len() - 1) * p / 100];
+        return Latencies {
+            count: samples.len() as u64,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: samples[samples.len() - 1],
+        };
+    }
+}
+
+/// Results of a benchmark run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub elapsed: Duration,
+    /// Operations per second across all threads.
+    pub throughput: f64,
+    pub reads: Latencies,
+    pub writes: Latencies,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} operations in {:.2?} ({:.0} ops/s)",
+            self.reads.count + self.writes.count,
+            self.elapsed,
+            self.throughput
+        )?;
+        for (name, latencies) in [("reads", &self.reads), ("writes", &self.writes)] {
+            writeln!(
+                f,
+                "{:<6} {:>9}  p50 {:>9.2?}  p90 {:>9.2?}  p99 {:>9.2?}  max {:>9.2?}",
+                name, latencies.count, latencies.p50, latencies.p90, latencies.p99, latencies.max
+            )?;
+        }
+        return Ok(());
+    }
+}
+
+/// Generates the key and operation sequence of one thread, the same on every run.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        return self.0;
+    }
+
+    /// Returns a number in [0, 1).
+    fn next_ratio(&mut self) -> f64 {
+        return (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+    }
+}
+
+fn bench_key(index: u64) -> String {
+    return format!("bench-{:012}", index);
+}
+
+/// Writes every key of the workload, then runs and measures its operations.
+pub fn run(mut store: RCask, workload: &Workload) -> Result<Report> {
+    let value = vec![b'v'; workload.value_size];
+    store.bulk_load((0..workload.keys).map(|i| (bench_key(i), value.as_slice())))?;
+
+    let threads = workload.threads.max(1);
+    let keys = workload.keys.max(1);
+    let store = Arc::new(Mutex::new(store));
+    let started = Instant::now();
+    let handles = (0..threads)
+        .map(|thread| {
+            let store = store.clone();
+            let mut reader = store.lock().unwrap_or_else(|e| e.into_inner()).reader()?;
+            let value = value.clone();
+            let read_ratio = workload.read_ratio;
+            let operations = workload.operations / threads as u64
+                + u64::from((thread as u64) < workload.operations % threads as u64);
+            let handle = thread::spawn(move || -> Result<(Vec<Duration>, Vec<Duration>)> {
+                let mut random = XorShift(0x9E37_79B9_7F4A_7C15 ^ (thread as u64 + 1));
+                let (mut reads, mut writes) = (Vec::new(), Vec::new());
+                for _ in 0..operations {
+                    let key = bench_key(random.next() % keys);
+                    let begun = Instant::now();
+                    if random.next_ratio() < read_ratio {
+                        reader.get_bytes(&key)?;
+                        reads.push(begun.elapsed());
+                    } else {
+                        store
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .set(&key, &value)?;
+                        writes.push(begun.elapsed());
+                    }
+                }
+                return Ok((reads, writes));
+            });
+            return Ok(handle);
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (mut reads, mut writes) = (Vec::new(), Vec::new());
+    for handle in handles {
+        let (thread_reads, thread_writes) =
+            handle.join().unwrap_or_else(|e| panic::resume_unwind(e))?;
+        reads.extend(thread_reads);
+        writes.extend(thread_writes);
+    }
+    let elapsed = started.elapsed();
+    let operations = (reads.len() + writes.len()) as f64;
+    return Ok(Report {
+        elapsed,
+        throughput: operations / elapsed.as_secs_f64().max(f64::EPSILON),
+        reads: Latencies::from_samples(reads),
+        writes: Latencies::from_samples(writes),
+    });
+}
@@ -0,0 +1,96 @@
+//! Separate blob files for oversized values.
+//!
+//! When enabled, every value at least `min_value_size` bytes long is written to its own file,
+//! `<directory>/<pattern>.large/<id>.blob`, and the log record only holds the blob's id.
+//! Compaction carries these references over without reading the blobs, so large values are
+//! written exactly once, and deletes the blobs that are no longer referenced by any live key.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Tuning for blob files.
+#[derive(Debug, Clone)]
+pub struct BlobOptions {
+    /// Values smaller than this are stored in the log. Defaults to 1 MiB.
+    pub min_value_size: usize,
+}
+
+impl Default for BlobOptions {
+    fn default() -> Self {
+        return BlobOptions {
+            min_value_size: 1 << 20,
+        };
+    }
+}
+
+/// Directory of blob files, named by increasing ids.
+#[derive(Clone)]
+pub(crate) struct BlobStore {
+    pub(crate) options: BlobOptions,
+    dir: PathBuf,
+    next_id: u64,
+}
+
+impl BlobStore {
+    pub(crate) fn open(directory: &str, pattern: &str, options: BlobOptions) -> io::Result<Self> {
+        let dir = PathBuf::from(format!("{}/{}.large", directory, pattern));
+        fs::create_dir_all(&dir)?;
+
+        // Continue after the highest id in use, so ids are never reused.
+        let mut next_id = 0;
+        for entry in fs::read_dir(&dir)? {
+            if let Some(id) = blob_id(&entry?.path()) {
+                next_id = next_id.max(id + 1);
+            }
+        }
+        return Ok(BlobStore {
+            options,
+            dir,
+            next_id,
+        });
+    }
+
+    /// Writes a value to a new blob file and returns its id.
+    pub(crate) fn put(&mut self, value: &[u8]) -> io::Result<u64> {
+        let id = self.next_id;
+        let path = self.path(id);
+
+        // Write to a temporary file first so a crash never leaves a truncated blob behind.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, value)?;
+        fs::rename(&tmp, &path)?;
+        self.next_id += 1;
+        return Ok(id);
+    }
+
+    pub(crate) fn get(&self, id: u64) -> io::Result<Vec<u8>> {
+        return fs::read(self.path(id))
+            .map_err(|e| io::Error::new(e.kind(), format!("Failed to read blob {}: {}", id, e)));
+    }
+
+    /// Deletes every blob whose id is not in `live`.
+    pub(crate) fn retain(&self, live: &HashSet<u64>) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            match blob_id(&path) {
+                Some(id) if live.contains(&id) => {}
+                // Unreferenced blobs and temporary files left behind by a crash.
+                _ => fs::remove_file(&path)?,
+            }
+        }
+        return Ok(());
+    }
+
+    fn path(&self, id: u64) -> PathBuf {
+        return self.dir.join(format!("{}.blob", id));
+    }
+}
+
+fn blob_id(path: &std::path::Path) -> Option<u64> {
+    if path.extension()? != "blob" {
+        return None;
+    }
+    return path.file_stem()?.to_str()?.parse().ok();
+}
@@ -0,0 +1,426 @@
+use crate::adaptive::AdaptiveCompaction;
+use crate::backpressure::{BackpressureOptions, PressureCallback, PressureLevel};
+use crate::blob::BlobOptions;
+use crate::checksum::ChecksumAlgorithm;
+#[cfg(feature = "zstd")]
+use crate::compression::DictionaryOptions;
+use crate::dedup::DedupOptions;
+use crate::delta::DeltaOptions;
+use crate::events::EventSink;
+use crate::eviction::EvictionOptions;
+use crate::expiry::{Expiration, ExpiryListener};
+use crate::filter::CompactionFilter;
+use crate::health::DEFAULT_MIN_FREE_DISK;
+use crate::history::HistoryOptions;
+use crate::keycodec::KeyCodec;
+use crate::observer::Observer;
+use crate::progress::{CancellationToken, LoadProgress, ProgressCallback};
+use crate::schema::SchemaRegistry;
+use crate::vfs::{FileSystem, OsFileSystem};
+use crate::CompactionScheduler;
+use crate::{RCask, Reader, Result};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Configures and opens an `RCask` store.
+///
+/// ```no_run
+/// use rcask::RCask;
+///
+/// # fn main() -> rcask::Result<()> {
+/// let store = RCask::builder("./".to_string(), "log".to_string())
+///     .max_writes(500)
+///     .open()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Builder {
+    pub(crate) directory: String,
+    pub(crate) pattern: String,
+    pub(crate) max_writes: u64,
+    pub(crate) schema: Option<SchemaRegistry>,
+    pub(crate) delta: Option<DeltaOptions>,
+    pub(crate) dedup: Option<DedupOptions>,
+    pub(crate) blobs: Option<BlobOptions>,
+    /// `Some(verify)` writes checksums, verifying them on every read with `verify`.
+    pub(crate) checksums: Option<bool>,
+    pub(crate) checksum_algorithm: ChecksumAlgorithm,
+    pub(crate) cache_capacity: Option<usize>,
+    pub(crate) expiry: Option<ExpiryListener>,
+    pub(crate) filter: Option<Box<dyn CompactionFilter>>,
+    pub(crate) hot_keys: Option<usize>,
+    /// Threshold and capacity of the slow-operation log.
+    pub(crate) slow_log: Option<(Duration, usize)>,
+    pub(crate) min_free_disk: u64,
+    pub(crate) fs: Arc<dyn FileSystem>,
+    pub(crate) compaction_batch: Option<usize>,
+    pub(crate) block_size: Option<usize>,
+    pub(crate) alignment: Option<u64>,
+    pub(crate) compress_index: bool,
+    pub(crate) block_cache: Option<usize>,
+    pub(crate) progress: Option<ProgressCallback>,
+    pub(crate) cancel: Option<CancellationToken>,
+    pub(crate) scheduler: Option<Arc<CompactionScheduler>>,
+    pub(crate) backpressure: Option<BackpressureOptions>,
+    pub(crate) on_backpressure: Option<PressureCallback>,
+    pub(crate) sync_interval: Option<Duration>,
+    pub(crate) adaptive: Option<AdaptiveCompaction>,
+    pub(crate) eviction: Option<EvictionOptions>,
+    pub(crate) history: Option<HistoryOptions>,
+    pub(crate) quotas: Vec<(String, u64)>,
+    pub(crate) events: Option<EventSink>,
+    pub(crate) disk_headroom: u64,
+    pub(crate) quarantine_orphans: bool,
+    pub(crate) refresh_interval: Duration,
+    pub(crate) observers: Vec<Box<dyn Observer>>,
+    pub(crate) key_codec: Option<Arc<dyn KeyCodec>>,
+    pub(crate) retention: Option<Duration>,
+    #[cfg(feature = "zstd")]
+    pub(crate) compression: Option<DictionaryOptions>,
+}
+
+impl Builder {
+    pub(crate) fn new(directory: String, pattern: String) -> Self {
+        return Builder {
+            directory,
+            pattern,
+            max_writes: 10000,
+            schema: None,
+            delta: None,
+            dedup: None,
+            blobs: None,
+            checksums: None,
+            checksum_algorithm: ChecksumAlgorithm::Crc32,
+            cache_capacity: None,
+            expiry: None,
+            filter: None,
+            hot_keys: None,
+            slow_log: None,
+            min_free_disk: DEFAULT_MIN_FREE_DISK,
+            fs: Arc::new(OsFileSystem),
+            compaction_batch: None,
+            block_size: None,
+            alignment: None,
+            compress_index: false,
+            block_cache: None,
+            progress: None,
+            cancel: None,
+            scheduler: None,
+            backpressure: None,
+            on_backpressure: None,
+            sync_interval: None,
+            adaptive: None,
+            eviction: None,
+            history: None,
+            quotas: Vec::new(),
+            events: None,
+            disk_headroom: 0,
+            quarantine_orphans: false,
+            refresh_interval: Duration::from_secs(1),
+            observers: Vec::new(),
+            key_codec: None,
+            retention: None,
+            #[cfg(feature = "zstd")]
+            compression: None,
+        };
+    }
+
+    /// Maximum number of writes before compaction is triggered. Defaults to 10,000.
+    pub fn max_writes(mut self, max_writes: u64) -> Self {
+        self.max_writes = max_writes;
+        return self;
+    }
+
+    /// Tunes the number of writes between compactions to the targets in `targets` after
+    /// every compaction, starting from `max_writes`. See `RCask::compaction_threshold`.
+    pub fn adaptive_compaction(mut self, targets: AdaptiveCompaction) -> Self {
+        self.adaptive = Some(targets);
+        return self;
+    }
+
+    /// Compacts incrementally instead of in one blocking pass: once `max_writes` is reached,
+    /// every write relocates `batch` keys into the next segment until it is complete, which
+    /// bounds the latency a compaction adds to a single write. See `RCask::compaction_tick`.
+    pub fn incremental_compaction(mut self, batch: usize) -> Self {
+        self.compaction_batch = Some(batch);
+        return self;
+    }
+
+    /// Packs the small records that compaction writes into blocks of about `block_size`
+    /// bytes (at most 64 KiB, e.g. 4096) with a small index of their own, instead of writing
+    /// each one with its own framing. This saves most of the framing of small records.
+    /// Compaction relocates keys in sorted order, so neighbouring keys share a block. Records
+    /// written between compactions are not packed.
+    pub fn block_packing(mut self, block_size: usize) -> Self {
+        self.block_size = Some(block_size);
+        return self;
+    }
+
+    /// Pads every write to the log to a multiple of `alignment` bytes, e.g. 4096 for the
+    /// sector size of most NVMe drives, and starts each write on such a boundary, as direct
+    /// I/O requires. Writes then never share a sector with earlier ones. Compaction groups its
+    /// records into aligned extents instead of padding each one. Logs written this way stay
+    /// readable without the option.
+    pub fn aligned_records(mut self, alignment: u64) -> Self {
+        self.alignment = Some(alignment);
+        return self;
+    }
+
+    /// Keeps the in-memory index as a radix tree, so that keys sharing long prefixes, such as
+    /// URLs or file paths, take up less memory, at the cost of slower lookups. See `index`.
+    pub fn prefix_compressed_index(mut self) -> Self {
+        self.compress_index = true;
+        return self;
+    }
+
+    /// Runs this store's compactions through `scheduler`, which may be shared with other
+    /// stores to limit how many of them compact at once and how fast.
+    pub fn compaction_scheduler(mut self, scheduler: Arc<CompactionScheduler>) -> Self {
+        self.scheduler = Some(scheduler);
+        return self;
+    }
+
+    /// Slows down and eventually rejects writes when compaction falls behind or the disk
+    /// fills up, instead of letting them fail abruptly. See `BackpressureOptions`.
+    pub fn backpressure(mut self, options: BackpressureOptions) -> Self {
+        self.backpressure = Some(options);
+        return self;
+    }
+
+    /// Calls `callback` whenever backpressure starts or stops slowing down or rejecting
+    /// writes, so the application can shed load. Only takes effect with `backpressure`.
+    pub fn on_backpressure<F: FnMut(PressureLevel) + Send + 'static>(
+        mut self,
+        callback: F,
+    ) -> Self {
+        self.on_backpressure = Some(Box::new(callback));
+        return self;
+    }
+
+    /// Syncs unsynced writes once `interval` has passed since the last sync, so the writes a
+    /// crash can lose are bounded in time. The next write syncs when it is due; to sync
+    /// stores that stop writing too, call `RCask::sync_if_due` from a timer or use
+    /// `BackgroundRuntime::maintain`.
+    pub fn sync_interval(mut self, interval: Duration) -> Self {
+        self.sync_interval = Some(interval);
+        return self;
+    }
+
+    /// Keeps the store within `budget` by writing tombstones for the least recently used
+    /// keys whenever a write exceeds it, turning the store into a persistent LRU cache.
+    pub fn evict_lru(mut self, budget: EvictionOptions) -> Self {
+        self.eviction = Some(budget);
+        return self;
+    }
+
+    /// Stamps every value and deletion with its write time and keeps older versions through
+    /// compaction as `options` says, so `get_as_of` can read a key as it was earlier.
+    pub fn history(mut self, options: HistoryOptions) -> Self {
+        self.history = Some(options);
+        return self;
+    }
+
+    /// Limits the live keys and values starting with `prefix` to `max_bytes`, rejecting
+    /// writes beyond it with `Error::QuotaExceeded`. Can be called once per namespace.
+    pub fn quota(mut self, prefix: impl Into<String>, max_bytes: u64) -> Self {
+        self.quotas.push((prefix.into(), max_bytes));
+        return self;
+    }
+
+    /// Tags every value with a schema version and upgrades older values through the registry.
+    /// The version byte is part of the stored value, so which of `schema`, `delta_encoding`,
+    /// `deduplication`, `blob_files` and `zstd_dictionary` are set is recorded with the store
+    /// and opening it with a different choice fails.
+    pub fn schema(mut self, schema: SchemaRegistry) -> Self {
+        self.schema = Some(schema);
+        return self;
+    }
+
+    /// Writes a checksum with every record, of the algorithm picked by `checksum_algorithm`.
+    /// With `verify`, every read checks it; otherwise only reads with
+    /// `ReadOptions::verify_checksum` do.
+    pub fn checksums(mut self, verify: bool) -> Self {
+        self.checksums = Some(verify);
+        return self;
+    }
+
+    /// Picks the checksum that `checksums` writes, e.g. `ChecksumAlgorithm::Crc32c` for
+    /// hardware-accelerated CRC-32C or `ChecksumAlgorithm::None` for none. Each segment
+    /// records its algorithm, so existing segments keep theirs until compaction rewrites
+    /// them. Defaults to CRC-32.
+    pub fn checksum_algorithm(mut self, algorithm: ChecksumAlgorithm) -> Self {
+        self.checksum_algorithm = algorithm;
+        return self;
+    }
+
+    /// Caches up to `capacity` bytes of recently read keys and values in memory.
+    pub fn read_cache(mut self, capacity: usize) -> Self {
+        self.cache_capacity = Some(capacity);
+        return self;
+    }
+
+    /// Caches up to `capacity` bytes of the blocks packed by compaction (see `block_packing`),
+    /// shared with every reader handle, so reads of keys in the same block are served from
+    /// memory. Its counters are part of `RCask::stats`.
+    pub fn block_cache(mut self, capacity: usize) -> Self {
+        self.block_cache = Some(capacity);
+        return self;
+    }
+
+    /// Calls `callback` with the progress of `open` while it reads the log into the index:
+    /// every few megabytes, and once the scan is complete.
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&LoadProgress) + Send + 'static,
+    {
+        self.progress = Some(Box::new(callback));
+        return self;
+    }
+
+    /// Calls `callback` for every key whose TTL has passed, when `RCask::sweep_expired` or
+    /// compaction finds it. With `with_value` the event carries the key's last value. To
+    /// receive events on another thread, send them through a channel from the callback.
+    pub fn on_expire<F>(mut self, with_value: bool, callback: F) -> Self
+    where
+        F: FnMut(Expiration) + Send + 'static,
+    {
+        self.expiry = Some(ExpiryListener::new(with_value, Box::new(callback)));
+        return self;
+    }
+
+    /// Counts reads and writes per key so `RCask::top_keys` can report the hottest keys,
+    /// remembering up to `capacity` candidates for each.
+    pub fn hot_keys(mut self, capacity: usize) -> Self {
+        self.hot_keys = Some(capacity);
+        return self;
+    }
+
+    /// Keeps the last `capacity` gets, sets and compactions that took longer than `threshold`
+    /// for `RCask::slow_log`.
+    pub fn slow_log(mut self, threshold: Duration, capacity: usize) -> Self {
+        self.slow_log = Some((threshold, capacity));
+        return self;
+    }
+
+    /// Free disk space in bytes below which `RCask::health` reports the disk as low.
+    /// Defaults to 64 MiB.
+    pub fn min_free_disk(mut self, bytes: u64) -> Self {
+        self.min_free_disk = bytes;
+        return self;
+    }
+
+    /// Disk space in bytes kept free for compaction: writes fail with `Error::DiskFull` once
+    /// less is free, so that compaction can still run and reclaim space. Defaults to 0, no
+    /// reserve. See `headroom`.
+    pub fn disk_headroom(mut self, bytes: u64) -> Self {
+        self.disk_headroom = bytes;
+        return self;
+    }
+
+    /// Moves the files an earlier crash left behind to `<directory>/<pattern>.orphaned/`
+    /// instead of deleting them when the store is opened. See `orphans`.
+    pub fn quarantine_orphans(mut self) -> Self {
+        self.quarantine_orphans = true;
+        return self;
+    }
+
+    /// Opens log files through `fs` instead of the operating system's file system, e.g. to
+    /// inject faults with `vfs::FaultyFileSystem` in tests.
+    pub fn file_system(mut self, fs: Arc<dyn FileSystem>) -> Self {
+        self.fs = fs;
+        return self;
+    }
+
+    /// Runs `filter` on every live value during compaction to keep, drop or replace it.
+    pub fn compaction_filter<F: CompactionFilter + 'static>(mut self, filter: F) -> Self {
+        self.filter = Some(Box::new(filter));
+        return self;
+    }
+
+    /// Encodes every key with `codec` before it reaches the index, see `keycodec`. A store
+    /// must always be opened with the same codec; opening fails otherwise.
+    pub fn key_codec<C: KeyCodec + 'static>(mut self, codec: C) -> Self {
+        self.key_codec = Some(Arc::new(codec));
+        return self;
+    }
+
+    /// Stamps every record with its write time and has compaction drop records older than
+    /// `retention`, whatever their key, see `retention`.
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = Some(retention);
+        return self;
+    }
+
+    /// Registers `observer` to see the writes, deletions, missed reads and compactions of
+    /// the store, after any observers registered before it, see `observer`.
+    pub fn observer<O: Observer + 'static>(mut self, observer: O) -> Self {
+        self.observers.push(Box::new(observer));
+        return self;
+    }
+
+    /// Writes a JSON line to `writer` for every compaction, expiry sweep, periodic sync and
+    /// the recovery scan of `open`, see `events`.
+    pub fn event_log<W: Write + Send + 'static>(mut self, writer: W) -> Self {
+        self.events = Some(EventSink::Writer(Box::new(writer)));
+        return self;
+    }
+
+    /// Like `event_log`, but sends the events to the `log` facade.
+    #[cfg(feature = "log")]
+    pub fn log_events(mut self) -> Self {
+        self.events = Some(EventSink::Log);
+        return self;
+    }
+
+    /// Stores updates of a key as deltas against its previous value when that is smaller.
+    /// Recorded with the store like `schema`, since it changes how values are framed.
+    pub fn delta_encoding(mut self, options: DeltaOptions) -> Self {
+        self.delta = Some(options);
+        return self;
+    }
+
+    /// Stores large values once in a content store shared by every key that holds them.
+    /// Recorded with the store like `schema`.
+    pub fn deduplication(mut self, options: DedupOptions) -> Self {
+        self.dedup = Some(options);
+        return self;
+    }
+
+    /// Writes oversized values to their own blob files, so compaction never rewrites them.
+    /// Recorded with the store like `schema`; the threshold may change between opens.
+    pub fn blob_files(mut self, options: BlobOptions) -> Self {
+        self.blobs = Some(options);
+        return self;
+    }
+
+    /// Compresses values with a zstd dictionary trained from live values during compaction.
+    /// Every stored value gets a compression tag, so this is recorded like `schema`.
+    #[cfg(feature = "zstd")]
+    pub fn zstd_dictionary(mut self, options: DictionaryOptions) -> Self {
+        self.compression = Some(options);
+        return self;
+    }
+
+    /// How often a reader opened with `open_reader` looks for a new segment and records the
+    /// writer appended since. Defaults to one second; `Reader::refresh` checks at once. With
+    /// the `notify` feature on Linux, readers look whenever the writer changes the store's
+    /// files instead.
+    pub fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        return self;
+    }
+
+    /// Opens the store, creating the directory and first log file if needed. Fails with
+    /// `Error::Locked` if the store is already open for writing.
+    pub fn open(self) -> Result<RCask> {
+        return RCask::open(self);
+    }
+
+    /// Opens the store read-only, e.g. from a CLI tool or a sidecar while another process
+    /// writes to it, see `Reader`. The value options must match the writer's. The reader never
+    /// writes to the store's files and does not take the writer's lock.
+    pub fn open_reader(self) -> Result<Reader> {
+        return Reader::open(self);
+    }
+}
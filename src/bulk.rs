@@ -0,0 +1,140 @@
+//! Bulk load sessions: ingesting records without per-record bookkeeping.
+//!
+//! `RCask::bulk_load` takes every record of a load at once. A `BulkLoadSession` takes them one
+//! at a time, e.g. while reading a large export, and defers all the bookkeeping a `set` does:
+//! records are gathered in a large buffer and appended a buffer at a time, and the index, the
+//! eviction and quota accounting and the compaction check are brought up to date once, when
+//! the session finishes. Until then nothing the session wrote can be read, neither on the
+//! handle, which the session borrows, nor on its readers.
+//!
+//! Like `bulk_load`, a session writes plain records: no expiry, metadata, history or large
+//! value storage, and observers are not called. A session that is dropped without `finish`
+//! finishes on drop, ignoring errors.
+
+use crate::{RCask, Result};
+
+/// Bytes of records a session gathers before appending them to the log.
+const SESSION_BUFFER_SIZE: usize = 16 << 20;
+
+/// A bulk load of records given one at a time, see `RCask::bulk_load_session`.
+pub struct BulkLoadSession<'a> {
+    store: &'a mut RCask,
+    /// Stored keys and values not appended yet.
+    buffer: Vec<(String, Vec<u8>)>,
+    buffered_bytes: usize,
+    /// Bytes the caller gave for the records in the buffer.
+    buffered_user_bytes: u64,
+    /// Stored key and offset of each record appended, for the index.
+    offsets: Vec<(String, u64)>,
+    /// Stored key and size of each record, for bounded mode and quotas.
+    sizes: Vec<(String, u64)>,
+    track_sizes: bool,
+    user_bytes: u64,
+    finished: bool,
+}
+
+impl RCask {
+    /// Starts a bulk load of records given one at a time with `BulkLoadSession::set`, see the
+    /// module docs. The records are indexed when the session finishes.
+    pub fn bulk_load_session(&mut self) -> Result<BulkLoadSession<'_>> {
+        self.begin_bulk_load()?;
+        let track_sizes = self.lru.is_some() || self.quotas.is_some();
+        return Ok(BulkLoadSession {
+            store: self,
+            buffer: Vec::new(),
+            buffered_bytes: 0,
+            buffered_user_bytes: 0,
+            offsets: Vec::new(),
+            sizes: Vec::new(),
+            track_sizes,
+            user_bytes: 0,
+            finished: false,
+        });
+    }
+}
+
+impl BulkLoadSession<'_> {
+    /// Adds a record to the load. A later record of the same key replaces an earlier one.
+    pub fn set<T: AsRef<[u8]>, U: AsRef<[u8]>>(&mut self, key: T, value: U) -> Result<()> {
+        let (key, value) = (key.as_ref(), value.as_ref());
+        self.buffered_user_bytes += (key.len() + value.len()) as u64;
+        let key = String::from_utf8_lossy(key);
+        let key = self.store.stored_key(&key).into_owned();
+        let stored = self.store.values.encode(value)?.into_owned();
+        self.buffered_bytes += key.len() + stored.len();
+        if self.track_sizes {
+            self.sizes
+                .push((key.clone(), (key.len() + stored.len()) as u64));
+        }
+        self.buffer.push((key, stored));
+        if self.buffered_bytes >= SESSION_BUFFER_SIZE {
+            self.append()?;
+        }
+        return Ok(());
+    }
+
+    /// Number of records added so far.
+    pub fn len(&self) -> u64 {
+        return (self.offsets.len() + self.buffer.len()) as u64;
+    }
+
+    /// Whether no record has been added yet.
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// Appends the records left in the buffer, indexes every record of the session and runs
+    /// the deferred compaction check. Returns the number of records written.
+    pub fn finish(mut self) -> Result<u64> {
+        return self.complete();
+    }
+
+    /// Appends the buffered records to the log. Records of a failed append are left out.
+    fn append(&mut self) -> Result<()> {
+        let records = std::mem::take(&mut self.buffer);
+        let count = records.len();
+        let user_bytes = std::mem::take(&mut self.buffered_user_bytes);
+        self.buffered_bytes = 0;
+        let appended = self.store.store.append_unindexed(records);
+        self.store.health.wrote(&appended);
+        match appended {
+            Ok(offsets) => {
+                self.offsets.extend(offsets);
+                self.user_bytes += user_bytes;
+                return Ok(());
+            }
+            Err(e) => {
+                if self.track_sizes {
+                    self.sizes.truncate(self.sizes.len() - count);
+                }
+                return Err(e.into());
+            }
+        }
+    }
+
+    fn complete(&mut self) -> Result<u64> {
+        self.finished = true;
+        // Records appended before a failed append are complete, so they are indexed anyway.
+        let appended = self.append();
+        let written = self.offsets.len() as u64;
+        if written > 0 {
+            self.store
+                .store
+                .index_appended(std::mem::take(&mut self.offsets));
+            self.store.traffic.user_bytes += self.user_bytes;
+            let sizes = std::mem::take(&mut self.sizes);
+            self.store
+                .lru_wrote(sizes.iter().map(|(key, size)| (key.as_str(), *size)), false)?;
+        }
+        appended?;
+        return self.store.finish_bulk_load(written);
+    }
+}
+
+impl Drop for BulkLoadSession<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.complete();
+        }
+    }
+}
@@ -0,0 +1,208 @@
+//! Bounded caches of decoded values and of packed blocks, evicting the least recently used
+//! entries.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+struct Entry {
+    value: Vec<u8>,
+    expires_at: Option<u64>,
+    last_used: u64,
+}
+
+pub(crate) struct ReadCache {
+    capacity: usize,
+    size: usize,
+    entries: HashMap<String, Entry>,
+    /// Keys by the tick of their last use, oldest first.
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl ReadCache {
+    /// Creates a cache holding at most `capacity` bytes of keys and values.
+    pub(crate) fn new(capacity: usize) -> Self {
+        return ReadCache {
+            capacity,
+            size: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        };
+    }
+
+    /// Returns the cached value of `key` if it has not expired at `now`.
+    pub(crate) fn get(&mut self, key: &str, now: u64) -> Option<Vec<u8>> {
+        if !self.touch(key, now) {
+            return None;
+        }
+        return self.peek(key).map(<[u8]>::to_vec);
+    }
+
+    /// Marks `key` as just used and returns whether it is cached and has not expired at
+    /// `now`, for `peek` to borrow its value.
+    pub(crate) fn touch(&mut self, key: &str, now: u64) -> bool {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return false;
+        };
+        if entry.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            self.remove(key);
+            return false;
+        }
+        self.tick += 1;
+        self.recency.remove(&entry.last_used);
+        entry.last_used = self.tick;
+        self.recency.insert(self.tick, key.to_string());
+        return true;
+    }
+
+    /// Returns the cached value of `key` without marking it used or checking its expiry.
+    pub(crate) fn peek(&self, key: &str) -> Option<&[u8]> {
+        return self.entries.get(key).map(|entry| entry.value.as_slice());
+    }
+
+    pub(crate) fn insert(&mut self, key: &str, value: Vec<u8>, expires_at: Option<u64>) {
+        self.remove(key);
+        let size = key.len() + value.len();
+        if size > self.capacity {
+            return;
+        }
+        while self.size + size > self.capacity {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+
+        self.tick += 1;
+        self.size += size;
+        self.recency.insert(self.tick, key.to_string());
+        self.entries.insert(
+            key.to_string(),
+            Entry {
+                value,
+                expires_at,
+                last_used: self.tick,
+            },
+        );
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+            self.size -= key.len() + entry.value.len();
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.size = 0;
+    }
+}
+
+/// Counters of a `BlockCache`, returned by `RCask::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Block reads served from memory.
+    pub hits: u64,
+    /// Block reads that went to the disk.
+    pub misses: u64,
+    /// Blocks dropped to make room for others.
+    pub evictions: u64,
+    /// Bytes of blocks held.
+    pub size: usize,
+    pub capacity: usize,
+}
+
+impl CacheStats {
+    /// Fraction of block reads served from memory, or 0 before the first one.
+    pub fn hit_ratio(&self) -> f64 {
+        let reads = self.hits + self.misses;
+        if reads == 0 {
+            return 0.0;
+        }
+        return self.hits as f64 / reads as f64;
+    }
+}
+
+/// Identifies a block by its segment number and offset.
+type BlockId = (u64, u64);
+
+struct Blocks {
+    entries: HashMap<BlockId, (Arc<[u8]>, u64)>,
+    /// Blocks by the tick of their last use, oldest first.
+    recency: BTreeMap<u64, BlockId>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+/// A cache of the blocks packed by compaction (see `Builder::block_packing`), shared by a
+/// store and its readers. Blocks never change once written, so entries are never invalidated.
+pub(crate) struct BlockCache {
+    blocks: Mutex<Blocks>,
+}
+
+impl BlockCache {
+    /// Creates a cache holding at most `capacity` bytes of blocks.
+    pub(crate) fn new(capacity: usize) -> Self {
+        return BlockCache {
+            blocks: Mutex::new(Blocks {
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+                stats: CacheStats {
+                    capacity,
+                    ..Default::default()
+                },
+            }),
+        };
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Blocks> {
+        return self.blocks.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    /// Returns the cached block at `offset` of `segment`.
+    pub(crate) fn get(&self, segment: u64, offset: u64) -> Option<Arc<[u8]>> {
+        let mut guard = self.lock();
+        let blocks = &mut *guard;
+        let (block, last_used) = blocks.entries.get_mut(&(segment, offset))?;
+        blocks.tick += 1;
+        blocks.recency.remove(last_used);
+        *last_used = blocks.tick;
+        blocks.recency.insert(blocks.tick, (segment, offset));
+        blocks.stats.hits += 1;
+        return Some(block.clone());
+    }
+
+    /// Caches a block that was just read from the disk.
+    pub(crate) fn insert(&self, segment: u64, offset: u64, block: Arc<[u8]>) {
+        let mut guard = self.lock();
+        let blocks = &mut *guard;
+        blocks.stats.misses += 1;
+        if block.len() > blocks.stats.capacity || blocks.entries.contains_key(&(segment, offset)) {
+            return;
+        }
+        while blocks.stats.size + block.len() > blocks.stats.capacity {
+            let Some((_, oldest)) = blocks.recency.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = blocks.entries.remove(&oldest) {
+                blocks.stats.size -= evicted.len();
+                blocks.stats.evictions += 1;
+            }
+        }
+
+        blocks.tick += 1;
+        blocks.stats.size += block.len();
+        blocks.recency.insert(blocks.tick, (segment, offset));
+        blocks
+            .entries
+            .insert((segment, offset), (block, blocks.tick));
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        return self.lock().stats;
+    }
+}
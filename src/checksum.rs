@@ -0,0 +1,392 @@
+//! Checksums of log records: CRC-32 (IEEE), CRC-32C and XXH64.
+//!
+//! CRC-32C uses the SSE4.2 `crc32` instruction where the CPU has it. A segment records its
+//! algorithm in its header, see `KVStore`; segments without a header use CRC-32.
+
+use std::io;
+
+/// The checksum written with every record when checksums are enabled, see
+/// `Builder::checksum_algorithm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumAlgorithm {
+    /// CRC-32 (IEEE), the original format.
+    #[default]
+    Crc32,
+    /// CRC-32C (Castagnoli), hardware accelerated with SSE4.2.
+    Crc32c,
+    /// 64-bit xxHash, which is fast everywhere and has a wider checksum.
+    XxHash64,
+    /// No checksums at all.
+    None,
+}
+
+impl ChecksumAlgorithm {
+    /// Identifier stored in a segment header.
+    pub(crate) fn id(self) -> u64 {
+        return match self {
+            ChecksumAlgorithm::Crc32 => 0,
+            ChecksumAlgorithm::Crc32c => 1,
+            ChecksumAlgorithm::XxHash64 => 2,
+            ChecksumAlgorithm::None => 3,
+        };
+    }
+
+    pub(crate) fn from_id(id: u64) -> io::Result<Self> {
+        return match id {
+            0 => Ok(ChecksumAlgorithm::Crc32),
+            1 => Ok(ChecksumAlgorithm::Crc32c),
+            2 => Ok(ChecksumAlgorithm::XxHash64),
+            3 => Ok(ChecksumAlgorithm::None),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown checksum algorithm {}", id),
+            )),
+        };
+    }
+
+    /// Bytes of a checksum.
+    pub(crate) fn width(self) -> usize {
+        return match self {
+            ChecksumAlgorithm::Crc32 | ChecksumAlgorithm::Crc32c => 4,
+            ChecksumAlgorithm::XxHash64 => 8,
+            ChecksumAlgorithm::None => 0,
+        };
+    }
+
+    /// Returns an incremental checksum over several byte slices.
+    pub(crate) fn hasher(self) -> Checksum {
+        return match self {
+            ChecksumAlgorithm::Crc32 => Checksum::Crc32(Crc32::new()),
+            ChecksumAlgorithm::Crc32c => Checksum::Crc32c(!0),
+            ChecksumAlgorithm::XxHash64 => Checksum::XxHash64(XxHash64::new()),
+            ChecksumAlgorithm::None => Checksum::None,
+        };
+    }
+}
+
+/// An incremental checksum, see `ChecksumAlgorithm::hasher`.
+pub(crate) enum Checksum {
+    Crc32(Crc32),
+    Crc32c(u32),
+    XxHash64(XxHash64),
+    None,
+}
+
+impl Checksum {
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Checksum::Crc32(crc) => crc.update(bytes),
+            Checksum::Crc32c(crc) => *crc = crc32c_update(*crc, bytes),
+            Checksum::XxHash64(hash) => hash.update(bytes),
+            Checksum::None => {}
+        }
+    }
+
+    /// Returns the checksum as stored after a record.
+    pub(crate) fn finish(&self) -> Vec<u8> {
+        return match self {
+            Checksum::Crc32(crc) => crc.finish().to_le_bytes().to_vec(),
+            Checksum::Crc32c(crc) => (!crc).to_le_bytes().to_vec(),
+            Checksum::XxHash64(hash) => hash.finish().to_le_bytes().to_vec(),
+            Checksum::None => Vec::new(),
+        };
+    }
+}
+
+const POLYNOMIAL: u32 = 0xEDB88320;
+const CASTAGNOLI: u32 = 0x82F63B78;
+
+const TABLE: [u32; 256] = build_table(POLYNOMIAL);
+const CASTAGNOLI_TABLE: [u32; 256] = build_table(CASTAGNOLI);
+
+const fn build_table(polynomial: u32) -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ polynomial
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    return table;
+}
+
+/// Incremental CRC-32 over several byte slices.
+pub(crate) struct Crc32(u32);
+
+impl Crc32 {
+    pub(crate) fn new() -> Self {
+        return Crc32(!0);
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = TABLE[((self.0 ^ byte as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u32 {
+        return !self.0;
+    }
+}
+
+/// Feeds `bytes` into the running (inverted) CRC-32C `crc`.
+fn crc32c_update(crc: u32, bytes: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    if std::arch::is_x86_feature_detected!("sse4.2") {
+        // SAFETY: the CPU supports SSE4.2, checked just above.
+        return unsafe { crc32c_sse42(crc, bytes) };
+    }
+    return crc32c_table(crc, bytes);
+}
+
+/// `crc32c_update` for CPUs without SSE4.2.
+fn crc32c_table(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = crc;
+    for &byte in bytes {
+        crc = CASTAGNOLI_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    return crc;
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn crc32c_sse42(crc: u32, bytes: &[u8]) -> u32 {
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    let mut crc = crc as u64;
+    let mut words = bytes.chunks_exact(8);
+    for word in &mut words {
+        crc = _mm_crc32_u64(crc, u64::from_le_bytes(word.try_into().unwrap_or_default()));
+    }
+    let mut crc = crc as u32;
+    for &byte in words.remainder() {
+        crc = _mm_crc32_u8(crc, byte);
+    }
+    return crc;
+}
+
+const PRIME64_1: u64 = 0x9E3779B185EBCA87;
+const PRIME64_2: u64 = 0xC2B2AE3D27D4EB4F;
+const PRIME64_3: u64 = 0x165667B19E3779F9;
+const PRIME64_4: u64 = 0x85EBCA77C2B2AE63;
+const PRIME64_5: u64 = 0x27D4EB2F165667C5;
+
+/// Incremental XXH64 with seed 0 over several byte slices.
+pub(crate) struct XxHash64 {
+    lanes: [u64; 4],
+    /// Input not yet consumed by a full 32-byte stripe.
+    buffer: [u8; 32],
+    buffered: usize,
+    total: u64,
+}
+
+impl XxHash64 {
+    fn new() -> Self {
+        return XxHash64 {
+            lanes: [
+                PRIME64_1.wrapping_add(PRIME64_2),
+                PRIME64_2,
+                0,
+                0u64.wrapping_sub(PRIME64_1),
+            ],
+            buffer: [0; 32],
+            buffered: 0,
+            total: 0,
+        };
+    }
+
+    fn update(&mut self, mut bytes: &[u8]) {
+        self.total += bytes.len() as u64;
+        if self.buffered > 0 {
+            let take = bytes.len().min(32 - self.buffered);
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(&bytes[..take]);
+            self.buffered += take;
+            bytes = &bytes[take..];
+            if self.buffered < 32 {
+                return;
+            }
+            let stripe = self.buffer;
+            self.consume(&stripe);
+            self.buffered = 0;
+        }
+        let mut stripes = bytes.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.consume(stripe);
+        }
+        let rest = stripes.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    fn consume(&mut self, stripe: &[u8]) {
+        for (lane, word) in self.lanes.iter_mut().zip(stripe.chunks_exact(8)) {
+            *lane = round(*lane, read_u64(word));
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        let mut hash = if self.total >= 32 {
+            let [v1, v2, v3, v4] = self.lanes;
+            let mut hash = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            for lane in self.lanes {
+                hash = (hash ^ round(0, lane))
+                    .wrapping_mul(PRIME64_1)
+                    .wrapping_add(PRIME64_4);
+            }
+            hash
+        } else {
+            PRIME64_5
+        };
+        hash = hash.wrapping_add(self.total);
+
+        let mut rest = &self.buffer[..self.buffered];
+        while rest.len() >= 8 {
+            hash ^= round(0, read_u64(&rest[..8]));
+            hash = hash
+                .rotate_left(27)
+                .wrapping_mul(PRIME64_1)
+                .wrapping_add(PRIME64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let word = u32::from_le_bytes(rest[..4].try_into().unwrap_or_default()) as u64;
+            hash ^= word.wrapping_mul(PRIME64_1);
+            hash = hash
+                .rotate_left(23)
+                .wrapping_mul(PRIME64_2)
+                .wrapping_add(PRIME64_3);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            hash ^= (byte as u64).wrapping_mul(PRIME64_5);
+            hash = hash.rotate_left(11).wrapping_mul(PRIME64_1);
+        }
+
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME64_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME64_3);
+        hash ^= hash >> 32;
+        return hash;
+    }
+}
+
+fn round(lane: u64, word: u64) -> u64 {
+    return lane
+        .wrapping_add(word.wrapping_mul(PRIME64_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME64_1);
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    return u64::from_le_bytes(bytes.try_into().unwrap_or_default());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inputs of the known answers below: the usual check string and one longer than an
+    /// XXH64 stripe and a CRC-32C word.
+    fn inputs() -> [Vec<u8>; 3] {
+        return [Vec::new(), b"123456789".to_vec(), (0..100).collect()];
+    }
+
+    /// Checksums `input` in one piece and in three, which must agree.
+    fn checksum(algorithm: ChecksumAlgorithm, input: &[u8]) -> Vec<u8> {
+        let mut whole = algorithm.hasher();
+        whole.update(input);
+        let mut pieces = algorithm.hasher();
+        let (first, rest) = input.split_at(input.len().min(7));
+        let (second, third) = rest.split_at(rest.len().min(33));
+        for piece in [first, second, third] {
+            pieces.update(piece);
+        }
+        assert_eq!(whole.finish(), pieces.finish());
+        assert_eq!(whole.finish().len(), algorithm.width());
+        return whole.finish();
+    }
+
+    #[test]
+    fn crc32_known_answers() {
+        let expected: [u32; 3] = [0, 0xCBF43926, 0x58C932F5];
+        for (input, expected) in inputs().iter().zip(expected) {
+            assert_eq!(
+                checksum(ChecksumAlgorithm::Crc32, input),
+                expected.to_le_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn crc32c_known_answers() {
+        let expected: [u32; 3] = [0, 0xE3069283, 0xC1CAEBE5];
+        for (input, expected) in inputs().iter().zip(expected) {
+            assert_eq!(
+                checksum(ChecksumAlgorithm::Crc32c, input),
+                expected.to_le_bytes()
+            );
+        }
+        let zeros = checksum(ChecksumAlgorithm::Crc32c, &[0; 32]);
+        assert_eq!(zeros, 0x8A9136AAu32.to_le_bytes());
+        let ones = checksum(ChecksumAlgorithm::Crc32c, &[0xFF; 32]);
+        assert_eq!(ones, 0x62A8AB43u32.to_le_bytes());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn crc32c_instruction_agrees_with_the_table() {
+        if !std::arch::is_x86_feature_detected!("sse4.2") {
+            return;
+        }
+        let input: Vec<u8> = (0..=255).collect();
+        for length in 0..input.len() {
+            let bytes = &input[..length];
+            // SAFETY: the CPU supports SSE4.2, checked above.
+            let hardware = unsafe { crc32c_sse42(!0, bytes) };
+            assert_eq!(hardware, crc32c_table(!0, bytes), "length {}", length);
+        }
+    }
+
+    #[test]
+    fn xxhash64_known_answers() {
+        let expected: [u64; 3] = [0xEF46DB3751D8E999, 0x8CB841DB40E6AE83, 0x6AC1E58032166597];
+        for (input, expected) in inputs().iter().zip(expected) {
+            assert_eq!(
+                checksum(ChecksumAlgorithm::XxHash64, input),
+                expected.to_le_bytes()
+            );
+        }
+        let abc = checksum(ChecksumAlgorithm::XxHash64, b"abc");
+        assert_eq!(abc, 0x44BC2CF5AD770999u64.to_le_bytes());
+    }
+
+    #[test]
+    fn ids_round_trip() {
+        for algorithm in [
+            ChecksumAlgorithm::Crc32,
+            ChecksumAlgorithm::Crc32c,
+            ChecksumAlgorithm::XxHash64,
+            ChecksumAlgorithm::None,
+        ] {
+            assert_eq!(
+                ChecksumAlgorithm::from_id(algorithm.id()).unwrap(),
+                algorithm
+            );
+        }
+        assert!(ChecksumAlgorithm::from_id(4).is_err());
+    }
+}
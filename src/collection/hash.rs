@@ -0,0 +1,115 @@
+//! Hashes of fields to values.
+//!
+//! Snapshots hold the number of fields (u64) followed by the length-prefixed field and value
+//! of each entry, in field order.
+
+use super::{invalid, put_bytes, take_bytes, take_u64, Collection};
+use crate::{RCask, Result};
+use std::collections::BTreeMap;
+use std::io;
+
+const OP_SET: u8 = 0;
+const OP_DELETE: u8 = 1;
+
+#[derive(Default)]
+pub(crate) struct Hash(BTreeMap<Vec<u8>, Vec<u8>>);
+
+pub(crate) enum HashOp {
+    Set(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+impl Collection for Hash {
+    const TYPE: u8 = 3;
+    const NAME: &'static str = "hash";
+    type Op = HashOp;
+
+    fn apply(&mut self, op: HashOp) {
+        match op {
+            HashOp::Set(field, value) => {
+                self.0.insert(field, value);
+            }
+            HashOp::Delete(field) => {
+                self.0.remove(&field);
+            }
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.0.len() as u64).to_le_bytes());
+        for (field, value) in &self.0 {
+            put_bytes(out, field);
+            put_bytes(out, value);
+        }
+    }
+
+    fn decode(mut input: &[u8]) -> io::Result<Self> {
+        let count = take_u64(&mut input)?;
+        let mut fields = BTreeMap::new();
+        for _ in 0..count {
+            let field = take_bytes(&mut input)?;
+            fields.insert(field, take_bytes(&mut input)?);
+        }
+        return Ok(Hash(fields));
+    }
+
+    fn encode_op(op: &HashOp, out: &mut Vec<u8>) {
+        match op {
+            HashOp::Set(field, value) => {
+                out.push(OP_SET);
+                put_bytes(out, field);
+                put_bytes(out, value);
+            }
+            HashOp::Delete(field) => {
+                out.push(OP_DELETE);
+                put_bytes(out, field);
+            }
+        }
+    }
+
+    fn decode_op(input: &[u8]) -> io::Result<HashOp> {
+        return match input.split_first() {
+            Some((&OP_SET, mut rest)) => {
+                let field = take_bytes(&mut rest)?;
+                Ok(HashOp::Set(field, take_bytes(&mut rest)?))
+            }
+            Some((&OP_DELETE, mut rest)) => Ok(HashOp::Delete(take_bytes(&mut rest)?)),
+            _ => Err(invalid("Unknown hash operation")),
+        };
+    }
+}
+
+impl RCask {
+    /// Sets `field` of the hash stored under `key` to `value`, creating the hash if the key
+    /// does not exist. Only the field is written, not the whole hash.
+    pub fn hset<F: AsRef<[u8]>, T: AsRef<[u8]>>(
+        &mut self,
+        key: &str,
+        field: F,
+        value: T,
+    ) -> Result<()> {
+        let op = HashOp::Set(field.as_ref().to_vec(), value.as_ref().to_vec());
+        return self.write_collection_op::<Hash>(key, op);
+    }
+
+    /// Returns the value of `field` in the hash stored under `key`.
+    pub fn hget<F: AsRef<[u8]>>(&mut self, key: &str, field: F) -> Result<Option<Vec<u8>>> {
+        return Ok(self
+            .read_collection::<Hash>(key)?
+            .and_then(|mut hash| hash.0.remove(field.as_ref())));
+    }
+
+    /// Removes `field` from the hash stored under `key`. Removing a field that is not in the
+    /// hash, or from a key without a hash, is not an error and writes nothing.
+    pub fn hdel<F: AsRef<[u8]>>(&mut self, key: &str, field: F) -> Result<()> {
+        return self.remove_from_collection::<Hash>(key, HashOp::Delete(field.as_ref().to_vec()));
+    }
+
+    /// Returns all fields and values of the hash stored under `key`, in field order.
+    pub fn hgetall(&mut self, key: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        return Ok(self
+            .read_collection::<Hash>(key)?
+            .map(|hash| hash.0.into_iter().collect())
+            .unwrap_or_default());
+    }
+}
@@ -0,0 +1,114 @@
+//! Lists used as queues: items are pushed at the back and popped from the front.
+//!
+//! Snapshots hold the number of items (u64) followed by the length-prefixed items.
+
+use super::{invalid, put_bytes, take_bytes, take_u64, Collection};
+use crate::{RCask, Result};
+use std::collections::VecDeque;
+use std::io;
+use std::ops::{Bound, RangeBounds};
+
+const OP_PUSH_BACK: u8 = 0;
+const OP_POP_FRONT: u8 = 1;
+
+#[derive(Default)]
+pub(crate) struct List(VecDeque<Vec<u8>>);
+
+pub(crate) enum ListOp {
+    PushBack(Vec<u8>),
+    PopFront,
+}
+
+impl Collection for List {
+    const TYPE: u8 = 1;
+    const NAME: &'static str = "list";
+    type Op = ListOp;
+
+    fn apply(&mut self, op: ListOp) {
+        match op {
+            ListOp::PushBack(item) => self.0.push_back(item),
+            ListOp::PopFront => {
+                self.0.pop_front();
+            }
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.0.len() as u64).to_le_bytes());
+        for item in &self.0 {
+            put_bytes(out, item);
+        }
+    }
+
+    fn decode(mut input: &[u8]) -> io::Result<Self> {
+        let count = take_u64(&mut input)?;
+        let mut items = VecDeque::new();
+        for _ in 0..count {
+            items.push_back(take_bytes(&mut input)?);
+        }
+        return Ok(List(items));
+    }
+
+    fn encode_op(op: &ListOp, out: &mut Vec<u8>) {
+        match op {
+            ListOp::PushBack(item) => {
+                out.push(OP_PUSH_BACK);
+                put_bytes(out, item);
+            }
+            ListOp::PopFront => out.push(OP_POP_FRONT),
+        }
+    }
+
+    fn decode_op(input: &[u8]) -> io::Result<ListOp> {
+        return match input.split_first() {
+            Some((&OP_PUSH_BACK, mut rest)) => Ok(ListOp::PushBack(take_bytes(&mut rest)?)),
+            Some((&OP_POP_FRONT, _)) => Ok(ListOp::PopFront),
+            _ => Err(invalid("Unknown list operation")),
+        };
+    }
+}
+
+impl RCask {
+    /// Appends an item to the back of the list stored under `key`, creating the list if the
+    /// key does not exist. Only the item is written, not the whole list.
+    pub fn push_back<T: AsRef<[u8]>>(&mut self, key: &str, item: T) -> Result<()> {
+        return self.write_collection_op::<List>(key, ListOp::PushBack(item.as_ref().to_vec()));
+    }
+
+    /// Removes and returns the item at the front of the list stored under `key`.
+    /// Returns `None` if the list is empty or the key does not exist.
+    pub fn pop_front(&mut self, key: &str) -> Result<Option<Vec<u8>>> {
+        let Some(mut list) = self.read_collection::<List>(key)? else {
+            return Ok(None);
+        };
+        let Some(item) = list.0.pop_front() else {
+            return Ok(None);
+        };
+        self.write_collection_op::<List>(key, ListOp::PopFront)?;
+        return Ok(Some(item));
+    }
+
+    /// Returns the items of the list stored under `key` whose positions, counted from the
+    /// front, fall in `range`. Positions past the end of the list are ignored.
+    pub fn list_range<R: RangeBounds<usize>>(
+        &mut self,
+        key: &str,
+        range: R,
+    ) -> Result<Vec<Vec<u8>>> {
+        let Some(list) = self.read_collection::<List>(key)? else {
+            return Ok(Vec::new());
+        };
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.saturating_add(1),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => usize::MAX,
+        };
+        let count = end.saturating_sub(start);
+        return Ok(list.0.into_iter().skip(start).take(count).collect());
+    }
+}
@@ -0,0 +1,280 @@
+//! Lists, sets and hashes stored under a single key.
+//!
+//! A collection is stored as a chain of records: every change appends a small operation record
+//! that refers to the key's previous record, and reads rebuild the collection by replaying the
+//! operations on top of the latest snapshot. Once a chain holds `MAX_CHAIN` operations the next
+//! change writes a fresh snapshot instead, and compaction writes every collection as a single
+//! snapshot, so no change ever rewrites the whole collection on its own.
+//!
+//! Collection records bypass the value pipeline (schema tags, framing and compression) and are
+//! marked with `COLLECTION_FLAG` in the log. Each starts with a header,
+//! [type: u8] [kind: u8] [depth: u16] [base offset: u64]
+//! followed by an encoded snapshot or operation. `depth` counts the operations back to the
+//! snapshot, and `base` is the offset of the previous record, or `NO_BASE` if the operation
+//! applies to an empty collection.
+
+mod hash;
+mod list;
+mod set;
+
+use crate::kvstore::Attributes;
+use crate::{reader, Error, RCask, Result};
+use std::io;
+
+/// Maximum number of operation records between a read and the snapshot it starts from.
+const MAX_CHAIN: u16 = 64;
+
+const KIND_SNAPSHOT: u8 = 0;
+const KIND_OP: u8 = 1;
+const NO_BASE: u64 = u64::MAX;
+const HEADER_SIZE: usize = 12;
+
+/// A collection type and its operations.
+pub(crate) trait Collection: Default {
+    /// Type byte in the header of the collection's records.
+    const TYPE: u8;
+    /// Name reported by `Error::WrongType`.
+    const NAME: &'static str;
+    type Op;
+
+    fn apply(&mut self, op: Self::Op);
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(input: &[u8]) -> io::Result<Self>;
+    fn encode_op(op: &Self::Op, out: &mut Vec<u8>);
+    fn decode_op(input: &[u8]) -> io::Result<Self::Op>;
+}
+
+struct Header {
+    collection_type: u8,
+    kind: u8,
+    depth: u16,
+    base: u64,
+}
+
+fn parse(record: &[u8]) -> io::Result<(Header, &[u8])> {
+    if record.len() < HEADER_SIZE {
+        return Err(invalid("Truncated collection record"));
+    }
+    let header = Header {
+        collection_type: record[0],
+        kind: record[1],
+        depth: u16::from_le_bytes([record[2], record[3]]),
+        base: u64::from_le_bytes(record[4..12].try_into().unwrap_or_default()),
+    };
+    return Ok((header, &record[HEADER_SIZE..]));
+}
+
+fn header(collection_type: u8, kind: u8, depth: u16, base: u64) -> Vec<u8> {
+    let mut record = Vec::with_capacity(HEADER_SIZE);
+    record.push(collection_type);
+    record.push(kind);
+    record.extend_from_slice(&depth.to_le_bytes());
+    record.extend_from_slice(&base.to_le_bytes());
+    return record;
+}
+
+/// Encodes a collection as a snapshot record.
+fn snapshot_record<C: Collection>(collection: &C) -> Vec<u8> {
+    let mut record = header(C::TYPE, KIND_SNAPSHOT, 0, NO_BASE);
+    collection.encode(&mut record);
+    return record;
+}
+
+impl RCask {
+    /// Reads the latest record of `key` if it belongs to a collection of type `C`.
+    /// Missing and expired keys read as `None`; keys holding anything else fail.
+    fn latest_collection_record<C: Collection>(
+        &mut self,
+        key: &str,
+    ) -> Result<Option<(u64, Vec<u8>, Attributes)>> {
+        let verify = self.verify_checksums;
+        let Some((record, attributes)) =
+            reader::read_latest(&mut self.store, &mut self.values, key, verify)?
+        else {
+            return Ok(None);
+        };
+        let is_type = attributes.collection && record.first() == Some(&C::TYPE);
+        if !is_type {
+            return Err(Error::WrongType {
+                key: key.to_string(),
+                expected: C::NAME,
+            });
+        }
+        let offset = self.store.offset(key).unwrap_or_default();
+        return Ok(Some((offset, record, attributes)));
+    }
+
+    /// Rebuilds the collection stored under the caller's `key`, encoded first, for a read.
+    pub(crate) fn read_collection<C: Collection>(&mut self, key: &str) -> Result<Option<C>> {
+        let stored = self.stored_key(key);
+        let key = stored.as_ref();
+        self.record_read(key);
+        let collection = self.rebuild_collection(key);
+        self.health.read(&collection);
+        return collection;
+    }
+
+    /// Rebuilds the collection stored under `key`.
+    fn rebuild_collection<C: Collection>(&mut self, key: &str) -> Result<Option<C>> {
+        let Some((mut offset, mut record, _)) = self.latest_collection_record::<C>(key)? else {
+            return Ok(None);
+        };
+
+        let mut ops = Vec::new();
+        let mut collection = loop {
+            let (header, body) = parse(&record)?;
+            if header.collection_type != C::TYPE {
+                return Err(corrupt_chain(key));
+            }
+            if header.kind == KIND_SNAPSHOT {
+                break C::decode(body)?;
+            }
+            ops.push(C::decode_op(body)?);
+            if header.base == NO_BASE {
+                break C::default();
+            }
+            // Bases always precede their operations, which also rules out cycles.
+            if header.base >= offset {
+                return Err(corrupt_chain(key));
+            }
+            offset = header.base;
+            let verify = self.verify_checksums;
+            record = match self.store.get_value_bytes_at(key, offset, verify)? {
+                Some((record, attributes)) if attributes.collection => record,
+                _ => return Err(corrupt_chain(key)),
+            };
+        };
+        for op in ops.into_iter().rev() {
+            collection.apply(op);
+        }
+        return Ok(Some(collection));
+    }
+
+    /// Applies an operation to the collection stored under `key`, creating it if the key
+    /// does not exist. Expiry and metadata of the collection are kept.
+    pub(crate) fn write_collection_op<C: Collection>(
+        &mut self,
+        key: &str,
+        op: C::Op,
+    ) -> Result<()> {
+        let stored = self.stored_key(key);
+        let key = stored.as_ref();
+        self.admit_write()?;
+        let (record, attributes) = match self.latest_collection_record::<C>(key)? {
+            Some((offset, record, attributes)) => {
+                let (header, _) = parse(&record)?;
+                let record = if header.depth < MAX_CHAIN {
+                    let mut next = header_for_op::<C>(header.depth + 1, offset);
+                    C::encode_op(&op, &mut next);
+                    next
+                } else {
+                    let mut collection = self.rebuild_collection::<C>(key)?.unwrap_or_default();
+                    collection.apply(op);
+                    snapshot_record(&collection)
+                };
+                (record, attributes)
+            }
+            None => {
+                let mut record = header_for_op::<C>(1, NO_BASE);
+                C::encode_op(&op, &mut record);
+                let attributes = Attributes {
+                    collection: true,
+                    ..Default::default()
+                };
+                (record, attributes)
+            }
+        };
+
+        let record_len = (key.len() + record.len()) as u64;
+        self.admit_quota(key, record_len, true)?;
+        self.invalidate(key);
+        self.record_write(key);
+        self.traffic.user_bytes += record_len;
+        let written = self.store.set(key, record, &attributes);
+        self.health.wrote(&written);
+        written?;
+        self.lru_wrote([(key, record_len)], true)?;
+        return self.finish_write(false);
+    }
+
+    /// Applies a removal to the collection stored under `key`. A key without a collection
+    /// has nothing to remove, so nothing is written for it.
+    pub(crate) fn remove_from_collection<C: Collection>(
+        &mut self,
+        key: &str,
+        op: C::Op,
+    ) -> Result<()> {
+        let stored = self.stored_key(key);
+        if self.latest_collection_record::<C>(&stored)?.is_none() {
+            return Ok(());
+        }
+        return self.write_collection_op::<C>(key, op);
+    }
+
+    /// Rebuilds the collection whose latest record is `record` into a single snapshot record,
+    /// as compaction writes it.
+    pub(crate) fn collapse_collection(&mut self, key: &str, record: &[u8]) -> Result<Vec<u8>> {
+        return match record.first().copied() {
+            Some(list::List::TYPE) => self.collapse::<list::List>(key),
+            Some(set::Set::TYPE) => self.collapse::<set::Set>(key),
+            Some(hash::Hash::TYPE) => self.collapse::<hash::Hash>(key),
+            _ => Err(corrupt_chain(key)),
+        };
+    }
+
+    fn collapse<C: Collection>(&mut self, key: &str) -> Result<Vec<u8>> {
+        let collection = self.rebuild_collection::<C>(key)?.unwrap_or_default();
+        return Ok(snapshot_record(&collection));
+    }
+}
+
+/// Returns the offset of the record a collection record builds on, if any.
+pub(crate) fn chain_base(record: &[u8]) -> io::Result<Option<u64>> {
+    let (header, _) = parse(record)?;
+    if header.kind == KIND_SNAPSHOT || header.base == NO_BASE {
+        return Ok(None);
+    }
+    return Ok(Some(header.base));
+}
+
+fn header_for_op<C: Collection>(depth: u16, base: u64) -> Vec<u8> {
+    return header(C::TYPE, KIND_OP, depth, base);
+}
+
+/// Appends a length-prefixed byte string.
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Reads a length-prefixed byte string written by `put_bytes`.
+fn take_bytes(input: &mut &[u8]) -> io::Result<Vec<u8>> {
+    let len = take_u64(input)? as usize;
+    if len > input.len() {
+        return Err(invalid("Truncated collection item"));
+    }
+    let (bytes, rest) = input.split_at(len);
+    *input = rest;
+    return Ok(bytes.to_vec());
+}
+
+fn take_u64(input: &mut &[u8]) -> io::Result<u64> {
+    if input.len() < 8 {
+        return Err(invalid("Truncated collection record"));
+    }
+    let (bytes, rest) = input.split_at(8);
+    *input = rest;
+    return Ok(u64::from_le_bytes(bytes.try_into().unwrap_or_default()));
+}
+
+fn invalid(message: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+}
+
+fn corrupt_chain(key: &str) -> Error {
+    return io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Data corruption: broken collection chain for key {}", key),
+    )
+    .into();
+}
@@ -0,0 +1,99 @@
+//! Sets of distinct members.
+//!
+//! Snapshots hold the number of members (u64) followed by the length-prefixed members in
+//! sorted order.
+
+use super::{invalid, put_bytes, take_bytes, take_u64, Collection};
+use crate::{RCask, Result};
+use std::collections::BTreeSet;
+use std::io;
+
+const OP_ADD: u8 = 0;
+const OP_REMOVE: u8 = 1;
+
+#[derive(Default)]
+pub(crate) struct Set(BTreeSet<Vec<u8>>);
+
+pub(crate) enum SetOp {
+    Add(Vec<u8>),
+    Remove(Vec<u8>),
+}
+
+impl Collection for Set {
+    const TYPE: u8 = 2;
+    const NAME: &'static str = "set";
+    type Op = SetOp;
+
+    fn apply(&mut self, op: SetOp) {
+        match op {
+            SetOp::Add(member) => {
+                self.0.insert(member);
+            }
+            SetOp::Remove(member) => {
+                self.0.remove(&member);
+            }
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.0.len() as u64).to_le_bytes());
+        for member in &self.0 {
+            put_bytes(out, member);
+        }
+    }
+
+    fn decode(mut input: &[u8]) -> io::Result<Self> {
+        let count = take_u64(&mut input)?;
+        let mut members = BTreeSet::new();
+        for _ in 0..count {
+            members.insert(take_bytes(&mut input)?);
+        }
+        return Ok(Set(members));
+    }
+
+    fn encode_op(op: &SetOp, out: &mut Vec<u8>) {
+        let (code, member) = match op {
+            SetOp::Add(member) => (OP_ADD, member),
+            SetOp::Remove(member) => (OP_REMOVE, member),
+        };
+        out.push(code);
+        put_bytes(out, member);
+    }
+
+    fn decode_op(input: &[u8]) -> io::Result<SetOp> {
+        return match input.split_first() {
+            Some((&OP_ADD, mut rest)) => Ok(SetOp::Add(take_bytes(&mut rest)?)),
+            Some((&OP_REMOVE, mut rest)) => Ok(SetOp::Remove(take_bytes(&mut rest)?)),
+            _ => Err(invalid("Unknown set operation")),
+        };
+    }
+}
+
+impl RCask {
+    /// Adds a member to the set stored under `key`, creating the set if the key does not
+    /// exist. Only the membership change is written, not the whole set.
+    pub fn sadd<T: AsRef<[u8]>>(&mut self, key: &str, member: T) -> Result<()> {
+        return self.write_collection_op::<Set>(key, SetOp::Add(member.as_ref().to_vec()));
+    }
+
+    /// Removes a member from the set stored under `key`. Removing a member that is not in
+    /// the set, or from a key without a set, is not an error and writes nothing.
+    pub fn srem<T: AsRef<[u8]>>(&mut self, key: &str, member: T) -> Result<()> {
+        return self.remove_from_collection::<Set>(key, SetOp::Remove(member.as_ref().to_vec()));
+    }
+
+    /// Whether `member` is in the set stored under `key`.
+    pub fn sismember<T: AsRef<[u8]>>(&mut self, key: &str, member: T) -> Result<bool> {
+        return Ok(self
+            .read_collection::<Set>(key)?
+            .is_some_and(|set| set.0.contains(member.as_ref())));
+    }
+
+    /// Returns all members of the set stored under `key`, in sorted order.
+    pub fn smembers(&mut self, key: &str) -> Result<Vec<Vec<u8>>> {
+        return Ok(self
+            .read_collection::<Set>(key)?
+            .map(|set| set.0.into_iter().collect())
+            .unwrap_or_default());
+    }
+}
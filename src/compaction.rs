@@ -0,0 +1,366 @@
+//! Relocating live records into a new segment, all at once or incrementally.
+//!
+//! Full compaction relocates every key in one blocking pass. Incremental compaction (see
+//! `Builder::incremental_compaction`) instead builds the next segment a few keys at a time:
+//! every write, and every `RCask::compaction_tick`, relocates one batch from the active segment
+//! into the pending one, and keys written in the meantime are queued to be relocated again.
+//! Reads keep using the active segment, which stays complete until the pending one replaces it
+//! once the queue runs dry. The pending segment is written under a temporary name, so a crash
+//! never leaves a half-built segment that could be opened.
+//!
+//! `RCask::compaction_task` runs the same state machine in slices of a given time budget,
+//! so an async application can drive a compaction from its own executor.
+//!
+//! Incremental compaction keeps the active zstd dictionary instead of training a new one, since
+//! both segments are read and written with it while the pending one is built.
+
+use crate::adaptive::Observation;
+use crate::events::Field;
+use crate::expiry::Expiration;
+use crate::kvstore::{Attributes, KVStore};
+use crate::{CompactionReport, CompactionScheduler, FilterDecision, RCask, Result};
+use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Keys relocated per step of a `CompactionTask`, between checks of its budget.
+const TASK_BATCH: usize = 64;
+
+/// A live record of the active segment, decoded for the next one.
+pub(crate) enum Relocated {
+    /// A plain value, encoded again when it is written.
+    Value(Vec<u8>),
+    /// A reference to a value stored outside the log at the current schema version, carried
+    /// over as it is.
+    Reference(Vec<u8>),
+    /// A list, set or hash collapsed into a single snapshot.
+    Collection(Vec<u8>),
+}
+
+/// A segment being built by incremental compaction.
+pub(crate) struct Migration {
+    pub(crate) store: KVStore,
+    /// Where the segment goes once it is complete.
+    path: PathBuf,
+    queue: VecDeque<String>,
+    queued: HashSet<String>,
+    expired: Vec<Expiration>,
+    /// Time spent relocating so far.
+    busy: Duration,
+}
+
+impl RCask {
+    /// Decodes the latest record of `key` for the next segment. Expired keys are reported
+    /// into `expired`; they, and keys the compaction filter drops, yield `None`.
+    pub(crate) fn relocate(
+        &mut self,
+        key: &str,
+        expired: &mut Vec<Expiration>,
+    ) -> Result<Option<(Relocated, Attributes)>> {
+        if let Some(event) = self.expiration(key)? {
+            expired.push(event);
+        }
+        let verify = self.verify_checksums;
+        let Some((framed, attributes)) = self.read_latest(key, verify)? else {
+            return Ok(None);
+        };
+        if self.outlived(&attributes) {
+            return Ok(None);
+        }
+        if attributes.collection {
+            let snapshot = self.collapse_collection(key, &framed)?;
+            return Ok(Some((Relocated::Collection(snapshot), attributes)));
+        }
+        let is_reference = self.values.is_framed() && self.values.is_reference(&framed);
+        if is_reference && self.filter.is_none() && self.values.schema.is_none() {
+            return Ok(Some((Relocated::Reference(framed), attributes)));
+        }
+        let reference = is_reference.then(|| framed.clone());
+        let offset = self.store.offset(key).unwrap_or_default();
+        let (plain, _) = self.resolve(key, offset, framed, verify)?;
+        // Values stored outside the log at an older schema version are written again at the
+        // current one, like any other value.
+        let current = match &self.values.schema {
+            Some(schema) => plain.first() == Some(&schema.current()),
+            None => true,
+        };
+        let reference = reference.filter(|_| current);
+        let value = self.values.untag_schema(plain)?;
+        let decision = match &mut self.filter {
+            Some(filter) => filter.filter(key, &value, attributes.meta.as_deref()),
+            None => FilterDecision::Keep,
+        };
+        let record = match (decision, reference) {
+            (FilterDecision::Keep, Some(framed)) => Relocated::Reference(framed),
+            (FilterDecision::Keep, None) => Relocated::Value(value),
+            (FilterDecision::Drop, _) => return Ok(None),
+            (FilterDecision::Replace(value), _) => Relocated::Value(value),
+        };
+        return Ok(Some((record, attributes)));
+    }
+
+    /// Returns the keys of the active segment in the order compaction relocates them: sorted,
+    /// so that neighbouring keys share a block when packing blocks, and so that scans in key
+    /// order read the compacted segment front to back.
+    pub(crate) fn relocation_order(&self) -> Vec<String> {
+        let mut keys = self.store.keys();
+        keys.sort_unstable();
+        return keys;
+    }
+
+    /// Writes a relocated record of `key` to `store`, packed into a block if block packing is
+    /// enabled.
+    pub(crate) fn write_relocated(
+        &mut self,
+        store: &mut KVStore,
+        key: &str,
+        record: Relocated,
+        attributes: &Attributes,
+    ) -> Result<()> {
+        let stored = self.encode_relocated(record)?;
+        match self.block_size {
+            Some(block_size) => store.pack(key, stored, attributes, block_size)?,
+            None => store.stage(key, stored, attributes)?,
+        }
+        return Ok(());
+    }
+
+    /// Encodes a relocated record as it is stored.
+    pub(crate) fn encode_relocated(&mut self, record: Relocated) -> Result<Cow<'static, [u8]>> {
+        return Ok(match record {
+            Relocated::Value(value) => Cow::Owned(self.values.encode(&value)?.into_owned()),
+            Relocated::Reference(framed) => {
+                self.values.retain_reference(&framed)?;
+                self.values.seal(Cow::Owned(framed))?
+            }
+            Relocated::Collection(snapshot) => Cow::Owned(snapshot),
+        });
+    }
+
+    /// Runs one step of an incremental compaction, if one is in progress, and returns whether
+    /// one is still in progress afterwards. Writes already run a step each; calling this
+    /// from a timer lets a compaction finish while the store is idle.
+    pub fn compaction_tick(&mut self) -> Result<bool> {
+        if self.migration.is_some() {
+            self.migrate_step()?;
+        }
+        return Ok(self.migration.is_some());
+    }
+
+    /// Returns a handle that runs a compaction in slices, starting one unless an incremental
+    /// compaction is already in progress. The compaction's state lives in the store, so a
+    /// dropped task resumes where it stopped when the next one runs.
+    ///
+    /// ```no_run
+    /// use rcask::RCask;
+    /// use std::time::Duration;
+    ///
+    /// # fn main() -> rcask::Result<()> {
+    /// let mut store = RCask::new("./".to_string(), "log".to_string())?;
+    /// let mut task = store.compaction_task()?;
+    /// while !task.run_for(Duration::from_millis(2))? {
+    ///     // Yield to the executor, e.g. with `tokio::task::yield_now().await`.
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn compaction_task(&mut self) -> Result<CompactionTask<'_>> {
+        if self.migration.is_none() {
+            self.start_migration()?;
+        }
+        return Ok(CompactionTask { store: self });
+    }
+
+    /// Starts building the next segment incrementally.
+    pub(crate) fn start_migration(&mut self) -> Result<()> {
+        if self.drop_outlived_segment()? {
+            return Ok(());
+        }
+        let path = PathBuf::from(self.get_next_segment_path());
+        let pending = pending_path(&path);
+        // A leftover from a crash during an earlier incremental compaction.
+        if pending.exists() {
+            self.fs.remove_file(&pending)?;
+        }
+        let mut store = KVStore::new(self.fs.as_ref(), &pending, self.checksum)?;
+        store.align_records(self.alignment);
+        store.compress_index(self.compress_index);
+        self.values.begin_relocation();
+        let queue: VecDeque<String> = self.relocation_order().into_iter().collect();
+        self.migration = Some(Migration {
+            store,
+            path,
+            queued: queue.iter().cloned().collect(),
+            queue,
+            expired: Vec::new(),
+            busy: Duration::ZERO,
+        });
+        return Ok(());
+    }
+
+    /// Queues a key that is being written to be relocated again.
+    pub(crate) fn requeue(&mut self, key: &str) {
+        if let Some(migration) = &mut self.migration {
+            if migration.queued.insert(key.to_string()) {
+                migration.queue.push_back(key.to_string());
+            }
+        }
+    }
+
+    /// Relocates the next batch of queued keys, and replaces the active segment once the queue
+    /// is empty.
+    pub(crate) fn migrate_step(&mut self) -> Result<()> {
+        let batch = self.compaction_batch.unwrap_or(1);
+        return self.migrate_batch(batch);
+    }
+
+    fn migrate_batch(&mut self, batch: usize) -> Result<()> {
+        let Some(mut migration) = self.migration.take() else {
+            return Ok(());
+        };
+        let scheduler = self.scheduler.clone();
+        let mut permit = scheduler.as_deref().map(CompactionScheduler::acquire);
+        let written = migration.store.end();
+        let started = Instant::now();
+        let result = self.relocate_batch(&mut migration, batch);
+        migration.busy += started.elapsed();
+        if let Some(permit) = &mut permit {
+            permit.wrote(migration.store.end() - written);
+        }
+        if let Err(e) = result {
+            self.migration = Some(migration);
+            return Err(e);
+        }
+        if !migration.queue.is_empty() {
+            self.migration = Some(migration);
+            return Ok(());
+        }
+        let writes = self.writes;
+        let before = self.store.end();
+        let busy = migration.busy;
+        let result = self.finish_migration(migration);
+        self.health.compacted(&result);
+        self.log_compaction(&result, "incremental", busy, before);
+        if result.is_ok() {
+            self.tune_compaction(Observation {
+                writes,
+                before,
+                after: self.store.end(),
+                busy,
+            });
+        }
+        return result;
+    }
+
+    /// Reports a compaction that took `busy` to the event log.
+    pub(crate) fn log_compaction(
+        &mut self,
+        result: &Result<()>,
+        mode: &str,
+        busy: Duration,
+        before: u64,
+    ) {
+        let fields = [
+            ("mode", Field::Str(mode)),
+            ("duration_ms", Field::U64(busy.as_millis() as u64)),
+            ("bytes_before", Field::U64(before)),
+            ("bytes_after", Field::U64(self.store.end())),
+        ];
+        self.log_event("compaction", Some(result), &fields);
+        self.observe_compaction(&CompactionReport {
+            incremental: mode == "incremental",
+            duration: busy,
+            bytes_before: before,
+            bytes_after: self.store.end(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+    }
+
+    /// Relocates every key still queued, e.g. before a bulk load whose keys cannot be queued.
+    pub(crate) fn finish_pending_migration(&mut self) -> Result<()> {
+        while self.migration.is_some() {
+            self.migrate_step()?;
+        }
+        return Ok(());
+    }
+
+    fn relocate_batch(&mut self, migration: &mut Migration, batch: usize) -> Result<()> {
+        for _ in 0..batch.max(1) {
+            let Some(key) = migration.queue.pop_front() else {
+                break;
+            };
+            migration.queued.remove(&key);
+            let latest = self.relocate(&key, &mut migration.expired)?;
+            let history = self.relocate_history(&key, latest.is_some(), &mut migration.store)?;
+            let deleted = latest.is_none() && !history.is_empty();
+            self.write_history(&mut migration.store, &key, history)?;
+            match latest {
+                Some((record, attributes)) => {
+                    self.write_relocated(&mut migration.store, &key, record, &attributes)?;
+                }
+                // A copy relocated before the key was written again must not come back.
+                None if !deleted && migration.store.offset(&key).is_some() => {
+                    migration.store.set(&key, [], &Attributes::tombstone())?;
+                }
+                None => {}
+            }
+        }
+        return Ok(());
+    }
+
+    fn finish_migration(&mut self, migration: Migration) -> Result<()> {
+        let Migration {
+            mut store,
+            path,
+            expired,
+            ..
+        } = migration;
+        store.sync()?;
+        self.fs.rename(Path::new(&store.path), &path)?;
+        store.path = path.to_string_lossy().to_string();
+        store.cache_blocks(self.block_cache.clone());
+        self.values.keep_dictionary(&path)?;
+
+        self.replace_segment(store)?;
+
+        self.emit_expirations(expired);
+        if let Some(listener) = &mut self.expiry {
+            listener.clear();
+        }
+        self.writes = 0;
+        return Ok(());
+    }
+}
+
+/// A compaction that runs in slices, see `RCask::compaction_task`.
+pub struct CompactionTask<'a> {
+    store: &'a mut RCask,
+}
+
+impl CompactionTask<'_> {
+    /// Relocates keys until `budget` has passed or the compaction is complete, and returns
+    /// whether it is complete. A slice may overrun its budget by one batch of keys.
+    pub fn run_for(&mut self, budget: Duration) -> Result<bool> {
+        let started = Instant::now();
+        while self.store.migration.is_some() {
+            self.store.migrate_batch(TASK_BATCH)?;
+            if started.elapsed() >= budget {
+                break;
+            }
+        }
+        return Ok(self.is_complete());
+    }
+
+    /// Whether the compaction has replaced the active segment.
+    pub fn is_complete(&self) -> bool {
+        return self.store.migration.is_none();
+    }
+}
+
+/// The temporary name of a segment while incremental compaction builds it.
+fn pending_path(path: &Path) -> PathBuf {
+    let mut pending = path.as_os_str().to_owned();
+    pending.push(".compacting");
+    return PathBuf::from(pending);
+}
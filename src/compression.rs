@@ -0,0 +1,145 @@
+//! Zstd dictionary compression for small values.
+//!
+//! Small values barely compress on their own, so compaction samples the live values, trains a
+//! zstd dictionary from them and compresses every value it rewrites with that dictionary.
+//! Each segment's dictionary is stored next to it as `<pattern>.<n>.dict`.
+//!
+//! Every stored value starts with a one byte tag: `TAG_RAW` values follow unchanged, while
+//! `TAG_DICTIONARY` values hold the original length (u64) followed by the compressed bytes.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use zstd::bulk::{Compressor, Decompressor};
+
+const TAG_RAW: u8 = 0;
+const TAG_DICTIONARY: u8 = 1;
+
+/// Tuning for dictionary training and compression.
+#[derive(Debug, Clone)]
+pub struct DictionaryOptions {
+    /// Maximum size of a trained dictionary, in bytes. Defaults to 16 KiB.
+    pub dictionary_size: usize,
+    /// Maximum number of live values sampled for training. Defaults to 10,000.
+    pub max_samples: usize,
+    /// Zstd compression level. Defaults to 3.
+    pub level: i32,
+}
+
+impl Default for DictionaryOptions {
+    fn default() -> Self {
+        return DictionaryOptions {
+            dictionary_size: 16 * 1024,
+            max_samples: 10_000,
+            level: 3,
+        };
+    }
+}
+
+/// A trained dictionary, prepared for compressing and decompressing values.
+pub(crate) struct Dictionary {
+    bytes: Vec<u8>,
+    compressor: Compressor<'static>,
+    decompressor: Decompressor<'static>,
+}
+
+impl Dictionary {
+    /// Trains a dictionary from sample values.
+    /// Returns `None` when zstd cannot build one, e.g. because there are too few samples.
+    pub(crate) fn train(
+        samples: &[&[u8]],
+        options: &DictionaryOptions,
+    ) -> io::Result<Option<Self>> {
+        return match zstd::dict::from_samples(samples, options.dictionary_size) {
+            Ok(bytes) => Ok(Some(Self::new(bytes, options.level)?)),
+            Err(_) => Ok(None),
+        };
+    }
+
+    /// Loads the dictionary stored alongside a segment, if there is one.
+    pub(crate) fn load(segment: &Path, options: &DictionaryOptions) -> io::Result<Option<Self>> {
+        return match fs::read(dictionary_path(segment)) {
+            Ok(bytes) => Ok(Some(Self::new(bytes, options.level)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        };
+    }
+
+    /// Stores the dictionary alongside a segment and syncs it, and its directory, before any
+    /// record compressed with it is written.
+    pub(crate) fn save(&self, segment: &Path) -> io::Result<()> {
+        let path = dictionary_path(segment);
+        let mut file = fs::File::create(&path)?;
+        file.write_all(&self.bytes)?;
+        file.sync_all()?;
+        #[cfg(unix)]
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::File::open(parent)?.sync_all()?;
+        }
+        return Ok(());
+    }
+
+    fn new(bytes: Vec<u8>, level: i32) -> io::Result<Self> {
+        return Ok(Dictionary {
+            compressor: Compressor::with_dictionary(level, &bytes)?,
+            decompressor: Decompressor::with_dictionary(&bytes)?,
+            bytes,
+        });
+    }
+}
+
+/// Tags a value, compressing it when a dictionary is available and it actually gets smaller.
+pub(crate) fn encode(dictionary: Option<&mut Dictionary>, value: &[u8]) -> io::Result<Vec<u8>> {
+    if let Some(dictionary) = dictionary {
+        let compressed = dictionary.compressor.compress(value)?;
+        if compressed.len() + 8 < value.len() {
+            let mut stored = Vec::with_capacity(compressed.len() + 9);
+            stored.push(TAG_DICTIONARY);
+            stored.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            stored.extend_from_slice(&compressed);
+            return Ok(stored);
+        }
+    }
+
+    let mut stored = Vec::with_capacity(value.len() + 1);
+    stored.push(TAG_RAW);
+    stored.extend_from_slice(value);
+    return Ok(stored);
+}
+
+/// Reverses `encode`.
+pub(crate) fn decode(dictionary: Option<&mut Dictionary>, stored: &[u8]) -> io::Result<Vec<u8>> {
+    return match stored.split_first() {
+        Some((&TAG_RAW, value)) => Ok(value.to_vec()),
+        Some((&TAG_DICTIONARY, rest)) if rest.len() >= 8 => {
+            let Some(dictionary) = dictionary else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Value is dictionary compressed but the segment has no dictionary",
+                ));
+            };
+            let (len, compressed) = rest.split_at(8);
+            let len = u64::from_le_bytes(len.try_into().unwrap_or_default());
+            dictionary.decompressor.decompress(compressed, len as usize)
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unknown value compression tag",
+        )),
+    };
+}
+
+/// Removes the dictionary stored alongside a segment, if any.
+pub(crate) fn remove(segment: &Path) -> io::Result<()> {
+    return match fs::remove_file(dictionary_path(segment)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    };
+}
+
+fn dictionary_path(segment: &Path) -> PathBuf {
+    return segment.with_extension("dict");
+}
@@ -0,0 +1,236 @@
+//! Crash and torn-write simulation.
+//!
+//! `run` writes a workload into a fresh store, then damages a copy of the resulting log at
+//! every byte boundary and reopens it. Every truncation must read back exactly the writes that
+//! completed before the cut, so acknowledged records are never lost and torn ones never
+//! surface. Every corrupted byte must read back as an error, a missing key or one of the
+//! values written to the key, never as garbage, which takes checksums (`Builder::checksums`).
+//! Panics while reopening or reading count as failures.
+//!
+//! ```no_run
+//! use rcask::crash::{self, CrashWrite};
+//! use std::path::Path;
+//!
+//! # fn main() -> rcask::Result<()> {
+//! let writes: Vec<CrashWrite> = (0..20)
+//!     .map(|i| CrashWrite::new(format!("key-{}", i % 5), format!("value-{}", i), i % 4 == 0))
+//!     .collect();
+//! let report = crash::run(Path::new("./crash-test"), &writes, |builder| builder.checksums(true))?;
+//! assert!(report.failures.is_empty(), "{:?}", report.failures);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{Builder, RCask, Result, WriteOptions};
+use std::collections::HashMap;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+const PATTERN: &str = "crash";
+
+/// One write of a workload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashWrite {
+    pub key: String,
+    pub value: Vec<u8>,
+    /// Whether the write is synced to disk before it is acknowledged.
+    pub sync: bool,
+}
+
+impl CrashWrite {
+    pub fn new<K: Into<String>, V: Into<Vec<u8>>>(key: K, value: V, sync: bool) -> Self {
+        return CrashWrite {
+            key: key.into(),
+            value: value.into(),
+            sync,
+        };
+    }
+}
+
+/// How a copy of the log was damaged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Damage {
+    /// The log was cut off at the offset.
+    Truncated,
+    /// The byte at the offset was flipped.
+    Corrupted,
+}
+
+/// A damaged log that did not read back as required.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashFailure {
+    pub damage: Damage,
+    pub offset: u64,
+    pub message: String,
+}
+
+/// Results of `run`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CrashReport {
+    /// Number of damaged logs that were reopened.
+    pub cases: u64,
+    pub failures: Vec<CrashFailure>,
+}
+
+/// Writes `writes` to a new store under `directory` and checks every truncation and every
+/// single-byte corruption of its log. `configure` sets the store's options; compaction is
+/// disabled so that the whole workload stays in one segment. `directory` is cleared first.
+pub fn run<F>(directory: &Path, writes: &[CrashWrite], configure: F) -> Result<CrashReport>
+where
+    F: Fn(Builder) -> Builder,
+{
+    let source = directory.join("source");
+    let case = directory.join("case");
+    if directory.exists() {
+        fs::remove_dir_all(directory)?;
+    }
+    let open = |path: &Path| {
+        let builder = RCask::builder(path.to_string_lossy().to_string(), PATTERN.to_string());
+        return configure(builder).max_writes(u64::MAX).open();
+    };
+
+    // 1. Write the workload, remembering where each record ends. Padding after a record is
+    // not part of it, so a cut inside it keeps the record.
+    let log = source.join(format!("{}.0.log", PATTERN));
+    let mut ends = Vec::with_capacity(writes.len());
+    {
+        let mut store = open(&source)?;
+        for write in writes {
+            let options = WriteOptions {
+                sync: write.sync,
+                ..Default::default()
+            };
+            store.set_opt(&write.key, &write.value, &options)?;
+            let key = store.stored_key(&write.key).into_owned();
+            let offset = store.store.offset(&key).unwrap_or_default();
+            ends.push(store.store.record_end(offset)?);
+        }
+    }
+    let original = fs::read(&log)?;
+
+    let mut written: HashMap<&str, Vec<&[u8]>> = HashMap::new();
+    for write in writes {
+        written.entry(&write.key).or_default().push(&write.value);
+    }
+    let keys: Vec<&str> = written.keys().copied().collect();
+
+    // 2. Reopen every truncation and every corruption of the log.
+    let mut report = CrashReport::default();
+    for offset in 0..=original.len() {
+        let expected = expected_at(writes, &ends, offset as u64);
+        let outcome = check(
+            &source,
+            &case,
+            &original[..offset],
+            &keys,
+            &open,
+            |key, value| {
+                let wanted = expected.get(key).copied();
+                if value.as_deref() == wanted {
+                    return None;
+                }
+                return Some(match (wanted, value) {
+                    (Some(_), None) => format!("acknowledged write of {} was lost", key),
+                    _ => format!("{} read back a value that was not its last write", key),
+                });
+            },
+        );
+        report.cases += 1;
+        if let Some(message) = outcome? {
+            report.failures.push(CrashFailure {
+                damage: Damage::Truncated,
+                offset: offset as u64,
+                message,
+            });
+        }
+    }
+    for offset in 0..original.len() {
+        let mut corrupted = original.clone();
+        corrupted[offset] ^= 0xFF;
+        let outcome = check(&source, &case, &corrupted, &keys, &open, |key, value| {
+            let valid = value.as_deref().is_none_or(|value| {
+                written
+                    .get(key)
+                    .is_some_and(|values| values.contains(&value))
+            });
+            return (!valid).then(|| format!("{} read back garbage", key));
+        });
+        report.cases += 1;
+        if let Some(message) = outcome? {
+            report.failures.push(CrashFailure {
+                damage: Damage::Corrupted,
+                offset: offset as u64,
+                message,
+            });
+        }
+    }
+
+    fs::remove_dir_all(directory)?;
+    return Ok(report);
+}
+
+/// The last value of every key whose record ends at or before `offset`.
+fn expected_at<'a>(
+    writes: &'a [CrashWrite],
+    ends: &[u64],
+    offset: u64,
+) -> HashMap<&'a str, &'a [u8]> {
+    let mut expected = HashMap::new();
+    for (write, &end) in writes.iter().zip(ends) {
+        if end <= offset {
+            expected.insert(write.key.as_str(), write.value.as_slice());
+        }
+    }
+    return expected;
+}
+
+/// Copies the store with `log` as its log, reopens it and reads every key of the workload
+/// and of the reopened index. `judge` returns a failure message for a key that read back
+/// wrongly; read errors always pass, since they are how damage is detected.
+fn check<O, J>(
+    source: &Path,
+    case: &Path,
+    log: &[u8],
+    workload_keys: &[&str],
+    open: &O,
+    judge: J,
+) -> Result<Option<String>>
+where
+    O: Fn(&Path) -> Result<RCask>,
+    J: Fn(&str, Option<Vec<u8>>) -> Option<String>,
+{
+    if case.exists() {
+        fs::remove_dir_all(case)?;
+    }
+    fs::create_dir_all(case)?;
+    for entry in fs::read_dir(source)? {
+        let path = entry?.path();
+        if path.is_file() {
+            if let Some(name) = path.file_name() {
+                fs::copy(&path, case.join(name))?;
+            }
+        }
+    }
+    fs::write(case.join(format!("{}.0.log", PATTERN)), log)?;
+
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        // A log too damaged to open is detected damage, not garbage.
+        let Ok(mut store) = open(case) else {
+            return None;
+        };
+        let mut keys: Vec<String> = store.store.keys();
+        keys.extend(workload_keys.iter().map(|key| key.to_string()));
+        keys.sort_unstable();
+        keys.dedup();
+        for key in keys {
+            if let Ok(value) = store.get_bytes(&key) {
+                if let Some(message) = judge(&key, value) {
+                    return Some(message);
+                }
+            }
+        }
+        return None;
+    }));
+    return Ok(outcome.unwrap_or_else(|_| Some("reopening or reading panicked".to_string())));
+}
@@ -0,0 +1,105 @@
+//! Content-addressed deduplication of identical values.
+//!
+//! When enabled, every value at least `min_value_size` bytes long is written once to a content
+//! store, `<directory>/<pattern>.blobs/<hash>.blob`, and the log record only holds a reference
+//! to it. Writing the same value again reuses the stored copy. Compaction keeps references as
+//! they are and deletes the stored values that are no longer referenced by any live key.
+//!
+//! Hashes only locate candidates: a stored value is reused only when its bytes are identical,
+//! and values whose hash collides with different content are kept inline.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Tuning for deduplication.
+#[derive(Debug, Clone)]
+pub struct DedupOptions {
+    /// Values smaller than this are always stored inline. Defaults to 4 KiB.
+    pub min_value_size: usize,
+}
+
+impl Default for DedupOptions {
+    fn default() -> Self {
+        return DedupOptions {
+            min_value_size: 4096,
+        };
+    }
+}
+
+/// Directory of deduplicated values, named by the hash of their content.
+#[derive(Clone)]
+pub(crate) struct ContentStore {
+    pub(crate) options: DedupOptions,
+    dir: PathBuf,
+}
+
+impl ContentStore {
+    pub(crate) fn open(directory: &str, pattern: &str, options: DedupOptions) -> io::Result<Self> {
+        let dir = PathBuf::from(format!("{}/{}.blobs", directory, pattern));
+        fs::create_dir_all(&dir)?;
+        return Ok(ContentStore { options, dir });
+    }
+
+    /// Stores a value unless an identical one is already stored, returning its hash.
+    /// Returns `None` if a different value with the same hash is stored, in which case the
+    /// caller keeps the value inline.
+    pub(crate) fn put(&self, value: &[u8]) -> io::Result<Option<u64>> {
+        let hash = fnv1a(value);
+        let path = self.path(hash);
+        match fs::read(&path) {
+            Ok(existing) if existing == value => return Ok(Some(hash)),
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        // Write to a temporary file first so a crash never leaves a truncated value behind.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, value)?;
+        fs::rename(&tmp, &path)?;
+        return Ok(Some(hash));
+    }
+
+    pub(crate) fn get(&self, hash: u64) -> io::Result<Vec<u8>> {
+        return fs::read(self.path(hash)).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read deduplicated value {:016x}: {}", hash, e),
+            )
+        });
+    }
+
+    /// Deletes every stored value whose hash is not in `live`.
+    pub(crate) fn retain(&self, live: &HashSet<u64>) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let hash = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| u64::from_str_radix(stem, 16).ok())
+                .filter(|_| path.extension().is_some_and(|ext| ext == "blob"));
+            match hash {
+                Some(hash) if live.contains(&hash) => {}
+                // Unreferenced values and temporary files left behind by a crash.
+                _ => fs::remove_file(&path)?,
+            }
+        }
+        return Ok(());
+    }
+
+    fn path(&self, hash: u64) -> PathBuf {
+        return self.dir.join(format!("{:016x}.blob", hash));
+    }
+}
+
+/// 64-bit FNV-1a. Stable across releases, unlike the standard library's hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
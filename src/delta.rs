@@ -0,0 +1,146 @@
+//! Delta encoding for keys that are updated with slightly different values.
+//!
+//! When enabled, an update may be stored as a delta against the key's previous record
+//! instead of as a full value. Reads resolve the chain of deltas back to the last full
+//! value, and compaction collapses every chain into a single full value.
+//!
+//! Deltas are stored in `FRAME_DELTA` frames (see `frame`).
+
+use std::collections::HashMap;
+use std::io;
+
+const OP_COPY: u8 = 0;
+const OP_INSERT: u8 = 1;
+
+/// Bytes per block when matching the new value against the previous one.
+const BLOCK: usize = 16;
+
+/// Tuning for delta encoding.
+#[derive(Debug, Clone)]
+pub struct DeltaOptions {
+    /// Maximum number of deltas between a read and the full value it resolves to.
+    /// Defaults to 8.
+    pub max_chain: u16,
+    /// Values smaller than this are always stored in full. Defaults to 256 bytes.
+    pub min_value_size: usize,
+}
+
+impl Default for DeltaOptions {
+    fn default() -> Self {
+        return DeltaOptions {
+            max_chain: 8,
+            min_value_size: 256,
+        };
+    }
+}
+
+/// Computes the operations that turn `base` into `target`.
+///
+/// `base` is cut into fixed blocks; `target` is scanned for those blocks, and every match is
+/// extended as far as possible and emitted as a copy. Bytes without a match are inserted.
+pub(crate) fn diff(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for start in (0..base.len().saturating_sub(BLOCK - 1)).step_by(BLOCK) {
+        blocks.entry(&base[start..start + BLOCK]).or_insert(start);
+    }
+
+    let mut ops = Vec::new();
+    let mut pending = 0; // Start of the bytes waiting to be inserted.
+    let mut pos = 0;
+    while pos + BLOCK <= target.len() {
+        let Some(&start) = blocks.get(&target[pos..pos + BLOCK]) else {
+            pos += 1;
+            continue;
+        };
+
+        // Grow the match backwards over pending bytes and forwards as far as it goes.
+        let mut from = start;
+        let mut to = pos;
+        while from > 0 && to > pending && base[from - 1] == target[to - 1] {
+            from -= 1;
+            to -= 1;
+        }
+        let mut len = pos - to + BLOCK;
+        while from + len < base.len()
+            && to + len < target.len()
+            && base[from + len] == target[to + len]
+        {
+            len += 1;
+        }
+
+        push_insert(&mut ops, &target[pending..to]);
+        ops.push(OP_COPY);
+        write_varint(&mut ops, from as u64);
+        write_varint(&mut ops, len as u64);
+        pos = to + len;
+        pending = pos;
+    }
+    push_insert(&mut ops, &target[pending..]);
+    return ops;
+}
+
+/// Applies the operations produced by `diff` to `base`.
+pub(crate) fn apply(base: &[u8], mut ops: &[u8]) -> io::Result<Vec<u8>> {
+    let mut target = Vec::with_capacity(base.len());
+    while let Some((&op, rest)) = ops.split_first() {
+        ops = rest;
+        match op {
+            OP_COPY => {
+                let from = read_varint(&mut ops)? as usize;
+                let len = read_varint(&mut ops)? as usize;
+                let bytes = base
+                    .get(from..from.saturating_add(len))
+                    .ok_or_else(|| invalid("Delta copies past the end of its base"))?;
+                target.extend_from_slice(bytes);
+            }
+            OP_INSERT => {
+                let len = read_varint(&mut ops)? as usize;
+                if len > ops.len() {
+                    return Err(invalid("Truncated delta insert"));
+                }
+                let (bytes, rest) = ops.split_at(len);
+                target.extend_from_slice(bytes);
+                ops = rest;
+            }
+            _ => return Err(invalid("Unknown delta operation")),
+        }
+    }
+    return Ok(target);
+}
+
+fn push_insert(ops: &mut Vec<u8>, bytes: &[u8]) {
+    if bytes.is_empty() {
+        return;
+    }
+    ops.push(OP_INSERT);
+    write_varint(ops, bytes.len() as u64);
+    ops.extend_from_slice(bytes);
+}
+
+/// LEB128 encoding, so small offsets and lengths take a single byte.
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let Some((&byte, rest)) = input.split_first() else {
+            return Err(invalid("Truncated delta varint"));
+        };
+        *input = rest;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    return Err(invalid("Delta varint is too long"));
+}
+
+fn invalid(message: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+}
@@ -0,0 +1,71 @@
+//! Integrity digests of the live data, for checking that two stores hold the same keys and
+//! values, e.g. after replication or a migration.
+//!
+//! Every live key and its value hash to a 64-bit leaf. Keys are spread over ranges by the top
+//! bits of a hash of the key alone, so two stores always agree on which range a key belongs
+//! to. The hash of a range is the wrapping sum of its leaves, which makes the ranges of any
+//! depth a Merkle tree: each range hashes to the sum of its two halves, and the root, the hash
+//! of the whole store, to the sum of every range. Two stores compare `root_hash` first and
+//! narrow a difference down with `range_hashes`, and only the keys of ranges that differ need
+//! to be compared one by one.
+//!
+//! Digests cover the logical values, so they do not depend on compression, delta encoding,
+//! blob files or the layout of the log. Metadata, expiry times and history are not covered,
+//! and keys holding lists, sets or hashes are skipped, like in `get_all_key_values`.
+
+use crate::checksum::ChecksumAlgorithm;
+use crate::{Error, RCask, ReadOptions, Result};
+
+/// Deepest split `range_hashes` supports, 65536 ranges.
+pub const MAX_DIGEST_DEPTH: u32 = 16;
+
+impl RCask {
+    /// Returns the hash of every live key and value. Two stores holding the same data have
+    /// the same root hash, however it was written.
+    pub fn root_hash(&mut self) -> Result<u64> {
+        return Ok(self.range_hashes(0)?[0]);
+    }
+
+    /// Splits the keys into `2^depth` ranges and returns the hash of each, see the module
+    /// docs. `depth` is capped at `MAX_DIGEST_DEPTH`. An empty range hashes to 0.
+    pub fn range_hashes(&mut self, depth: u32) -> Result<Vec<u64>> {
+        let depth = depth.min(MAX_DIGEST_DEPTH);
+        let mut ranges = vec![0u64; 1 << depth];
+        let options = ReadOptions {
+            fill_cache: false,
+            ..Default::default()
+        };
+        for key in self.store.keys() {
+            let value = match self.get_opt_into(&key, &options, Vec::new()) {
+                Ok(Some(value)) => value,
+                Ok(None) | Err(Error::WrongType { .. }) => continue,
+                Err(e) => return Err(e),
+            };
+            let range = &mut ranges[key_range(&key, depth)];
+            *range = range.wrapping_add(leaf_hash(&key, &value));
+        }
+        return Ok(ranges);
+    }
+}
+
+/// Returns the range of `key` among the `2^depth` ranges of `range_hashes`.
+pub fn key_range(key: &str, depth: u32) -> usize {
+    let depth = depth.min(MAX_DIGEST_DEPTH);
+    if depth == 0 {
+        return 0;
+    }
+    let mut hasher = ChecksumAlgorithm::XxHash64.hasher();
+    hasher.update(key.as_bytes());
+    let hash = u64::from_le_bytes(hasher.finish().try_into().unwrap_or_default());
+    return (hash >> (64 - depth)) as usize;
+}
+
+/// Hashes a key and its value. The key's length goes first so that moving bytes between the
+/// key and the value changes the hash.
+fn leaf_hash(key: &str, value: &[u8]) -> u64 {
+    let mut hasher = ChecksumAlgorithm::XxHash64.hasher();
+    hasher.update(&(key.len() as u64).to_le_bytes());
+    hasher.update(key.as_bytes());
+    hasher.update(value);
+    return u64::from_le_bytes(hasher.finish().try_into().unwrap_or_default());
+}
@@ -0,0 +1,196 @@
+//! Read-modify-write helpers: `get_or_insert_with`, the `Entry` API, `update` and
+//! `delete_if`.
+//!
+//! Each helper reads and writes through one `&mut RCask`, the only handle that writes to the
+//! store, so no other write can land between its read and its write.
+
+use crate::{RCask, Result};
+
+impl RCask {
+    /// Returns the value of `key`, or stores and returns the value computed by `compute` if
+    /// the key has none, e.g. to read through to a slower source on a cache miss.
+    pub fn get_or_insert_with<F, V>(&mut self, key: &str, compute: F) -> Result<Vec<u8>>
+    where
+        F: FnOnce() -> V,
+        V: Into<Vec<u8>>,
+    {
+        if let Some(value) = self.get_bytes(key)? {
+            return Ok(value);
+        }
+        let value = compute().into();
+        self.set(key, &value)?;
+        return Ok(value);
+    }
+
+    /// Replaces the value of `key` with what `modify` returns for the current value, deleting
+    /// the key if it returns `None`, and returns the new value. Nothing is written if the
+    /// value is unchanged.
+    ///
+    /// The key's lock (see `RCask::lock`) is not taken: `&mut self` already keeps other
+    /// writes out. Callers that share the handle and also run read-modify-write sequences of
+    /// their own take the lock before locking the handle, and hold it around `update` too.
+    pub fn update<F>(&mut self, key: &str, modify: F) -> Result<Option<Vec<u8>>>
+    where
+        F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    {
+        let old = self.get_bytes(key)?;
+        let new = modify(old.as_deref());
+        if new == old {
+            return Ok(new);
+        }
+        match &new {
+            Some(value) => {
+                self.set(key, value)?;
+            }
+            None => {
+                self.delete(key)?;
+            }
+        }
+        return Ok(new);
+    }
+
+    /// Deletes `key` only if its value is `expected` and returns whether it did, e.g. to
+    /// release a lease without removing one a newer owner has taken since.
+    pub fn delete_if<V: AsRef<[u8]>>(&mut self, key: &str, expected: V) -> Result<bool> {
+        if self.get_bytes(key)?.as_deref() != Some(expected.as_ref()) {
+            return Ok(false);
+        }
+        return self.delete(key);
+    }
+}
+
+/// A key that has a value or not, returned by `RCask::entry`.
+pub enum Entry<'a> {
+    Occupied(OccupiedEntry<'a>),
+    Vacant(VacantEntry<'a>),
+}
+
+/// A key that has a value, see `Entry`.
+pub struct OccupiedEntry<'a> {
+    store: &'a mut RCask,
+    key: String,
+    value: Vec<u8>,
+}
+
+/// A key without a value, see `Entry`.
+pub struct VacantEntry<'a> {
+    store: &'a mut RCask,
+    key: String,
+}
+
+impl RCask {
+    /// Reads `key` into an `Entry` for a read-modify-write that only writes when the value
+    /// changes.
+    ///
+    /// ```no_run
+    /// use rcask::RCask;
+    ///
+    /// # fn main() -> rcask::Result<()> {
+    /// let mut store = RCask::new("./".to_string(), "log".to_string())?;
+    /// let visits = store
+    ///     .entry("visits")?
+    ///     .and_modify(|count| count.push(b'|'))?
+    ///     .or_insert("|")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn entry(&mut self, key: &str) -> Result<Entry<'_>> {
+        return Ok(match self.get_bytes(key)? {
+            Some(value) => Entry::Occupied(OccupiedEntry {
+                store: self,
+                key: key.to_string(),
+                value,
+            }),
+            None => Entry::Vacant(VacantEntry {
+                store: self,
+                key: key.to_string(),
+            }),
+        });
+    }
+}
+
+impl<'a> Entry<'a> {
+    pub fn key(&self) -> &str {
+        return match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        };
+    }
+
+    /// Returns the value, storing `default` first if the key has none.
+    pub fn or_insert<V: Into<Vec<u8>>>(self, default: V) -> Result<Vec<u8>> {
+        return self.or_insert_with(|| default);
+    }
+
+    /// Returns the value, storing the value computed by `default` first if the key has none.
+    pub fn or_insert_with<F, V>(self, default: F) -> Result<Vec<u8>>
+    where
+        F: FnOnce() -> V,
+        V: Into<Vec<u8>>,
+    {
+        return match self {
+            Entry::Occupied(entry) => Ok(entry.value),
+            Entry::Vacant(entry) => entry.insert(default()),
+        };
+    }
+
+    /// Lets `modify` change the value if the key has one, and writes it if it changed.
+    pub fn and_modify<F: FnOnce(&mut Vec<u8>)>(self, modify: F) -> Result<Self> {
+        let Entry::Occupied(mut entry) = self else {
+            return Ok(self);
+        };
+        let mut value = entry.value.clone();
+        modify(&mut value);
+        if value != entry.value {
+            entry.store.set(&entry.key, &value)?;
+            entry.value = value;
+        }
+        return Ok(Entry::Occupied(entry));
+    }
+
+    /// Deletes the key and returns its value, if it had one.
+    pub fn remove(self) -> Result<Option<Vec<u8>>> {
+        return match self {
+            Entry::Occupied(entry) => entry.remove().map(Some),
+            Entry::Vacant(_) => Ok(None),
+        };
+    }
+}
+
+impl<'a> OccupiedEntry<'a> {
+    pub fn key(&self) -> &str {
+        return &self.key;
+    }
+
+    pub fn get(&self) -> &[u8] {
+        return &self.value;
+    }
+
+    /// Replaces the value, unless it is unchanged, and returns the old one.
+    pub fn insert<V: Into<Vec<u8>>>(&mut self, value: V) -> Result<Vec<u8>> {
+        let value = value.into();
+        if value != self.value {
+            self.store.set(&self.key, &value)?;
+        }
+        return Ok(std::mem::replace(&mut self.value, value));
+    }
+
+    /// Deletes the key and returns its value.
+    pub fn remove(self) -> Result<Vec<u8>> {
+        self.store.delete(&self.key)?;
+        return Ok(self.value);
+    }
+}
+
+impl<'a> VacantEntry<'a> {
+    pub fn key(&self) -> &str {
+        return &self.key;
+    }
+
+    /// Stores `value` and returns it.
+    pub fn insert<V: Into<Vec<u8>>>(self, value: V) -> Result<Vec<u8>> {
+        let value = value.into();
+        self.store.set(&self.key, &value)?;
+        return Ok(value);
+    }
+}
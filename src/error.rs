@@ -0,0 +1,153 @@
+use crate::backpressure::PressureCause;
+use crate::version::Version;
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Boxed error produced by codecs and other user-supplied conversions.
+pub type BoxError = Box<dyn error::Error + Send + Sync>;
+
+/// Errors returned by rcask.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing the log files failed.
+    Io(io::Error),
+    /// A write failed because the disk is full (ENOSPC), or because less space is free than
+    /// `Builder::disk_headroom` reserves for compaction. Nothing of the write is left in the
+    /// log or the index.
+    DiskFull(io::Error),
+    /// A key or value could not be encoded to, or decoded from, its stored bytes.
+    Codec(BoxError),
+    /// A conditional write found the key at a different version than expected.
+    /// `actual` is `None` if the key does not exist.
+    Conflict {
+        key: String,
+        expected: Version,
+        actual: Option<Version>,
+    },
+    /// The key holds a different kind of data than the operation expects, e.g. a list was
+    /// read as a plain value.
+    WrongType { key: String, expected: &'static str },
+    /// The destination of a rename or copy already holds a value.
+    KeyExists { key: String },
+    /// A write would take the keys starting with `prefix` over their quota, see
+    /// `Builder::quota`.
+    QuotaExceeded { prefix: String },
+    /// The operation was cancelled through a `CancellationToken`.
+    Cancelled,
+    /// A write was rejected because compaction is too far behind or the disk is nearly
+    /// full; see `Builder::backpressure`.
+    Backpressure { cause: PressureCause },
+    /// The store is already open for writing, in another process or through another handle;
+    /// `path` is its lock file. Open it with `Builder::open_reader` to read it meanwhile.
+    Locked { path: String },
+    /// An `Observer` rejected a write to `key` for `reason`.
+    Rejected { key: String, reason: BoxError },
+    /// A key given as bytes is not valid UTF-8, which every stored key must be. Nothing of
+    /// the write is in the log.
+    InvalidKey { key: Vec<u8> },
+    /// A `ReplicatedRCask` that does not lead its group was asked to write or read, or lost
+    /// the leadership meanwhile. `leader` is the id of the member it knows as the leader.
+    #[cfg(feature = "raft")]
+    NotLeader { leader: Option<u64> },
+}
+
+/// Result type used throughout the public rcask API.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::DiskFull(err) => write!(f, "disk full: {}", err),
+            Error::Codec(err) => write!(f, "codec error: {}", err),
+            Error::Conflict {
+                key,
+                expected,
+                actual: Some(actual),
+            } => write!(
+                f,
+                "version conflict on key {}: expected {}, found {}",
+                key, expected, actual
+            ),
+            Error::Conflict { key, expected, .. } => write!(
+                f,
+                "version conflict on key {}: expected {}, but the key does not exist",
+                key, expected
+            ),
+            Error::WrongType { key, expected } => {
+                write!(f, "key {} does not hold a {}", key, expected)
+            }
+            Error::KeyExists { key } => write!(f, "key {} already exists", key),
+            Error::QuotaExceeded { prefix } => {
+                write!(f, "write rejected: namespace {} is over its quota", prefix)
+            }
+            Error::Cancelled => write!(f, "operation cancelled"),
+            Error::Backpressure {
+                cause: PressureCause::CompactionBacklog,
+            } => write!(f, "write rejected: compaction is too far behind"),
+            Error::Backpressure {
+                cause: PressureCause::DiskSpace,
+            } => write!(f, "write rejected: the disk is nearly full"),
+            Error::Rejected { key, reason } => {
+                write!(f, "write to key {} rejected: {}", key, reason)
+            }
+            Error::InvalidKey { key } => {
+                write!(f, "key {} is not valid UTF-8", String::from_utf8_lossy(key))
+            }
+            Error::Locked { path } => {
+                write!(
+                    f,
+                    "store is open for writing elsewhere (locked by {})",
+                    path
+                )
+            }
+            #[cfg(feature = "raft")]
+            Error::NotLeader {
+                leader: Some(leader),
+            } => write!(f, "not the leader of the group, member {} is", leader),
+            #[cfg(feature = "raft")]
+            Error::NotLeader { leader: None } => {
+                write!(f, "not the leader of the group, and no leader is known")
+            }
+        };
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        return match self {
+            Error::Io(err) | Error::DiskFull(err) => Some(err),
+            Error::Codec(err) | Error::Rejected { reason: err, .. } => Some(err.as_ref()),
+            Error::Conflict { .. }
+            | Error::WrongType { .. }
+            | Error::KeyExists { .. }
+            | Error::QuotaExceeded { .. }
+            | Error::Cancelled
+            | Error::Backpressure { .. }
+            | Error::Locked { .. }
+            | Error::InvalidKey { .. } => None,
+            #[cfg(feature = "raft")]
+            Error::NotLeader { .. } => None,
+        };
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        if err.kind() == io::ErrorKind::StorageFull {
+            return Error::DiskFull(err);
+        }
+        return Error::Io(err);
+    }
+}
+
+/// Lets rcask errors flow into code that works with `io::Result`.
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        return match err {
+            Error::Io(err) | Error::DiskFull(err) => err,
+            other => io::Error::other(other),
+        };
+    }
+}
@@ -0,0 +1,118 @@
+//! Structured event log of background work.
+//!
+//! With `Builder::event_log`, compactions, expiry sweeps, periodic syncs, and the recovery scan
+//! and orphaned-file cleanup of `open` are each reported as one JSON object per line, so log
+//! pipelines can ingest them without parsing free-form text, e.g.
+//!
+//! ```text
+//! {"ts":1760400000000,"event":"compaction","mode":"full","outcome":"ok","duration_ms":12,"bytes_before":4096,"bytes_after":1024}
+//! ```
+//!
+//! Every event has the time in milliseconds since the Unix epoch as `ts`, its kind as `event`
+//! and, for operations that can fail, `outcome` and, on failure, `error`. With the `log`
+//! feature, `Builder::log_events` sends the same lines to the `log` facade under the `rcask`
+//! target instead, at info level or warn level for failures. Writing an event never fails
+//! the operation it reports.
+
+use crate::{RCask, Result};
+use std::fmt::Write as _;
+use std::io::Write;
+
+/// A field value of an event.
+pub(crate) enum Field<'a> {
+    U64(u64),
+    Str(&'a str),
+}
+
+pub(crate) enum EventSink {
+    Writer(Box<dyn Write + Send>),
+    #[cfg(feature = "log")]
+    Log,
+}
+
+pub(crate) struct EventLog {
+    sink: EventSink,
+}
+
+impl EventLog {
+    pub(crate) fn new(sink: EventSink) -> Self {
+        return EventLog { sink };
+    }
+
+    /// Writes an event of kind `event` with `fields`, and with the outcome of `result` if
+    /// given.
+    pub(crate) fn emit<T>(
+        &mut self,
+        event: &str,
+        result: Option<&Result<T>>,
+        fields: &[(&str, Field)],
+    ) {
+        let mut line = format!("{{\"ts\":{},\"event\":", crate::now_millis());
+        push_string(&mut line, event);
+        if let Some(result) = result {
+            match result {
+                Ok(_) => line.push_str(",\"outcome\":\"ok\""),
+                Err(e) => {
+                    line.push_str(",\"outcome\":\"error\",\"error\":");
+                    push_string(&mut line, &e.to_string());
+                }
+            }
+        }
+        for (name, value) in fields {
+            line.push(',');
+            push_string(&mut line, name);
+            line.push(':');
+            match value {
+                Field::U64(n) => {
+                    let _ = write!(line, "{}", n);
+                }
+                Field::Str(s) => push_string(&mut line, s),
+            }
+        }
+        line.push('}');
+        match &mut self.sink {
+            EventSink::Writer(writer) => {
+                let _ = writeln!(writer, "{}", line).and_then(|_| writer.flush());
+            }
+            #[cfg(feature = "log")]
+            EventSink::Log if result.is_some_and(|result| result.is_err()) => {
+                log::warn!(target: "rcask", "{}", line)
+            }
+            #[cfg(feature = "log")]
+            EventSink::Log => log::info!(target: "rcask", "{}", line),
+        }
+    }
+}
+
+/// Appends `s` as a JSON string.
+fn push_string(line: &mut String, s: &str) {
+    line.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(line, "\\u{:04x}", c as u32);
+            }
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+impl RCask {
+    /// Writes an event to the event log, if the store has one.
+    pub(crate) fn log_event<T>(
+        &mut self,
+        event: &str,
+        result: Option<&Result<T>>,
+        fields: &[(&str, Field)],
+    ) {
+        if let Some(log) = &mut self.events {
+            log.emit(event, result, fields);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Bounded mode: evicting the least recently used keys to stay within a budget.
+//!
+//! With `Builder::evict_lru` the store remembers when each live key was last read with `get`
+//! or written, and after a write that takes it over its budget of live entries or bytes it
+//! writes tombstones for the least recently used keys until it fits again, which turns it
+//! into a persistent LRU cache. Evicted keys read as missing, and compaction drops them.
+//!
+//! A key's size is its length plus the length of its value as stored, e.g. after
+//! compression. Lists, sets and hashes are counted by the records appended to them, so their
+//! sizes are estimates. On open, every key is read once to learn its size, and keys are
+//! ordered by when they were last written.
+
+use crate::{RCask, Result};
+use std::collections::{BTreeMap, HashMap};
+
+/// The budget of a store in bounded mode, see `Builder::evict_lru`.
+#[derive(Debug, Clone, Default)]
+pub struct EvictionOptions {
+    /// Maximum number of live keys. Defaults to no limit.
+    pub max_entries: Option<u64>,
+    /// Maximum number of bytes of live keys and values. Defaults to no limit.
+    pub max_bytes: Option<u64>,
+}
+
+/// Recency and size of every live key.
+pub(crate) struct Lru {
+    options: EvictionOptions,
+    /// Last use and size of every key.
+    entries: HashMap<String, (u64, u64)>,
+    /// Keys by last use.
+    order: BTreeMap<u64, String>,
+    clock: u64,
+    bytes: u64,
+    evicted: u64,
+}
+
+impl Lru {
+    pub(crate) fn new(options: EvictionOptions) -> Self {
+        return Lru {
+            options,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            clock: 0,
+            bytes: 0,
+            evicted: 0,
+        };
+    }
+
+    /// Marks `key` as just used and adds `grown` bytes to its size, or sets its size to
+    /// `grown` with `replace`.
+    fn used(&mut self, key: &str, grown: u64, replace: bool) {
+        self.clock += 1;
+        let size = match self.entries.remove(key) {
+            Some((last, size)) => {
+                self.order.remove(&last);
+                self.bytes -= size;
+                if replace {
+                    grown
+                } else {
+                    size + grown
+                }
+            }
+            None => grown,
+        };
+        self.order.insert(self.clock, key.to_string());
+        self.entries.insert(key.to_string(), (self.clock, size));
+        self.bytes += size;
+    }
+
+    /// Marks a key that was read as just used.
+    fn read(&mut self, key: &str) {
+        if self.entries.contains_key(key) {
+            self.used(key, 0, false);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((last, size)) = self.entries.remove(key) {
+            self.order.remove(&last);
+            self.bytes -= size;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+        self.bytes = 0;
+    }
+
+    fn is_over_budget(&self) -> bool {
+        let entries = self.entries.len() as u64;
+        return self.options.max_entries.is_some_and(|max| entries > max)
+            || self.options.max_bytes.is_some_and(|max| self.bytes > max);
+    }
+
+    /// The least recently used key other than `keep`.
+    fn victim(&self, keep: &str) -> Option<String> {
+        return self.order.values().find(|key| *key != keep).cloned();
+    }
+}
+
+impl RCask {
+    /// Number of keys evicted to stay within the budget of `Builder::evict_lru` since the
+    /// store was opened.
+    pub fn evictions(&self) -> u64 {
+        return self.lru.as_ref().map_or(0, |lru| lru.evicted);
+    }
+
+    /// Learns the size of every live key after the store was opened.
+    pub(crate) fn seed_lru(&mut self) -> Result<()> {
+        if self.lru.is_none() {
+            return Ok(());
+        }
+        let mut keys: Vec<(u64, String)> = self
+            .store
+            .keys()
+            .into_iter()
+            .filter_map(|key| Some((self.store.offset(&key)?, key)))
+            .collect();
+        keys.sort_unstable();
+        let now = crate::now_millis();
+        for (offset, key) in keys {
+            let verify = self.verify_checksums;
+            let Some((stored, attributes)) = self.store.get_value_bytes_at(&key, offset, verify)?
+            else {
+                continue;
+            };
+            if attributes.is_expired(now) {
+                continue;
+            }
+            if let Some(lru) = &mut self.lru {
+                lru.used(&key, (key.len() + stored.len()) as u64, true);
+            }
+        }
+        return Ok(());
+    }
+
+    /// Marks a key that was read as just used.
+    pub(crate) fn lru_read(&mut self, key: &str) {
+        if let Some(lru) = &mut self.lru {
+            lru.read(key);
+        }
+    }
+
+    /// Accounts for records of `size` bytes written for every key, replacing the key's
+    /// size or, with `append`, adding to it, and evicts keys until the store fits its
+    /// budget again. The keys just written are evicted last. Quotas are kept up to date
+    /// too.
+    pub(crate) fn lru_wrote<'a, I>(&mut self, written: I, append: bool) -> Result<()>
+    where
+        I: IntoIterator<Item = (&'a str, u64)>,
+    {
+        if self.lru.is_none() && self.quotas.is_none() {
+            return Ok(());
+        }
+        let written: Vec<(&str, u64)> = written.into_iter().collect();
+        for &(key, size) in &written {
+            self.quotas_wrote(key, size, append);
+        }
+        let Some(lru) = &mut self.lru else {
+            return Ok(());
+        };
+        let mut newest = None;
+        for (key, size) in written {
+            lru.used(key, size, !append);
+            newest = Some(key);
+        }
+        let Some(newest) = newest else {
+            return Ok(());
+        };
+        while let Some(victim) = self
+            .lru
+            .as_ref()
+            .filter(|lru| lru.is_over_budget())
+            .and_then(|lru| lru.victim(newest))
+        {
+            self.evict(&victim)?;
+        }
+        return Ok(());
+    }
+
+    /// Forgets every key after the store was cleared.
+    pub(crate) fn lru_cleared(&mut self) {
+        if let Some(lru) = &mut self.lru {
+            lru.clear();
+        }
+        self.quotas_cleared();
+    }
+
+    /// Forgets a key that was deleted.
+    pub(crate) fn lru_removed(&mut self, key: &str) {
+        if let Some(lru) = &mut self.lru {
+            lru.remove(key);
+        }
+        self.quotas_removed(key);
+    }
+
+    /// Writes a tombstone for `key`. The write that went over the budget runs the compaction
+    /// check once the evictions are done.
+    fn evict(&mut self, key: &str) -> Result<()> {
+        self.write_tombstone(key)?;
+        if let Some(lru) = &mut self.lru {
+            lru.evicted += 1;
+        }
+        self.sync_timer.appended();
+        self.published.appended(&self.store);
+        self.writes += 1;
+        return Ok(());
+    }
+}
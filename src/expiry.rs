@@ -0,0 +1,187 @@
+//! Expiration events.
+//!
+//! Expired keys are detected by `RCask::sweep_expired` and by compaction, which drops them.
+//! Each expiration is reported once per handle; writing the key again re-arms it.
+//!
+//! Keys written with a TTL are scheduled in a timer wheel, so a sweep only looks at the keys
+//! that came due instead of the whole index. Records already in the log when the store was
+//! opened are scheduled by the first sweep, which reads the expiry time of every key once.
+//! Timers are not cancelled when a key is written again; a key that came due is checked
+//! against its latest record before it is reported.
+
+use crate::events::Field;
+use crate::wheel::TimerWheel;
+use crate::{reader, RCask, Result};
+use std::collections::HashSet;
+
+/// A key whose TTL has passed, as passed to the callback registered with `Builder::on_expire`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expiration {
+    pub key: String,
+    /// The key's last value, if the callback asked for values. Always `None` for lists, sets
+    /// and hashes.
+    pub value: Option<Vec<u8>>,
+}
+
+pub(crate) type ExpireCallback = Box<dyn FnMut(Expiration) + Send>;
+
+pub(crate) struct ExpiryListener {
+    callback: ExpireCallback,
+    with_value: bool,
+    /// Expired keys already reported and not written since.
+    reported: HashSet<String>,
+    wheel: TimerWheel,
+    /// Whether the keys in the log when the store was opened have been scheduled.
+    seeded: bool,
+}
+
+impl ExpiryListener {
+    pub(crate) fn new(with_value: bool, callback: ExpireCallback) -> Self {
+        return ExpiryListener {
+            callback,
+            with_value,
+            reported: HashSet::new(),
+            wheel: TimerWheel::new(crate::now_millis()),
+            seeded: false,
+        };
+    }
+
+    /// Schedules `key`, which was just written to expire at `expires_at`.
+    pub(crate) fn schedule(&mut self, key: &str, expires_at: u64) {
+        self.wheel.insert(key.to_string(), expires_at);
+    }
+
+    /// Re-arms `key` after it was written.
+    pub(crate) fn forget(&mut self, key: &str) {
+        self.reported.remove(key);
+    }
+
+    /// Forgets every reported key once compaction dropped them.
+    pub(crate) fn clear(&mut self) {
+        self.reported.clear();
+    }
+}
+
+impl RCask {
+    /// Reports every key that expired since the last sweep to the callback registered with
+    /// `Builder::on_expire` and returns how many were reported. Expired keys stay in the log
+    /// until the next compaction. Without a callback this does nothing.
+    pub fn sweep_expired(&mut self) -> Result<usize> {
+        let result = self.sweep();
+        // Most sweeps find nothing, so only those that did something are logged.
+        if !matches!(result, Ok(0)) {
+            let expired = Field::U64(*result.as_ref().unwrap_or(&0) as u64);
+            self.log_event("expiry_sweep", Some(&result), &[("expired", expired)]);
+        }
+        return result;
+    }
+
+    fn sweep(&mut self) -> Result<usize> {
+        let now = crate::now_millis();
+        let due = match &mut self.expiry {
+            Some(listener) if listener.seeded => listener.wheel.advance(now),
+            Some(_) => self.seed_expiry()?,
+            None => return Ok(0),
+        };
+        let mut expired = Vec::new();
+        let mut seen = HashSet::new();
+        let mut due = due.into_iter();
+        while let Some((key, expires_at)) = due.next() {
+            if !seen.insert(key.clone()) {
+                continue;
+            }
+            match self.expiration(&key) {
+                Ok(Some(event)) => expired.push(event),
+                Ok(None) => {}
+                Err(e) => {
+                    // Keep the keys not checked yet for the next sweep.
+                    if let Some(listener) = &mut self.expiry {
+                        listener.schedule(&key, expires_at);
+                        for (key, expires_at) in due {
+                            listener.schedule(&key, expires_at);
+                        }
+                    }
+                    self.emit_expirations(expired);
+                    return Err(e);
+                }
+            }
+        }
+        let count = expired.len();
+        self.emit_expirations(expired);
+        return Ok(count);
+    }
+
+    /// Schedules every key of the log that expires in the future and returns those that
+    /// already expired.
+    fn seed_expiry(&mut self) -> Result<Vec<(String, u64)>> {
+        let now = crate::now_millis();
+        let verify = self.verify_checksums;
+        let mut due = Vec::new();
+        for key in self.store.keys() {
+            let Some(offset) = self.store.offset(&key) else {
+                continue;
+            };
+            let Some((_, attributes)) = self.store.get_value_bytes_at(&key, offset, verify)? else {
+                continue;
+            };
+            let Some(expires_at) = attributes.expires_at.filter(|_| !attributes.is_tombstone())
+            else {
+                continue;
+            };
+            if expires_at <= now {
+                due.push((key, expires_at));
+            } else if let Some(listener) = &mut self.expiry {
+                listener.schedule(&key, expires_at);
+            }
+        }
+        if let Some(listener) = &mut self.expiry {
+            listener.seeded = true;
+        }
+        return Ok(due);
+    }
+
+    /// Builds the expiration event of `key` if it has expired and was not reported yet.
+    pub(crate) fn expiration(&mut self, key: &str) -> Result<Option<Expiration>> {
+        let Some(listener) = &self.expiry else {
+            return Ok(None);
+        };
+        let with_value = listener.with_value;
+        if listener.reported.contains(key) {
+            return Ok(None);
+        }
+        let Some(offset) = self.store.offset(key) else {
+            return Ok(None);
+        };
+        let verify = self.verify_checksums;
+        let Some((_, attributes)) = self.store.get_value_bytes_at(key, offset, verify)? else {
+            return Ok(None);
+        };
+        if !attributes.is_expired(crate::now_millis()) || attributes.is_tombstone() {
+            return Ok(None);
+        }
+
+        let value = if with_value {
+            match reader::read_plain(&mut self.store, &mut self.values, key, offset, verify)? {
+                Some((plain, _)) => Some(self.values.untag_schema(plain)?),
+                None => None,
+            }
+        } else {
+            None
+        };
+        return Ok(Some(Expiration {
+            key: key.to_string(),
+            value,
+        }));
+    }
+
+    /// Hands the events to the callback and marks their keys as reported.
+    pub(crate) fn emit_expirations(&mut self, expired: Vec<Expiration>) {
+        let Some(listener) = &mut self.expiry else {
+            return;
+        };
+        for event in expired {
+            listener.reported.insert(event.key.clone());
+            (listener.callback)(event);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Compaction filters.
+//!
+//! A filter sees every live value while compaction rewrites the log and decides whether it is
+//! kept, dropped or replaced, so retention policies apply without a separate pass over the
+//! store. Lists, sets and hashes are not filtered.
+
+/// What compaction does with a value, as decided by a `CompactionFilter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterDecision {
+    /// Keep the value as it is.
+    Keep,
+    /// Drop the key from the store.
+    Drop,
+    /// Keep the key with a new value. Its TTL and metadata are kept.
+    Replace(Vec<u8>),
+}
+
+/// Decides the fate of every live value during compaction, see `Builder::compaction_filter`.
+///
+/// ```no_run
+/// use rcask::{CompactionFilter, FilterDecision, RCask};
+///
+/// /// Drops every key under `tmp/`.
+/// struct DropTemporary;
+///
+/// impl CompactionFilter for DropTemporary {
+///     fn filter(&mut self, key: &str, _value: &[u8], _meta: Option<&[u8]>) -> FilterDecision {
+///         if key.starts_with("tmp/") {
+///             return FilterDecision::Drop;
+///         }
+///         return FilterDecision::Keep;
+///     }
+/// }
+///
+/// # fn main() -> rcask::Result<()> {
+/// let store = RCask::builder("./".to_string(), "log".to_string())
+///     .compaction_filter(DropTemporary)
+///     .open()?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait CompactionFilter: Send {
+    /// Called with the key, its value and the metadata it was written with. Policies based on
+    /// age can store the write time in the metadata, see `WriteOptions::meta`.
+    fn filter(&mut self, key: &str, value: &[u8], meta: Option<&[u8]>) -> FilterDecision;
+}
@@ -0,0 +1,66 @@
+//! Syncing the log on a timer.
+//!
+//! Writes are synced to disk only when `WriteOptions::sync` asks for it, so on a store that
+//! rarely syncs, acknowledged writes can sit in the page cache for a long time. With
+//! `Builder::sync_interval`, unsynced writes are synced once the interval has passed: by the
+//! next write, or by `RCask::sync_if_due`, which `BackgroundRuntime::maintain` calls, so that
+//! the window of writes a crash can lose is bounded in time even when writes stop.
+
+use crate::events::Field;
+use crate::{RCask, Result};
+use std::time::{Duration, Instant};
+
+pub(crate) struct SyncTimer {
+    interval: Option<Duration>,
+    /// Whether records were appended since the last sync.
+    dirty: bool,
+    last_sync: Instant,
+}
+
+impl SyncTimer {
+    pub(crate) fn new(interval: Option<Duration>) -> Self {
+        return SyncTimer {
+            interval,
+            dirty: false,
+            last_sync: Instant::now(),
+        };
+    }
+
+    pub(crate) fn appended(&mut self) {
+        self.dirty = true;
+    }
+
+    fn is_due(&self) -> bool {
+        return self.dirty
+            && self
+                .interval
+                .is_some_and(|interval| self.last_sync.elapsed() >= interval);
+    }
+}
+
+impl RCask {
+    /// Syncs every write so far to disk.
+    pub fn sync(&mut self) -> Result<()> {
+        let synced = self.store.sync();
+        self.health.wrote(&synced);
+        synced?;
+        self.health.synced();
+        self.sync_timer.dirty = false;
+        self.sync_timer.last_sync = Instant::now();
+        return Ok(());
+    }
+
+    /// Syncs if there are unsynced writes and the `Builder::sync_interval` has passed since
+    /// the last sync, and returns whether it did.
+    pub fn sync_if_due(&mut self) -> Result<bool> {
+        if !self.sync_timer.is_due() {
+            return Ok(false);
+        }
+        let started = Instant::now();
+        let result = self.sync();
+        let duration = Field::U64(started.elapsed().as_millis() as u64);
+        self.log_event("sync", Some(&result), &[("duration_ms", duration)]);
+        result?;
+        return Ok(true);
+    }
+}
@@ -0,0 +1,84 @@
+//! Framing of plain values in the log.
+//!
+//! When delta encoding, deduplication or blob files are enabled, every plain value is wrapped in a frame
+//! that says how to reconstruct it:
+//! * `[FRAME_FULL][value]` holds the value itself.
+//! * `[FRAME_DELTA][depth: u16][base offset: u64][ops]` is a delta against the record of the
+//!   same key at `base offset` in the same log (see `delta`).
+//! * `[FRAME_SHARED][hash: u64]` refers to a deduplicated value in the content store
+//!   (see `dedup`).
+//! * `[FRAME_BLOB][id: u64]` refers to a value stored in its own blob file (see `blob`).
+
+use std::io;
+
+const FRAME_FULL: u8 = 0;
+const FRAME_DELTA: u8 = 1;
+const FRAME_SHARED: u8 = 2;
+const FRAME_BLOB: u8 = 3;
+
+/// A parsed frame.
+pub(crate) enum Frame<'a> {
+    Full(&'a [u8]),
+    Delta {
+        depth: u16,
+        base: u64,
+        ops: &'a [u8],
+    },
+    Shared {
+        hash: u64,
+    },
+    Blob {
+        id: u64,
+    },
+}
+
+pub(crate) fn full(value: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(value.len() + 1);
+    framed.push(FRAME_FULL);
+    framed.extend_from_slice(value);
+    return framed;
+}
+
+pub(crate) fn delta(depth: u16, base: u64, ops: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(ops.len() + 11);
+    framed.push(FRAME_DELTA);
+    framed.extend_from_slice(&depth.to_le_bytes());
+    framed.extend_from_slice(&base.to_le_bytes());
+    framed.extend_from_slice(ops);
+    return framed;
+}
+
+pub(crate) fn shared(hash: u64) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(9);
+    framed.push(FRAME_SHARED);
+    framed.extend_from_slice(&hash.to_le_bytes());
+    return framed;
+}
+
+pub(crate) fn blob(id: u64) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(9);
+    framed.push(FRAME_BLOB);
+    framed.extend_from_slice(&id.to_le_bytes());
+    return framed;
+}
+
+pub(crate) fn parse(framed: &[u8]) -> io::Result<Frame<'_>> {
+    return match framed.split_first() {
+        Some((&FRAME_FULL, value)) => Ok(Frame::Full(value)),
+        Some((&FRAME_DELTA, rest)) if rest.len() >= 10 => Ok(Frame::Delta {
+            depth: u16::from_le_bytes([rest[0], rest[1]]),
+            base: u64::from_le_bytes(rest[2..10].try_into().unwrap_or_default()),
+            ops: &rest[10..],
+        }),
+        Some((&FRAME_SHARED, rest)) if rest.len() == 8 => Ok(Frame::Shared {
+            hash: u64::from_le_bytes(rest.try_into().unwrap_or_default()),
+        }),
+        Some((&FRAME_BLOB, rest)) if rest.len() == 8 => Ok(Frame::Blob {
+            id: u64::from_le_bytes(rest.try_into().unwrap_or_default()),
+        }),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unknown value frame",
+        )),
+    };
+}
@@ -0,0 +1,96 @@
+//! Reading values without copying them.
+//!
+//! `get_bytes` hands out a `Vec<u8>` of its own, so a value served from memory is copied on
+//! every read. `RCask::get_ref` returns a `ValueGuard` borrowing the value instead: straight
+//! from the pinned values or the read cache, or from a buffer the store keeps for reads from
+//! the log. The guard borrows the store, so it has to be dropped before the next call; a
+//! caller that inspects a value and moves on copies nothing.
+
+use crate::{now_millis, Operation, RCask, ReadOptions, Result};
+use std::mem;
+use std::ops::Deref;
+use std::time::Instant;
+
+/// A value borrowed from the store by `RCask::get_ref`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueGuard<'a> {
+    value: &'a [u8],
+}
+
+impl Deref for ValueGuard<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        return self.value;
+    }
+}
+
+impl AsRef<[u8]> for ValueGuard<'_> {
+    fn as_ref(&self) -> &[u8] {
+        return self.value;
+    }
+}
+
+/// Where `get_ref` finds a value.
+enum Source {
+    Pinned,
+    Cached,
+    Log,
+}
+
+impl RCask {
+    /// Like `get_bytes`, but borrows the value from the store instead of copying it, see the
+    /// module docs. Values read from the log are decoded into a buffer the store keeps, which
+    /// grows to the largest value read this way.
+    pub fn get_ref(&mut self, key: &str) -> Result<Option<ValueGuard<'_>>> {
+        let started = Instant::now();
+        let key = self.stored_key(key);
+        let key = key.as_ref();
+        let source = match self.verify_checksums {
+            true => Source::Log,
+            false if self.pinned_value(key).is_some() => Source::Pinned,
+            // A pinned key not held yet is read from the log to be held.
+            false if self.pins.contains(key) => Source::Log,
+            false => {
+                let now = now_millis();
+                match self
+                    .cache
+                    .as_mut()
+                    .is_some_and(|cache| cache.touch(key, now))
+                {
+                    true => Source::Cached,
+                    false => Source::Log,
+                }
+            }
+        };
+        let value = match source {
+            Source::Log => {
+                let buf = mem::take(&mut self.ref_buffer);
+                let options = ReadOptions::default();
+                let Some(value) = self.get_opt_into(key, &options, buf)? else {
+                    return Ok(None);
+                };
+                self.ref_buffer = value;
+                return Ok(Some(ValueGuard {
+                    value: &self.ref_buffer,
+                }));
+            }
+            Source::Pinned | Source::Cached => {
+                self.record_read(key);
+                self.lru_read(key);
+                match source {
+                    Source::Pinned => self.pins.get(key, now_millis()),
+                    _ => self.cache.as_ref().and_then(|cache| cache.peek(key)),
+                }
+            }
+        };
+        // Only a pinned value that expired since it was checked is missing.
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        if let Some(log) = &mut self.slow_log {
+            log.record(Operation::Get, key.len(), value.len() as u64, started, None);
+        }
+        return Ok(Some(ValueGuard { value }));
+    }
+}
@@ -0,0 +1,67 @@
+//! Running out of disk space.
+//!
+//! When the disk fills up in the middle of an append (ENOSPC), the write is not retried:
+//! whatever part of the record made it to the disk is cut off again, keys already pointed at
+//! a pending block or extent get back their previous records, and the write fails with
+//! `Error::DiskFull`, leaving the store as it was before it. Writes succeed again once space
+//! is freed.
+//!
+//! Compaction is what frees space, but it first writes the live records to a new segment, so
+//! it needs some space itself. `Builder::disk_headroom` keeps that much in reserve: writes
+//! fail with `Error::DiskFull` as soon as less is free, while compaction can still use it.
+
+use crate::backpressure::DISK_CHECK_INTERVAL;
+use crate::{Error, RCask, Result};
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+/// Disk space reserved for compaction.
+pub(crate) struct Headroom {
+    reserved: u64,
+    /// Free disk space when it was last checked.
+    free_disk: Option<(Instant, u64)>,
+}
+
+impl Headroom {
+    pub(crate) fn new(reserved: u64) -> Self {
+        return Headroom {
+            reserved,
+            free_disk: None,
+        };
+    }
+
+    fn free_disk(&mut self, directory: &Path) -> Option<u64> {
+        if let Some((checked, free)) = self.free_disk {
+            if checked.elapsed() < DISK_CHECK_INTERVAL {
+                return Some(free);
+            }
+        }
+        let free = fs2::available_space(directory).ok()?;
+        self.free_disk = Some((Instant::now(), free));
+        return Some(free);
+    }
+}
+
+impl RCask {
+    /// Fails with `Error::DiskFull` if less disk space is free than the headroom reserved for
+    /// compaction, if any.
+    pub(crate) fn admit_headroom(&mut self) -> Result<()> {
+        let Some(headroom) = &mut self.headroom else {
+            return Ok(());
+        };
+        let Some(free) = headroom.free_disk(Path::new(&self.directory)) else {
+            return Ok(());
+        };
+        if free >= headroom.reserved {
+            return Ok(());
+        }
+        return Err(Error::DiskFull(io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!(
+                "{} bytes free on the disk of {}, {} are reserved for compaction",
+                free, self.directory, headroom.reserved
+            ),
+        )));
+    }
+}
@@ -0,0 +1,105 @@
+//! Health reporting for readiness and liveness probes.
+
+use crate::{Error, RCask, Result};
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Default free disk space below which `Health::disk_space_low` is set.
+pub(crate) const DEFAULT_MIN_FREE_DISK: u64 = 64 << 20;
+
+/// The status of a store, as returned by `RCask::health`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    /// Whether the last write succeeded. A store that never wrote is writable.
+    pub writable: bool,
+    /// Time since the log was last synced to disk, or `None` if this handle never synced.
+    pub last_sync_age: Option<Duration>,
+    /// Outcome of the last compaction, or `None` if this handle never compacted.
+    pub last_compaction: Option<CompactionOutcome>,
+    /// Free space on the store's disk, if it could be determined.
+    pub free_disk: Option<u64>,
+    /// Whether the free disk space is below the threshold set with `Builder::min_free_disk`.
+    pub disk_space_low: bool,
+    /// Whether any read or compaction found corrupt data since the store was opened.
+    pub corruption_detected: bool,
+}
+
+impl Health {
+    /// Whether the store can serve reads and writes: it is writable, has disk space left and
+    /// never found corrupt data.
+    pub fn is_healthy(&self) -> bool {
+        return self.writable && !self.disk_space_low && !self.corruption_detected;
+    }
+}
+
+/// When the last compaction finished and the error it failed with, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionOutcome {
+    pub finished_at: SystemTime,
+    pub error: Option<String>,
+}
+
+/// What a store remembers for `RCask::health`.
+pub(crate) struct HealthState {
+    min_free_disk: u64,
+    writable: bool,
+    last_sync: Option<Instant>,
+    last_compaction: Option<CompactionOutcome>,
+    corruption_detected: bool,
+}
+
+impl HealthState {
+    pub(crate) fn new(min_free_disk: u64) -> Self {
+        return HealthState {
+            min_free_disk,
+            writable: true,
+            last_sync: None,
+            last_compaction: None,
+            corruption_detected: false,
+        };
+    }
+
+    pub(crate) fn wrote(&mut self, result: &io::Result<impl Sized>) {
+        self.writable = result.is_ok();
+    }
+
+    pub(crate) fn synced(&mut self) {
+        self.last_sync = Some(Instant::now());
+    }
+
+    pub(crate) fn read(&mut self, result: &Result<impl Sized>) {
+        if let Err(e) = result {
+            self.corruption_detected |= is_corruption(e);
+        }
+    }
+
+    pub(crate) fn compacted(&mut self, result: &Result<()>) {
+        self.read(result);
+        self.last_compaction = Some(CompactionOutcome {
+            finished_at: SystemTime::now(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+    }
+}
+
+/// Corrupt records surface as `InvalidData` I/O errors.
+fn is_corruption(error: &Error) -> bool {
+    return matches!(error, Error::Io(e) if e.kind() == io::ErrorKind::InvalidData);
+}
+
+impl RCask {
+    /// Returns the store's status, e.g. to answer a service's readiness or liveness probe.
+    pub fn health(&self) -> Health {
+        let state = &self.health;
+        let free_disk = fs2::available_space(Path::new(&self.directory)).ok();
+        return Health {
+            writable: state.writable,
+            last_sync_age: state.last_sync.map(|at| at.elapsed()),
+            last_compaction: state.last_compaction.clone(),
+            free_disk,
+            disk_space_low: free_disk.is_some_and(|free| free < state.min_free_disk),
+            corruption_detected: state.corruption_detected,
+        };
+    }
+}
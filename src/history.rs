@@ -0,0 +1,322 @@
+//! Version history: reading a key as it was at an earlier time.
+//!
+//! With `Builder::history`, every value and every deletion is stamped with its write time and
+//! linked to the key's previous record, so `get_as_of` can walk a key's versions from the
+//! newest one back, and `get_versions` can list them. Compaction normally keeps only the
+//! latest version of each key; with history it also keeps the older versions
+//! `HistoryOptions` retains, and the tombstones of recently deleted keys, linked in the same
+//! way. `punch_holes` keeps them too, and `undelete` restores a key deleted within the
+//! retention window.
+//!
+//! Older versions are written without block packing, and lists, sets and hashes have no
+//! history. Records written without history, e.g. before it was enabled or by `bulk_load`,
+//! have no write time; a walk ends at them, treating them as older than every stamped
+//! record. Keys that expired are dropped with their history by compaction.
+
+use crate::compaction::Relocated;
+use crate::kvstore::{Attributes, KVStore};
+use crate::{reader, Error, RCask, Result, WriteOptions};
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How much history compaction preserves, see `Builder::history`. A replaced version is
+/// kept while either limit keeps it.
+#[derive(Debug, Clone)]
+pub struct HistoryOptions {
+    /// How long a version is kept after a newer one replaced it, and a deleted key after its
+    /// deletion. Defaults to one hour.
+    pub retention: Duration,
+    /// Number of most recent versions of a key that are kept regardless of their age,
+    /// counting the latest one and deletions, e.g. 10 with a zero `retention` to keep just
+    /// the last 10 versions. Defaults to none.
+    pub max_versions: Option<usize>,
+}
+
+impl Default for HistoryOptions {
+    fn default() -> Self {
+        return HistoryOptions {
+            retention: Duration::from_secs(60 * 60),
+            max_versions: None,
+        };
+    }
+}
+
+/// One version of a key, see `RCask::get_versions`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Revision {
+    /// When the version was written, if it was written with history.
+    pub written_at: Option<SystemTime>,
+    /// The value, or `None` if the key was deleted.
+    pub value: Option<Vec<u8>>,
+}
+
+impl RCask {
+    /// Returns the value `key` held at `at`, or `None` if it had none then, e.g. because it
+    /// was deleted, had expired or was not written yet. Versions older than the retention of
+    /// `HistoryOptions` may have been dropped by compaction and also read as `None`.
+    pub fn get_as_of(&mut self, key: &str, at: SystemTime) -> Result<Option<Vec<u8>>> {
+        let key = self.stored_key(key);
+        let key = key.as_ref();
+        let at = at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        let verify = self.verify_checksums;
+        let mut offset = self.store.offset(key);
+        while let Some(current) = offset {
+            let Some((_, attributes)) = self.store.get_value_bytes_at(key, current, verify)? else {
+                return Ok(None);
+            };
+            if attributes
+                .written_at
+                .is_some_and(|written_at| written_at > at)
+            {
+                offset = previous_version(key, current, &attributes)?;
+                continue;
+            }
+            if attributes.is_expired(at) || attributes.is_tombstone() {
+                return Ok(None);
+            }
+            reader::expect_value(key, &attributes)?;
+            let Some((plain, _)) =
+                reader::read_plain(&mut self.store, &mut self.values, key, current, verify)?
+            else {
+                return Ok(None);
+            };
+            return Ok(Some(self.values.untag_schema(plain)?));
+        }
+        return Ok(None);
+    }
+
+    /// Returns the versions of `key` that are still in the log, newest first, e.g. to undo a
+    /// bad write. Without `Builder::history` only the latest one is known.
+    pub fn get_versions(&mut self, key: &str) -> Result<Vec<Revision>> {
+        let key = self.stored_key(key);
+        let key = key.as_ref();
+        let verify = self.verify_checksums;
+        let mut versions = Vec::new();
+        let mut offset = self.store.offset(key);
+        while let Some(current) = offset {
+            let Some((_, attributes)) = self.store.get_value_bytes_at(key, current, verify)? else {
+                break;
+            };
+            if attributes.collection {
+                if versions.is_empty() {
+                    reader::expect_value(key, &attributes)?;
+                }
+                break;
+            }
+            let value = match attributes.is_tombstone() {
+                true => None,
+                false => {
+                    match reader::read_plain(
+                        &mut self.store,
+                        &mut self.values,
+                        key,
+                        current,
+                        verify,
+                    )? {
+                        Some((plain, _)) => Some(self.values.untag_schema(plain)?),
+                        None => None,
+                    }
+                }
+            };
+            versions.push(Revision {
+                written_at: attributes
+                    .written_at
+                    .map(|at| UNIX_EPOCH + Duration::from_millis(at)),
+                value,
+            });
+            offset = previous_version(key, current, &attributes)?;
+        }
+        return Ok(versions);
+    }
+
+    /// Restores the value `key` had before it was deleted and returns whether it did. It
+    /// does nothing unless the key's latest record is a deletion written with history within
+    /// the retention of `HistoryOptions`, and the value before it is still in the log and has
+    /// not expired. The value keeps its metadata and expiry.
+    pub fn undelete(&mut self, key: &str) -> Result<bool> {
+        let key = self.stored_key(key);
+        let key = key.as_ref();
+        let Some(options) = &self.history else {
+            return Ok(false);
+        };
+        let now = crate::now_millis();
+        let cutoff = now.saturating_sub(options.retention.as_millis() as u64);
+        let verify = self.verify_checksums;
+        let Some(latest) = self.store.offset(key) else {
+            return Ok(false);
+        };
+        let Some((_, deletion)) = self.store.get_value_bytes_at(key, latest, verify)? else {
+            return Ok(false);
+        };
+        let recent = deletion.written_at.is_some_and(|at| at > cutoff);
+        if !deletion.is_tombstone() || !recent {
+            return Ok(false);
+        }
+        let Some(previous) = previous_version(key, latest, &deletion)? else {
+            return Ok(false);
+        };
+        let Some((_, attributes)) = self.store.get_value_bytes_at(key, previous, verify)? else {
+            return Ok(false);
+        };
+        if attributes.collection || attributes.is_tombstone() || attributes.is_expired(now) {
+            return Ok(false);
+        }
+        let Some((plain, _)) =
+            reader::read_plain(&mut self.store, &mut self.values, key, previous, verify)?
+        else {
+            return Ok(false);
+        };
+        let value = self.values.untag_schema(plain)?;
+        let options = WriteOptions {
+            ttl: attributes
+                .expires_at
+                .map(|at| Duration::from_millis(at - now)),
+            meta: attributes.meta,
+            ..Default::default()
+        };
+        self.set_stored(key.as_bytes(), &value, &options)?;
+        return Ok(true);
+    }
+
+    /// Write time of a new record, if records carry one.
+    pub(crate) fn stamp(&self) -> Option<u64> {
+        let stamped = self.history.is_some() || self.retention.is_some();
+        return stamped.then(crate::now_millis);
+    }
+
+    /// Returns the offsets of the versions of `key` that compaction keeps besides its latest
+    /// value, newest first, or none if that value is not kept. For a deleted key they start
+    /// with its tombstone.
+    pub(crate) fn retained_versions(&mut self, key: &str, value_kept: bool) -> Result<Vec<u64>> {
+        let Some(options) = &self.history else {
+            return Ok(Vec::new());
+        };
+        let cutoff = crate::now_millis().saturating_sub(options.retention.as_millis() as u64);
+        let max_versions = options.max_versions.unwrap_or(0);
+        let verify = self.verify_checksums;
+        let mut versions = Vec::new();
+        let Some(latest) = self.store.offset(key) else {
+            return Ok(versions);
+        };
+        let Some((_, mut attributes)) = self.store.get_value_bytes_at(key, latest, verify)? else {
+            return Ok(versions);
+        };
+        if attributes.collection {
+            return Ok(versions);
+        }
+        if attributes.is_tombstone() {
+            if attributes
+                .written_at
+                .is_none_or(|written_at| written_at <= cutoff)
+                || self.outlived(&attributes)
+            {
+                return Ok(versions);
+            }
+            versions.push(latest);
+        } else if !value_kept || attributes.is_expired(crate::now_millis()) {
+            return Ok(versions);
+        }
+        let mut current = latest;
+        // The latest version counts, even where it is not among `versions`.
+        let mut newer = 1;
+        // A version is kept while the one that replaced it is recent, or while it has fewer
+        // than `max_versions` newer ones.
+        while newer < max_versions
+            || attributes
+                .written_at
+                .is_some_and(|written_at| written_at > cutoff)
+        {
+            let Some(previous) = previous_version(key, current, &attributes)? else {
+                break;
+            };
+            let Some((_, older)) = self.store.get_value_bytes_at(key, previous, verify)? else {
+                break;
+            };
+            if older.collection || self.outlived(&older) {
+                break;
+            }
+            versions.push(previous);
+            newer += 1;
+            current = previous;
+            attributes = older;
+        }
+        return Ok(versions);
+    }
+
+    /// Reads the versions of `key` that compaction keeps besides its latest value, oldest
+    /// first, skipping those `target` already holds from an earlier relocation. A tombstone
+    /// reads as `None`. `value_kept` tells whether compaction keeps the latest value.
+    pub(crate) fn relocate_history(
+        &mut self,
+        key: &str,
+        value_kept: bool,
+        target: &mut KVStore,
+    ) -> Result<Vec<(Option<Relocated>, Attributes)>> {
+        let verify = self.verify_checksums;
+        let relocated = match target.offset(key) {
+            Some(offset) => target
+                .get_value_bytes_at(key, offset, verify)?
+                .and_then(|(_, attributes)| attributes.written_at),
+            None => None,
+        };
+        let mut versions = Vec::new();
+        for offset in self.retained_versions(key, value_kept)?.into_iter().rev() {
+            let Some((_, attributes)) = self.store.get_value_bytes_at(key, offset, verify)? else {
+                continue;
+            };
+            if relocated.is_some() && attributes.written_at <= relocated {
+                continue;
+            }
+            if attributes.is_tombstone() {
+                versions.push((None, attributes));
+                continue;
+            }
+            let Some((framed, _)) =
+                reader::read_framed(&mut self.store, &mut self.values, key, offset, verify)?
+            else {
+                continue;
+            };
+            let record = if self.values.is_framed() && self.values.is_reference(&framed) {
+                Relocated::Reference(framed)
+            } else {
+                let (plain, _) = self.resolve(key, offset, framed, verify)?;
+                Relocated::Value(self.values.untag_schema(plain)?)
+            };
+            versions.push((Some(record), attributes));
+        }
+        return Ok(versions);
+    }
+
+    /// Writes the versions read by `relocate_history` to `store`.
+    pub(crate) fn write_history(
+        &mut self,
+        store: &mut KVStore,
+        key: &str,
+        versions: Vec<(Option<Relocated>, Attributes)>,
+    ) -> Result<()> {
+        for (record, attributes) in versions {
+            match record {
+                Some(record) => {
+                    let stored = self.encode_relocated(record)?;
+                    store.stage(key, stored, &attributes)?;
+                }
+                None => store.stage(key, [], &attributes)?,
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Returns the offset of the version before the record of `key` at `offset`.
+fn previous_version(key: &str, offset: u64, attributes: &Attributes) -> Result<Option<u64>> {
+    return match attributes.previous {
+        // Earlier records always come first, which also rules out cycles.
+        Some(previous) if previous >= offset => Err(Error::from(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Data corruption: broken version chain for key {}", key),
+        ))),
+        previous => Ok(previous),
+    };
+}
@@ -0,0 +1,126 @@
+//! Hot-key detection.
+//!
+//! Accesses are counted in a count-min sketch, which never undercounts a key and only
+//! overcounts by a small fraction of all accesses, in constant memory. Next to it, a small set
+//! of candidates remembers the keys with the highest estimates seen so far, from which
+//! `RCask::top_keys` reports.
+
+use crate::RCask;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+const DEPTH: usize = 4;
+const WIDTH: usize = 2048;
+
+/// The most accessed keys of a store since it was opened, with their estimated access counts,
+/// highest first. See `RCask::top_keys`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HotKeys {
+    pub reads: Vec<(String, u64)>,
+    pub writes: Vec<(String, u64)>,
+}
+
+/// Access counts of one kind of access.
+pub(crate) struct Sketch {
+    counters: Vec<u64>,
+    candidates: HashMap<String, u64>,
+    capacity: usize,
+}
+
+impl Sketch {
+    /// Tracks up to `capacity` candidate keys.
+    pub(crate) fn new(capacity: usize) -> Self {
+        return Sketch {
+            counters: vec![0; DEPTH * WIDTH],
+            candidates: HashMap::new(),
+            capacity,
+        };
+    }
+
+    pub(crate) fn record(&mut self, key: &str) {
+        let mut estimate = u64::MAX;
+        for row in 0..DEPTH {
+            let mut hasher = DefaultHasher::new();
+            row.hash(&mut hasher);
+            key.hash(&mut hasher);
+            let cell = row * WIDTH + (hasher.finish() % WIDTH as u64) as usize;
+            self.counters[cell] += 1;
+            estimate = estimate.min(self.counters[cell]);
+        }
+
+        if let Some(count) = self.candidates.get_mut(key) {
+            *count = estimate;
+            return;
+        }
+        if self.candidates.len() < self.capacity {
+            self.candidates.insert(key.to_string(), estimate);
+            return;
+        }
+        // Replace the coldest candidate if this key is now hotter.
+        let coldest = self
+            .candidates
+            .iter()
+            .min_by_key(|(_, &count)| count)
+            .map(|(key, &count)| (key.clone(), count));
+        if let Some((coldest, count)) = coldest {
+            if estimate > count {
+                self.candidates.remove(&coldest);
+                self.candidates.insert(key.to_string(), estimate);
+            }
+        }
+    }
+
+    /// Returns the `n` hottest candidates, highest first.
+    pub(crate) fn top(&self, n: usize) -> Vec<(String, u64)> {
+        let mut top: Vec<(String, u64)> = self
+            .candidates
+            .iter()
+            .map(|(key, &count)| (key.clone(), count))
+            .collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(n);
+        return top;
+    }
+}
+
+/// Read and write counts of a store.
+pub(crate) struct HotKeyTracker {
+    pub(crate) reads: Sketch,
+    pub(crate) writes: Sketch,
+}
+
+impl HotKeyTracker {
+    pub(crate) fn new(capacity: usize) -> Self {
+        return HotKeyTracker {
+            reads: Sketch::new(capacity),
+            writes: Sketch::new(capacity),
+        };
+    }
+}
+
+impl RCask {
+    /// Returns the `n` most read and most written keys since the store was opened, with their
+    /// estimated access counts. Requires `Builder::hot_keys`; empty otherwise.
+    pub fn top_keys(&self, n: usize) -> HotKeys {
+        let Some(tracker) = &self.hot_keys else {
+            return HotKeys::default();
+        };
+        return HotKeys {
+            reads: tracker.reads.top(n),
+            writes: tracker.writes.top(n),
+        };
+    }
+
+    pub(crate) fn record_read(&mut self, key: &str) {
+        if let Some(tracker) = &mut self.hot_keys {
+            tracker.reads.record(key);
+        }
+    }
+
+    pub(crate) fn record_write(&mut self, key: &str) {
+        if let Some(tracker) = &mut self.hot_keys {
+            tracker.writes.record(key);
+        }
+    }
+}
@@ -0,0 +1,9 @@
+//! Importers that migrate data from other key-value stores into an RCask store.
+
+pub mod rocksdb;
+
+#[cfg(feature = "rdb")]
+pub mod rdb;
+
+#[cfg(feature = "sled")]
+pub mod sled;
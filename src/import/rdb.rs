@@ -0,0 +1,575 @@
+//! Imports a Redis RDB snapshot (`dump.rdb`) into an RCask store.
+//!
+//! String keys are imported as-is. Hashes can optionally be flattened into one key per field,
+//! named `<key><separator><field>`. Other value types (lists, sets, sorted sets) are skipped;
+//! streams and module types cannot be skipped safely and abort the import.
+//!
+//! Keys with a TTL keep their remaining time to live. Keys whose TTL has already passed at
+//! import time are skipped.
+
+use crate::{RCask, Result, WriteOptions};
+use std::io::{self, Read};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Opcodes that may appear in place of a value type.
+const OPCODE_SLOT_INFO: u8 = 0xF4;
+const OPCODE_FUNCTION2: u8 = 0xF5;
+const OPCODE_MODULE_AUX: u8 = 0xF7;
+const OPCODE_IDLE: u8 = 0xF8;
+const OPCODE_FREQ: u8 = 0xF9;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+// Value types.
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_HASH_ZIPMAP: u8 = 9;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_SET_LISTPACK: u8 = 20;
+
+// Special string encodings (the low bits of a length byte tagged `11`).
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+/// Controls which parts of an RDB file are imported.
+#[derive(Debug, Clone, Default)]
+pub struct RdbOptions {
+    /// When set, every hash field is imported as `<key><separator><field>`.
+    /// Hashes are skipped when `None`.
+    pub hash_separator: Option<String>,
+    /// Only import keys from this logical database. All databases are imported when `None`.
+    pub database: Option<u64>,
+}
+
+/// Summary of an RDB import.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RdbImport {
+    /// Number of records written to the store.
+    pub imported: u64,
+    /// Keys skipped because their TTL had already passed.
+    pub expired: u64,
+    /// Keys skipped because their type is not imported (or filtered out by `database`).
+    pub skipped: u64,
+}
+
+/// Parses an RDB snapshot and writes its string keys (and optionally flattened hashes) into the store.
+pub fn import_rdb<R: Read>(
+    reader: R,
+    store: &mut RCask,
+    options: &RdbOptions,
+) -> Result<RdbImport> {
+    let mut parser = Parser { reader };
+    parser.read_header()?;
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+
+    let mut summary = RdbImport::default();
+    let mut database = 0;
+    let mut expires_at_ms: Option<u64> = None;
+
+    loop {
+        let opcode = parser.read_u8()?;
+        match opcode {
+            OPCODE_EOF => break,
+            OPCODE_SELECTDB => database = parser.read_length()?,
+            OPCODE_RESIZEDB => {
+                parser.read_length()?;
+                parser.read_length()?;
+            }
+            OPCODE_AUX => {
+                parser.read_string()?;
+                parser.read_string()?;
+            }
+            OPCODE_EXPIRETIME_MS => expires_at_ms = Some(parser.read_u64_le()?),
+            OPCODE_EXPIRETIME => expires_at_ms = Some(parser.read_u32_le()? as u64 * 1000),
+            OPCODE_FREQ => {
+                parser.read_u8()?;
+            }
+            OPCODE_IDLE => {
+                parser.read_length()?;
+            }
+            OPCODE_SLOT_INFO => {
+                parser.read_length()?;
+                parser.read_length()?;
+                parser.read_length()?;
+            }
+            OPCODE_FUNCTION2 => {
+                parser.read_string()?;
+            }
+            OPCODE_MODULE_AUX => return Err(unsupported("module auxiliary data").into()),
+            value_type => {
+                let key = parser.read_string()?;
+                let value = parser.read_value(value_type)?;
+                let expires_at_ms = expires_at_ms.take();
+                if expires_at_ms.is_some_and(|at| at <= now_ms) {
+                    summary.expired += 1;
+                    continue;
+                }
+                if options.database.is_some_and(|db| db != database) {
+                    summary.skipped += 1;
+                    continue;
+                }
+
+                let write = WriteOptions {
+                    ttl: expires_at_ms.map(|at| Duration::from_millis(at - now_ms)),
+                    ..Default::default()
+                };
+                match (value, &options.hash_separator) {
+                    (Value::String(value), _) => {
+                        store.set_opt(&key, value, &write)?;
+                        summary.imported += 1;
+                    }
+                    (Value::Hash(fields), Some(separator)) => {
+                        for (field, value) in fields {
+                            let mut flat_key = key.clone();
+                            flat_key.extend_from_slice(separator.as_bytes());
+                            flat_key.extend_from_slice(&field);
+                            store.set_opt(flat_key, value, &write)?;
+                            summary.imported += 1;
+                        }
+                    }
+                    _ => summary.skipped += 1,
+                }
+            }
+        }
+    }
+
+    return Ok(summary);
+}
+
+/// A decoded RDB value. Types that are not imported are parsed only to be skipped.
+enum Value {
+    String(Vec<u8>),
+    Hash(Vec<(Vec<u8>, Vec<u8>)>),
+    Skipped,
+}
+
+/// A single element of a ziplist or listpack, which may be stored as an integer.
+enum Element {
+    Bytes(Vec<u8>),
+    Int(i64),
+}
+
+impl Element {
+    fn into_bytes(self) -> Vec<u8> {
+        return match self {
+            Element::Bytes(bytes) => bytes,
+            Element::Int(int) => int.to_string().into_bytes(),
+        };
+    }
+}
+
+/// Either a real length or one of the special string encodings.
+enum Length {
+    Len(u64),
+    Encoded(u8),
+}
+
+struct Parser<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> Parser<R> {
+    /// Validates the `REDIS` magic and the four digit format version.
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut header = [0; 9];
+        self.reader.read_exact(&mut header)?;
+        if &header[..5] != b"REDIS" || !header[5..].iter().all(u8::is_ascii_digit) {
+            return Err(invalid("Not an RDB file: bad magic"));
+        }
+        return Ok(());
+    }
+
+    fn read_exact_vec(&mut self, len: u64) -> io::Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        let read = (&mut self.reader).take(len).read_to_end(&mut buffer)?;
+        if read as u64 != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Truncated RDB file",
+            ));
+        }
+        return Ok(buffer);
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        let mut byte = [0; 1];
+        self.reader.read_exact(&mut byte)?;
+        return Ok(byte[0]);
+    }
+
+    fn read_u32_le(&mut self) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        self.reader.read_exact(&mut bytes)?;
+        return Ok(u32::from_le_bytes(bytes));
+    }
+
+    fn read_u64_le(&mut self) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        self.reader.read_exact(&mut bytes)?;
+        return Ok(u64::from_le_bytes(bytes));
+    }
+
+    /// Reads the variable length encoding used for lengths and string headers.
+    fn read_length_or_encoding(&mut self) -> io::Result<Length> {
+        let first = self.read_u8()?;
+        return match first >> 6 {
+            0b00 => Ok(Length::Len((first & 0x3F) as u64)),
+            0b01 => {
+                let second = self.read_u8()?;
+                Ok(Length::Len((((first & 0x3F) as u64) << 8) | second as u64))
+            }
+            0b10 if first == 0x80 => {
+                let mut bytes = [0; 4];
+                self.reader.read_exact(&mut bytes)?;
+                Ok(Length::Len(u32::from_be_bytes(bytes) as u64))
+            }
+            0b10 if first == 0x81 => {
+                let mut bytes = [0; 8];
+                self.reader.read_exact(&mut bytes)?;
+                Ok(Length::Len(u64::from_be_bytes(bytes)))
+            }
+            0b11 => Ok(Length::Encoded(first & 0x3F)),
+            _ => Err(invalid("Unknown length encoding")),
+        };
+    }
+
+    fn read_length(&mut self) -> io::Result<u64> {
+        return match self.read_length_or_encoding()? {
+            Length::Len(len) => Ok(len),
+            Length::Encoded(_) => Err(invalid("Expected a length, found a string encoding")),
+        };
+    }
+
+    /// Reads a string, expanding integer and LZF encoded representations.
+    fn read_string(&mut self) -> io::Result<Vec<u8>> {
+        return match self.read_length_or_encoding()? {
+            Length::Len(len) => self.read_exact_vec(len),
+            Length::Encoded(ENC_INT8) => Ok((self.read_u8()? as i8).to_string().into_bytes()),
+            Length::Encoded(ENC_INT16) => {
+                let mut bytes = [0; 2];
+                self.reader.read_exact(&mut bytes)?;
+                Ok(i16::from_le_bytes(bytes).to_string().into_bytes())
+            }
+            Length::Encoded(ENC_INT32) => Ok((self.read_u32_le()? as i32).to_string().into_bytes()),
+            Length::Encoded(ENC_LZF) => {
+                let compressed_len = self.read_length()?;
+                let len = self.read_length()?;
+                let compressed = self.read_exact_vec(compressed_len)?;
+                lzf_decompress(&compressed, len as usize)
+            }
+            Length::Encoded(_) => Err(invalid("Unknown string encoding")),
+        };
+    }
+
+    /// Reads the legacy ASCII encoded double used by `TYPE_ZSET`.
+    fn skip_ascii_double(&mut self) -> io::Result<()> {
+        let len = self.read_u8()?;
+        // 253, 254 and 255 encode NaN, +inf and -inf without a payload.
+        if len < 253 {
+            self.read_exact_vec(len as u64)?;
+        }
+        return Ok(());
+    }
+
+    fn read_value(&mut self, value_type: u8) -> io::Result<Value> {
+        return match value_type {
+            TYPE_STRING => Ok(Value::String(self.read_string()?)),
+            TYPE_LIST | TYPE_SET => {
+                for _ in 0..self.read_length()? {
+                    self.read_string()?;
+                }
+                Ok(Value::Skipped)
+            }
+            TYPE_ZSET => {
+                for _ in 0..self.read_length()? {
+                    self.read_string()?;
+                    self.skip_ascii_double()?;
+                }
+                Ok(Value::Skipped)
+            }
+            TYPE_ZSET_2 => {
+                for _ in 0..self.read_length()? {
+                    self.read_string()?;
+                    self.read_exact_vec(8)?;
+                }
+                Ok(Value::Skipped)
+            }
+            TYPE_HASH => {
+                let len = self.read_length()?;
+                let mut fields = Vec::new();
+                for _ in 0..len {
+                    let field = self.read_string()?;
+                    let value = self.read_string()?;
+                    fields.push((field, value));
+                }
+                Ok(Value::Hash(fields))
+            }
+            TYPE_HASH_ZIPMAP => Ok(Value::Hash(parse_zipmap(&self.read_string()?)?)),
+            TYPE_HASH_ZIPLIST => Ok(Value::Hash(pairs(parse_ziplist(&self.read_string()?)?)?)),
+            TYPE_HASH_LISTPACK => Ok(Value::Hash(pairs(parse_listpack(&self.read_string()?)?)?)),
+            TYPE_LIST_ZIPLIST | TYPE_SET_INTSET | TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK
+            | TYPE_SET_LISTPACK => {
+                self.read_string()?;
+                Ok(Value::Skipped)
+            }
+            TYPE_LIST_QUICKLIST => {
+                for _ in 0..self.read_length()? {
+                    self.read_string()?;
+                }
+                Ok(Value::Skipped)
+            }
+            TYPE_LIST_QUICKLIST_2 => {
+                for _ in 0..self.read_length()? {
+                    // Container kind (plain or packed) followed by the node payload.
+                    self.read_length()?;
+                    self.read_string()?;
+                }
+                Ok(Value::Skipped)
+            }
+            other => Err(unsupported(&format!("value type {}", other))),
+        };
+    }
+}
+
+/// Groups a flat `field, value, field, value, ...` sequence into pairs.
+fn pairs(elements: Vec<Element>) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    if !elements.len().is_multiple_of(2) {
+        return Err(invalid("Hash encoding has an odd number of elements"));
+    }
+    let mut fields = Vec::with_capacity(elements.len() / 2);
+    let mut elements = elements.into_iter();
+    while let (Some(field), Some(value)) = (elements.next(), elements.next()) {
+        fields.push((field.into_bytes(), value.into_bytes()));
+    }
+    return Ok(fields);
+}
+
+/// Bounds checked cursor over an in-memory encoded blob.
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.pos + len > self.bytes.len() {
+            return Err(invalid("Truncated encoded value"));
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        return Ok(slice);
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    fn peek(&self) -> io::Result<u8> {
+        return self
+            .bytes
+            .get(self.pos)
+            .copied()
+            .ok_or_else(|| invalid("Truncated encoded value"));
+    }
+
+    fn int_le(&mut self, len: usize) -> io::Result<i64> {
+        let bytes = self.take(len)?;
+        let mut buffer = [0; 8];
+        buffer[..len].copy_from_slice(bytes);
+        // Sign extend from the encoded width.
+        let shift = 64 - 8 * len as u32;
+        return Ok((i64::from_le_bytes(buffer) << shift) >> shift);
+    }
+}
+
+/// Parses the zipmap encoding used by very old hash values.
+fn parse_zipmap(bytes: &[u8]) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let read_len = |cursor: &mut Cursor| -> io::Result<usize> {
+        let len = cursor.byte()?;
+        return match len {
+            0..=253 => Ok(len as usize),
+            254 => Ok(u32::from_le_bytes(cursor.take(4)?.try_into().unwrap_or_default()) as usize),
+            _ => Err(invalid("Unexpected end of zipmap")),
+        };
+    };
+
+    // The leading byte is an element count hint that is not needed to parse the entries.
+    let mut cursor = Cursor { bytes, pos: 1 };
+
+    let mut fields = Vec::new();
+    while cursor.peek()? != 0xFF {
+        let field_len = read_len(&mut cursor)?;
+        let field = cursor.take(field_len)?.to_vec();
+        let value_len = read_len(&mut cursor)?;
+        let free = cursor.byte()? as usize;
+        let value = cursor.take(value_len)?.to_vec();
+        cursor.take(free)?;
+        fields.push((field, value));
+    }
+    return Ok(fields);
+}
+
+/// Parses a ziplist into its elements.
+fn parse_ziplist(bytes: &[u8]) -> io::Result<Vec<Element>> {
+    // zlbytes (u32), zltail (u32) and zllen (u16) precede the entries.
+    let mut cursor = Cursor { bytes, pos: 10 };
+    let mut elements = Vec::new();
+    while cursor.peek()? != 0xFF {
+        // Length of the previous entry, only needed for reverse traversal.
+        if cursor.byte()? == 0xFE {
+            cursor.take(4)?;
+        }
+
+        let encoding = cursor.byte()?;
+        let element = match encoding >> 6 {
+            0b00 => Element::Bytes(cursor.take((encoding & 0x3F) as usize)?.to_vec()),
+            0b01 => {
+                let len = (((encoding & 0x3F) as usize) << 8) | cursor.byte()? as usize;
+                Element::Bytes(cursor.take(len)?.to_vec())
+            }
+            0b10 => {
+                let len = u32::from_be_bytes(cursor.take(4)?.try_into().unwrap_or_default());
+                Element::Bytes(cursor.take(len as usize)?.to_vec())
+            }
+            _ => match encoding {
+                0xC0 => Element::Int(cursor.int_le(2)?),
+                0xD0 => Element::Int(cursor.int_le(4)?),
+                0xE0 => Element::Int(cursor.int_le(8)?),
+                0xF0 => Element::Int(cursor.int_le(3)?),
+                0xFE => Element::Int(cursor.int_le(1)?),
+                0xF1..=0xFD => Element::Int((encoding & 0x0F) as i64 - 1),
+                _ => return Err(invalid("Unknown ziplist entry encoding")),
+            },
+        };
+        elements.push(element);
+    }
+    return Ok(elements);
+}
+
+/// Parses a listpack into its elements.
+fn parse_listpack(bytes: &[u8]) -> io::Result<Vec<Element>> {
+    // Total bytes (u32) and element count (u16) precede the entries.
+    let mut cursor = Cursor { bytes, pos: 6 };
+    let mut elements = Vec::new();
+    while cursor.peek()? != 0xFF {
+        let start = cursor.pos;
+        let encoding = cursor.byte()?;
+        let element = if encoding & 0x80 == 0 {
+            Element::Int((encoding & 0x7F) as i64)
+        } else if encoding & 0xC0 == 0x80 {
+            Element::Bytes(cursor.take((encoding & 0x3F) as usize)?.to_vec())
+        } else if encoding & 0xE0 == 0xC0 {
+            let raw = (((encoding & 0x1F) as i64) << 8) | cursor.byte()? as i64;
+            // 13 bit two's complement.
+            Element::Int((raw << 51) >> 51)
+        } else if encoding & 0xF0 == 0xE0 {
+            let len = (((encoding & 0x0F) as usize) << 8) | cursor.byte()? as usize;
+            Element::Bytes(cursor.take(len)?.to_vec())
+        } else {
+            match encoding {
+                0xF0 => {
+                    let len = u32::from_le_bytes(cursor.take(4)?.try_into().unwrap_or_default());
+                    Element::Bytes(cursor.take(len as usize)?.to_vec())
+                }
+                0xF1 => Element::Int(cursor.int_le(2)?),
+                0xF2 => Element::Int(cursor.int_le(3)?),
+                0xF3 => Element::Int(cursor.int_le(4)?),
+                0xF4 => Element::Int(cursor.int_le(8)?),
+                _ => return Err(invalid("Unknown listpack entry encoding")),
+            }
+        };
+
+        // Skip the back-length, which grows with the size of the entry.
+        let entry_len = cursor.pos - start;
+        let backlen_size = match entry_len {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        cursor.take(backlen_size)?;
+        elements.push(element);
+    }
+    return Ok(elements);
+}
+
+/// Decompresses an LZF block into a buffer of exactly `len` bytes.
+fn lzf_decompress(input: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(len);
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            // Literal run of ctrl + 1 bytes.
+            let run = ctrl + 1;
+            if i + run > input.len() {
+                return Err(invalid("Truncated LZF literal"));
+            }
+            output.extend_from_slice(&input[i..i + run]);
+            i += run;
+        } else {
+            // Back reference.
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input
+                    .get(i)
+                    .ok_or_else(|| invalid("Truncated LZF reference"))?
+                    as usize;
+                i += 1;
+            }
+            run += 2;
+            let low = *input
+                .get(i)
+                .ok_or_else(|| invalid("Truncated LZF reference"))? as usize;
+            i += 1;
+            let distance = ((ctrl & 0x1F) << 8) + low + 1;
+            if distance > output.len() {
+                return Err(invalid("Invalid LZF back reference"));
+            }
+            let start = output.len() - distance;
+            // References may overlap the bytes being produced, so copy one at a time.
+            for j in 0..run {
+                output.push(output[start + j]);
+            }
+        }
+    }
+
+    if output.len() != len {
+        return Err(invalid("LZF length mismatch"));
+    }
+    return Ok(output);
+}
+
+fn invalid(message: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+}
+
+fn unsupported(what: &str) -> io::Error {
+    return io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("RDB import does not support {}", what),
+    );
+}
@@ -0,0 +1,86 @@
+//! Imports RocksDB/LevelDB data into an RCask store.
+//!
+//! Two sources are supported:
+//! * The text output of `ldb dump` (optionally with `--hex`), where every record is
+//!   printed as `key ==> value`. Hex encoded fields are prefixed with `0x`.
+//! * Any iterator of key/value pairs, such as the one returned by the `rust-rocksdb`
+//!   crate's `DB::iterator`.
+//!
+//! Keys must be UTF-8: the import stops with `Error::InvalidKey` at the first key that is
+//! not, keeping the records before it.
+
+use crate::{RCask, Result};
+use std::io::{self, BufRead};
+
+/// Separator printed by `ldb dump` between a key and its value.
+const SEPARATOR: &str = " ==> ";
+
+/// Reads an `ldb dump` listing and writes every record into the store.
+/// Lines that are not records (e.g. the trailing "Keys in range" summary) are skipped.
+/// Returns the number of records imported.
+pub fn import_dump<R: BufRead>(reader: R, store: &mut RCask) -> Result<u64> {
+    // Stop at the first unreadable line and report it once the loaded prefix is indexed.
+    let mut error = None;
+    let records = reader
+        .lines()
+        .map_while(|line| match line.and_then(|line| parse_line(&line)) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                error = Some(e);
+                None
+            }
+        })
+        .flatten();
+
+    let imported = store.bulk_load(records)?;
+    return match error {
+        Some(e) => Err(e.into()),
+        None => Ok(imported),
+    };
+}
+
+/// Writes every key/value pair produced by the iterator into the store.
+/// Returns the number of records imported.
+pub fn import_iter<I, K, V>(iter: I, store: &mut RCask) -> Result<u64>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<[u8]>,
+    V: AsRef<[u8]>,
+{
+    return store.bulk_load(iter);
+}
+
+/// Parses a single `ldb dump` line into its key and value bytes.
+/// Returns `None` for lines that do not contain a record.
+fn parse_line(line: &str) -> io::Result<Option<(Vec<u8>, Vec<u8>)>> {
+    // Plain (non hex) dumps cannot escape the separator, so the first occurrence wins.
+    let Some((key, value)) = line.split_once(SEPARATOR) else {
+        return Ok(None);
+    };
+    return Ok(Some((decode_field(key)?, decode_field(value)?)));
+}
+
+/// Decodes a dumped field, which is either `0x` prefixed hex or raw text.
+fn decode_field(field: &str) -> io::Result<Vec<u8>> {
+    let Some(hex) = field.strip_prefix("0x") else {
+        return Ok(field.as_bytes().to_vec());
+    };
+
+    if hex.len() % 2 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Odd length hex field: {}", field),
+        ));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            // A pair that splits a multi-byte character is no hex and fails to parse.
+            let digits = hex.get(i..i + 2).unwrap_or_default();
+            u8::from_str_radix(digits, 16).map_err(|err| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", field, err))
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,41 @@
+//! Migrates the contents of a sled database into a new RCask store.
+
+use crate::{RCask, Result};
+use std::io;
+use std::path::Path;
+
+/// Opens the sled database at `sled_path` and copies every record of its default tree
+/// into the RCask store at `directory`, using `pattern` for the log file names.
+/// The sled database is only read. Returns the number of records migrated.
+pub fn migrate_from_sled<P: AsRef<Path>>(
+    sled_path: P,
+    directory: String,
+    pattern: String,
+) -> Result<u64> {
+    let db = sled::open(sled_path).map_err(to_io)?;
+    let mut store = RCask::new(directory, pattern)?;
+
+    // Stop at the first failed read and report it once the copied prefix is indexed.
+    let mut error = None;
+    let records = db.iter().map_while(|entry| match entry {
+        Ok(record) => Some(record),
+        Err(e) => {
+            error = Some(to_io(e));
+            None
+        }
+    });
+
+    let migrated = store.bulk_load(records)?;
+    return match error {
+        Some(e) => Err(e.into()),
+        None => Ok(migrated),
+    };
+}
+
+fn to_io(err: sled::Error) -> io::Error {
+    // sled already wraps io errors, so unwrap those instead of nesting them.
+    return match err {
+        sled::Error::Io(err) => err,
+        other => io::Error::other(other),
+    };
+}
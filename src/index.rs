@@ -0,0 +1,258 @@
+//! The in-memory index from keys to the offsets of their latest records.
+//!
+//! By default the index is a hash table holding every key as its own `String`. With
+//! `Builder::prefix_compressed_index` it is a radix tree instead: keys sharing a prefix share
+//! the nodes that spell it, so a keyspace of URLs or file paths stores each common prefix
+//! once rather than once per key. Lookups then walk the key's bytes instead of hashing it,
+//! which is slower for short keys, and keys come out sorted, which lets prefix scans visit
+//! only the keys under the prefix.
+
+use std::collections::HashMap;
+use std::mem;
+
+/// Offset of a radix tree node that only joins its children.
+const NO_OFFSET: u64 = u64::MAX;
+
+pub(crate) enum KeyIndex {
+    Hashed(HashMap<String, u64>),
+    Radix(Node),
+}
+
+/// A node of the radix tree, holding the bytes of the key between its parent and itself.
+pub(crate) struct Node {
+    label: Box<[u8]>,
+    offset: u64,
+    /// Sorted by the first byte of their labels, which differ.
+    children: Vec<Node>,
+}
+
+impl KeyIndex {
+    pub(crate) fn new() -> Self {
+        return KeyIndex::Hashed(HashMap::new());
+    }
+
+    /// Switches to the radix tree, moving every key over.
+    pub(crate) fn compress(&mut self) {
+        let KeyIndex::Hashed(map) = self else {
+            return;
+        };
+        let mut root = Node::new(&[], NO_OFFSET);
+        for (key, offset) in mem::take(map) {
+            root.insert(key.as_bytes(), offset);
+        }
+        *self = KeyIndex::Radix(root);
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<u64> {
+        return match self {
+            KeyIndex::Hashed(map) => map.get(key).copied(),
+            KeyIndex::Radix(root) => root.get(key.as_bytes()),
+        };
+    }
+
+    /// Points `key` at `offset` and returns the offset it had before, if any.
+    pub(crate) fn insert(&mut self, key: String, offset: u64) -> Option<u64> {
+        return match self {
+            KeyIndex::Hashed(map) => map.insert(key, offset),
+            KeyIndex::Radix(root) => root.insert(key.as_bytes(), offset),
+        };
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) -> Option<u64> {
+        return match self {
+            KeyIndex::Hashed(map) => map.remove(key),
+            KeyIndex::Radix(root) if key.is_empty() => root.take_offset(),
+            KeyIndex::Radix(root) => root.remove(key.as_bytes()),
+        };
+    }
+
+    pub(crate) fn extend<I: IntoIterator<Item = (String, u64)>>(&mut self, entries: I) {
+        for (key, offset) in entries {
+            self.insert(key, offset);
+        }
+    }
+
+    pub(crate) fn keys(&self) -> Vec<String> {
+        return self.keys_with_prefix("");
+    }
+
+    /// Returns every key that starts with `prefix`, sorted with the radix tree.
+    pub(crate) fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let mut keys = Vec::new();
+        match self {
+            KeyIndex::Hashed(map) => {
+                keys.extend(map.keys().filter(|key| key.starts_with(prefix)).cloned())
+            }
+            KeyIndex::Radix(root) => {
+                if let Some((node, mut path)) = root.find_prefix(prefix.as_bytes()) {
+                    node.collect(&mut path, &mut keys);
+                }
+            }
+        }
+        return keys;
+    }
+
+    /// Estimates the heap memory the index takes up.
+    pub(crate) fn memory_bytes(&self) -> u64 {
+        return match self {
+            KeyIndex::Hashed(map) => {
+                let keys: usize = map.keys().map(|key| key.capacity()).sum();
+                // The table keeps at most 7/8 of its power-of-two buckets full, and one
+                // control byte per bucket plus a group's worth more.
+                let buckets = match map.capacity() {
+                    0 => 0,
+                    capacity => (capacity * 8 / 7).next_power_of_two(),
+                };
+                let table = match buckets {
+                    0 => 0,
+                    buckets => buckets * (mem::size_of::<(String, u64)>() + 1) + 16,
+                };
+                (keys + table) as u64
+            }
+            KeyIndex::Radix(root) => root.memory_bytes() as u64,
+        };
+    }
+}
+
+impl Node {
+    fn new(label: &[u8], offset: u64) -> Self {
+        return Node {
+            label: label.into(),
+            offset,
+            children: Vec::new(),
+        };
+    }
+
+    fn child(&self, first: u8) -> Result<usize, usize> {
+        return self
+            .children
+            .binary_search_by_key(&first, |child| child.label[0]);
+    }
+
+    fn take_offset(&mut self) -> Option<u64> {
+        let offset = mem::replace(&mut self.offset, NO_OFFSET);
+        return (offset != NO_OFFSET).then_some(offset);
+    }
+
+    fn get(&self, key: &[u8]) -> Option<u64> {
+        let mut node = self;
+        let mut rest = key;
+        while let Some(&first) = rest.first() {
+            node = &node.children[node.child(first).ok()?];
+            rest = rest.strip_prefix(&*node.label)?;
+        }
+        return (node.offset != NO_OFFSET).then_some(node.offset);
+    }
+
+    fn insert(&mut self, key: &[u8], offset: u64) -> Option<u64> {
+        let mut node = self;
+        let mut rest = key;
+        while let Some(&first) = rest.first() {
+            let at = match node.child(first) {
+                Ok(at) => at,
+                Err(at) => {
+                    // Nodes have few children, which are better not over-allocated.
+                    node.children.reserve_exact(1);
+                    node.children.insert(at, Node::new(rest, offset));
+                    return None;
+                }
+            };
+            let child = &mut node.children[at];
+            let common = child
+                .label
+                .iter()
+                .zip(rest)
+                .take_while(|(a, b)| a == b)
+                .count();
+            if common < child.label.len() {
+                child.split(common);
+            }
+            rest = &rest[common..];
+            node = child;
+        }
+        let previous = mem::replace(&mut node.offset, offset);
+        return (previous != NO_OFFSET).then_some(previous);
+    }
+
+    /// Makes the first `at` bytes of the label a node of their own, with the rest below it.
+    fn split(&mut self, at: usize) {
+        let tail = Node {
+            label: self.label[at..].into(),
+            offset: self.offset,
+            children: mem::take(&mut self.children),
+        };
+        self.label = self.label[..at].into();
+        self.offset = NO_OFFSET;
+        self.children = vec![tail];
+    }
+
+    /// Removes the non-empty `key` below this node, and then any node that no longer holds
+    /// a key or joins several children.
+    fn remove(&mut self, key: &[u8]) -> Option<u64> {
+        let at = self.child(key[0]).ok()?;
+        let child = &mut self.children[at];
+        let rest = key.strip_prefix(&*child.label)?;
+        let removed = match rest.is_empty() {
+            true => child.take_offset(),
+            false => child.remove(rest),
+        };
+        if child.offset == NO_OFFSET {
+            match child.children.len() {
+                0 => {
+                    self.children.remove(at);
+                }
+                1 => child.merge(),
+                _ => {}
+            }
+        }
+        return removed;
+    }
+
+    /// Joins this node with its only child.
+    fn merge(&mut self) {
+        let Some(only) = self.children.pop() else {
+            return;
+        };
+        let mut label = mem::take(&mut self.label).into_vec();
+        label.extend_from_slice(&only.label);
+        self.label = label.into_boxed_slice();
+        self.offset = only.offset;
+        self.children = only.children;
+    }
+
+    /// Returns the node holding every key that starts with `prefix`, and the bytes of the
+    /// keys up to and including its label.
+    fn find_prefix(&self, prefix: &[u8]) -> Option<(&Node, Vec<u8>)> {
+        let mut node = self;
+        let mut path = Vec::new();
+        let mut rest = prefix;
+        while let Some(&first) = rest.first() {
+            node = &node.children[node.child(first).ok()?];
+            path.extend_from_slice(&node.label);
+            if node.label.len() >= rest.len() {
+                return node.label.starts_with(rest).then_some((node, path));
+            }
+            rest = rest.strip_prefix(&*node.label)?;
+        }
+        return Some((node, path));
+    }
+
+    /// Adds the keys of this node and every node below it, in order, where `path` spells
+    /// this node's key.
+    fn collect(&self, path: &mut Vec<u8>, keys: &mut Vec<String>) {
+        if self.offset != NO_OFFSET {
+            keys.push(String::from_utf8_lossy(path).into_owned());
+        }
+        for child in &self.children {
+            path.extend_from_slice(&child.label);
+            child.collect(path, keys);
+            path.truncate(path.len() - child.label.len());
+        }
+    }
+
+    fn memory_bytes(&self) -> usize {
+        let children: usize = self.children.iter().map(Node::memory_bytes).sum();
+        let slots = self.children.capacity() * mem::size_of::<Node>();
+        return self.label.len() + slots + children;
+    }
+}
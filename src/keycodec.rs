@@ -0,0 +1,144 @@
+//! Key codecs: transforming keys between the API and the log.
+//!
+//! A `KeyCodec` set with `Builder::key_codec` encodes every key before it reaches the index,
+//! e.g. to hash long keys, add a tenant prefix or fold case, and decodes stored keys where the
+//! store hands them back. Keys of the methods of `RCask`, `Reader` and what is built on them
+//! are encoded, prefixes included, so codecs that keep prefixes intact suit prefix scans.
+//! Everything below the API sees stored keys: the read cache, pins, quotas, observers, hot
+//! keys, compaction filters and exports. `get_all_key_values` and entry streams decode them.
+//!
+//! Keys written with one codec are not found with another, so the codec's id is recorded in
+//! `<directory>/<pattern>.keycodec` when a store is first opened with it, and opening fails if
+//! the codec differs from the recorded one, including a store that was written without a
+//! codec. Archives, backups and restored copies carry the record along; a follower has to be
+//! opened with its primary's codec.
+
+use crate::{setting, Result};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+/// First line of a key codec record.
+const RECORD_HEADER: &str = "rcask-key-codec 1";
+
+/// Transforms keys on the way into and out of a store, see `Builder::key_codec`.
+pub trait KeyCodec: Send + Sync {
+    /// Identifies the codec and its settings, e.g. `tenant-prefix:acme/`. A store only opens
+    /// with a codec of the id it was written with.
+    fn id(&self) -> String;
+
+    /// Returns the key stored for `key`.
+    fn encode<'a>(&self, key: &'a str) -> Cow<'a, str>;
+
+    /// Returns the key `stored` was encoded from, or `stored` itself where it cannot be
+    /// recovered, e.g. a hashed key. Encoding the decoded key must give `stored` back, as
+    /// scans hand decoded keys to methods that encode them again.
+    fn decode<'a>(&self, stored: &'a str) -> Cow<'a, str> {
+        return Cow::Borrowed(stored);
+    }
+}
+
+/// Folds keys to lower case, so keys that differ only in case are the same key. Stored keys
+/// decode to their lower-case form.
+pub struct CaseFold;
+
+impl KeyCodec for CaseFold {
+    fn id(&self) -> String {
+        return "case-fold".to_string();
+    }
+
+    fn encode<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        if key.chars().any(char::is_uppercase) {
+            return Cow::Owned(key.to_lowercase());
+        }
+        return Cow::Borrowed(key);
+    }
+}
+
+/// Puts every key under a tenant's prefix, e.g. to share one store between tenants that each
+/// see only their own keys.
+pub struct TenantPrefix(pub String);
+
+impl KeyCodec for TenantPrefix {
+    fn id(&self) -> String {
+        return format!("tenant-prefix:{}", self.0);
+    }
+
+    fn encode<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        return Cow::Owned(format!("{}{}", self.0, key));
+    }
+
+    fn decode<'a>(&self, stored: &'a str) -> Cow<'a, str> {
+        return Cow::Borrowed(stored.strip_prefix(self.0.as_str()).unwrap_or(stored));
+    }
+}
+
+/// Replaces keys longer than `max_len` bytes by their start and a 64-bit hash of the whole
+/// key, which keeps the index small for stores with very long keys. Two long keys that share
+/// their start and hash alike are the same key, and hashed keys cannot be decoded.
+pub struct HashLongKeys {
+    /// Length of the longest key kept as it is, at least 24.
+    pub max_len: usize,
+}
+
+impl KeyCodec for HashLongKeys {
+    fn id(&self) -> String {
+        return format!("hash-long-keys:{}", self.max_len);
+    }
+
+    fn encode<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        if key.len() <= self.max_len {
+            return Cow::Borrowed(key);
+        }
+        // The hashed key is at most `max_len` long too, so encoding it again keeps it.
+        let mut start = self.max_len.max(24) - 17;
+        while !key.is_char_boundary(start) {
+            start -= 1;
+        }
+        return Cow::Owned(format!("{}#{:016x}", &key[..start], fnv1a(key.as_bytes())));
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
+
+/// A key of a bulk load, as given or encoded.
+pub(crate) enum CodedKey<T> {
+    Plain(T),
+    Encoded(String),
+}
+
+impl<T: AsRef<[u8]>> AsRef<[u8]> for CodedKey<T> {
+    fn as_ref(&self) -> &[u8] {
+        return match self {
+            CodedKey::Plain(key) => key.as_ref(),
+            CodedKey::Encoded(key) => key.as_bytes(),
+        };
+    }
+}
+
+/// Path of the key codec record of the store `pattern` in `directory`.
+pub(crate) fn record_path(directory: &Path, pattern: &str) -> PathBuf {
+    return directory.join(format!("{}.keycodec", pattern));
+}
+
+/// Checks `codec` against the one recorded for the store, recording it if the store is
+/// `empty` and has no record yet.
+pub(crate) fn check(
+    directory: &str,
+    pattern: &str,
+    codec: Option<&dyn KeyCodec>,
+    empty: bool,
+) -> Result<()> {
+    let path = record_path(Path::new(directory), pattern);
+    let id = codec.map(|codec| codec.id());
+    return setting::check(&path, RECORD_HEADER, id, empty, |id| match id {
+        Some(id) => format!("key codec {}", id),
+        None => "no key codec".to_string(),
+    });
+}
@@ -0,0 +1,197 @@
+//! Operations on whole keys: `rename`, `copy` and `delete_prefix`.
+//!
+//! A key's latest record is rewritten for its new key without handing the value to the
+//! caller: delta chains are resolved, since their bases belong to the old key, references to
+//! values stored outside the log are carried over as they are, and lists, sets and hashes
+//! become a single snapshot, as in compaction.
+
+use crate::compaction::Relocated;
+use crate::kvstore::Attributes;
+use crate::{reader, Error, RCask, ReadOptions, Result};
+
+impl RCask {
+    /// Moves the value of `from` to `to` and returns whether `from` had one. The new record
+    /// and the deletion of `from` are written as one group, so after a crash either both or
+    /// neither are in the log. Fails with `Error::KeyExists` if `to` has a value, unless
+    /// `overwrite`. Renaming a key to itself changes nothing.
+    pub fn rename(&mut self, from: &str, to: &str, overwrite: bool) -> Result<bool> {
+        let (from, to) = (self.stored_key(from), self.stored_key(to));
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let Some((stored, mut attributes)) = self.standalone_record(from)? else {
+            return Ok(false);
+        };
+        if from == to {
+            return Ok(true);
+        }
+        if !overwrite && self.read_latest(to, self.verify_checksums)?.is_some() {
+            return Err(Error::KeyExists {
+                key: to.to_string(),
+            });
+        }
+        self.observe_moved(from, to)?;
+        let size = (to.len() + stored.len()) as u64;
+        self.admit_quota(to, size, false)?;
+        self.admit_write()?;
+        attributes.written_at = self.stamp();
+        let deletion = Attributes {
+            written_at: self.stamp(),
+            ..Attributes::tombstone()
+        };
+        self.invalidate(from);
+        self.invalidate(to);
+        self.record_write(from);
+        self.record_write(to);
+        let records = [
+            (to, stored, attributes.clone()),
+            (from, Vec::new(), deletion),
+        ];
+        let written = self.store.set_group(&records);
+        self.health.wrote(&written);
+        written?;
+        if let (Some(listener), Some(expires_at)) = (&mut self.expiry, attributes.expires_at) {
+            listener.schedule(to, expires_at);
+        }
+        self.lru_removed(from);
+        self.lru_wrote([(to, size)], false)?;
+        self.finish_write(false)?;
+        self.observe_delete(from);
+        return Ok(true);
+    }
+
+    /// Copies the value of `from` to `to` inside the store and returns whether `from` had
+    /// one, e.g. to snapshot a key or instantiate a template record. Values stored in blob
+    /// files or deduplicated are shared rather than copied. Fails with `Error::KeyExists` if
+    /// `to` has a value, unless `overwrite`. Copying a key to itself changes nothing.
+    pub fn copy(&mut self, from: &str, to: &str, overwrite: bool) -> Result<bool> {
+        let (from, to) = (self.stored_key(from), self.stored_key(to));
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let Some((stored, mut attributes)) = self.standalone_record(from)? else {
+            return Ok(false);
+        };
+        if from == to {
+            return Ok(true);
+        }
+        if !overwrite && self.read_latest(to, self.verify_checksums)?.is_some() {
+            return Err(Error::KeyExists {
+                key: to.to_string(),
+            });
+        }
+        self.observe_moved(from, to)?;
+        let size = (to.len() + stored.len()) as u64;
+        self.admit_quota(to, size, false)?;
+        self.admit_write()?;
+        attributes.written_at = self.stamp();
+        self.invalidate(to);
+        self.record_write(to);
+        let written = self.store.set(to, &stored, &attributes);
+        self.health.wrote(&written);
+        written?;
+        if let (Some(listener), Some(expires_at)) = (&mut self.expiry, attributes.expires_at) {
+            listener.schedule(to, expires_at);
+        }
+        self.lru_wrote([(to, size)], false)?;
+        self.finish_write(false)?;
+        return Ok(true);
+    }
+
+    /// Deletes every key that starts with `prefix` and returns how many had a value, e.g. to
+    /// drop a tenant's namespace. The deletions are written as one group, so after a crash
+    /// either all or none of them are in the log. With a `KeyCodec`, keys are matched as they
+    /// decode, so keys a codec cannot decode, e.g. hashed ones, only match by what they are
+    /// stored as.
+    pub fn delete_prefix(&mut self, prefix: &str) -> Result<u64> {
+        let verify = self.verify_checksums;
+        let mut keys = Vec::new();
+        for key in self.keys_starting_with(prefix) {
+            if self.read_latest(&key, verify)?.is_some() {
+                keys.push(key);
+            }
+        }
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        keys.sort_unstable();
+        self.admit_write()?;
+        let deletion = Attributes {
+            written_at: self.stamp(),
+            ..Attributes::tombstone()
+        };
+        let records: Vec<_> = keys
+            .iter()
+            .map(|key| (key.as_str(), [], deletion.clone()))
+            .collect();
+        for key in &keys {
+            self.invalidate(key);
+            self.record_write(key);
+        }
+        let written = self.store.set_group(&records);
+        self.health.wrote(&written);
+        written?;
+        for key in &keys {
+            self.lru_removed(key);
+        }
+        self.finish_bulk_load(keys.len() as u64)?;
+        for key in &keys {
+            self.observe_delete(key);
+        }
+        return Ok(keys.len() as u64);
+    }
+
+    /// Returns the stored keys whose decoded key starts with `prefix`. Codecs need not keep
+    /// prefixes or order, so with one every key is decoded.
+    fn keys_starting_with(&self, prefix: &str) -> Vec<String> {
+        let Some(codec) = &self.key_codec else {
+            return self.store.keys_with_prefix(prefix);
+        };
+        // Decoding the encoded prefix folds it like the keys, e.g. for `CaseFold`.
+        let prefix = codec.decode(&codec.encode(prefix)).into_owned();
+        let mut keys = self.store.keys();
+        keys.retain(|key| codec.decode(key).starts_with(&prefix));
+        return keys;
+    }
+
+    /// Shows observers the value `to` is about to get from `from` by a rename or copy, as a set
+    /// they may reject.
+    /// Lists, sets and hashes are not observed.
+    fn observe_moved(&mut self, from: &str, to: &str) -> Result<()> {
+        if self.observers.is_empty() {
+            return Ok(());
+        }
+        let options = ReadOptions {
+            fill_cache: false,
+            ..Default::default()
+        };
+        return match self.read_value(from, &options, Vec::new()) {
+            Ok(Some(value)) => self.observe_set(to, &value),
+            Ok(None) | Err(Error::WrongType { .. }) => Ok(()),
+            Err(e) => Err(e),
+        };
+    }
+
+    /// Reads the latest record of `key` and encodes it again so that it can be stored
+    /// under another key, or returns `None` if the key has no value.
+    fn standalone_record(&mut self, key: &str) -> Result<Option<(Vec<u8>, Attributes)>> {
+        let verify = self.verify_checksums;
+        let Some((framed, attributes)) = self.read_latest(key, verify)? else {
+            return Ok(None);
+        };
+        let record = if attributes.collection {
+            Relocated::Collection(self.collapse_collection(key, &framed)?)
+        } else if self.values.is_framed() && self.values.is_reference(&framed) {
+            Relocated::Reference(framed)
+        } else {
+            let offset = self.store.offset(key).unwrap_or_default();
+            let (plain, _) = reader::resolve(
+                &mut self.store,
+                &mut self.values,
+                key,
+                offset,
+                framed,
+                verify,
+            )?;
+            Relocated::Value(self.values.untag_schema(plain)?)
+        };
+        let stored = self.encode_relocated(record)?.into_owned();
+        return Ok(Some((stored, attributes)));
+    }
+}
@@ -1,89 +1,699 @@
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use crate::cache::BlockCache;
+use crate::checksum::ChecksumAlgorithm;
+use crate::index::KeyIndex;
+use crate::vfs::{FileSystem, LogFile};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::error;
+use std::fmt;
+use std::io::{self, BufWriter, IoSlice, Read, Seek, SeekFrom, Write};
 use std::path::Path;
-use std::vec;
+use std::sync::Arc;
+
+/// Written in place of a key length at the start of a region whose records are all dead:
+/// [HOLE_MARKER: u64] [region_length: u64]. The rest of the region may have been punched out
+/// of the file, and loading skips it. See `RCask::punch_holes`.
+const HOLE_MARKER: u64 = u64::MAX;
+pub(crate) const HOLE_HEADER_SIZE: u64 = 16;
+/// Holes are punched in whole blocks of this size.
+const PUNCH_ALIGNMENT: u64 = 4096;
+
+/// Written in place of the first key length of a segment whose records carry a checksum
+/// other than CRC-32: [HEADER_MARKER: u64] [algorithm: u64], see `ChecksumAlgorithm::id`.
+/// Segments without a header use CRC-32.
+const HEADER_MARKER: u64 = u64::MAX - 2;
+const SEGMENT_HEADER_SIZE: u64 = 16;
+
+/// Written in place of a key length at the start of a block of small records packed by
+/// compaction: [BLOCK_MARKER: u64] [block_length: u64] [entry_count: u16]
+/// [entry_offset: u16; entry_count] [entries]. Entries are sorted by key and their offsets
+/// are relative to the entry count. Each entry is [key_length: u16] [value_length: u16]
+/// [flags: u8] [key_bytes] [value_bytes], where the value is laid out as in a record and the
+/// flags are the top byte of a record's value length. Every key of a block is indexed at the
+/// block's offset. See `KVStore::pack`.
+const BLOCK_MARKER: u64 = u64::MAX - 1;
+const BLOCK_HEADER_SIZE: usize = 18;
+/// Blocks address their entries with u16 offsets.
+pub(crate) const MAX_BLOCK_SIZE: usize = u16::MAX as usize;
+/// Bytes of an entry before its key.
+const ENTRY_HEADER_SIZE: usize = 5;
+
+/// Written in place of a key length before records that must become visible together:
+/// [GROUP_MARKER: u64] [group_length: u64] [records]. The length includes the marker. Loading
+/// indexes the records only once the whole group is in the file, so a torn group at the end of
+/// the log is dropped as one. See `KVStore::set_group`.
+const GROUP_MARKER: u64 = u64::MAX - 3;
+const GROUP_HEADER_SIZE: usize = 16;
+
+/// Written in place of a key length before records of a batch too large to write as one
+/// group: [CHUNK_MARKER: u64] [chunk_length: u64] [batch: u64] [records]. The length includes
+/// the header. A batch is spilled to the log in any number of chunks and ends with a commit
+/// marker: [COMMIT_MARKER: u64] [BATCH_HEADER_SIZE: u64] [batch: u64]. Loading indexes the
+/// records of a batch only at its commit marker, so a batch without one, e.g. cut short by a
+/// crash, is dropped as a whole. The batch is the offset of its first chunk. See
+/// `KVStore::spill_batch`.
+const CHUNK_MARKER: u64 = u64::MAX - 4;
+const COMMIT_MARKER: u64 = u64::MAX - 5;
+pub(crate) const BATCH_HEADER_SIZE: u64 = 24;
+
+/// Bytes `KVStore::load_with` scans between two progress reports.
+const PROGRESS_INTERVAL: u64 = 16 << 20;
+
+/// Called by `KVStore::load_with` with the bytes scanned, the length of the log and the
+/// records indexed so far.
+pub(crate) type LoadHook<'a> = &'a mut dyn FnMut(u64, u64, u64) -> io::Result<()>;
+
+/// Size of the write buffer used by `set_all`.
+const BULK_BUFFER_SIZE: usize = 1 << 20;
+
+/// Bytes of records `stage` collects into one extent before writing it.
+const EXTENT_SIZE: usize = 256 << 10;
+
+/// Bytes a read reserves up front; longer values grow the buffer as they are read.
+const READ_RESERVE: u64 = 64 << 10;
+
+/// Reads in a row that each start at most `READ_AHEAD_GAP` bytes after the previous record
+/// before reads are served from a window read ahead, see `KVStore::read_ahead`.
+const READ_AHEAD_AFTER: u32 = 4;
+const READ_AHEAD_GAP: u64 = 64 << 10;
+
+/// Bytes of the log read into a read-ahead window at once.
+const READ_AHEAD_SIZE: u64 = 1 << 20;
+/// Bytes read past the start of the last record of a coalesced read, see
+/// `KVStore::read_ahead_of`. Longer records are read to their end with a second read.
+const COALESCED_TAIL: u64 = 4 << 10;
+
+/// Set on a record's value length when the value starts with an attribute block:
+/// [expires_at: u64] [meta_length: u64] [meta_bytes]
+/// `expires_at` is in milliseconds since the Unix epoch, or 0 if the record never expires.
+/// Records without attributes keep the original format.
+const ATTRIBUTES_FLAG: u64 = 1 << 63;
+
+/// Set on a record's value length when the value ends with a checksum of the key, the
+/// attribute block and the value, computed with the segment's algorithm (see
+/// `HEADER_MARKER`).
+const CHECKSUM_FLAG: u64 = 1 << 62;
+
+/// Set on a record's value length when the value is a collection record (see `collection`)
+/// rather than a value.
+const COLLECTION_FLAG: u64 = 1 << 61;
+
+/// Set on a record's value length when the value starts with a history block:
+/// [written_at: u64] [previous: u64]
+/// `written_at` is in milliseconds since the Unix epoch and `previous` is the offset of the
+/// key's previous record in the same segment, or `u64::MAX` if there is none. The history
+/// block comes before the attribute block. See `history`.
+const HISTORY_FLAG: u64 = 1 << 60;
+const HISTORY_SIZE: usize = 16;
+
+/// Bits of a value length that are flags rather than part of the length.
+const LENGTH_FLAGS: u64 = ATTRIBUTES_FLAG | CHECKSUM_FLAG | COLLECTION_FLAG | HISTORY_FLAG;
+
+/// Per-record attributes, most of them set through `WriteOptions`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Attributes {
+    pub expires_at: Option<u64>,
+    pub meta: Option<Vec<u8>>,
+    /// Whether the record belongs to a list, set or hash instead of holding a value.
+    pub collection: bool,
+    /// When the record was written, in milliseconds since the Unix epoch, if it has a history
+    /// block.
+    pub written_at: Option<u64>,
+    /// Offset of the key's previous record in the same segment, if the record has a history
+    /// block. Read from the log only; `KVStore` links every record it writes with a write
+    /// time to the key's record in its index.
+    pub previous: Option<u64>,
+}
+
+impl Attributes {
+    /// Whether the record needs no attribute block.
+    fn is_empty(&self) -> bool {
+        return self.expires_at.is_none() && self.meta.is_none();
+    }
+
+    /// Attributes of a record that hides every earlier record of its key, e.g. after
+    /// compaction dropped the key or it was evicted. It reads as a long expired record.
+    pub(crate) fn tombstone() -> Self {
+        return Attributes {
+            expires_at: Some(1),
+            ..Default::default()
+        };
+    }
+
+    /// Whether the record was written by `tombstone` rather than expiring.
+    pub(crate) fn is_tombstone(&self) -> bool {
+        return self.expires_at == Some(1);
+    }
+
+    /// Whether the record has expired at `now` (milliseconds since the Unix epoch).
+    pub fn is_expired(&self, now: u64) -> bool {
+        return self.expires_at.is_some_and(|expires_at| expires_at <= now);
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let meta = self.meta.as_deref().unwrap_or_default();
+        let mut block = Vec::with_capacity(16 + meta.len());
+        block.extend_from_slice(&self.expires_at.unwrap_or(0).to_le_bytes());
+        block.extend_from_slice(&(meta.len() as u64).to_le_bytes());
+        block.extend_from_slice(meta);
+        return block;
+    }
+
+    /// Splits a value with an attribute block into its attributes and the value itself.
+    fn decode(mut value: Vec<u8>) -> io::Result<(Self, Vec<u8>)> {
+        let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "Corrupt record attributes");
+        if value.len() < 16 {
+            return Err(corrupt());
+        }
+        let expires_at = u64::from_le_bytes(value[0..8].try_into().unwrap_or_default());
+        let meta_len = u64::from_le_bytes(value[8..16].try_into().unwrap_or_default()) as usize;
+        if meta_len > value.len() - 16 {
+            return Err(corrupt());
+        }
+        let attributes = Attributes {
+            expires_at: (expires_at != 0).then_some(expires_at),
+            meta: (meta_len > 0).then(|| value[16..16 + meta_len].to_vec()),
+            ..Default::default()
+        };
+        value.drain(..16 + meta_len);
+        return Ok((attributes, value));
+    }
+}
+
+/// Small records waiting to be written as one block.
+struct PendingBlock {
+    /// Where the block will be written.
+    start: u64,
+    /// Encoded entries, without their offsets.
+    entries: BTreeMap<String, Vec<u8>>,
+    /// Length of the block as it stands.
+    len: usize,
+    /// Offset each of its keys had before it was packed, restored if the block cannot be
+    /// written.
+    previous: HashMap<String, Option<u64>>,
+}
+
+/// Values of at least this many bytes are handed to the file as they are in a vectored write
+/// rather than copied next to the rest of their record, see `Gathered`.
+const VECTORED_VALUE_SIZE: usize = 4 << 10;
+
+/// The bytes of an append, written with one vectored write: the parts of records are copied
+/// into runs, except for large values, which are borrowed.
+#[derive(Default)]
+struct Gathered<'a> {
+    parts: Vec<Cow<'a, [u8]>>,
+    len: u64,
+}
+
+impl<'a> Gathered<'a> {
+    /// Starts with a run that holds `capacity` bytes without growing.
+    fn with_capacity(capacity: usize) -> Self {
+        return Gathered {
+            parts: vec![Cow::Owned(Vec::with_capacity(capacity))],
+            len: 0,
+        };
+    }
+
+    fn copy(&mut self, bytes: &[u8]) {
+        match self.parts.last_mut() {
+            Some(Cow::Owned(run)) => run.extend_from_slice(bytes),
+            _ => self.parts.push(Cow::Owned(bytes.to_vec())),
+        }
+        self.len += bytes.len() as u64;
+    }
+
+    fn value(&mut self, value: &'a [u8]) {
+        if value.len() < VECTORED_VALUE_SIZE {
+            return self.copy(value);
+        }
+        self.parts.push(Cow::Borrowed(value));
+        self.len += value.len() as u64;
+    }
+
+    /// Overwrites bytes of the first run, e.g. a header written before its length was known.
+    fn patch(&mut self, at: usize, bytes: &[u8]) {
+        if let Some(Cow::Owned(run)) = self.parts.first_mut() {
+            run[at..at + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+}
+
+/// Records staged to be written as one aligned extent, see `KVStore::stage`.
+struct PendingExtent {
+    /// Where the extent will be written, a multiple of the alignment.
+    start: u64,
+    records: Vec<u8>,
+    /// Offset each of its keys had before it was staged, as for `PendingBlock`.
+    previous: HashMap<String, Option<u64>>,
+}
 
 /// A single key-value store that persists data to a file.
 pub struct KVStore {
-    index: HashMap<String, u64>,
-    file: File,
+    index: KeyIndex,
+    file: Box<dyn LogFile>,
     pub path: String,
+    /// Whether new records carry a checksum.
+    checksums: bool,
+    /// Checksum of the records of this segment.
+    checksum: ChecksumAlgorithm,
+    /// Offset of the first record, after the header if there is one.
+    data_start: u64,
+    /// End of the last complete record that is in the index, or that a bulk load session
+    /// has yet to add to it.
+    end: u64,
+    block: Option<PendingBlock>,
+    extent: Option<PendingExtent>,
+    /// Boundary that every write starts and ends on, see `align_records`.
+    alignment: Option<u64>,
+    /// Where blocks read from this segment are cached, with the segment's number.
+    block_cache: Option<(Arc<BlockCache>, u64)>,
+    /// Bytes this handle has written to the log.
+    written: u64,
+    /// Reused for the keys of records that are read, to check them against the key asked for.
+    scratch: Vec<u8>,
+    /// Where the record read last starts and ends, and how many reads in a row followed the
+    /// one before.
+    read_start: u64,
+    read_end: u64,
+    sequential_reads: u32,
+    read_ahead: Option<ReadAhead>,
+    /// Records of batches without a commit marker yet, by batch.
+    pending: HashMap<u64, Vec<(String, u64)>>,
+    /// Batch and end of the chunk loading is in, if any.
+    chunk: Option<(u64, u64)>,
+    /// Offsets of the commit markers in the log, which must stay while their batch's chunks do.
+    commits: Vec<u64>,
+    /// Newest write time of the records in the log, and whether any record has none.
+    newest_write: Option<u64>,
+    unstamped: bool,
+}
+
+/// A batch marker found by `KVStore::read_batch_marker`.
+enum BatchMarker {
+    /// The header of a chunk of `batch`, whose records end at `end`.
+    Chunk {
+        batch: u64,
+        end: u64,
+    },
+    Commit {
+        batch: u64,
+    },
+    /// A marker that does not fit in the file, as after a torn write.
+    Torn,
+}
+
+/// Bytes of the log read ahead of a sequential scan, see `KVStore::read_ahead`.
+struct ReadAhead {
+    /// Offset of the first byte.
+    start: u64,
+    bytes: Vec<u8>,
+    /// End of the log when the window was read; the window is dropped once it moves.
+    end: u64,
 }
 
 impl KVStore {
     /// Creates a new KVStore instance.
     /// If the file exists, it will open it and load the existing index.
     /// If the file does not exist, it will create a new one.
-    pub fn new(path: &Path) -> io::Result<Self> {
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path)
-            .expect("failed to open keystore file");
+    /// Unless `checksum` is `ChecksumAlgorithm::None`, every record written from now on
+    /// carries a checksum: one with `checksum` in a new segment, or one with the segment's
+    /// algorithm in an existing one.
+    pub fn new(fs: &dyn FileSystem, path: &Path, checksum: ChecksumAlgorithm) -> io::Result<Self> {
+        let file = fs.open_writable(path).map_err(|e| open_failed(path, e))?;
+
+        return Self::with_file(file, path, checksum, &mut |_, _, _| Ok(()));
+    }
+
+    /// Like `new`, but calls `progress` while the index is loaded; see `load_with`.
+    pub(crate) fn with_progress(
+        fs: &dyn FileSystem,
+        path: &Path,
+        checksum: ChecksumAlgorithm,
+        progress: LoadHook,
+    ) -> io::Result<Self> {
+        let file = fs.open_writable(path).map_err(|e| open_failed(path, e))?;
 
+        return Self::with_file(file, path, checksum, progress);
+    }
+
+    /// Opens an existing log for reading only, e.g. for a reader handle.
+    pub fn open_read_only(fs: &dyn FileSystem, path: &Path) -> io::Result<Self> {
+        let file = fs.open_read_only(path).map_err(|e| open_failed(path, e))?;
+        return Self::with_file(file, path, ChecksumAlgorithm::None, &mut |_, _, _| Ok(()));
+    }
+
+    fn with_file(
+        file: Box<dyn LogFile>,
+        path: &Path,
+        checksum: ChecksumAlgorithm,
+        progress: LoadHook,
+    ) -> io::Result<Self> {
         let mut store = KVStore {
-            index: HashMap::new(),
-            file: file,
+            index: KeyIndex::new(),
+            file,
             path: path.to_string_lossy().to_string(),
+            checksums: checksum != ChecksumAlgorithm::None,
+            checksum: ChecksumAlgorithm::Crc32,
+            data_start: 0,
+            end: 0,
+            block: None,
+            extent: None,
+            alignment: None,
+            block_cache: None,
+            written: 0,
+            scratch: Vec::new(),
+            read_start: 0,
+            read_end: 0,
+            sequential_reads: 0,
+            read_ahead: None,
+            pending: HashMap::new(),
+            chunk: None,
+            commits: Vec::new(),
+            newest_write: None,
+            unstamped: false,
         };
 
-        store.load()?;
+        store
+            .load_with(progress)
+            .map_err(|e| store.locate(e, "load", None, Some(store.end)))?;
+        let is_new = store.file.seek(SeekFrom::End(0))? == 0;
+        let needs_header = !matches!(checksum, ChecksumAlgorithm::Crc32 | ChecksumAlgorithm::None);
+        if is_new && needs_header {
+            let mut header = Vec::with_capacity(SEGMENT_HEADER_SIZE as usize);
+            header.extend_from_slice(&HEADER_MARKER.to_le_bytes());
+            header.extend_from_slice(&checksum.id().to_le_bytes());
+            store.retry_write(&header)?;
+            store.checksum = checksum;
+            store.data_start = SEGMENT_HEADER_SIZE;
+            store.end = SEGMENT_HEADER_SIZE;
+            store.written += SEGMENT_HEADER_SIZE;
+        }
 
         return Ok(store);
     }
 
-    pub fn get_all_key_values(&mut self) -> io::Result<HashMap<String, Vec<u8>>> {
-        let mut entries = HashMap::new();
-        // Clone keys to avoid borrowing issues while calling get_value_bytes
-        let keys: Vec<String> = self.index.keys().cloned().collect();
+    /// Reads every record after the last indexed one into the in-memory index.
+    /// This is called when the KVStore is initialized to restore state, and by readers to
+    /// pick up records appended since.
+    pub fn load(&mut self) -> io::Result<()> {
+        return self.load_with(&mut |_, _, _| Ok(()));
+    }
 
-        for key in keys {
-            // We read the value for each key using its offset.
-            // This ensures we get the *latest* value according to the index.
-            if let Some(value_bytes) = self.get_value_bytes(&key)? {
-                entries.insert(key, value_bytes);
-            }
-            // We ignore keys that might exist in the index but fail to read,
-            // though this indicates potential issues.
-        }
-        Ok(entries)
+    /// Like `load`, but calls `progress` with the bytes scanned so far, the length of the log
+    /// and the number of records indexed every `PROGRESS_INTERVAL` bytes and once the whole
+    /// log is scanned. An error from `progress` stops loading.
+    pub(crate) fn load_with(&mut self, progress: LoadHook) -> io::Result<()> {
+        return self.scan(progress, &mut |_| {});
     }
 
-    /// Rebuilds the in-memory index by reading through the entire file.
-    /// This is called when the KVStore is initialized to restore state.
-    pub fn load(&mut self) -> io::Result<()> {
-        self.file.seek(SeekFrom::Start(0))?;
+    /// Appends records copied verbatim from another log, e.g. by replication, where they
+    /// started at the end of this log's last complete record, and returns the keys they
+    /// indexed. Records after the last complete one are dropped first.
+    pub(crate) fn append_raw(&mut self, bytes: &[u8]) -> io::Result<Vec<String>> {
+        self.flush_block()?;
+        let _ = self.file.truncate(self.end);
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.retry_write(bytes)?;
+        self.written += bytes.len() as u64;
+        let mut keys = Vec::new();
+        self.scan(&mut |_, _, _| Ok(()), &mut |key| keys.push(key.to_string()))?;
+        return Ok(keys);
+    }
+
+    /// Loads like `load_with`, calling `indexed` with every key it indexes.
+    fn scan(&mut self, progress: LoadHook, indexed: &mut dyn FnMut(&str)) -> io::Result<()> {
+        let length = self.file.seek(SeekFrom::End(0))?;
+        self.file.seek(SeekFrom::Start(self.end))?;
+        let mut records = 0;
+        let mut reported = self.end;
 
         loop {
-            let offset = self.file.seek(SeekFrom::Current(0))?;
-            match self.read() {
-                Ok(key) => {
-                    let key_str = String::from_utf8(key)
-                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
-                    self.index.insert(key_str, offset);
+            let offset = self.file.stream_position()?;
+            if offset >= reported + PROGRESS_INTERVAL {
+                progress(offset, length, records)?;
+                reported = offset;
+            }
+            if offset == 0 {
+                if let Some(checksum) = self.read_header()? {
+                    self.checksum = checksum;
+                    self.data_start = SEGMENT_HEADER_SIZE;
+                    self.end = SEGMENT_HEADER_SIZE;
+                    continue;
+                }
+                self.file.seek(SeekFrom::Start(0))?;
+            }
+            // The key length of a record, or a marker in its place. A marker that is torn or
+            // does not fit in the file ends the log like a torn record.
+            let Ok(slot) = self.read_slot() else {
+                break;
+            };
+            match slot {
+                HOLE_MARKER => {
+                    let Some(region_end) = self.read_hole(offset, length) else {
+                        break;
+                    };
+                    self.file.seek(SeekFrom::Start(region_end))?;
+                    self.end = region_end;
+                    continue;
+                }
+                // The records of a group follow, laid out as usual.
+                GROUP_MARKER => match self.read_group(offset, length) {
+                    true => continue,
+                    false => break,
+                },
+                CHUNK_MARKER | COMMIT_MARKER => {
+                    match self.read_batch_marker(slot, offset, length) {
+                        BatchMarker::Chunk { batch, end } => {
+                            self.chunk = Some((batch, end));
+                            continue;
+                        }
+                        BatchMarker::Commit { batch } => {
+                            for (key, at) in self.pending.remove(&batch).unwrap_or_default() {
+                                indexed(&key);
+                                self.index.insert(key, at);
+                                records += 1;
+                            }
+                            self.commits.push(offset);
+                            self.end = offset + BATCH_HEADER_SIZE;
+                            continue;
+                        }
+                        BatchMarker::Torn => break,
+                    }
                 }
+                BLOCK_MARKER => {
+                    let Some((block_end, keys)) = self.read_block(offset, length) else {
+                        break;
+                    };
+                    // The write times of packed records are not looked at.
+                    self.unstamped = true;
+                    records += keys.len() as u64;
+                    for key in keys {
+                        indexed(&key);
+                        self.index.insert(key, offset);
+                    }
+                    self.file.seek(SeekFrom::Start(block_end))?;
+                    self.end = block_end;
+                    continue;
+                }
+                _ => {}
+            }
+            let key = match self.read_length(slot & !LENGTH_FLAGS) {
+                Ok(key) => String::from_utf8(key)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
                 Err(_) => {
                     break;
                 }
-            }
+            };
 
-            // Read value to move the cursor forward
-            match self.read() {
-                Ok(_) => {}
+            // Read value to move the cursor forward; only complete records are indexed.
+            match self.read_flagged() {
+                Ok((value, flags)) => {
+                    // A history block starts with the write time.
+                    let written_at = value
+                        .get(..8)
+                        .filter(|_| flags & HISTORY_FLAG != 0)
+                        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap_or_default()));
+                    self.stamped(written_at);
+                    self.end = self.file.stream_position()?;
+                    match self.chunk.filter(|&(_, end)| offset < end) {
+                        // Records of a batch wait for its commit marker.
+                        Some((batch, _)) => {
+                            self.pending.entry(batch).or_default().push((key, offset));
+                        }
+                        None => {
+                            self.chunk = None;
+                            indexed(&key);
+                            self.index.insert(key, offset);
+                            records += 1;
+                        }
+                    }
+                }
                 Err(_) => {
                     break;
                 }
             }
         }
-        return Ok(());
+        return progress(length, length, records);
+    }
+
+    /// Returns the newest write time of the records in the log, or `None` if it is empty or
+    /// holds a record without a write time, such as a bulk write.
+    pub(crate) fn newest_write(&self) -> Option<u64> {
+        return self.newest_write.filter(|_| !self.unstamped);
+    }
+
+    /// Accounts for a record with write time `written_at` in the log.
+    fn stamped(&mut self, written_at: Option<u64>) {
+        match written_at {
+            Some(written_at) => {
+                self.newest_write = Some(self.newest_write.unwrap_or_default().max(written_at))
+            }
+            None => self.unstamped = true,
+        }
+    }
+
+    /// End of the last complete record that is in the index.
+    pub fn end(&self) -> u64 {
+        return self.end;
+    }
+
+    /// Offset of the first record, after the segment header if there is one.
+    pub fn data_start(&self) -> u64 {
+        return self.data_start;
+    }
+
+    /// Reads the segment header at the start of the file, if there is one, and returns the
+    /// checksum algorithm it names.
+    fn read_header(&mut self) -> io::Result<Option<ChecksumAlgorithm>> {
+        let mut header = [0; SEGMENT_HEADER_SIZE as usize];
+        if self.file.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+        if u64::from_le_bytes(header[0..8].try_into().unwrap_or_default()) != HEADER_MARKER {
+            return Ok(None);
+        }
+        let id = u64::from_le_bytes(header[8..16].try_into().unwrap_or_default());
+        return ChecksumAlgorithm::from_id(id).map(Some);
+    }
+
+    /// Reads a key length, marker or header field at the cursor.
+    fn read_slot(&mut self) -> io::Result<u64> {
+        let mut slot = [0; 8];
+        self.file.read_exact(&mut slot)?;
+        return Ok(u64::from_le_bytes(slot));
+    }
+
+    /// Reads the rest of the hole marker at `offset` and returns the end of the dead region it
+    /// marks, if it fits in a file of `length` bytes.
+    fn read_hole(&mut self, offset: u64, length: u64) -> Option<u64> {
+        let region = self.read_slot().ok()?;
+        if region < HOLE_HEADER_SIZE {
+            return None;
+        }
+        return offset.checked_add(region).filter(|&end| end <= length);
+    }
+
+    /// Reads the rest of the group marker at `offset` and returns whether the group it starts
+    /// is complete in a file of `length` bytes. The cursor is left after the header.
+    fn read_group(&mut self, offset: u64, length: u64) -> bool {
+        let Ok(group_length) = self.read_slot() else {
+            return false;
+        };
+        return group_length >= GROUP_HEADER_SIZE as u64
+            && group_length <= length.saturating_sub(offset);
+    }
+
+    /// Reads the rest of the batch marker `marker` at `offset` and returns what it marks in a
+    /// file of `length` bytes. The cursor is left after the header.
+    fn read_batch_marker(&mut self, marker: u64, offset: u64, length: u64) -> BatchMarker {
+        let (Ok(header_length), Ok(batch)) = (self.read_slot(), self.read_slot()) else {
+            return BatchMarker::Torn;
+        };
+        if header_length < BATCH_HEADER_SIZE || header_length > length.saturating_sub(offset) {
+            return BatchMarker::Torn;
+        }
+        return match marker {
+            CHUNK_MARKER => BatchMarker::Chunk {
+                batch,
+                end: offset + header_length,
+            },
+            _ => BatchMarker::Commit { batch },
+        };
     }
 
-    /// Reads a string from the file.
-    /// It first reads the length of the string (u64),
-    /// then reads the string bytes based on that length.
-    fn read(&mut self) -> Result<Vec<u8>, io::Error> {
+    /// Reads the rest of the block at `offset` and returns its end and keys, if it is a
+    /// complete block that fits in a file of `length` bytes.
+    fn read_block(&mut self, offset: u64, length: u64) -> Option<(u64, Vec<String>)> {
+        let block_length = self.read_slot().ok()?;
+        if block_length > length.saturating_sub(offset) {
+            return None;
+        }
+        let body = self.read_length(block_length.checked_sub(16)?).ok()?;
+        let mut keys = Vec::new();
+        for at in block_entries(&body).ok()? {
+            let (key, _, _) = block_entry(&body, at).ok()?;
+            keys.push(String::from_utf8(key.to_vec()).ok()?);
+        }
+        return Some((offset + block_length, keys));
+    }
+
+    /// Returns the end of the record or block that starts at `offset`.
+    pub fn record_end(&mut self, offset: u64) -> io::Result<u64> {
+        return self
+            .read_record_end(offset)
+            .map_err(|e| self.locate(e, "read", None, Some(offset)));
+    }
+
+    fn read_record_end(&mut self, offset: u64) -> io::Result<u64> {
+        let mut length = [0; 8];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut length)?;
+        let key_length = u64::from_le_bytes(length);
+        if key_length == BLOCK_MARKER {
+            self.file.read_exact(&mut length)?;
+            return Ok(offset + u64::from_le_bytes(length));
+        }
+        self.file.seek(SeekFrom::Current(key_length as i64))?;
+        self.file.read_exact(&mut length)?;
+        let value_length = u64::from_le_bytes(length) & !LENGTH_FLAGS;
+        return Ok(offset + 16 + key_length + value_length);
+    }
+
+    /// Marks the records in `start..end` as dead and punches the region out of the file,
+    /// keeping only the marker. Returns the number of bytes punched, which is 0 if the file
+    /// system cannot punch holes. Only regions without any live record may be passed.
+    pub fn punch_hole(&mut self, start: u64, end: u64) -> io::Result<u64> {
+        return self
+            .punch(start, end)
+            .map_err(|e| self.locate(e, "hole punch", None, Some(start)));
+    }
+
+    fn punch(&mut self, start: u64, end: u64) -> io::Result<u64> {
+        self.read_ahead = None;
+        let mut header = Vec::with_capacity(HOLE_HEADER_SIZE as usize);
+        header.extend_from_slice(&HOLE_MARKER.to_le_bytes());
+        header.extend_from_slice(&(end - start).to_le_bytes());
+        self.file.seek(SeekFrom::Start(start))?;
+        self.file.write_all(&header)?;
+        self.written += HOLE_HEADER_SIZE;
+        // The marker must be durable before the records it covers are gone.
+        self.file.sync_data()?;
+
+        // Only whole file system blocks are freed.
+        let first = (start + HOLE_HEADER_SIZE).next_multiple_of(PUNCH_ALIGNMENT);
+        let last = end / PUNCH_ALIGNMENT * PUNCH_ALIGNMENT;
+        if last <= first {
+            return Ok(0);
+        }
+        return match self.file.punch_hole(first, last - first) {
+            Ok(()) => Ok(last - first),
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => Ok(0),
+            Err(e) => Err(e),
+        };
+    }
+
+    /// Reads a length-prefixed byte array and returns it with the flags carried by the length.
+    fn read_flagged(&mut self) -> io::Result<(Vec<u8>, u64)> {
         // Read the length of the value.
         let mut length_bytes = [0; 8];
         if self.file.read_exact(&mut length_bytes).is_err() {
@@ -94,32 +704,100 @@ impl KVStore {
         }
 
         // Read the value bytes based on the length.
-        let mut value_bytes = vec![0; usize::from_le_bytes(length_bytes) as usize];
-        if self.file.read_exact(&mut value_bytes).is_err() {
+        let length = u64::from_le_bytes(length_bytes);
+        if let Ok(value_bytes) = self.read_length(length & !LENGTH_FLAGS) {
+            return Ok((value_bytes, length & LENGTH_FLAGS));
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Failed to read value bytes",
+        ));
+    }
+
+    /// Reads exactly `length` bytes. Beyond `READ_RESERVE` the buffer grows with the bytes
+    /// actually read, so a torn or corrupt length never allocates much more than the file
+    /// holds.
+    fn read_length(&mut self, length: u64) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.read_length_into(length, &mut bytes)?;
+        return Ok(bytes);
+    }
+
+    /// Like `read_length`, but reads into `bytes`, replacing what it held and reusing its
+    /// allocation.
+    fn read_length_into(&mut self, length: u64, bytes: &mut Vec<u8>) -> io::Result<()> {
+        bytes.clear();
+        bytes.reserve(length.min(READ_RESERVE) as usize);
+        Read::by_ref(&mut self.file)
+            .take(length)
+            .read_to_end(bytes)?;
+        if (bytes.len() as u64) < length {
             return Err(io::Error::new(
                 io::ErrorKind::UnexpectedEof,
-                "Failed to read value bytes",
+                "Record extends past the end of the log",
             ));
         }
-        return Ok(value_bytes.to_vec());
+        return Ok(());
     }
 
     /// Helper function to read a length-prefixed byte array from the file.
     /// It first reads a u64 length, then reads that many bytes.
-    /// This is used for reading both keys and values.
-    fn read_bytes(&mut self) -> Result<Vec<u8>, io::Error> {
+    /// This is used for reading both keys and values into `bytes`, reusing its allocation,
+    /// and returns the flags carried by the length.
+    fn read_bytes(&mut self, bytes: &mut Vec<u8>) -> io::Result<u64> {
         let mut length_buffer = [0; 8];
 
         // Read the length of the upcoming data.
         self.file.read_exact(&mut length_buffer)?;
 
-        let length = usize::from_le_bytes(length_buffer);
+        let length = u64::from_le_bytes(length_buffer);
 
         // Read the actual data based on the length.
-        let mut data_bytes = vec![0; length];
-        self.file.read_exact(&mut data_bytes)?;
+        self.read_length_into(length & !LENGTH_FLAGS, bytes)?;
 
-        return Ok(data_bytes);
+        return Ok(length & LENGTH_FLAGS);
+    }
+
+    /// Builds everything of a record that follows the key: the flagged value length, the
+    /// history and attribute blocks and the trailing checksum (all possibly empty).
+    /// `previous` is where the history block links to.
+    fn encode_value(
+        checksum: Option<ChecksumAlgorithm>,
+        key: &[u8],
+        value: &[u8],
+        attributes: &Attributes,
+        previous: Option<u64>,
+    ) -> (u64, Vec<u8>, Vec<u8>) {
+        let mut flags = 0;
+        let mut block = Vec::new();
+        if let Some(written_at) = attributes.written_at {
+            flags |= HISTORY_FLAG;
+            block.extend_from_slice(&written_at.to_le_bytes());
+            block.extend_from_slice(&previous.unwrap_or(u64::MAX).to_le_bytes());
+        }
+        if !attributes.is_empty() {
+            flags |= ATTRIBUTES_FLAG;
+            block.extend_from_slice(&attributes.encode());
+        }
+        if attributes.collection {
+            flags |= COLLECTION_FLAG;
+        }
+        let mut trailer = Vec::new();
+        if let Some(checksum) = checksum {
+            flags |= CHECKSUM_FLAG;
+            let mut hasher = checksum.hasher();
+            hasher.update(key);
+            hasher.update(&block);
+            hasher.update(value);
+            trailer = hasher.finish();
+        }
+        let length = (block.len() + value.len() + trailer.len()) as u64;
+        return (length | flags, block, trailer);
+    }
+
+    /// The checksum new records carry, if any.
+    fn write_checksum(&self) -> Option<ChecksumAlgorithm> {
+        return self.checksums.then_some(self.checksum);
     }
 
     /// Sets a key-value pair in the store.
@@ -127,86 +805,736 @@ impl KVStore {
     ///
     /// The data is written in the format:
     /// [key_length: u64] [key_bytes] [value_length: u64] [value_bytes]
-    /// The offset of the key (start of its entry) is then stored in the in-memory index.
-    pub fn set<T: AsRef<[u8]>, U: AsRef<[u8]>>(&mut self, key: T, value: U) -> io::Result<()> {
-        // Get the current file offset.
-        let offset = self.file.seek(SeekFrom::Current(0))?;
-
-        // Byte slices for key and value.
-        let key_bytes = key.as_ref();
-        let value_bytes = value.as_ref();
-
-        // Write key length (u64)
-        // Helper closure to retry write_all up to 3 times
-        let mut retry_write = |buf: &[u8]| -> io::Result<()> {
-            let mut attempts = 0;
-            loop {
-                match self.file.write_all(buf) {
-                    Ok(_) => return Ok(()),
-                    Err(_) if attempts < 2 => {
-                        attempts += 1;
-                        continue;
-                    }
-                    Err(e) => return Err(e),
-                }
-            }
-        };
+    /// with the attribute block (see `ATTRIBUTES_FLAG`) before the value if there are any
+    /// attributes, and the checksum (see `CHECKSUM_FLAG`) after it if checksums are enabled.
+    /// With aligned records (see `align_records`) the record is followed by padding.
+    /// The offset of the key (start of its entry) is then stored in the in-memory index
+    /// and returned.
+    pub fn set<T: AsRef<[u8]>, U: AsRef<[u8]>>(
+        &mut self,
+        key: T,
+        value: U,
+        attributes: &Attributes,
+    ) -> io::Result<u64> {
+        let (key, end) = (key.as_ref(), self.end);
+        return self
+            .write_record(key, value.as_ref(), attributes)
+            .map_err(|e| self.locate(e, "write", Some(key), Some(end)));
+    }
+
+    fn write_record(
+        &mut self,
+        key_bytes: &[u8],
+        value: &[u8],
+        attributes: &Attributes,
+    ) -> io::Result<u64> {
+        self.flush_block()?;
+        // Records are always appended; reads may have moved the cursor elsewhere.
+        let offset = self.append_offset()?;
 
-        retry_write(&(key_bytes.len() as u64).to_le_bytes())?;
-        retry_write(key_bytes)?;
-        retry_write(&(value_bytes.len() as u64).to_le_bytes())?;
-        retry_write(value_bytes)?;
+        let copied = if value.len() < VECTORED_VALUE_SIZE {
+            value.len()
+        } else {
+            0
+        };
+        // The header, attributes, checksum and padding of a record rarely take more.
+        let mut record = Gathered::with_capacity(key_bytes.len() + copied + 128);
+        self.gather_record(&mut record, key_bytes, value, attributes);
+        record.copy(&padding(self.alignment, offset + record.len));
+        self.retry_write_gathered(&record)?;
 
         // Store the offset for the key in the index
         self.index
             .insert(String::from_utf8_lossy(key_bytes).to_string(), offset);
-        Ok(())
+        self.end = offset + record.len;
+        self.written += record.len;
+        Ok(offset)
     }
 
-    /// Retrieves the value associated with a given key in string format.
-    ///
-    /// It first retrieves the value bytes using the `get` method,
-    /// then attempts to convert those bytes to a String.
-    pub fn get(&mut self, key: &str) -> io::Result<Option<String>> {
-        return match self.get_value_bytes(key) {
-            Ok(Some(value_bytes)) => {
-                // Convert the value bytes to a String.
-                self.to_string(value_bytes)
+    /// Encodes a record as `set` writes it, without padding.
+    fn encode_record(&mut self, key: &[u8], value: &[u8], attributes: &Attributes) -> Vec<u8> {
+        let mut record = Gathered::default();
+        self.gather_record(&mut record, key, value, attributes);
+        return record.parts.concat();
+    }
+
+    /// Adds a record to `gathered`, encoded as `set` writes it, without padding.
+    fn gather_record<'a>(
+        &mut self,
+        gathered: &mut Gathered<'a>,
+        key: &[u8],
+        value: &'a [u8],
+        attributes: &Attributes,
+    ) {
+        self.stamped(attributes.written_at);
+        let previous = self.index.get(&String::from_utf8_lossy(key));
+        let (value_length, block, checksum) =
+            Self::encode_value(self.write_checksum(), key, value, attributes, previous);
+        gathered.copy(&(key.len() as u64).to_le_bytes());
+        gathered.copy(key);
+        gathered.copy(&value_length.to_le_bytes());
+        gathered.copy(&block);
+        gathered.value(value);
+        gathered.copy(&checksum);
+    }
+
+    /// Writes several records as one group that loading indexes all or none of, even if the
+    /// write is torn by a crash, and returns their offsets. The records use the same format
+    /// as `set`.
+    pub fn set_group<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &mut self,
+        records: &[(K, V, Attributes)],
+    ) -> io::Result<Vec<u64>> {
+        let end = self.end;
+        return self
+            .write_group(records)
+            .map_err(|e| self.locate(e, "group write", None, Some(end)));
+    }
+
+    fn write_group<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &mut self,
+        records: &[(K, V, Attributes)],
+    ) -> io::Result<Vec<u64>> {
+        self.flush_block()?;
+        let start = self.append_offset()?;
+        let mut group = Gathered::default();
+        group.copy(&[0; GROUP_HEADER_SIZE]);
+        let mut offsets = Vec::with_capacity(records.len());
+        for (key, value, attributes) in records {
+            offsets.push(start + group.len);
+            self.gather_record(&mut group, key.as_ref(), value.as_ref(), attributes);
+        }
+        let group_length = group.len;
+        group.patch(0, &GROUP_MARKER.to_le_bytes());
+        group.patch(8, &group_length.to_le_bytes());
+        group.copy(&padding(self.alignment, start + group_length));
+        self.retry_write_gathered(&group)?;
+
+        for ((key, _, _), offset) in records.iter().zip(&offsets) {
+            let key = String::from_utf8_lossy(key.as_ref()).to_string();
+            self.index.insert(key, *offset);
+        }
+        self.end = start + group.len;
+        self.written += group.len;
+        return Ok(offsets);
+    }
+
+    /// Appends records of a batch as one chunk and returns the batch, see `CHUNK_MARKER`. The
+    /// records start a new batch unless `batch` names one that is pending. They are not
+    /// indexed, and loading drops them, unless `commit_batch` commits the batch.
+    pub(crate) fn spill_batch<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &mut self,
+        batch: Option<u64>,
+        records: &[(K, V, Attributes)],
+    ) -> io::Result<u64> {
+        let end = self.end;
+        return self
+            .write_chunk(batch, records)
+            .map_err(|e| self.locate(e, "batch write", None, Some(end)));
+    }
+
+    fn write_chunk<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        &mut self,
+        batch: Option<u64>,
+        records: &[(K, V, Attributes)],
+    ) -> io::Result<u64> {
+        self.flush_block()?;
+        let start = self.append_offset()?;
+        let batch = batch.unwrap_or(start);
+        let mut chunk = Gathered::default();
+        chunk.copy(&[0; BATCH_HEADER_SIZE as usize]);
+        let mut offsets = Vec::with_capacity(records.len());
+        for (key, value, attributes) in records {
+            let key = key.as_ref();
+            offsets.push((String::from_utf8_lossy(key).to_string(), start + chunk.len));
+            self.gather_record(&mut chunk, key, value.as_ref(), attributes);
+        }
+        let chunk_length = chunk.len;
+        chunk.patch(0, &CHUNK_MARKER.to_le_bytes());
+        chunk.patch(8, &chunk_length.to_le_bytes());
+        chunk.patch(16, &batch.to_le_bytes());
+        chunk.copy(&padding(self.alignment, start + chunk_length));
+        if let Err(e) = self.retry_write_gathered(&chunk) {
+            self.discard_from(start);
+            return Err(e);
+        }
+        self.pending.entry(batch).or_default().extend(offsets);
+        self.end = start + chunk.len;
+        self.written += chunk.len;
+        return Ok(batch);
+    }
+
+    /// Makes the chunks written to the log durable, writes the commit marker of `batch` and
+    /// indexes its records. Returns the keys indexed, in the order they were written.
+    pub(crate) fn commit_batch(&mut self, batch: u64) -> io::Result<Vec<String>> {
+        let end = self.end;
+        return self
+            .write_commit(batch)
+            .map_err(|e| self.locate(e, "batch commit", None, Some(end)));
+    }
+
+    fn write_commit(&mut self, batch: u64) -> io::Result<Vec<String>> {
+        // The marker must not reach the disk before the records it commits.
+        self.file.sync_data()?;
+        let start = self.append_offset()?;
+        let mut marker = Vec::with_capacity(BATCH_HEADER_SIZE as usize);
+        marker.extend_from_slice(&COMMIT_MARKER.to_le_bytes());
+        marker.extend_from_slice(&BATCH_HEADER_SIZE.to_le_bytes());
+        marker.extend_from_slice(&batch.to_le_bytes());
+        marker.extend_from_slice(&padding(self.alignment, start + BATCH_HEADER_SIZE));
+        if let Err(e) = self.retry_write(&marker) {
+            self.discard_from(start);
+            return Err(e);
+        }
+        self.commits.push(start);
+        self.end = start + marker.len() as u64;
+        self.written += marker.len() as u64;
+        let records = self.pending.remove(&batch).unwrap_or_default();
+        let mut keys = Vec::with_capacity(records.len());
+        for (key, offset) in records {
+            keys.push(key.clone());
+            self.index.insert(key, offset);
+        }
+        return Ok(keys);
+    }
+
+    /// Forgets the records of a pending batch, which stay in the log as dead records.
+    pub(crate) fn abort_batch(&mut self, batch: u64) {
+        self.pending.remove(&batch);
+    }
+
+    /// Offsets of the commit markers in the log.
+    pub(crate) fn commit_markers(&self) -> &[u64] {
+        return &self.commits;
+    }
+
+    /// Pads every write from now on to a multiple of `alignment` bytes and starts it on such
+    /// a boundary, or stops padding with `None`. The padding is a hole marker (see
+    /// `HOLE_MARKER`) that loading skips, so aligned and unaligned records can share a
+    /// segment and reading them needs no setting.
+    pub(crate) fn align_records(&mut self, alignment: Option<u64>) {
+        self.alignment = alignment.filter(|&alignment| alignment > 1);
+    }
+
+    /// Returns the offset the next write is appended at, first padding the log to the
+    /// alignment if it ends between two boundaries, e.g. after the segment header or after
+    /// unaligned records.
+    fn append_offset(&mut self) -> io::Result<u64> {
+        let end = self.file.seek(SeekFrom::End(0))?;
+        let padding = padding(self.alignment, end);
+        if padding.is_empty() {
+            return Ok(end);
+        }
+        self.retry_write(&padding)?;
+        self.written += padding.len() as u64;
+        self.end = end + padding.len() as u64;
+        return Ok(self.end);
+    }
+
+    /// Like `set`, but with aligned records (see `align_records`) adds the record to an
+    /// extent of up to `EXTENT_SIZE` bytes instead of padding it on its own. The extent is
+    /// written once it is full, or by the next `set`, `sync` or read, whichever comes first;
+    /// its keys are indexed right away. Compaction writes its records this way.
+    pub fn stage<T: AsRef<[u8]>, U: AsRef<[u8]>>(
+        &mut self,
+        key: T,
+        value: U,
+        attributes: &Attributes,
+    ) -> io::Result<()> {
+        let (key, end) = (key.as_ref(), self.end);
+        return self
+            .stage_record(key, value.as_ref(), attributes)
+            .map_err(|e| self.locate(e, "write", Some(key), Some(end)));
+    }
+
+    fn stage_record(
+        &mut self,
+        key_bytes: &[u8],
+        value: &[u8],
+        attributes: &Attributes,
+    ) -> io::Result<()> {
+        if self.alignment.is_none() || self.block.is_some() {
+            self.set(key_bytes, value, attributes)?;
+            return Ok(());
+        }
+        if self.extent.is_none() {
+            self.extent = Some(PendingExtent {
+                start: self.append_offset()?,
+                records: Vec::new(),
+                previous: HashMap::new(),
+            });
+        }
+        let record = self.encode_record(key_bytes, value, attributes);
+        let Some(extent) = &mut self.extent else {
+            return Ok(());
+        };
+        let offset = extent.start + extent.records.len() as u64;
+        extent.records.extend_from_slice(&record);
+        let full = extent.records.len() >= EXTENT_SIZE;
+        let key = String::from_utf8_lossy(key_bytes).to_string();
+        let before = self.index.insert(key.clone(), offset);
+        extent.previous.entry(key).or_insert(before);
+        if full {
+            self.flush_extent()?;
+        }
+        return Ok(());
+    }
+
+    /// Writes the extent that `stage` is filling, if any.
+    fn flush_extent(&mut self) -> io::Result<()> {
+        let Some(mut extent) = self.extent.take() else {
+            return Ok(());
+        };
+        let end = extent.start + extent.records.len() as u64;
+        extent
+            .records
+            .extend_from_slice(&padding(self.alignment, end));
+        self.file.seek(SeekFrom::Start(extent.start))?;
+        if let Err(e) = self.retry_write(&extent.records) {
+            self.restore_index(extent.previous);
+            return Err(e);
+        }
+        self.end = extent.start + extent.records.len() as u64;
+        self.written += extent.records.len() as u64;
+        return Ok(());
+    }
+
+    /// Writes `buf` at the cursor, retrying up to 3 times unless the disk is full. If every
+    /// attempt fails, whatever part of the buffer was written is cut off again.
+    fn retry_write(&mut self, buf: &[u8]) -> io::Result<()> {
+        return self.retry_write_parts(&[buf]);
+    }
+
+    /// Like `retry_write`, for the bytes `gathered` holds.
+    fn retry_write_gathered(&mut self, gathered: &Gathered) -> io::Result<()> {
+        return self.retry_write_parts(&gathered.parts);
+    }
+
+    fn retry_write_parts<T: AsRef<[u8]>>(&mut self, parts: &[T]) -> io::Result<()> {
+        let start = self.file.stream_position()?;
+        let mut attempts = 0;
+        loop {
+            match write_all_vectored(self.file.as_mut(), parts) {
+                Ok(_) => return Ok(()),
+                Err(e) if attempts < 2 && e.kind() != io::ErrorKind::StorageFull => {
+                    attempts += 1;
+                    // Overwrite whatever part of the buffer was written before the error.
+                    self.file.seek(SeekFrom::Start(start))?;
+                    continue;
+                }
+                Err(e) => {
+                    self.discard_from(start);
+                    return Err(e);
+                }
             }
-            Ok(None) => Ok(None), // Key not found
-            Err(e) => Err(e),     // Propagate other I/O errors
+        }
+    }
+
+    /// Drops the bytes from `start` on that a failed append left at the end of the log, so
+    /// that no partial record stays behind. Where the file cannot be shortened they stay and
+    /// loading treats them as a torn write.
+    fn discard_from(&mut self, start: u64) {
+        let _ = self.file.truncate(start);
+        let _ = self.file.seek(SeekFrom::Start(start));
+    }
+
+    /// Points the keys of a pending block or extent that could not be written back at their
+    /// records from before it.
+    fn restore_index(&mut self, previous: HashMap<String, Option<u64>>) {
+        for (key, offset) in previous {
+            match offset {
+                Some(offset) => self.index.insert(key, offset),
+                None => self.index.remove(&key),
+            };
+        }
+    }
+
+    /// Adds a record to a block of up to `block_size` bytes (at most `MAX_BLOCK_SIZE`) instead
+    /// of writing it on its own, or writes it with `set` if it is too large for a block. The
+    /// block is written once it is full, or by the next `set`, `sync` or read, whichever comes
+    /// first; its keys are indexed right away. Packing the same key again before then replaces
+    /// its entry.
+    pub fn pack<T: AsRef<[u8]>, U: AsRef<[u8]>>(
+        &mut self,
+        key: T,
+        value: U,
+        attributes: &Attributes,
+        block_size: usize,
+    ) -> io::Result<()> {
+        let (key, end) = (key.as_ref(), self.end);
+        return self
+            .pack_record(key, value.as_ref(), attributes, block_size)
+            .map_err(|e| self.locate(e, "write", Some(key), Some(end)));
+    }
+
+    fn pack_record(
+        &mut self,
+        key_bytes: &[u8],
+        value_bytes: &[u8],
+        attributes: &Attributes,
+        block_size: usize,
+    ) -> io::Result<()> {
+        let block_size = block_size.min(MAX_BLOCK_SIZE);
+        self.stamped(attributes.written_at);
+        // A key packed again replaces its entry in the pending block, which must not link to
+        // the block itself.
+        let pending = self.block.as_ref().map(|pending| pending.start);
+        let previous = self
+            .index
+            .get(&String::from_utf8_lossy(key_bytes))
+            .filter(|&offset| Some(offset) != pending);
+        let (value_length, block, checksum) = Self::encode_value(
+            self.write_checksum(),
+            key_bytes,
+            value_bytes,
+            attributes,
+            previous,
+        );
+        let body_length = (value_length & !LENGTH_FLAGS) as usize;
+        let entry_length = ENTRY_HEADER_SIZE + key_bytes.len() + body_length;
+        if BLOCK_HEADER_SIZE + 2 + entry_length > block_size || body_length > u16::MAX as usize {
+            self.set(key_bytes, value_bytes, attributes)?;
+            return Ok(());
+        }
+
+        let key = String::from_utf8_lossy(key_bytes).to_string();
+        let replaced = match &self.block {
+            Some(pending) => pending.entries.get(&key).map_or(0, |entry| entry.len() + 2),
+            None => 0,
         };
+        if let Some(pending) = &self.block {
+            if pending.len - replaced + 2 + entry_length > block_size {
+                self.flush_block()?;
+            }
+        }
+        if self.block.is_none() {
+            self.flush_extent()?;
+            self.block = Some(PendingBlock {
+                start: self.append_offset()?,
+                entries: BTreeMap::new(),
+                len: BLOCK_HEADER_SIZE,
+                previous: HashMap::new(),
+            });
+        }
+
+        let mut entry = Vec::with_capacity(entry_length);
+        entry.extend_from_slice(&(key_bytes.len() as u16).to_le_bytes());
+        entry.extend_from_slice(&(body_length as u16).to_le_bytes());
+        entry.push((value_length >> 56) as u8);
+        entry.extend_from_slice(key_bytes);
+        entry.extend_from_slice(&block);
+        entry.extend_from_slice(value_bytes);
+        entry.extend_from_slice(&checksum);
+
+        if let Some(pending) = &mut self.block {
+            if let Some(old) = pending.entries.insert(key.clone(), entry) {
+                pending.len -= old.len() + 2;
+            }
+            pending.len += 2 + entry_length;
+            let before = self.index.insert(key.clone(), pending.start);
+            pending.previous.entry(key).or_insert(before);
+        }
+        return Ok(());
+    }
+
+    /// Caches the blocks read from this segment in `cache`. Must be called again if the
+    /// segment is renamed.
+    pub(crate) fn cache_blocks(&mut self, cache: Option<Arc<BlockCache>>) {
+        let segment = crate::segment_number(Path::new(&self.path));
+        self.block_cache = cache.map(|cache| (cache, segment));
+    }
+
+    /// Writes the block that `pack` is filling and the extent that `stage` is filling, if
+    /// any.
+    pub fn flush_block(&mut self) -> io::Result<()> {
+        let end = self.end;
+        return self
+            .write_block()
+            .map_err(|e| self.locate(e, "block write", None, Some(end)));
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        self.flush_extent()?;
+        let Some(pending) = self.block.take() else {
+            return Ok(());
+        };
+        let mut offsets = Vec::with_capacity(2 * pending.entries.len());
+        let mut at = 2 + 2 * pending.entries.len();
+        for entry in pending.entries.values() {
+            offsets.extend_from_slice(&(at as u16).to_le_bytes());
+            at += entry.len();
+        }
+        let mut block = Vec::with_capacity(pending.len);
+        block.extend_from_slice(&BLOCK_MARKER.to_le_bytes());
+        block.extend_from_slice(&(pending.len as u64).to_le_bytes());
+        block.extend_from_slice(&(pending.entries.len() as u16).to_le_bytes());
+        block.extend_from_slice(&offsets);
+        for entry in pending.entries.values() {
+            block.extend_from_slice(entry);
+        }
+        block.extend_from_slice(&padding(self.alignment, pending.start + block.len() as u64));
+
+        self.file.seek(SeekFrom::Start(pending.start))?;
+        if let Err(e) = self.retry_write(&block) {
+            self.restore_index(pending.previous);
+            return Err(e);
+        }
+        self.end = pending.start + block.len() as u64;
+        self.written += block.len() as u64;
+        return Ok(());
+    }
+
+    /// Appends many key-value pairs through a single pre-sized write buffer.
+    /// The records use the same format as `set`, but the index is only updated once
+    /// every record has been written and the buffer flushed. With aligned records the
+    /// records are padded once, at the end.
+    /// Returns the number of records written.
+    pub fn set_all<I, T, U>(&mut self, entries: I) -> io::Result<u64>
+    where
+        I: IntoIterator<Item = (T, U)>,
+        T: AsRef<[u8]>,
+        U: AsRef<[u8]>,
+    {
+        let offsets = self.append_unindexed(entries)?;
+        let written = offsets.len() as u64;
+        self.index_appended(offsets);
+        return Ok(written);
+    }
+
+    /// Like `set_all`, but leaves the index as it is and returns the offset of each record,
+    /// for `index_appended` to add once a bulk load session is done with its records.
+    pub(crate) fn append_unindexed<I, T, U>(&mut self, entries: I) -> io::Result<Vec<(String, u64)>>
+    where
+        I: IntoIterator<Item = (T, U)>,
+        T: AsRef<[u8]>,
+        U: AsRef<[u8]>,
+    {
+        let end = self.end;
+        return self
+            .write_all_records(entries)
+            .map_err(|e| self.locate(e, "bulk write", None, Some(end)));
+    }
+
+    /// Points the keys of records written by `append_unindexed` at them.
+    pub(crate) fn index_appended(&mut self, offsets: Vec<(String, u64)>) {
+        self.index.extend(offsets);
+    }
+
+    fn write_all_records<I, T, U>(&mut self, entries: I) -> io::Result<Vec<(String, u64)>>
+    where
+        I: IntoIterator<Item = (T, U)>,
+        T: AsRef<[u8]>,
+        U: AsRef<[u8]>,
+    {
+        self.flush_block()?;
+        let start = self.append_offset()?;
+        let checksum = self.write_checksum();
+        let written =
+            Self::write_buffered(&mut self.file, entries, checksum, self.alignment, start);
+        let (offsets, offset) = match written {
+            Ok(written) => written,
+            Err(e) => {
+                self.discard_from(start);
+                return Err(e);
+            }
+        };
+
+        self.written += offset - start;
+        self.end = offset;
+        // Bulk writes carry no write time.
+        self.unstamped |= !offsets.is_empty();
+        return Ok(offsets);
+    }
+
+    /// Writes the records of `set_all` from `start` on and returns the offset of each key and
+    /// the end of the last record.
+    fn write_buffered<I, T, U>(
+        file: &mut Box<dyn LogFile>,
+        entries: I,
+        checksum: Option<ChecksumAlgorithm>,
+        alignment: Option<u64>,
+        start: u64,
+    ) -> io::Result<(Vec<(String, u64)>, u64)>
+    where
+        I: IntoIterator<Item = (T, U)>,
+        T: AsRef<[u8]>,
+        U: AsRef<[u8]>,
+    {
+        let mut offset = start;
+        let mut offsets = Vec::new();
+        let no_attributes = Attributes::default();
+        let mut writer = BufWriter::with_capacity(BULK_BUFFER_SIZE, file);
+        for (key, value) in entries {
+            let key_bytes = key.as_ref();
+            let value_bytes = value.as_ref();
+            let (value_length, _, checksum) =
+                Self::encode_value(checksum, key_bytes, value_bytes, &no_attributes, None);
+
+            writer.write_all(&(key_bytes.len() as u64).to_le_bytes())?;
+            writer.write_all(key_bytes)?;
+            writer.write_all(&value_length.to_le_bytes())?;
+            writer.write_all(value_bytes)?;
+            writer.write_all(&checksum)?;
+
+            offsets.push((String::from_utf8_lossy(key_bytes).to_string(), offset));
+            offset += 16 + key_bytes.len() as u64 + (value_length & !LENGTH_FLAGS);
+        }
+        let padding = padding(alignment, offset);
+        writer.write_all(&padding)?;
+        writer.flush()?;
+        offset += padding.len() as u64;
+        return Ok((offsets, offset));
+    }
+
+    /// Adds where `error` happened to it, keeping its kind, unless it already says: the
+    /// operation, the key if known, the offset of the record if known and this segment's path.
+    fn locate(
+        &self,
+        error: io::Error,
+        operation: &'static str,
+        key: Option<&[u8]>,
+        offset: Option<u64>,
+    ) -> io::Error {
+        if error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<LocatedError>())
+        {
+            return error;
+        }
+        let located = LocatedError {
+            operation,
+            path: self.path.clone(),
+            key: key.map(|key| String::from_utf8_lossy(key).into_owned()),
+            offset,
+            source: error,
+        };
+        return io::Error::new(located.source.kind(), located);
+    }
+
+    /// Returns the number of bytes this handle has written to the log, including hole markers
+    /// and records that were later superseded.
+    pub fn written(&self) -> u64 {
+        return self.written;
+    }
+
+    /// Flushes written records to the disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.flush_block()?;
+        return self
+            .file
+            .sync_data()
+            .map_err(|e| self.locate(e, "sync", None, None));
+    }
+
+    /// Returns the offset of the key's latest record, if the key exists.
+    pub fn offset(&self, key: &str) -> Option<u64> {
+        return self.index.get(key);
+    }
+
+    /// Returns all keys currently in the index.
+    pub fn keys(&self) -> Vec<String> {
+        return self.index.keys();
+    }
+
+    /// Returns the keys in the index in the order of their records in the log, so that reading
+    /// them one after another reads through the log.
+    pub fn keys_in_log_order(&self) -> Vec<String> {
+        let mut keys: Vec<(u64, String)> = self
+            .index
+            .keys()
+            .into_iter()
+            .map(|key| (self.index.get(&key).unwrap_or_default(), key))
+            .collect();
+        keys.sort_unstable();
+        return keys.into_iter().map(|(_, key)| key).collect();
+    }
+
+    /// Returns the keys in the index that start with `prefix`.
+    pub fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        return self.index.keys_with_prefix(prefix);
+    }
+
+    /// Keeps the index as a radix tree from now on, see `index`.
+    pub(crate) fn compress_index(&mut self, compress: bool) {
+        if compress {
+            self.index.compress();
+        }
     }
 
-    /// Retrieves the value associated with a given key.
-    /// The returned value is now a generic `Vec<u8>`.
+    /// Estimates the heap memory the index takes up: the bytes of every key and the table or
+    /// tree holding them.
+    pub fn index_memory_bytes(&self) -> u64 {
+        return self.index.memory_bytes();
+    }
+
+    /// Retrieves the value and attributes of the record stored at `offset`, which must belong
+    /// to `key`. This also reaches older records of a key that are no longer in the index.
     ///
-    /// It uses the stored offset to seek directly to the key's position in the file,
+    /// It seeks directly to the record's position in the file,
     /// then reads the key (to advance pointer) and finally the value bytes.
-    fn get_value_bytes(&mut self, key: &str) -> io::Result<Option<Vec<u8>>> {
-        // 1. Check if the key exists in the index.
-        let &offset = match self.index.get(key) {
-            Some(o) => o,
-            None => return Ok(None), // Key not found in index
-        };
+    /// With `verify`, the record's checksum is checked if it has one.
+    pub fn get_value_bytes_at(
+        &mut self,
+        key: &str,
+        offset: u64,
+        verify: bool,
+    ) -> io::Result<Option<(Vec<u8>, Attributes)>> {
+        return self.get_value_into(key, offset, verify, Vec::new());
+    }
+
+    /// Like `get_value_bytes_at`, but reads the value into `buf`, reusing its allocation
+    /// unless the record is packed into a block.
+    pub fn get_value_into(
+        &mut self,
+        key: &str,
+        offset: u64,
+        verify: bool,
+        buf: Vec<u8>,
+    ) -> io::Result<Option<(Vec<u8>, Attributes)>> {
+        return self
+            .read_value_at(key, offset, verify, buf)
+            .map_err(|e| self.locate(e, "read", Some(key.as_bytes()), Some(offset)));
+    }
 
+    fn read_value_at(
+        &mut self,
+        key: &str,
+        offset: u64,
+        verify: bool,
+        mut buf: Vec<u8>,
+    ) -> io::Result<Option<(Vec<u8>, Attributes)>> {
+        self.flush_block()?;
+        // Only blocks are cached.
+        if let Some((cache, segment)) = &self.block_cache {
+            if let Some(body) = cache.get(*segment, offset) {
+                return find_packed(key, &body, self.checksum, verify).map(Some);
+            }
+        }
+        if let Some(found) = self.read_ahead(key, offset, verify)? {
+            return Ok(Some(found));
+        }
         // Seek to the stored offset (start of the key-value entry).
         self.file.seek(SeekFrom::Start(offset))?;
+        let mut marker = [0; 8];
+        if self.file.read_exact(&mut marker).is_ok() && u64::from_le_bytes(marker) == BLOCK_MARKER {
+            return self.get_packed(key, offset, verify);
+        }
+        self.file.seek(SeekFrom::Start(offset))?;
 
         // 2. Read the key and validate it to ensure there is no data corruption.
-        match self.read_bytes() {
-            Ok(key_bytes) => {
-                let key_str = String::from_utf8(key_bytes)
-                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
-
-                // Validate that the key matches the requested key.
-                if key_str != key {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Data corruption: key mismatch",
-                    ));
-                }
+        let mut key_bytes = std::mem::take(&mut self.scratch);
+        let read = self.read_bytes(&mut key_bytes);
+        let matches = key_bytes == key.as_bytes();
+        self.scratch = key_bytes;
+        match read {
+            // Validate that the key matches the requested key.
+            Ok(_) if !matches => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Data corruption: key mismatch",
+                ));
             }
+            Ok(_) => {}
             Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
                 return Ok(None); // Incomplete entry, return None
             }
@@ -214,18 +1542,393 @@ impl KVStore {
         }
 
         // 3. Read the value bytes.
-        match self.read_bytes() {
-            Ok(value_bytes) => Ok(Some(value_bytes)),
+        let flags = match self.read_bytes(&mut buf) {
+            Ok(flags) => flags,
             // If EOF is reached *after* reading the key but before the value, it's an incomplete entry.
-            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
-            Err(e) => Err(e), // Propagate other I/O errors
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e), // Propagate other I/O errors
+        };
+        self.read_start = offset;
+        self.read_end = offset + 16 + key.len() as u64 + buf.len() as u64;
+
+        // 4. Strip the checksum, verifying it if requested, and the attributes.
+        return decode_value(key, buf, flags, self.checksum, verify).map(Some);
+    }
+
+    /// Reads the record of `key` at `offset` from a window of the log read ahead, once reads
+    /// have followed each other through the log for a while, as in a scan of a compacted
+    /// segment. A scan then costs one read of `READ_AHEAD_SIZE` bytes per window instead of
+    /// a seek and reads per record. Returns `None` for the caller to read the record itself:
+    /// outside a sequential run, and for blocks and records the window cannot hold.
+    fn read_ahead(
+        &mut self,
+        key: &str,
+        offset: u64,
+        verify: bool,
+    ) -> io::Result<Option<(Vec<u8>, Attributes)>> {
+        let follows = offset >= self.read_end && offset - self.read_end <= READ_AHEAD_GAP;
+        if offset != self.read_start {
+            // Reading a record again, e.g. its attributes and then its value, keeps the run.
+            self.sequential_reads = match follows {
+                true => self.sequential_reads.saturating_add(1),
+                false => 0,
+            };
+        }
+        if self.sequential_reads < READ_AHEAD_AFTER {
+            self.read_ahead = None;
+            return Ok(None);
+        }
+        if self.read_ahead.is_none() {
+            // Reading on from here is what the scan does, so let the kernel read ahead too.
+            self.file.advise_sequential();
+        }
+        for refilled in [false, true] {
+            let window = self
+                .read_ahead
+                .as_ref()
+                .filter(|window| window.end == self.end && offset >= window.start);
+            if let Some(window) = window {
+                let at = (offset - window.start) as usize;
+                let record = parse_record(window.bytes.get(at..).unwrap_or_default());
+                if let Ok((record_key, flags, value)) = record {
+                    if record_key != key.as_bytes() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Data corruption: key mismatch",
+                        ));
+                    }
+                    let value = value.to_vec();
+                    self.read_start = offset;
+                    self.read_end = offset + 16 + record_key.len() as u64 + value.len() as u64;
+                    return decode_value(key, value, flags, self.checksum, verify).map(Some);
+                }
+                if record.is_err_and(|length| length.is_some_and(|length| length > READ_AHEAD_SIZE))
+                {
+                    break;
+                }
+            }
+            if refilled || offset >= self.end {
+                break;
+            }
+            let mut bytes = Vec::new();
+            self.file.seek(SeekFrom::Start(offset))?;
+            Read::by_ref(&mut self.file)
+                .take(READ_AHEAD_SIZE.min(self.end - offset))
+                .read_to_end(&mut bytes)?;
+            self.read_ahead = Some(ReadAhead {
+                start: offset,
+                bytes,
+                end: self.end,
+            });
+        }
+        return Ok(None);
+    }
+
+    /// Reads the records at `offsets`, which ascend, from the first one on in a single read as
+    /// far as they lie within `READ_AHEAD_GAP` of each other and `READ_AHEAD_SIZE` of the
+    /// first, and serves the reads of those records that follow from it, as for a scan.
+    /// Returns how many of `offsets` the read covers; a lone record is left to be read itself.
+    pub(crate) fn read_ahead_of(&mut self, offsets: &[u64]) -> io::Result<usize> {
+        let Some(&start) = offsets.first() else {
+            return Ok(0);
+        };
+        let mut covered = 1;
+        while let Some(&offset) = offsets.get(covered) {
+            if offset - offsets[covered - 1] > READ_AHEAD_GAP || offset - start >= READ_AHEAD_SIZE {
+                break;
+            }
+            covered += 1;
+        }
+        if covered == 1 || start >= self.end {
+            return Ok(covered);
+        }
+        self.flush_block()?;
+        let last = offsets[covered - 1] - start;
+        let mut bytes = Vec::new();
+        self.file.seek(SeekFrom::Start(start))?;
+        let length = (last + COALESCED_TAIL).min(self.end - start);
+        Read::by_ref(&mut self.file)
+            .take(length)
+            .read_to_end(&mut bytes)?;
+        // The index holds no lengths, so a last record longer than the tail is read on.
+        if let Err(Some(record_length)) =
+            parse_record(bytes.get(last as usize..).unwrap_or_default())
+        {
+            if record_length != u64::MAX {
+                let end = last.saturating_add(record_length).min(self.end - start);
+                Read::by_ref(&mut self.file)
+                    .take(end.saturating_sub(bytes.len() as u64))
+                    .read_to_end(&mut bytes)?;
+            }
+        }
+        self.read_ahead = Some(ReadAhead {
+            start,
+            bytes,
+            end: self.end,
+        });
+        // The first record now follows the last one read.
+        self.sequential_reads = READ_AHEAD_AFTER;
+        self.read_start = u64::MAX;
+        self.read_end = start;
+        return Ok(covered);
+    }
+
+    /// Reads the entry of `key` from the block at `offset`, with the cursor just after its
+    /// marker.
+    fn get_packed(
+        &mut self,
+        key: &str,
+        offset: u64,
+        verify: bool,
+    ) -> io::Result<Option<(Vec<u8>, Attributes)>> {
+        let mut length = [0; 8];
+        if self.file.read_exact(&mut length).is_err() {
+            return Ok(None);
+        }
+        let Some(body_length) = u64::from_le_bytes(length).checked_sub(16) else {
+            return Err(corrupt_block());
+        };
+        let body = match self.read_length(body_length) {
+            Ok(body) => body,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let found = find_packed(key, &body, self.checksum, verify)?;
+        if let Some((cache, segment)) = &self.block_cache {
+            cache.insert(*segment, offset, body.into());
+        }
+        return Ok(Some(found));
+    }
+}
+
+fn open_failed(path: &Path, e: io::Error) -> io::Error {
+    return io::Error::new(
+        e.kind(),
+        format!("Failed to open segment {}: {}", path.display(), e),
+    );
+}
+
+/// An I/O error of a segment with where it happened, see `KVStore::locate`.
+#[derive(Debug)]
+struct LocatedError {
+    operation: &'static str,
+    path: String,
+    key: Option<String>,
+    offset: Option<u64>,
+    source: io::Error,
+}
+
+impl fmt::Display for LocatedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed", self.operation)?;
+        if let Some(key) = &self.key {
+            write!(f, " for key {:?}", key)?;
         }
+        if let Some(offset) = self.offset {
+            write!(f, " at offset {}", offset)?;
+        }
+        return write!(f, " of {}: {}", self.path, self.source);
+    }
+}
+
+impl error::Error for LocatedError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        return Some(&self.source);
+    }
+}
+
+/// Returns the padding that moves the end of the log from `end` to the next boundary of
+/// `alignment`, which is empty without aligned records.
+fn padding(alignment: Option<u64>, end: u64) -> Vec<u8> {
+    let Some(alignment) = alignment else {
+        return Vec::new();
+    };
+    let mut length = end.next_multiple_of(alignment) - end;
+    if length == 0 {
+        return Vec::new();
+    }
+    // The marker needs room too.
+    while length < HOLE_HEADER_SIZE {
+        length += alignment;
     }
+    // The rest is not zeros, which would read as a record of an empty key to a scan that a
+    // corrupted length sent into it; it reads as a torn write instead.
+    let mut padding = vec![0xFF; length as usize];
+    padding[0..8].copy_from_slice(&HOLE_MARKER.to_le_bytes());
+    padding[8..16].copy_from_slice(&length.to_le_bytes());
+    return padding;
+}
 
-    /// Converts a vector of bytes to a String.
-    fn to_string(&self, bytes: Vec<u8>) -> io::Result<Option<String>> {
-        return String::from_utf8(bytes)
-            .map(Some)
-            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err));
+/// Writes all of `parts` to `file` one after another, as `Write::write_all` writes a single
+/// buffer, in as few vectored writes as the file accepts.
+fn write_all_vectored<T: AsRef<[u8]>>(file: &mut dyn LogFile, parts: &[T]) -> io::Result<()> {
+    if let [part] = parts {
+        return file.write_all(part.as_ref());
     }
+    let mut slices: Vec<IoSlice> = parts
+        .iter()
+        .map(|part| IoSlice::new(part.as_ref()))
+        .filter(|slice| !slice.is_empty())
+        .collect();
+    let mut slices = slices.as_mut_slice();
+    while !slices.is_empty() {
+        match file.write_vectored(slices) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+            Ok(written) => IoSlice::advance_slices(&mut slices, written),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    return Ok(());
+}
+
+/// The key, value flags and value of a record, see `parse_record`.
+type ParsedRecord<'a> = (&'a [u8], u64, &'a [u8]);
+
+/// Splits the record at the start of `bytes` into its key, the flags of its value and the
+/// value. Where `bytes` do not hold the whole record, returns its length if that is known,
+/// or `u64::MAX` for a block.
+fn parse_record(bytes: &[u8]) -> Result<ParsedRecord<'_>, Option<u64>> {
+    let length_at = |at: usize| -> Option<u64> {
+        let length = bytes.get(at..at + 8)?;
+        return Some(u64::from_le_bytes(length.try_into().unwrap_or_default()));
+    };
+    let Some(key_length) = length_at(0) else {
+        return Err(None);
+    };
+    if key_length == BLOCK_MARKER {
+        return Err(Some(u64::MAX));
+    }
+    let key_length = key_length & !LENGTH_FLAGS;
+    let Some(value_length) = usize::try_from(key_length)
+        .ok()
+        .and_then(|key_length| length_at(8 + key_length))
+    else {
+        return Err(None);
+    };
+    let flags = value_length & LENGTH_FLAGS;
+    let value_length = value_length & !LENGTH_FLAGS;
+    let record_length = 16u64
+        .saturating_add(key_length)
+        .saturating_add(value_length);
+    if record_length > bytes.len() as u64 {
+        return Err(Some(record_length));
+    }
+    let key_end = 8 + key_length as usize;
+    let value_start = key_end + 8;
+    return Ok((
+        &bytes[8..key_end],
+        flags,
+        &bytes[value_start..record_length as usize],
+    ));
+}
+
+/// Finds the entry of `key` in a block's body and decodes it.
+fn find_packed(
+    key: &str,
+    body: &[u8],
+    checksum: ChecksumAlgorithm,
+    verify: bool,
+) -> io::Result<(Vec<u8>, Attributes)> {
+    // Entries are sorted by key.
+    let entries = block_entries(body)?;
+    let (mut low, mut high) = (0, entries.len());
+    while low < high {
+        let middle = (low + high) / 2;
+        let (entry_key, flags, value) = block_entry(body, entries[middle])?;
+        match entry_key.cmp(key.as_bytes()) {
+            std::cmp::Ordering::Less => low = middle + 1,
+            std::cmp::Ordering::Greater => high = middle,
+            std::cmp::Ordering::Equal => {
+                return decode_value(key, value.to_vec(), flags, checksum, verify)
+            }
+        }
+    }
+    return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Data corruption: key missing from its block",
+    ));
+}
+
+/// Strips the checksum of a record's value, verifying it if requested, and the attributes.
+fn decode_value(
+    key: &str,
+    mut value_bytes: Vec<u8>,
+    flags: u64,
+    checksum: ChecksumAlgorithm,
+    verify: bool,
+) -> io::Result<(Vec<u8>, Attributes)> {
+    if flags & CHECKSUM_FLAG != 0 {
+        let Some(split) = value_bytes.len().checked_sub(checksum.width()) else {
+            return Err(checksum_mismatch(key));
+        };
+        if verify {
+            let mut hasher = checksum.hasher();
+            hasher.update(key.as_bytes());
+            hasher.update(&value_bytes[..split]);
+            if hasher.finish() != value_bytes[split..] {
+                return Err(checksum_mismatch(key));
+            }
+        }
+        value_bytes.truncate(split);
+    }
+    let mut history = None;
+    if flags & HISTORY_FLAG != 0 {
+        if value_bytes.len() < HISTORY_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Corrupt record history",
+            ));
+        }
+        let written_at = u64::from_le_bytes(value_bytes[0..8].try_into().unwrap_or_default());
+        let previous = u64::from_le_bytes(value_bytes[8..16].try_into().unwrap_or_default());
+        value_bytes.drain(..HISTORY_SIZE);
+        history = Some((written_at, (previous != u64::MAX).then_some(previous)));
+    }
+    let (mut attributes, value_bytes) = match flags & ATTRIBUTES_FLAG != 0 {
+        true => Attributes::decode(value_bytes)?,
+        false => (Attributes::default(), value_bytes),
+    };
+    attributes.collection = flags & COLLECTION_FLAG != 0;
+    if let Some((written_at, previous)) = history {
+        attributes.written_at = Some(written_at);
+        attributes.previous = previous;
+    }
+    return Ok((value_bytes, attributes));
+}
+
+/// Returns the offsets of the entries of a block's body, which follows its length.
+fn block_entries(body: &[u8]) -> io::Result<Vec<usize>> {
+    let count = read_u16(body, 0)?;
+    return (0..count).map(|i| read_u16(body, 2 + 2 * i)).collect();
+}
+
+/// Splits the entry at `at` of a block's body into its key, flags and value.
+fn block_entry(body: &[u8], at: usize) -> io::Result<(&[u8], u64, &[u8])> {
+    let key_length = read_u16(body, at)?;
+    let value_length = read_u16(body, at + 2)?;
+    let flags = (*body.get(at + 4).ok_or_else(corrupt_block)? as u64) << 56 & LENGTH_FLAGS;
+    let key_start = at + ENTRY_HEADER_SIZE;
+    let value_start = key_start + key_length;
+    let key = body.get(key_start..value_start).ok_or_else(corrupt_block)?;
+    let value = body
+        .get(value_start..value_start + value_length)
+        .ok_or_else(corrupt_block)?;
+    return Ok((key, flags, value));
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> io::Result<usize> {
+    let value = bytes.get(at..at + 2).ok_or_else(corrupt_block)?;
+    return Ok(u16::from_le_bytes([value[0], value[1]]) as usize);
+}
+
+fn corrupt_block() -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, "Data corruption: corrupt block");
+}
+
+fn checksum_mismatch(key: &str) -> io::Error {
+    return io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Data corruption: checksum mismatch for key {}", key),
+    );
 }
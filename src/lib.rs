@@ -1,8 +1,131 @@
+#![allow(clippy::needless_return)]
+mod adaptive;
+pub mod archive;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+mod backpressure;
+pub mod backup;
+mod batch;
+pub mod bench;
+pub mod blob;
+mod builder;
+mod bulk;
+mod cache;
+mod checksum;
+mod collection;
+mod compaction;
+#[cfg(feature = "zstd")]
+pub mod compression;
+pub mod crash;
+pub mod dedup;
+pub mod delta;
+mod digest;
+mod entry;
+mod error;
+mod events;
+mod eviction;
+mod expiry;
+mod filter;
+mod flush;
+mod frame;
+mod guard;
+mod headroom;
+mod health;
+mod history;
+mod hotkeys;
+pub mod import;
+mod index;
+pub mod keycodec;
+mod keys;
 mod kvstore;
+mod locks;
+mod multiget;
+mod notify;
+mod observer;
+mod options;
+mod orphans;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod pin;
+mod pitr;
+mod progress;
+mod quota;
+#[cfg(feature = "raft")]
+pub mod raft;
+mod reader;
+mod reclaim;
+mod replication;
+mod retention;
+mod runtime;
+mod scheduler;
+pub mod schema;
+mod setting;
+mod slowlog;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+mod stats;
+#[cfg(feature = "futures")]
+mod stream;
+mod sync;
+pub mod typed;
+mod value;
+mod version;
+pub mod vfs;
+mod warm;
+mod wheel;
+
+pub use adaptive::AdaptiveCompaction;
+pub use backpressure::{BackpressureOptions, PressureCause, PressureLevel};
+pub use batch::LargeBatch;
+pub use builder::Builder;
+pub use bulk::BulkLoadSession;
+pub use cache::CacheStats;
+pub use checksum::ChecksumAlgorithm;
+pub use compaction::CompactionTask;
+pub use digest::{key_range, MAX_DIGEST_DEPTH};
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use error::{BoxError, Error, Result};
+pub use eviction::EvictionOptions;
+pub use expiry::Expiration;
+pub use filter::{CompactionFilter, FilterDecision};
+pub use guard::ValueGuard;
+pub use health::{CompactionOutcome, Health};
+pub use history::{HistoryOptions, Revision};
+pub use hotkeys::HotKeys;
+pub use keycodec::KeyCodec;
+pub use locks::{KeyGuard, KeyLocks};
+pub use observer::{CompactionReport, Observer};
+pub use options::{ReadOptions, WriteOptions};
+pub use orphans::OrphanReport;
+pub use pitr::RestorePoint;
+pub use progress::{CancellationToken, LoadProgress};
+pub use quota::QuotaUsage;
+pub use reader::{Reader, FOLLOW_INTERVAL};
+pub use replication::{ReplicationSink, ReplicationSource, ReplicationStatus};
+pub use runtime::BackgroundRuntime;
+pub use scheduler::CompactionScheduler;
+pub use schema::SchemaRegistry;
+pub use slowlog::{Operation, SlowOperation};
+pub use stats::Stats;
+#[cfg(feature = "futures")]
+pub use stream::{EntryStream, STREAM_BATCH};
+pub use sync::SyncReport;
+pub use typed::TypedRCask;
+pub use version::Version;
+
+#[cfg(feature = "sled")]
+pub use import::sled::migrate_from_sled;
+
+use blob::BlobStore;
+use dedup::ContentStore;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::Result;
+use std::io;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// RCask is a wrapper around the KVStore which manages the disk storage size does not exceed a limit.
 /// This is done using a blocking compaction process which is fired after a certain number of writes
@@ -13,6 +136,62 @@ pub struct RCask {
     max_writes: u64,
     store: kvstore::KVStore,
     writes: u64,
+    /// Number of the active segment, the high bits of every `Version`.
+    segment: u64,
+    values: value::ValueEncoding,
+    /// Checksum of the records of new segments, `None` without checksums.
+    checksum: ChecksumAlgorithm,
+    /// Whether every read verifies checksums.
+    verify_checksums: bool,
+    cache: Option<cache::ReadCache>,
+    /// Keys kept in memory with `pin`.
+    pins: pin::Pins,
+    /// Values `get_ref` read from the log.
+    ref_buffer: Vec<u8>,
+    /// What reader handles need to see this handle's writes.
+    published: Arc<reader::Published>,
+    locks: KeyLocks,
+    expiry: Option<expiry::ExpiryListener>,
+    filter: Option<Box<dyn CompactionFilter>>,
+    hot_keys: Option<hotkeys::HotKeyTracker>,
+    slow_log: Option<slowlog::SlowLog>,
+    health: health::HealthState,
+    fs: Arc<dyn vfs::FileSystem>,
+    /// Superseded segments that could not be deleted yet because they were still open
+    /// elsewhere, see `vfs`.
+    retired: Vec<PathBuf>,
+    /// Files left behind by an earlier run that `open` cleaned up.
+    orphans: OrphanReport,
+    /// Keys relocated per step of incremental compaction, or `None` for full compactions.
+    compaction_batch: Option<usize>,
+    migration: Option<compaction::Migration>,
+    /// Size of the blocks compaction packs small records into, if it does.
+    block_size: Option<usize>,
+    /// Boundary that writes to the log are padded to, if they are.
+    alignment: Option<u64>,
+    /// Whether indexes are radix trees, see `Builder::prefix_compressed_index`.
+    compress_index: bool,
+    block_cache: Option<Arc<cache::BlockCache>>,
+    scheduler: Option<Arc<CompactionScheduler>>,
+    traffic: stats::Traffic,
+    throttle: Option<backpressure::Throttle>,
+    sync_timer: flush::SyncTimer,
+    adaptive: Option<adaptive::Controller>,
+    /// Recency of every key in bounded mode.
+    lru: Option<eviction::Lru>,
+    /// How much history compaction keeps, if records carry it.
+    history: Option<history::HistoryOptions>,
+    /// Usage of the quotas of `Builder::quota`, if any.
+    quotas: Option<quota::Quotas>,
+    events: Option<events::EventLog>,
+    /// Disk space reserved for compaction, see `Builder::disk_headroom`.
+    headroom: Option<headroom::Headroom>,
+    observers: Vec<Box<dyn Observer>>,
+    key_codec: Option<Arc<dyn KeyCodec>>,
+    /// How long records are kept, see `Builder::retention`.
+    retention: Option<Duration>,
+    /// Held while the store is open, so only one handle writes to it.
+    _writer_lock: fs::File,
 }
 
 impl RCask {
@@ -21,36 +200,218 @@ impl RCask {
     /// /// If no matching files are found, it creates a new log file with the specified pattern.
     /// The `max_writes` parameter specifies the maximum number of writes before compaction is triggered.
     pub fn init(directory: String, pattern: String, max_writes: u64) -> Result<Self> {
-        fs::create_dir_all(&directory)?; // Ensure directory exists
+        return Self::builder(directory, pattern)
+            .max_writes(max_writes)
+            .open();
+    }
 
-        let logs = fs::read_dir(&directory)?;
-        let mut paths = Vec::new();
-        for file in logs {
-            let file = file?;
-            let path = file.path();
-            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
-            if file_name.starts_with(&pattern) && file_name.ends_with(".log") {
-                paths.push(path);
-            }
+    /// Returns a builder for configuring a store before opening it.
+    pub fn builder(directory: String, pattern: String) -> Builder {
+        return Builder::new(directory, pattern);
+    }
+
+    /// Opens a store like `Builder::open`, but gives up with `Error::Cancelled` once `token`
+    /// is cancelled, e.g. while scanning a huge or damaged log. A cancelled open writes
+    /// nothing to an existing store's directory.
+    pub fn open_with_cancel(mut builder: Builder, token: CancellationToken) -> Result<Self> {
+        builder.cancel = Some(token);
+        return Self::open(builder);
+    }
+
+    /// Opens a store with the options collected by a `Builder`.
+    pub(crate) fn open(builder: Builder) -> Result<Self> {
+        let started = Instant::now();
+        let Builder {
+            directory,
+            pattern,
+            max_writes,
+            schema,
+            delta,
+            dedup,
+            blobs,
+            checksums,
+            checksum_algorithm,
+            cache_capacity,
+            expiry,
+            filter,
+            hot_keys,
+            slow_log,
+            min_free_disk,
+            fs,
+            compaction_batch,
+            block_size,
+            alignment,
+            compress_index,
+            block_cache,
+            progress,
+            cancel,
+            scheduler,
+            backpressure,
+            on_backpressure,
+            sync_interval,
+            adaptive,
+            eviction,
+            history,
+            quotas,
+            events,
+            disk_headroom,
+            quarantine_orphans,
+            refresh_interval: _,
+            observers,
+            key_codec,
+            retention,
+            #[cfg(feature = "zstd")]
+            compression,
+        } = builder;
+        let is_cancelled = || cancel.as_ref().is_some_and(|token| token.is_cancelled());
+        if is_cancelled() {
+            return Err(Error::Cancelled);
         }
+        fs::create_dir_all(&directory)?; // Ensure directory exists
+        let writer_lock = reader::lock_writer(&directory, &pattern)?;
 
-        paths.sort();
+        // Superseded segments that are still open elsewhere may not be deleted yet.
+        let paths = segment_paths(&directory, &pattern)?;
 
+        let checksum = match checksums {
+            Some(_) => checksum_algorithm,
+            None => ChecksumAlgorithm::None,
+        };
+        let mut progress = progress;
+        let mut cancelled = false;
+        let mut report = |bytes_processed, bytes_total, records_indexed| {
+            if is_cancelled() {
+                cancelled = true;
+                return Err(io::Error::from(io::ErrorKind::Interrupted));
+            }
+            if let Some(callback) = &mut progress {
+                callback(&LoadProgress {
+                    segments_scanned: u64::from(bytes_processed == bytes_total),
+                    segments_total: 1,
+                    bytes_processed,
+                    bytes_total,
+                    records_indexed,
+                });
+            }
+            return Ok(());
+        };
         let store = if let Some(path) = paths.last() {
-            kvstore::KVStore::new(&path)?
+            kvstore::KVStore::with_progress(fs.as_ref(), path, checksum, &mut report)
         } else {
             // Create the first segment (e.g., data.0.log) if none exist
             let initial_path = PathBuf::from(format!("{}/{}.0.log", directory, pattern));
-            kvstore::KVStore::new(&initial_path)?
+            kvstore::KVStore::with_progress(fs.as_ref(), &initial_path, checksum, &mut report)
+        };
+        let mut store = match store {
+            Err(_) if cancelled => return Err(Error::Cancelled),
+            store => store?,
+        };
+        keycodec::check(
+            &directory,
+            &pattern,
+            key_codec.as_deref(),
+            store.keys().is_empty(),
+        )?;
+
+        let dedup = match dedup {
+            Some(options) => Some(ContentStore::open(&directory, &pattern, options)?),
+            None => None,
+        };
+        let blobs = match blobs {
+            Some(options) => Some(BlobStore::open(&directory, &pattern, options)?),
+            None => None,
         };
+        let mut values = value::ValueEncoding {
+            schema: schema.map(Arc::new),
+            delta,
+            dedup,
+            blobs,
+            shared: HashSet::new(),
+            live_blobs: HashSet::new(),
+            #[cfg(feature = "zstd")]
+            compression,
+            #[cfg(feature = "zstd")]
+            dictionary: None,
+        };
+        values.check_format(&directory, &pattern, store.keys().is_empty())?;
+        values.open_segment(Path::new(&store.path))?;
+        let block_cache = block_cache.map(|capacity| Arc::new(cache::BlockCache::new(capacity)));
+        store.cache_blocks(block_cache.clone());
+        store.align_records(alignment);
+        store.compress_index(compress_index);
 
-        Ok(RCask {
+        let published = Arc::new(reader::Published::new(&store));
+        let mut rcask = RCask {
             directory,
             pattern,
             max_writes,
+            segment: segment_number(Path::new(&store.path)),
             store,
             writes: 0,
-        })
+            values,
+            checksum,
+            verify_checksums: checksums.unwrap_or(false),
+            cache: cache_capacity.map(cache::ReadCache::new),
+            pins: pin::Pins::default(),
+            ref_buffer: Vec::new(),
+            published,
+            locks: KeyLocks::new(),
+            expiry,
+            filter,
+            hot_keys: hot_keys.map(hotkeys::HotKeyTracker::new),
+            slow_log: slow_log
+                .map(|(threshold, capacity)| slowlog::SlowLog::new(threshold, capacity)),
+            health: health::HealthState::new(min_free_disk),
+            fs,
+            compaction_batch,
+            retired: Vec::new(),
+            orphans: OrphanReport::default(),
+            migration: None,
+            block_size,
+            alignment,
+            compress_index,
+            block_cache,
+            scheduler,
+            traffic: stats::Traffic::default(),
+            throttle: backpressure
+                .map(|options| backpressure::Throttle::new(options, on_backpressure)),
+            sync_timer: flush::SyncTimer::new(sync_interval),
+            adaptive: adaptive.map(adaptive::Controller::new),
+            lru: eviction.map(eviction::Lru::new),
+            history,
+            quotas: (!quotas.is_empty()).then(|| quota::Quotas::new(quotas)),
+            events: events.map(events::EventLog::new),
+            headroom: (disk_headroom > 0).then(|| headroom::Headroom::new(disk_headroom)),
+            observers,
+            key_codec,
+            retention,
+            _writer_lock: writer_lock,
+        };
+        rcask.seed_lru()?;
+        rcask.measure_quotas()?;
+        if rcask.events.is_some() {
+            let path = rcask.store.path.clone();
+            let length = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+            let fields = [
+                ("segment", events::Field::Str(&path)),
+                (
+                    "records",
+                    events::Field::U64(rcask.store.keys().len() as u64),
+                ),
+                ("bytes", events::Field::U64(rcask.store.end())),
+                (
+                    "torn_bytes",
+                    events::Field::U64(length.saturating_sub(rcask.store.end())),
+                ),
+                (
+                    "duration_ms",
+                    events::Field::U64(started.elapsed().as_millis() as u64),
+                ),
+            ];
+            rcask.log_event::<()>("recovery", None, &fields);
+        }
+        rcask.collect_orphans(quarantine_orphans)?;
+        return Ok(rcask);
     }
 
     /// Creates a new RCask instance with a default `max_writes` of 10,000.
@@ -59,50 +420,709 @@ impl RCask {
         return Self::init(directory, pattern, 10000);
     }
 
-    /// Sets a key-value pair in the store.
+    /// Sets a key-value pair in the store and returns the version of the new record.
     /// If the number of writes exceeds `max_writes`, it triggers a compaction process.
-    pub fn set<T: AsRef<[u8]>, U: AsRef<[u8]>>(&mut self, key: T, value: U) -> Result<()> {
-        return match self.store.set(key, value) {
-            Ok(_) => {
-                self.writes += 1;
-                if self.writes >= self.max_writes {
-                    self.compact()?;
+    pub fn set<T: AsRef<[u8]>, U: AsRef<[u8]>>(&mut self, key: T, value: U) -> Result<Version> {
+        return self.set_opt(key, value, &WriteOptions::default());
+    }
+
+    /// Sets a key-value pair only if the key is still at the `expected` version, e.g. the one
+    /// returned by `version` before the caller read the value it is about to replace.
+    /// Fails with `Error::Conflict` if the key has been written in the meantime, and also if
+    /// a compaction ran since, even one started by `max_writes`: it moves every record and so
+    /// gives every key a new version. Callers read the key again and retry on a conflict.
+    pub fn set_if_version<T: AsRef<[u8]>, U: AsRef<[u8]>>(
+        &mut self,
+        key: T,
+        value: U,
+        expected: Version,
+    ) -> Result<Version> {
+        let key_str = String::from_utf8_lossy(key.as_ref()).to_string();
+        let actual = self.version(&key_str);
+        if actual != Some(expected) {
+            return Err(Error::Conflict {
+                key: key_str,
+                expected,
+                actual,
+            });
+        }
+        return self.set(key, value);
+    }
+
+    /// Returns the version of the key's latest record, if the key exists. Compaction changes
+    /// it, see `set_if_version`.
+    pub fn version(&self, key: &str) -> Option<Version> {
+        return self.stored_version(&self.stored_key(key));
+    }
+
+    fn stored_version(&self, key: &str) -> Option<Version> {
+        return self
+            .store
+            .offset(key)
+            .map(|offset| Version::new(self.segment, offset));
+    }
+
+    /// Returns the key stored for `key`, see `keycodec`.
+    pub(crate) fn stored_key<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        return match &self.key_codec {
+            Some(codec) => codec.encode(key),
+            None => Cow::Borrowed(key),
+        };
+    }
+
+    /// Returns the position just past the last record, which is below the version of every
+    /// later write, e.g. to take incremental backups from with `backup_since`.
+    pub fn sequence(&self) -> Version {
+        return Version::new(self.segment, self.store.end());
+    }
+
+    /// Locks a key for a read-modify-write sequence, blocking while another caller holds it.
+    /// The lock is released when the guard is dropped.
+    ///
+    /// Locks are advisory and only exclude other `lock` calls. Keys share a fixed number of
+    /// lock stripes, so unrelated keys occasionally wait on each other, and locking a key twice
+    /// from one thread deadlocks.
+    pub fn lock<T: AsRef<[u8]>>(&self, key: T) -> KeyGuard {
+        return self.locks.lock(key);
+    }
+
+    /// Returns a handle on this store's key locks. A store shared through a `Mutex` must not
+    /// be locked while waiting for a key, so take key locks through this handle and lock the
+    /// store only around the individual reads and writes.
+    pub fn key_locks(&self) -> KeyLocks {
+        return self.locks.clone();
+    }
+
+    /// Sets a key-value pair with per-call options, e.g. to fsync this write or give it a TTL.
+    pub fn set_opt<T: AsRef<[u8]>, U: AsRef<[u8]>>(
+        &mut self,
+        key: T,
+        value: U,
+        options: &WriteOptions,
+    ) -> Result<Version> {
+        let key = utf8_key(key.as_ref())?;
+        let Some(codec) = &self.key_codec else {
+            return self.set_stored(key.as_bytes(), value.as_ref(), options);
+        };
+        let key = codec.encode(key).into_owned();
+        return self.set_stored(key.as_bytes(), value.as_ref(), options);
+    }
+
+    /// Like `set_opt`, with the key as stored.
+    pub(crate) fn set_stored(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        options: &WriteOptions,
+    ) -> Result<Version> {
+        let started = Instant::now();
+        let key_str = utf8_key(key)?.to_string();
+        self.observe_set(&key_str, value)?;
+        self.admit_write()?;
+        let attributes = kvstore::Attributes {
+            expires_at: options
+                .ttl
+                .map(|ttl| now_millis().saturating_add(ttl.as_millis() as u64)),
+            meta: options.meta.clone(),
+            written_at: self.stamp(),
+            ..Default::default()
+        };
+        self.invalidate(&key_str);
+        self.record_write(&key_str);
+        let bytes = value.len() as u64;
+        self.traffic.user_bytes += key.len() as u64 + bytes;
+        // Values stored outside the log are written out while they are encoded.
+        if let Some(size) = self.values.reference_size(value) {
+            self.admit_quota(&key_str, (key_str.len() + size) as u64, false)?;
+        }
+        let value = self.encode_update(key, value)?;
+        let stored = (key_str.len() + value.len()) as u64;
+        self.admit_quota(&key_str, stored, false)?;
+        let offset = self.store.set(key, value, &attributes);
+        self.health.wrote(&offset);
+        let offset = offset?;
+        let written = Version::new(self.segment, offset);
+        if let (Some(listener), Some(expires_at)) = (&mut self.expiry, attributes.expires_at) {
+            listener.schedule(&key_str, expires_at);
+        }
+        self.lru_wrote([(key_str.as_str(), stored)], false)?;
+        let compacts = self.writes + 1 >= self.max_writes;
+        self.finish_write(options.sync)?;
+        if let Some(log) = &mut self.slow_log {
+            let cause = if compacts {
+                Some("blocked on compaction")
+            } else {
+                options.sync.then_some("fsync")
+            };
+            log.record(Operation::Set, key_str.len(), bytes, started, cause);
+        }
+        // Compaction may have moved the record.
+        return Ok(self.stored_version(&key_str).unwrap_or(written));
+    }
+
+    /// Removes a key and returns whether it had a value. The key reads as missing until it is
+    /// written again, and compaction drops it.
+    pub fn delete(&mut self, key: &str) -> Result<bool> {
+        let key = self.stored_key(key);
+        let key = key.as_ref();
+        if self.read_latest(key, self.verify_checksums)?.is_none() {
+            return Ok(false);
+        }
+        self.admit_write()?;
+        self.record_write(key);
+        self.write_tombstone(key)?;
+        self.finish_write(false)?;
+        self.observe_delete(key);
+        return Ok(true);
+    }
+
+    /// Removes every key. The keys are not deleted one by one: an empty segment replaces the
+    /// active one, which is deleted with every blob and deduplicated value, and any running
+    /// incremental compaction is abandoned. After a crash the store is either cleared or left
+    /// as it was. Readers see the empty store once it is cleared.
+    pub fn clear(&mut self) -> Result<()> {
+        if let Some(migration) = self.migration.take() {
+            let path = PathBuf::from(&migration.store.path);
+            drop(migration);
+            self.fs.remove_file(&path)?;
+        }
+        let path = PathBuf::from(self.get_next_segment_path());
+        let mut store = kvstore::KVStore::new(self.fs.as_ref(), &path, self.checksum)?;
+        store.align_records(self.alignment);
+        store.compress_index(self.compress_index);
+        self.values.prepare_segment(&path, std::iter::empty())?;
+        store.sync()?;
+        store.cache_blocks(self.block_cache.clone());
+        self.replace_segment(store)?;
+
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
+        self.pins.invalidate_all();
+        if let Some(listener) = &mut self.expiry {
+            listener.clear();
+        }
+        self.lru_cleared();
+        self.writes = 0;
+        return Ok(());
+    }
+
+    /// Appends a tombstone for `key`, which hides every earlier record of it.
+    fn write_tombstone(&mut self, key: &str) -> Result<()> {
+        self.invalidate(key);
+        let attributes = kvstore::Attributes {
+            written_at: self.stamp(),
+            ..kvstore::Attributes::tombstone()
+        };
+        let written = self.store.set(key, [], &attributes);
+        self.health.wrote(&written);
+        written?;
+        self.lru_removed(key);
+        return Ok(());
+    }
+
+    /// Drops any cached copy of a key that is about to be written.
+    fn invalidate(&mut self, key: &str) {
+        if let Some(cache) = &mut self.cache {
+            cache.remove(key);
+        }
+        self.pins.invalidate(key);
+        if let Some(listener) = &mut self.expiry {
+            listener.forget(key);
+        }
+        self.requeue(key);
+    }
+
+    /// Accounts for a record that was just appended and runs the compaction check.
+    fn finish_write(&mut self, sync: bool) -> Result<()> {
+        self.sync_timer.appended();
+        if sync {
+            self.sync()?;
+        } else {
+            self.sync_if_due()?;
+        }
+        self.published.appended(&self.store);
+        self.writes += 1;
+        return self.check_compaction();
+    }
+
+    /// Runs a step of the incremental compaction in progress, or starts a compaction once
+    /// enough writes have accumulated.
+    fn check_compaction(&mut self) -> Result<()> {
+        if self.migration.is_some() {
+            return self.migrate_step();
+        }
+        if self.writes < self.max_writes {
+            return Ok(());
+        }
+        if self.compaction_batch.is_some() {
+            self.start_migration()?;
+            return self.migrate_step();
+        }
+        return self.compact();
+    }
+
+    /// Loads many key-value pairs much faster than calling `set` in a loop.
+    /// Records are written through one large buffer, the index is updated once at the end,
+    /// and the compaction check runs once after the whole batch instead of per record.
+    /// Returns the number of records written. The store does not implement `Extend`, which
+    /// could not report a failed write; load collections through this instead.
+    pub fn bulk_load<I, T, U>(&mut self, entries: I) -> Result<u64>
+    where
+        I: IntoIterator<Item = (T, U)>,
+        T: AsRef<[u8]>,
+        U: AsRef<[u8]>,
+    {
+        self.begin_bulk_load()?;
+        let mut user_bytes = 0;
+        // Sizes of the stored records, for bounded mode and quotas.
+        let mut sizes = Vec::new();
+        let track_sizes = self.lru.is_some() || self.quotas.is_some();
+        let identity = self.values.is_identity();
+        let codec = self.key_codec.clone();
+        // Stop at the first key that is not UTF-8 and report it once the prefix is indexed.
+        let mut key_error = None;
+        let entries = entries.into_iter().map_while(|(key, value)| {
+            let text = match utf8_key(key.as_ref()) {
+                Ok(text) => text,
+                Err(e) => {
+                    key_error = Some(e);
+                    return None;
+                }
+            };
+            return Some(match &codec {
+                Some(codec) => {
+                    let key = codec.encode(text).into_owned();
+                    (keycodec::CodedKey::Encoded(key), value)
                 }
-                Ok(())
+                None => (keycodec::CodedKey::Plain(key), value),
+            });
+        });
+        let entries = entries.inspect(|(key, value)| {
+            let bytes = (key.as_ref().len() + value.as_ref().len()) as u64;
+            user_bytes += bytes;
+            if track_sizes && identity {
+                sizes.push((String::from_utf8_lossy(key.as_ref()).into_owned(), bytes));
             }
-            Err(e) => {
+        });
+        if identity {
+            let written = self.store.set_all(entries);
+            self.traffic.user_bytes += user_bytes;
+            self.health.wrote(&written);
+            let written = written?;
+            self.lru_wrote(sizes.iter().map(|(key, size)| (key.as_str(), *size)), false)?;
+            if let Some(e) = key_error {
+                self.finish_bulk_load(written)?;
                 return Err(e);
             }
-        };
+            return self.finish_bulk_load(written);
+        }
+
+        // Stop at the first value that fails to encode and report it once the prefix is indexed.
+        let values = &mut self.values;
+        let mut error = None;
+        let mut encoded_sizes = Vec::new();
+        let records = entries
+            .into_iter()
+            .map_while(|(key, value)| match values.encode(value.as_ref()) {
+                Ok(stored) => Some((key, stored.into_owned())),
+                Err(e) => {
+                    error = Some(e);
+                    None
+                }
+            })
+            .inspect(|(key, stored)| {
+                if track_sizes {
+                    let key = String::from_utf8_lossy(key.as_ref()).into_owned();
+                    let bytes = (key.len() + stored.len()) as u64;
+                    encoded_sizes.push((key, bytes));
+                }
+            });
+        let written = self.store.set_all(records);
+        self.traffic.user_bytes += user_bytes;
+        self.health.wrote(&written);
+        let written = written?;
+        let sizes = encoded_sizes
+            .iter()
+            .map(|(key, size)| (key.as_str(), *size));
+        self.lru_wrote(sizes, false)?;
+        if let Some(e) = error.or(key_error) {
+            self.finish_bulk_load(written)?;
+            return Err(e);
+        }
+        return self.finish_bulk_load(written);
+    }
+
+    /// Prepares the store for a bulk load, which does not invalidate keys one by one.
+    pub(crate) fn begin_bulk_load(&mut self) -> Result<()> {
+        self.admit_write()?;
+        // The loaded keys cannot be queued for relocation, so finish relocating first.
+        self.finish_pending_migration()?;
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
+        self.pins.invalidate_all();
+        return Ok(());
+    }
+
+    /// Accounts for a completed bulk load and runs the deferred compaction check.
+    pub(crate) fn finish_bulk_load(&mut self, written: u64) -> Result<u64> {
+        self.sync_timer.appended();
+        self.sync_if_due()?;
+        self.published.appended(&self.store);
+        self.writes += written;
+        self.check_compaction()?;
+        return Ok(written);
+    }
+
+    /// Returns a read-only handle that can read from another thread while this handle keeps
+    /// writing. Every write that returned on this handle is visible to the reader's
+    /// subsequent reads.
+    pub fn reader(&self) -> Result<Reader> {
+        return Reader::new(
+            self.fs.clone(),
+            self.published.clone(),
+            self.values.for_reader(),
+            self.verify_checksums,
+            self.compress_index,
+            self.block_cache.clone(),
+            self.key_codec.clone(),
+        );
     }
 
     /// Retrieves the value associated with a given key in string format.
     pub fn get(&mut self, key: &str) -> Result<Option<String>> {
-        return self.store.get(key);
+        return match self.get_bytes(key)? {
+            Some(bytes) => String::from_utf8(bytes)
+                .map(Some)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err).into()),
+            None => Ok(None),
+        };
+    }
+
+    /// Retrieves the raw value bytes associated with a given key.
+    pub fn get_bytes(&mut self, key: &str) -> Result<Option<Vec<u8>>> {
+        return self.get_opt(key, &ReadOptions::default());
+    }
+
+    /// Retrieves the raw value bytes associated with a given key with per-call options,
+    /// e.g. to verify the record's checksum or to keep a scan out of the read cache.
+    pub fn get_opt(&mut self, key: &str, options: &ReadOptions) -> Result<Option<Vec<u8>>> {
+        let key = self.stored_key(key);
+        return self.get_opt_into(&key, options, Vec::new());
+    }
+
+    /// Like `get_bytes`, but reads the value into `buf`, replacing what it held, and returns
+    /// whether the key has a value. Values read from the log reuse `buf`'s allocation unless
+    /// they have to be decoded, e.g. from a delta chain, a blob file or zstd compression, so a
+    /// hot loop reading into the same buffer need not allocate for every read.
+    pub fn get_into(&mut self, key: &str, buf: &mut Vec<u8>) -> Result<bool> {
+        let options = ReadOptions::default();
+        let key = self.stored_key(key);
+        return match self.get_opt_into(&key, &options, std::mem::take(buf))? {
+            Some(value) => {
+                *buf = value;
+                Ok(true)
+            }
+            None => Ok(false),
+        };
+    }
+
+    /// Reads a value for `get_opt` into `buf`, with the key as stored.
+    pub(crate) fn get_opt_into(
+        &mut self,
+        key: &str,
+        options: &ReadOptions,
+        buf: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>> {
+        let started = Instant::now();
+        let value = self.read_value(key, options, buf);
+        self.health.read(&value);
+        let value = value?;
+        match value {
+            Some(_) => self.lru_read(key),
+            None => self.observe_miss(key),
+        }
+        if let Some(log) = &mut self.slow_log {
+            let verify = options.verify_checksum || self.verify_checksums;
+            let bytes = value.as_ref().map_or(0, |value| value.len() as u64);
+            let cause = verify.then_some("checksum verification");
+            log.record(Operation::Get, key.len(), bytes, started, cause);
+        }
+        return Ok(value);
     }
 
+    /// Reads a value for `get_opt_into`.
+    fn read_value(
+        &mut self,
+        key: &str,
+        options: &ReadOptions,
+        buf: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>> {
+        self.record_read(key);
+        let verify = options.verify_checksum || self.verify_checksums;
+        if !verify {
+            if let Some(value) = self.pinned_value(key) {
+                return Ok(Some(value.to_vec()));
+            }
+            // A pinned key not held yet is read from the log to be held.
+            let cached = match self.pins.contains(key) {
+                true => None,
+                false => self.cache.as_mut().and_then(|c| c.get(key, now_millis())),
+            };
+            if let Some(value) = cached {
+                return Ok(Some(value));
+            }
+        }
+
+        let record = reader::read_latest_into(&mut self.store, &mut self.values, key, verify, buf)?;
+        let Some((framed, attributes)) = record else {
+            return Ok(None);
+        };
+        reader::expect_value(key, &attributes)?;
+        let offset = self.store.offset(key).unwrap_or_default();
+        let (plain, _) = self.resolve(key, offset, framed, verify)?;
+        let value = self.values.untag_schema(plain)?;
+        self.pins.loaded(key, &value, attributes.expires_at);
+        if options.fill_cache {
+            if let Some(cache) = &mut self.cache {
+                cache.insert(key, value.clone(), attributes.expires_at);
+            }
+        }
+        return Ok(Some(value));
+    }
+
+    /// Retrieves the metadata stored with a key through `WriteOptions::meta`.
+    pub fn get_meta(&mut self, key: &str) -> Result<Option<Vec<u8>>> {
+        let key = self.stored_key(key);
+        return Ok(self
+            .read_latest(&key, self.verify_checksums)?
+            .and_then(|(_, attributes)| attributes.meta));
+    }
+
+    /// Returns every live key together with its latest value.
+    /// Keys holding collections are skipped, and the scan bypasses the read cache. Keys are
+    /// read in the order of their records, so the scan reads through the log.
+    pub fn get_all_key_values(&mut self) -> Result<HashMap<String, Vec<u8>>> {
+        let options = ReadOptions {
+            fill_cache: false,
+            ..Default::default()
+        };
+        let mut entries = HashMap::new();
+        for key in self.store.keys_in_log_order() {
+            match self.get_opt_into(&key, &options, Vec::new()) {
+                Ok(Some(value)) => {
+                    let key = match &self.key_codec {
+                        Some(codec) => codec.decode(&key).into_owned(),
+                        None => key,
+                    };
+                    entries.insert(key, value);
+                }
+                Ok(None) | Err(Error::WrongType { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        return Ok(entries);
+    }
+
+    /// Encodes a value for `set`, as a delta against the key's previous record when
+    /// delta encoding is enabled and that makes the record smaller.
+    /// Values stored outside the log are never stored as deltas.
+    fn encode_update<'a>(&mut self, key: &[u8], value: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        let Some(options) = self.values.delta.clone() else {
+            return self.values.encode(value);
+        };
+        let key = String::from_utf8_lossy(key);
+        let previous = match self.store.offset(&key) {
+            Some(offset) if value.len() >= options.min_value_size => Some(offset),
+            _ => None,
+        };
+        let previous = previous.filter(|_| !self.values.is_external(value));
+        let Some(base) = previous else {
+            return self.values.encode(value);
+        };
+
+        let plain = self.values.tag_schema(value);
+        let framed = match self.read_plain(&key, base, self.verify_checksums)? {
+            Some((base_plain, depth)) if depth < options.max_chain => {
+                let ops = delta::diff(&base_plain, &plain);
+                if ops.len() + 10 < plain.len() {
+                    frame::delta(depth + 1, base, &ops)
+                } else {
+                    frame::full(&plain)
+                }
+            }
+            _ => frame::full(&plain),
+        };
+        return self.values.seal(Cow::Owned(framed));
+    }
+
+    fn read_plain(
+        &mut self,
+        key: &str,
+        offset: u64,
+        verify: bool,
+    ) -> Result<Option<(Vec<u8>, u16)>> {
+        return reader::read_plain(&mut self.store, &mut self.values, key, offset, verify);
+    }
+
+    fn resolve(
+        &mut self,
+        key: &str,
+        offset: u64,
+        framed: Vec<u8>,
+        verify: bool,
+    ) -> Result<(Vec<u8>, u16)> {
+        return reader::resolve(
+            &mut self.store,
+            &mut self.values,
+            key,
+            offset,
+            framed,
+            verify,
+        );
+    }
+
+    fn read_latest(
+        &mut self,
+        key: &str,
+        verify: bool,
+    ) -> Result<Option<(Vec<u8>, kvstore::Attributes)>> {
+        return reader::read_latest(&mut self.store, &mut self.values, key, verify);
+    }
+
+    /// Compacts the log and records the outcome for `health`.
     fn compact(&mut self) -> Result<()> {
+        let scheduler = self.scheduler.clone();
+        let mut permit = scheduler.as_deref().map(CompactionScheduler::acquire);
+        let (writes, before, started) = (self.writes, self.store.end(), Instant::now());
+        let result = self.compact_segment();
+        if let Some(permit) = &mut permit {
+            permit.wrote(self.store.end());
+        }
+        self.health.compacted(&result);
+        self.log_compaction(&result, "full", started.elapsed(), before);
+        if result.is_ok() {
+            self.tune_compaction(adaptive::Observation {
+                writes,
+                before,
+                after: self.store.end(),
+                busy: started.elapsed(),
+            });
+        }
+        return result;
+    }
+
+    fn compact_segment(&mut self) -> Result<()> {
+        if self.drop_outlived_segment()? {
+            return Ok(());
+        }
+        let started = Instant::now();
         // 1. Get the path for the new (compacted) segment file.
         let next_segment = self.get_next_segment_path();
         let segment_path = PathBuf::from(&next_segment);
 
-        let mut new_store = kvstore::KVStore::new(Path::new(&segment_path))?;
+        let mut new_store =
+            kvstore::KVStore::new(self.fs.as_ref(), Path::new(&segment_path), self.checksum)?;
+        new_store.align_records(self.alignment);
+        new_store.compress_index(self.compress_index);
 
-        // 2. Iterate over all keys in the current store and write them to the new store.
-        for (key, value) in self.store.get_all_key_values()? {
-            new_store.set(key, value)?;
+        // 2. Decode every live value of the current store. Re-encoding them below upgrades
+        // old schema versions, collapses delta chains into full values and recompresses values
+        // with a freshly trained dictionary. References to values stored outside the log are
+        // carried over as they are, so those values are never read or rewritten. Expired keys
+        // are dropped and reported, and every other record keeps its attributes. The compaction
+        // filter, if any, sees every value and may drop or replace it; references are only
+        // resolved to show them to the filter. Every collection becomes a single snapshot.
+        // With history, the older versions it retains come along (see `history`).
+        let mut live = Vec::new();
+        let mut versions = Vec::new();
+        let mut expired = Vec::new();
+        for key in self.relocation_order() {
+            let latest = self.relocate(&key, &mut expired)?;
+            let history = self.relocate_history(&key, latest.is_some(), &mut new_store)?;
+            if !history.is_empty() {
+                versions.push((key.clone(), history));
+            }
+            if let Some((record, attributes)) = latest {
+                live.push((key, record, attributes));
+            }
         }
+        let values = live.iter().filter_map(|(_, record, _)| match record {
+            compaction::Relocated::Value(value) => Some(value.as_slice()),
+            _ => None,
+        });
+        self.values.prepare_segment(&segment_path, values)?;
 
-        // 3. Replace the current store with the new store.
-        fs::remove_file(Path::new(&self.store.path))?;
-        self.store = new_store;
+        // 3. Write them to the new store, every key's older versions first.
+        for (key, history) in versions {
+            self.write_history(&mut new_store, &key, history)?;
+        }
+        for (key, record, attributes) in live {
+            self.write_relocated(&mut new_store, &key, record, &attributes)?;
+        }
+        new_store.flush_block()?;
+        new_store.cache_blocks(self.block_cache.clone());
+
+        // 4. Replace the current store with the new store and point readers at it, then
+        // delete the old one and everything only it referenced.
+        self.replace_segment(new_store)?;
 
-        // 4. Reset the write count.
+        // 5. Report the dropped keys and reset the write count.
+        self.emit_expirations(expired);
+        if let Some(listener) = &mut self.expiry {
+            listener.clear();
+        }
         self.writes = 0;
+        if let Some(log) = &mut self.slow_log {
+            log.record(Operation::Compaction, 0, self.store.end(), started, None);
+        }
 
         return Ok(());
     }
 
+    /// Makes `store` the active segment and points readers at it, then deletes the old one
+    /// and everything only it referenced.
+    fn replace_segment(&mut self, store: kvstore::KVStore) -> Result<()> {
+        self.traffic.retired_bytes += self.store.written();
+        let old_path = std::mem::replace(&mut self.store, store).path;
+        self.segment = segment_number(Path::new(&self.store.path));
+        self.published.replaced(&self.store);
+        // Compaction filters and retention drop or replace values the cache may hold.
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
+        self.pins.invalidate_all();
+        self.retire_segment(PathBuf::from(&old_path))?;
+        self.values.remove_segment(Path::new(&old_path))?;
+        return self.measure_quotas();
+    }
+
+    /// Deletes a superseded segment along with any that could not be deleted before. A
+    /// segment that is still open elsewhere, which only prevents deletion on Windows, is kept
+    /// for the next time.
+    fn retire_segment(&mut self, path: PathBuf) -> Result<()> {
+        self.retired.push(path);
+        let mut kept = Vec::new();
+        let mut failure = None;
+        for path in std::mem::take(&mut self.retired) {
+            match self.fs.remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) if failure.is_some() || is_in_use(&e) => kept.push(path),
+                Err(e) => {
+                    kept.push(path);
+                    failure = Some(e);
+                }
+            }
+        }
+        self.retired = kept;
+        return match failure {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        };
+    }
+
     fn get_next_segment_path(&self) -> String {
         let Ok(logs) = fs::read_dir(&self.directory) else {
             // Should not happen if new() worked.
@@ -122,8 +1142,7 @@ impl RCask {
                     return None;
                 }
 
-                let stem = path.file_stem()?.to_str()?;
-                stem.rsplit('.').next()?.parse::<u64>().ok()
+                Some(segment_number(&path))
             })
             .collect();
 
@@ -132,3 +1151,48 @@ impl RCask {
         return format!("{}/{}.{}.log", self.directory, self.pattern, next_index);
     }
 }
+
+/// Whether `error` means that a file could not be renamed or deleted because another handle
+/// has it open, as on Windows.
+fn is_in_use(error: &io::Error) -> bool {
+    return matches!(
+        error.kind(),
+        io::ErrorKind::PermissionDenied | io::ErrorKind::ResourceBusy
+    );
+}
+
+/// Parses the number of a segment from its path, `<pattern>.<n>.log`.
+/// Returns the segments of the store `pattern` in `directory`, oldest first.
+fn segment_paths(directory: &str, pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for file in fs::read_dir(directory)? {
+        let path = file?.path();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        if file_name.starts_with(pattern) && file_name.ends_with(".log") {
+            paths.push(path);
+        }
+    }
+    paths.sort_by_key(|path| segment_number(path));
+    return Ok(paths);
+}
+
+fn segment_number(path: &Path) -> u64 {
+    return path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.rsplit('.').next())
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0);
+}
+
+/// Returns `key` as text, or `Error::InvalidKey` if it is not UTF-8.
+fn utf8_key(key: &[u8]) -> Result<&str> {
+    return std::str::from_utf8(key).map_err(|_| Error::InvalidKey { key: key.to_vec() });
+}
+
+/// Milliseconds since the Unix epoch, the unit of record expiry times.
+pub(crate) fn now_millis() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64);
+}
@@ -0,0 +1,72 @@
+//! Advisory per-key locks.
+//!
+//! Keys are hashed onto a fixed number of stripes, each a flag guarded by a mutex and a
+//! condition variable. Locks are advisory: they only exclude other callers of `RCask::lock`,
+//! never plain reads or writes.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Number of stripes. Different keys on the same stripe also exclude each other.
+const STRIPES: usize = 64;
+
+#[derive(Default)]
+struct Stripe {
+    held: Mutex<bool>,
+    released: Condvar,
+}
+
+struct LockTable {
+    stripes: Vec<Stripe>,
+}
+
+/// The lock table of a store, see `RCask::key_locks`. Clones share the same locks.
+#[derive(Clone)]
+pub struct KeyLocks {
+    table: Arc<LockTable>,
+}
+
+impl KeyLocks {
+    pub(crate) fn new() -> Self {
+        return KeyLocks {
+            table: Arc::new(LockTable {
+                stripes: (0..STRIPES).map(|_| Stripe::default()).collect(),
+            }),
+        };
+    }
+
+    /// Blocks until no other caller holds the lock on `key`, then takes it.
+    /// The lock is released when the guard is dropped.
+    pub fn lock<T: AsRef<[u8]>>(&self, key: T) -> KeyGuard {
+        let mut hasher = DefaultHasher::new();
+        key.as_ref().hash(&mut hasher);
+        let stripe = (hasher.finish() % STRIPES as u64) as usize;
+
+        let entry = &self.table.stripes[stripe];
+        let mut held = entry.held.lock().unwrap_or_else(|e| e.into_inner());
+        while *held {
+            held = entry.released.wait(held).unwrap_or_else(|e| e.into_inner());
+        }
+        *held = true;
+        return KeyGuard {
+            table: self.table.clone(),
+            stripe,
+        };
+    }
+}
+
+/// Holds the lock on a key until it is dropped, see `RCask::lock`.
+#[must_use = "the key is unlocked as soon as the guard is dropped"]
+pub struct KeyGuard {
+    table: Arc<LockTable>,
+    stripe: usize,
+}
+
+impl Drop for KeyGuard {
+    fn drop(&mut self) {
+        let entry = &self.table.stripes[self.stripe];
+        *entry.held.lock().unwrap_or_else(|e| e.into_inner()) = false;
+        entry.released.notify_one();
+    }
+}
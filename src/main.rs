@@ -1,28 +1,209 @@
+#![allow(clippy::needless_return)]
+use rcask::bench::{self, Workload};
 use rcask::RCask;
+use std::env;
 use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process;
+use std::time::Duration;
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut store = RCask::init(
-        "./".to_string(), // Directory to store logs
-        "log".to_string(), // Pattern for log files
-        3)?; // Maximum number of writes before compaction
+const USAGE: &str = "Usage:
+    rcask import-rocksdb <DUMP_FILE|-> <DIRECTORY> <PATTERN>    Import the output of `ldb dump`
+    rcask bench <DIRECTORY> [OPTIONS]                           Benchmark a store in DIRECTORY
+    rcask shell <DIRECTORY> [PATTERN]                           Open an interactive shell on a store
 
-    // Default store with max_writes as 10000 before compaction
-    let mut default_store = RCask::new(
-        "./".to_string(), // Directory to store logs
-        "default_log".to_string(), // Pattern for log files
-    )?;
+Bench options:
+    --keys <N>          Number of distinct keys (default 10000)
+    --value-size <N>    Value size in bytes (default 100)
+    --read-ratio <R>    Share of reads, from 0.0 to 1.0 (default 0.9)
+    --threads <N>       Number of threads (default 4)
+    --operations <N>    Total number of operations (default 100000)
 
-    store.set("key1", "value1")?;
-    store.set("key2", "value2")?;
-    store.set("key3", "value3")?;
+Shell options:
+    PATTERN             Prefix of the segment files (default data)";
 
-    println!("Value for key1: {:?}", store.get("key1")?);
-    println!("Value for key2: {:?}", store.get("key2")?);
-    println!("Value for key3: {:?}", store.get("key3")?);
+const SHELL_HELP: &str = "Commands:
+    get <KEY>             Print the value of KEY
+    set <KEY> <VALUE>     Set KEY to the rest of the line
+    del <KEY>             Delete KEY
+    scan [PREFIX]         Print every key starting with PREFIX and its value
+    keys [PREFIX]         Complete PREFIX: print the known keys starting with it
+    stats                 Print the store's counters
+    compact               Compact the log
+    history               Print the commands entered so far
+    !<N>                  Run command N of the history again
+    help                  Print this help
+    quit                  Leave the shell";
 
-    store.set("key1", "response: { values: ['A', 'B', 'C'] } ")?;
-    println!("Updated value for key1: {:?}", store.get("key1")?);
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("import-rocksdb") => import_rocksdb(&args[1..]),
+        Some("bench") => run_bench(&args[1..]),
+        Some("shell") => run_shell(&args[1..]),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
 
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Bulk-loads an `ldb dump` listing (read from a file, or stdin for `-`) into a store.
+fn import_rocksdb(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let [dump, directory, pattern] = args else {
+        return Err(USAGE.into());
+    };
+
+    let mut store = RCask::new(directory.to_string(), pattern.to_string())?;
+    let imported = if dump == "-" {
+        rcask::import::rocksdb::import_dump(io::stdin().lock(), &mut store)?
+    } else {
+        rcask::import::rocksdb::import_dump(BufReader::new(File::open(dump)?), &mut store)?
+    };
+
+    println!("Imported {} records into {}", imported, directory);
+    return Ok(());
+}
+
+/// Runs a benchmark workload against a store in the given directory.
+fn run_bench(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let Some((directory, options)) = args.split_first() else {
+        return Err(USAGE.into());
+    };
+
+    let mut workload = Workload::default();
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let value = options.next().ok_or(USAGE)?;
+        match option.as_str() {
+            "--keys" => workload.keys = value.parse()?,
+            "--value-size" => workload.value_size = value.parse()?,
+            "--read-ratio" => workload.read_ratio = value.parse()?,
+            "--threads" => workload.threads = value.parse()?,
+            "--operations" => workload.operations = value.parse()?,
+            _ => return Err(USAGE.into()),
+        }
+    }
+
+    let store = RCask::new(directory.to_string(), "bench".to_string())?;
+    print!("{}", bench::run(store, &workload)?);
     return Ok(());
 }
+
+/// Reads commands from stdin and runs them against a store until `quit` or end of input.
+fn run_shell(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (directory, pattern) = match args {
+        [directory] => (directory, "data"),
+        [directory, pattern] => (directory, pattern.as_str()),
+        _ => return Err(USAGE.into()),
+    };
+
+    let mut store = RCask::new(directory.to_string(), pattern.to_string())?;
+    let mut history: Vec<String> = Vec::new();
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        print!("rcask> ");
+        io::stdout().flush()?;
+        let Some(line) = lines.next() else {
+            println!();
+            return Ok(());
+        };
+        let mut line = line?.trim().to_string();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(number) = line.strip_prefix('!') {
+            let entry = number
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| history.get(n.checked_sub(1)?));
+            let Some(entry) = entry else {
+                println!("error: no command {} in the history", number);
+                continue;
+            };
+            line = entry.clone();
+            println!("{}", line);
+        }
+        history.push(line.clone());
+        match run_shell_command(&mut store, &line, &history) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(e) => println!("error: {}", e),
+        }
+    }
+}
+
+/// Runs one shell command and returns whether the shell goes on.
+fn run_shell_command(
+    store: &mut RCask,
+    line: &str,
+    history: &[String],
+) -> Result<bool, Box<dyn Error>> {
+    let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let rest = rest.trim_start();
+    match command {
+        "get" if !rest.is_empty() => match store.get_bytes(rest)? {
+            Some(value) => println!("{}", String::from_utf8_lossy(&value)),
+            None => println!("(nil)"),
+        },
+        "set" => {
+            let Some((key, value)) = rest.split_once(' ') else {
+                return Err("usage: set <KEY> <VALUE>".into());
+            };
+            store.set(key, value.trim_start())?;
+            println!("OK");
+        }
+        "del" if !rest.is_empty() => println!("{}", u8::from(store.delete(rest)?)),
+        "scan" => {
+            for (key, value) in matching_entries(store, rest)? {
+                println!("{} = {}", key, String::from_utf8_lossy(&value));
+            }
+        }
+        "keys" => {
+            for (key, _) in matching_entries(store, rest)? {
+                println!("{}", key);
+            }
+        }
+        "stats" => {
+            let stats = store.stats()?;
+            println!("live bytes:          {}", stats.live_bytes);
+            println!("disk bytes:          {}", stats.disk_bytes);
+            println!("user bytes written:  {}", stats.user_bytes_written);
+            println!("bytes written:       {}", stats.physical_bytes_written);
+            println!("write amplification: {:.2}", stats.write_amplification());
+            println!("space amplification: {:.2}", stats.space_amplification());
+            println!("index memory:        {}", stats.index_memory_bytes);
+        }
+        "compact" => {
+            let mut task = store.compaction_task()?;
+            while !task.run_for(Duration::from_millis(100))? {}
+            println!("OK");
+        }
+        "history" => {
+            for (number, entry) in history.iter().enumerate() {
+                println!("{:>4}  {}", number + 1, entry);
+            }
+        }
+        "help" => println!("{}", SHELL_HELP),
+        "quit" | "exit" => return Ok(false),
+        _ => println!("unknown command, try `help`"),
+    }
+    return Ok(true);
+}
+
+/// Returns the live keys of `store` starting with `prefix` and their values, sorted by key.
+fn matching_entries(store: &mut RCask, prefix: &str) -> rcask::Result<Vec<(String, Vec<u8>)>> {
+    let mut entries: Vec<(String, Vec<u8>)> = store
+        .get_all_key_values()?
+        .into_iter()
+        .filter(|(key, _)| key.starts_with(prefix))
+        .collect();
+    entries.sort_unstable();
+    return Ok(entries);
+}
@@ -0,0 +1,46 @@
+//! Reading many keys at once in the order of their records in the log.
+//!
+//! `RCask::multi_get` looks every key up in the index first and reads the records sorted by
+//! their offsets rather than in the order the keys were given, so a batch of keys costs reads
+//! that move forward through the log instead of a seek per key. Records that lie close
+//! together are read with a single read, which the reads of their values are then served
+//! from, as when scanning the log. The store reads from one segment, so there is no grouping
+//! by segment to do.
+
+use crate::{RCask, ReadOptions, Result};
+
+impl RCask {
+    /// Retrieves the values of `keys`, in the order of `keys`, reading their records in the
+    /// order they lie in the log, see the module docs. Each key is read as `get_bytes` reads
+    /// it, so a key given twice is read twice.
+    pub fn multi_get<K: AsRef<str>>(&mut self, keys: &[K]) -> Result<Vec<Option<Vec<u8>>>> {
+        let options = ReadOptions::default();
+        let stored: Vec<String> = keys
+            .iter()
+            .map(|key| self.stored_key(key.as_ref()).into_owned())
+            .collect();
+        // Keys that are not in the index sort first and are read without touching the log.
+        let mut plan: Vec<(Option<u64>, usize)> = stored
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (self.store.offset(key), i))
+            .collect();
+        plan.sort_unstable();
+        let missing = plan.partition_point(|(offset, _)| offset.is_none());
+        let offsets: Vec<u64> = plan[missing..]
+            .iter()
+            .filter_map(|(offset, _)| *offset)
+            .collect();
+
+        let mut values = vec![None; keys.len()];
+        let mut covered = 0;
+        for (n, &(_, i)) in plan.iter().enumerate() {
+            if n >= missing && covered == 0 {
+                covered = self.store.read_ahead_of(&offsets[n - missing..])?;
+            }
+            covered = covered.saturating_sub(1);
+            values[i] = self.get_opt_into(&stored[i], &options, Vec::new())?;
+        }
+        return Ok(values);
+    }
+}
@@ -0,0 +1,130 @@
+//! File change notifications for readers that tail a directory.
+//!
+//! With the `notify` feature on Linux, a reader opened with `Builder::open_reader` or
+//! `RCask::follow` watches the store's directory with inotify and catches up as soon as the
+//! writer changes one of the store's files, instead of looking every refresh interval; when
+//! nothing changes it reads nothing. `Reader::wait` blocks until a change arrives. Elsewhere,
+//! or if the watch cannot be set up, e.g. past the limit of inotify watches, readers poll.
+
+use std::io;
+use std::time::Duration;
+
+/// Changes to the files of a store, as the system reports them.
+pub(crate) struct DirectoryEvents {
+    #[cfg(all(feature = "notify", target_os = "linux"))]
+    fd: std::os::fd::OwnedFd,
+    #[cfg(all(feature = "notify", target_os = "linux"))]
+    pattern: String,
+}
+
+#[cfg(all(feature = "notify", target_os = "linux"))]
+impl DirectoryEvents {
+    /// Starts watching the files of the store `pattern` in `directory`.
+    pub(crate) fn watch(directory: &str, pattern: &str) -> io::Result<Self> {
+        use std::ffi::CString;
+        use std::os::fd::{FromRawFd, OwnedFd};
+
+        // SAFETY: plain system calls; the descriptor is owned by `fd` from here on.
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let path = CString::new(directory).map_err(io::Error::other)?;
+        let mask = libc::IN_MODIFY | libc::IN_CREATE | libc::IN_MOVED_TO | libc::IN_DELETE;
+        // SAFETY: the descriptor is open and `path` is a valid C string for the call.
+        let watch = unsafe {
+            libc::inotify_add_watch(std::os::fd::AsRawFd::as_raw_fd(&fd), path.as_ptr(), mask)
+        };
+        if watch < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(DirectoryEvents {
+            fd,
+            pattern: pattern.to_string(),
+        });
+    }
+
+    /// Takes the changes reported since the last call and returns whether any of them was to a
+    /// file of the store.
+    pub(crate) fn changed(&mut self) -> io::Result<bool> {
+        use std::os::fd::AsRawFd;
+
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let mut buffer = [0u8; 4096];
+        let mut changed = false;
+        loop {
+            // SAFETY: the descriptor is open and the buffer is valid for its length.
+            let read = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr().cast(),
+                    buffer.len(),
+                )
+            };
+            if read < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::WouldBlock {
+                    return Ok(changed);
+                }
+                return Err(error);
+            }
+            let events = &buffer[..read as usize];
+            let mut at = 0;
+            while at + header <= events.len() {
+                let field = |offset: usize| {
+                    u32::from_ne_bytes(
+                        events[at + offset..at + offset + 4]
+                            .try_into()
+                            .unwrap_or_default(),
+                    )
+                };
+                let (mask, length) = (field(4), field(12) as usize);
+                let name = events
+                    .get(at + header..at + header + length)
+                    .unwrap_or_default();
+                let name = name.split(|&byte| byte == 0).next().unwrap_or_default();
+                // A lost event may have been anything.
+                changed |=
+                    mask & libc::IN_Q_OVERFLOW != 0 || name.starts_with(self.pattern.as_bytes());
+                at += header + length;
+            }
+        }
+    }
+
+    /// Waits until a change is reported or `timeout` has passed.
+    pub(crate) fn wait(&mut self, timeout: Duration) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let mut poll = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+        // SAFETY: `poll` is valid for the call.
+        if unsafe { libc::poll(&mut poll, 1, timeout) } < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(not(all(feature = "notify", target_os = "linux")))]
+impl DirectoryEvents {
+    pub(crate) fn watch(_directory: &str, _pattern: &str) -> io::Result<Self> {
+        return Err(io::Error::from(io::ErrorKind::Unsupported));
+    }
+
+    pub(crate) fn changed(&mut self) -> io::Result<bool> {
+        return Ok(true);
+    }
+
+    pub(crate) fn wait(&mut self, timeout: Duration) -> io::Result<()> {
+        std::thread::sleep(timeout);
+        return Ok(());
+    }
+}
@@ -0,0 +1,114 @@
+//! Observers of store operations.
+//!
+//! An `Observer` registered with `Builder::observer` sees the writes, deletions, missed reads
+//! and compactions of the store as they happen, e.g. to keep custom metrics, keep an external
+//! cache coherent or validate writes. Observers run in the order they were registered, on the
+//! thread of the operation, which waits for them.
+//!
+//! `on_set` runs before the value is written and can reject it; the others run once the
+//! operation has succeeded. Writes through `set`, `set_opt` and what is built on them, such as
+//! the entry API and `TypedRCask`, are observed, as are `delete` and `delete_prefix`. A copy
+//! is observed as a set of the new key, and a rename as that followed by a deletion of the
+//! old key. Bulk loads, collections, syncs and replication are not. Observers see keys
+//! as they are stored, encoded by the store's `KeyCodec` if it has one.
+
+use crate::{BoxError, Error, RCask, Result};
+use std::time::Duration;
+
+/// What a compaction did, as reported to `Observer::on_compaction`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// Whether the compaction ran in steps, see `Builder::incremental_compaction`.
+    pub incremental: bool,
+    /// Time spent compacting.
+    pub duration: Duration,
+    /// Size of the log before the compaction.
+    pub bytes_before: u64,
+    /// Size of the log after the compaction.
+    pub bytes_after: u64,
+    /// The error the compaction failed with, if it did.
+    pub error: Option<String>,
+}
+
+/// Hooks into the operations of a store, see `Builder::observer`. Every method does nothing
+/// by default.
+///
+/// ```no_run
+/// use rcask::{BoxError, Observer, RCask};
+///
+/// /// Rejects empty values and counts missed reads.
+/// #[derive(Default)]
+/// struct Checks {
+///     misses: u64,
+/// }
+///
+/// impl Observer for Checks {
+///     fn on_set(&mut self, _key: &str, value: &[u8]) -> Result<(), BoxError> {
+///         if value.is_empty() {
+///             return Err("empty values are not allowed".into());
+///         }
+///         return Ok(());
+///     }
+///
+///     fn on_get_miss(&mut self, _key: &str) {
+///         self.misses += 1;
+///     }
+/// }
+///
+/// # fn main() -> rcask::Result<()> {
+/// let mut store = RCask::builder("./".to_string(), "log".to_string())
+///     .observer(Checks::default())
+///     .open()?;
+/// assert!(store.set("key", "").is_err());
+/// # Ok(())
+/// # }
+/// ```
+pub trait Observer: Send {
+    /// Called with the key and value before a value is written. An error rejects the write
+    /// with `Error::Rejected`, and nothing is written.
+    fn on_set(&mut self, _key: &str, _value: &[u8]) -> std::result::Result<(), BoxError> {
+        return Ok(());
+    }
+
+    /// Called after a key that had a value was deleted.
+    fn on_delete(&mut self, _key: &str) {}
+
+    /// Called after a read found no value for the key.
+    fn on_get_miss(&mut self, _key: &str) {}
+
+    /// Called after every compaction, whether it succeeded or not.
+    fn on_compaction(&mut self, _report: &CompactionReport) {}
+}
+
+impl RCask {
+    /// Asks every observer whether `value` may be written to `key`.
+    pub(crate) fn observe_set(&mut self, key: &str, value: &[u8]) -> Result<()> {
+        for observer in &mut self.observers {
+            if let Err(reason) = observer.on_set(key, value) {
+                return Err(Error::Rejected {
+                    key: key.to_string(),
+                    reason,
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    pub(crate) fn observe_delete(&mut self, key: &str) {
+        for observer in &mut self.observers {
+            observer.on_delete(key);
+        }
+    }
+
+    pub(crate) fn observe_miss(&mut self, key: &str) {
+        for observer in &mut self.observers {
+            observer.on_get_miss(key);
+        }
+    }
+
+    pub(crate) fn observe_compaction(&mut self, report: &CompactionReport) {
+        for observer in &mut self.observers {
+            observer.on_compaction(report);
+        }
+    }
+}
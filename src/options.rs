@@ -0,0 +1,50 @@
+//! Options for individual reads and writes.
+
+use std::time::Duration;
+
+/// Options for a single write, see `RCask::set_opt`.
+///
+/// ```no_run
+/// use rcask::{RCask, WriteOptions};
+/// use std::time::Duration;
+///
+/// # fn main() -> rcask::Result<()> {
+/// let mut store = RCask::new("./".to_string(), "log".to_string())?;
+/// let options = WriteOptions {
+///     ttl: Some(Duration::from_secs(60)),
+///     ..Default::default()
+/// };
+/// store.set_opt("session", "token", &options)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// Flushes the record to the disk before returning.
+    pub sync: bool,
+    /// Expires the record after this long. Expired keys read as missing and are dropped by
+    /// compaction.
+    pub ttl: Option<Duration>,
+    /// Opaque bytes stored with the record, returned by `RCask::get_meta`.
+    pub meta: Option<Vec<u8>>,
+}
+
+/// Options for a single read, see `RCask::get_opt`.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    /// Verifies the record's checksum even if the store does not verify every read.
+    /// Records written without checksums cannot be verified. Always reads from the log.
+    pub verify_checksum: bool,
+    /// Adds the value to the read cache, if the store has one. Defaults to true; scans
+    /// should turn it off so they do not evict the values that are actually hot.
+    pub fill_cache: bool,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        return ReadOptions {
+            verify_checksum: false,
+            fill_cache: true,
+        };
+    }
+}
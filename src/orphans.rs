@@ -0,0 +1,128 @@
+//! Cleaning up files left behind by a crash.
+//!
+//! The active segment is the one with the highest number, and everything it depends on is
+//! named after it or lives in the blob and content-store directories. A crash during a
+//! compaction can leave other files behind: the superseded segment and its zstd dictionary,
+//! when it happens between the new segment taking over and the old one being deleted, a
+//! half-built `.compacting` segment of an incremental compaction, or a `.tmp` file of a blob
+//! or deduplicated value that was never renamed into place. Segments kept because they were
+//! still open elsewhere (see `vfs`) end up the same way once the store is closed.
+//!
+//! `open` deletes these files, or with `Builder::quarantine_orphans` moves them to
+//! `<directory>/<pattern>.orphaned/` for inspection, and reports them in `RCask::orphans`.
+//! Only files named like this store's own are touched.
+
+use crate::events::Field;
+use crate::{segment_number, RCask, Result};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Files `open` found left behind by an earlier run, see `RCask::orphans`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrphanReport {
+    /// Where the files were found.
+    pub files: Vec<PathBuf>,
+    /// Their total size in bytes.
+    pub bytes: u64,
+}
+
+impl RCask {
+    /// Returns the files left behind by an earlier run that `open` deleted or quarantined.
+    pub fn orphans(&self) -> &OrphanReport {
+        return &self.orphans;
+    }
+
+    /// Deletes or, with `quarantine`, moves away every file of the directory that the active
+    /// segment does not need, see the module docs.
+    pub(crate) fn collect_orphans(&mut self, quarantine: bool) -> Result<()> {
+        let active = Path::new(&self.store.path).to_path_buf();
+        let mut found = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path != active && self.is_orphan(&path, &active) {
+                found.push(path);
+            }
+        }
+        for name in ["large", "blobs"] {
+            let dir = Path::new(&self.directory).join(format!("{}.{}", self.pattern, name));
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().is_some_and(|extension| extension == "tmp") {
+                    found.push(path);
+                }
+            }
+        }
+        if found.is_empty() {
+            return Ok(());
+        }
+
+        let directory = Path::new(&self.directory);
+        let quarantine_dir = directory.join(format!("{}.orphaned", self.pattern));
+        for path in found {
+            let bytes = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+            let cleaned = match quarantine {
+                // Files of the blob directories keep their directory, so names cannot clash.
+                true => {
+                    let target = quarantine_dir.join(path.strip_prefix(directory).unwrap_or(&path));
+                    target
+                        .parent()
+                        .map_or(Ok(()), fs::create_dir_all)
+                        .and_then(|_| fs::rename(&path, &target))
+                }
+                false => self.fs.remove_file(&path),
+            };
+            match cleaned {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                // Still open elsewhere; deleted after a later compaction instead.
+                Err(e) if crate::is_in_use(&e) => {
+                    self.retired.push(path);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            }
+            self.orphans.files.push(path);
+            self.orphans.bytes += bytes;
+        }
+
+        if !self.orphans.files.is_empty() {
+            let fields = [
+                ("files", Field::U64(self.orphans.files.len() as u64)),
+                ("bytes", Field::U64(self.orphans.bytes)),
+                (
+                    "quarantined",
+                    Field::Str(if quarantine { "yes" } else { "no" }),
+                ),
+            ];
+            self.log_event::<()>("orphan_cleanup", None, &fields);
+        }
+        return Ok(());
+    }
+
+    /// Whether `path` is a segment, dictionary or pending segment of this store other than
+    /// those of `active`.
+    fn is_orphan(&self, path: &Path, active: &Path) -> bool {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return false;
+        };
+        let Some(rest) = name
+            .strip_prefix(self.pattern.as_str())
+            .and_then(|rest| rest.strip_prefix('.'))
+        else {
+            return false;
+        };
+        let (number, suffix) = rest.split_at(rest.find('.').unwrap_or(rest.len()));
+        if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+            return false;
+        }
+        return match suffix {
+            ".log.compacting" => true,
+            ".log" | ".dict" => number.parse().ok() != Some(segment_number(active)),
+            _ => false,
+        };
+    }
+}
@@ -0,0 +1,58 @@
+//! Parallel iteration over entries on a rayon thread pool.
+//!
+//! The keys are split into a few contiguous ranges of the sorted key space per worker thread.
+//! Every range is read through a `Reader` of its own while it runs, so workers never share a
+//! file handle, index or decompression state; there is one reader per thread, since each
+//! loads its own copy of the index. Ranges finish at different speeds, so rayon's work
+//! stealing keeps every thread busy until the last one is done.
+
+use crate::{Error, RCask, Result};
+use rayon::prelude::*;
+use std::sync::Mutex;
+
+/// Ranges per worker thread.
+const RANGES_PER_THREAD: usize = 4;
+
+impl RCask {
+    /// Calls `f` with every key and its value on the current rayon thread pool, e.g. to
+    /// deserialize or aggregate values on every core. Lists, sets and hashes are skipped.
+    /// Stops at the first error and returns it.
+    pub fn par_for_each<F>(&self, f: F) -> Result<()>
+    where
+        F: Fn(&str, &[u8]) + Send + Sync,
+    {
+        let mut keys = self.store.keys();
+        keys.sort_unstable();
+        let threads = rayon::current_num_threads();
+        let range_len = keys.len().div_ceil(threads * RANGES_PER_THREAD).max(1);
+        let ranges: Vec<&[String]> = keys.chunks(range_len).collect();
+        let mut readers = Vec::new();
+        for _ in 0..threads.min(ranges.len()) {
+            readers.push(self.reader()?);
+        }
+        let readers = Mutex::new(readers);
+        return ranges.into_par_iter().try_for_each(|range| {
+            // At most one range runs per thread, so a reader is always free.
+            let mut reader = match readers.lock().unwrap_or_else(|e| e.into_inner()).pop() {
+                Some(reader) => reader,
+                None => return Err(std::io::Error::other("No free reader").into()),
+            };
+            let mut result = Ok(());
+            for key in range {
+                match reader.get_bytes(key) {
+                    Ok(Some(value)) => f(key, &value),
+                    Ok(None) | Err(Error::WrongType { .. }) => {}
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+            readers
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(reader);
+            return result;
+        });
+    }
+}
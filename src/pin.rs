@@ -0,0 +1,101 @@
+//! Keys kept in memory whatever else is read.
+//!
+//! The read cache evicts the least recently used values, so a configuration value read once
+//! a minute loses its place to a scan or a burst of other traffic. `RCask::pin` keeps the
+//! value of a key in memory on the side, outside the cache's budget, and reads serve it from
+//! there. Writing or deleting a pinned key, clearing the store and compaction drop the copy
+//! and the next read loads the new value from the log, so a pinned key never serves stale
+//! data. Pins last until `unpin` or until the store is closed.
+
+use crate::{now_millis, RCask, ReadOptions, Result};
+use std::collections::HashMap;
+
+/// The in-memory copy of a pinned value.
+struct Pinned {
+    value: Vec<u8>,
+    expires_at: Option<u64>,
+}
+
+/// Pinned keys, with their values once read.
+#[derive(Default)]
+pub(crate) struct Pins {
+    entries: HashMap<String, Option<Pinned>>,
+}
+
+impl Pins {
+    /// Returns the value held for `key` if it is pinned, loaded and has not expired at `now`.
+    pub(crate) fn get(&self, key: &str, now: u64) -> Option<&[u8]> {
+        let pinned = self.entries.get(key)?.as_ref()?;
+        if pinned
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= now)
+        {
+            return None;
+        }
+        return Some(&pinned.value);
+    }
+
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        return self.entries.contains_key(key);
+    }
+
+    /// Holds `value`, just read from the log, if `key` is pinned.
+    pub(crate) fn loaded(&mut self, key: &str, value: &[u8], expires_at: Option<u64>) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            *entry = Some(Pinned {
+                value: value.to_vec(),
+                expires_at,
+            });
+        }
+    }
+
+    /// Drops the value held for `key`, which is about to be written.
+    pub(crate) fn invalidate(&mut self, key: &str) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            *entry = None;
+        }
+    }
+
+    /// Drops every value held, keeping the keys pinned.
+    pub(crate) fn invalidate_all(&mut self) {
+        for entry in self.entries.values_mut() {
+            *entry = None;
+        }
+    }
+}
+
+impl RCask {
+    /// Keeps the value of `key` in memory so reads never go to the disk for it, whatever the
+    /// read cache evicts, see the module docs. Returns whether the key has a value now; a
+    /// key without one stays pinned and is held once it is written and read.
+    pub fn pin(&mut self, key: &str) -> Result<bool> {
+        let key = self.stored_key(key);
+        let key = key.as_ref();
+        self.pins.entries.entry(key.to_string()).or_insert(None);
+        let options = ReadOptions {
+            fill_cache: false,
+            ..Default::default()
+        };
+        let value = self.get_opt_into(key, &options, Vec::new());
+        if value.is_err() {
+            self.pins.entries.remove(key);
+        }
+        return Ok(value?.is_some());
+    }
+
+    /// Stops keeping `key` in memory and returns whether it was pinned.
+    pub fn unpin(&mut self, key: &str) -> bool {
+        let key = self.stored_key(key);
+        return self.pins.entries.remove(key.as_ref()).is_some();
+    }
+
+    /// Returns whether `key` is pinned.
+    pub fn is_pinned(&self, key: &str) -> bool {
+        return self.pins.contains(&self.stored_key(key));
+    }
+
+    /// Returns the pinned value of `key` for a read that need not verify its checksum.
+    pub(crate) fn pinned_value(&self, key: &str) -> Option<&[u8]> {
+        return self.pins.get(key, now_millis());
+    }
+}
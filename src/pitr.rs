@@ -0,0 +1,184 @@
+//! Point-in-time recovery: rebuilding the store as it was at an earlier moment.
+//!
+//! `RCask::restore_to` writes a copy of the store as it stood at a `RestorePoint` into another
+//! directory, e.g. to recover from a bad deploy that wrote garbage for twenty minutes:
+//!
+//! * At a `RestorePoint::Sequence`, a position returned by `RCask::sequence` or
+//!   `backup::Link::until`, the copy holds exactly the records written before it. Records are
+//!   only appended between compactions, so the log of the active segment up to the position
+//!   is the store at that point, groups of records written together included or left out as a
+//!   whole. Positions before the last compaction are only held by backups, which
+//!   `backup::restore_to` cuts at the position instead.
+//! * At a `RestorePoint::Time`, the copy holds the value every key had at that time, as read
+//!   by `get_as_of`. This needs `Builder::history`, and reaches back as far as its
+//!   `HistoryOptions` retain; restoring a backup chain first reaches further. Lists, sets and
+//!   hashes, expiry times and metadata are not carried over.
+//!
+//! The copy is opened with `RCask::builder` on the target directory and the same pattern.
+
+use crate::keycodec;
+use crate::kvstore::KVStore;
+use crate::value;
+use crate::vfs::OsFileSystem;
+use crate::{Error, RCask, Result, Version};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The moment `RCask::restore_to` rebuilds the store at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestorePoint {
+    /// Just before the record at a position of the log.
+    Sequence(Version),
+    /// A wall-clock time.
+    Time(SystemTime),
+}
+
+impl RCask {
+    /// Writes the store as it was at `point` into `target_dir`, creating it if needed, see
+    /// the module docs. Never overwrites a file: restoring fails if a file of the copy already
+    /// exists there.
+    pub fn restore_to(&mut self, target_dir: &str, point: RestorePoint) -> Result<()> {
+        return match point {
+            RestorePoint::Sequence(until) => self.restore_to_sequence(Path::new(target_dir), until),
+            RestorePoint::Time(at) => self.restore_to_time(target_dir, at),
+        };
+    }
+
+    fn restore_to_sequence(&mut self, target: &Path, until: Version) -> Result<()> {
+        if until.segment() != self.segment || until.offset() > self.store.end() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the log no longer holds position {}; restore it from a backup chain",
+                    until
+                ),
+            )
+            .into());
+        }
+        self.sync()?;
+        fs::create_dir_all(target)?;
+        let segment = Path::new(&self.store.path);
+        let name = segment.file_name().unwrap_or_default();
+        let source = fs::File::open(segment)?;
+        copy_new(source.take(until.offset()), &target.join(name))?;
+        cut_log(&target.join(name), until.offset())?;
+
+        let dictionary = segment.with_extension("dict");
+        if dictionary.exists() {
+            let to = target.join(dictionary.file_name().unwrap_or_default());
+            copy_new(fs::File::open(&dictionary)?, &to)?;
+        }
+        self.copy_key_codec(target)?;
+        self.copy_value_format(target)?;
+        // Blob files written after the position are not referenced, and compaction of the
+        // copy deletes them.
+        for name in ["large", "blobs"] {
+            let blobs = format!("{}.{}", self.pattern, name);
+            let Ok(entries) = fs::read_dir(Path::new(&self.directory).join(&blobs)) else {
+                continue;
+            };
+            fs::create_dir_all(target.join(&blobs))?;
+            for entry in entries {
+                let path = entry?.path();
+                if path
+                    .extension()
+                    .is_some_and(|extension| extension == "blob")
+                {
+                    let to = target
+                        .join(&blobs)
+                        .join(path.file_name().unwrap_or_default());
+                    copy_new(fs::File::open(&path)?, &to)?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    fn restore_to_time(&mut self, target_dir: &str, at: SystemTime) -> Result<()> {
+        if self.history.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "restoring to a time needs Builder::history",
+            )
+            .into());
+        }
+        let mut copy = RCask::builder(target_dir.to_string(), self.pattern.clone()).open()?;
+        if !copy.store.keys().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already holds a store", target_dir),
+            )
+            .into());
+        }
+        let mut entries = Vec::new();
+        for key in self.store.keys() {
+            let decoded = match &self.key_codec {
+                Some(codec) => codec.decode(&key).into_owned(),
+                None => key.clone(),
+            };
+            match self.get_as_of(&decoded, at) {
+                Ok(Some(value)) => entries.push((key, value)),
+                Ok(None) | Err(Error::WrongType { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        copy.bulk_load(entries)?;
+        copy.sync()?;
+        // The keys were copied as stored, so the copy has this store's codec.
+        drop(copy);
+        return self.copy_key_codec(Path::new(target_dir));
+    }
+
+    /// Copies the key codec record of the store, if it has one, into `target`.
+    pub(crate) fn copy_key_codec(&self, target: &Path) -> Result<()> {
+        return copy_record(
+            keycodec::record_path(Path::new(&self.directory), &self.pattern),
+            target,
+        );
+    }
+
+    /// Copies the value format record of the store, if it has one, into `target`.
+    pub(crate) fn copy_value_format(&self, target: &Path) -> Result<()> {
+        return copy_record(
+            value::format_path(Path::new(&self.directory), &self.pattern),
+            target,
+        );
+    }
+}
+
+/// Copies the record of a setting at `record`, if there is one, into `target`.
+fn copy_record(record: PathBuf, target: &Path) -> Result<()> {
+    if record.exists() {
+        copy_new(
+            fs::File::open(&record)?,
+            &target.join(record.file_name().unwrap_or_default()),
+        )?;
+    }
+    return Ok(());
+}
+
+/// Cuts the copied log at `path` back to the end of the last complete record or group before
+/// `until`, and returns that end.
+pub(crate) fn cut_log(path: &Path, until: u64) -> io::Result<u64> {
+    let file = fs::OpenOptions::new().write(true).open(path)?;
+    if file.metadata()?.len() > until {
+        file.set_len(until)?;
+    }
+    // Loading stops at the first record that is not complete, as after a crash.
+    let end = KVStore::open_read_only(&OsFileSystem, path)?.end();
+    file.set_len(end)?;
+    file.sync_all()?;
+    return Ok(end);
+}
+
+/// Writes everything `source` reads to the new file `target` and syncs it.
+pub(crate) fn copy_new<R: Read>(mut source: R, target: &Path) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(target)?;
+    io::copy(&mut source, &mut file)?;
+    return file.sync_all();
+}
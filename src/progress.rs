@@ -0,0 +1,72 @@
+//! Progress reports and cancellation while a store is opened.
+//!
+//! Opening a store reads its active segment into the in-memory index, which can take minutes
+//! for a large log. With `Builder::on_progress`, a callback sees how far the scan has got
+//! every few megabytes, so a service can report its readiness and pick sensible timeouts.
+//! `RCask::open_with_cancel` checks a `CancellationToken` just as often and gives up once it
+//! is cancelled or its timeout has passed.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How far opening a store has got, passed to the `Builder::on_progress` callback.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadProgress {
+    /// Segments completely scanned.
+    pub segments_scanned: u64,
+    /// Segments to scan, which is the active one.
+    pub segments_total: u64,
+    pub bytes_processed: u64,
+    pub bytes_total: u64,
+    /// Records read into the index so far, including ones superseded by later records.
+    pub records_indexed: u64,
+}
+
+pub(crate) type ProgressCallback = Box<dyn FnMut(&LoadProgress) + Send>;
+
+/// Cancels an operation from another thread, or once a timeout has passed. Clones share the
+/// same state.
+///
+/// ```no_run
+/// use rcask::{CancellationToken, RCask};
+/// use std::time::Duration;
+///
+/// let token = CancellationToken::with_timeout(Duration::from_secs(300));
+/// let builder = RCask::builder("./".to_string(), "log".to_string());
+/// match RCask::open_with_cancel(builder, token) {
+///     Ok(store) => { /* ... */ }
+///     Err(rcask::Error::Cancelled) => eprintln!("startup took too long"),
+///     Err(e) => eprintln!("{}", e),
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Returns a token that cancels itself once `timeout` has passed.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        return CancellationToken {
+            cancelled: Arc::default(),
+            deadline: Instant::now().checked_add(timeout),
+        };
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.cancelled.load(Ordering::Acquire)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+    }
+}
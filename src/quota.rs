@@ -0,0 +1,173 @@
+//! Storage quotas for namespaces, the keys sharing a prefix.
+//!
+//! `Builder::quota` limits the bytes of the live keys and values under a prefix, counted like
+//! `EvictionOptions::max_bytes`: each key plus its stored record, after compression. A key
+//! counts towards every quota whose prefix it starts with. A write that would take a
+//! namespace over its quota fails with `Error::QuotaExceeded`, while deletions and writes
+//! that do not grow a namespace always go through. `bulk_load` is not checked, but what it
+//! writes counts. Compaction re-encodes records, so usage is measured again after each one;
+//! expired keys count until compaction drops them.
+
+use crate::{Error, RCask, Result};
+use std::collections::HashMap;
+
+/// Usage of one quota, see `Stats::quotas`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuotaUsage {
+    /// Prefix of the keys the quota covers.
+    pub prefix: String,
+    /// Bytes of the live keys and values under the prefix.
+    pub used_bytes: u64,
+    /// The quota.
+    pub max_bytes: u64,
+}
+
+/// Usage of every quota of a store.
+pub(crate) struct Quotas {
+    usage: Vec<QuotaUsage>,
+    /// Size of every key that counts towards a quota.
+    sizes: HashMap<String, u64>,
+}
+
+impl Quotas {
+    pub(crate) fn new(limits: Vec<(String, u64)>) -> Self {
+        let usage = limits
+            .into_iter()
+            .map(|(prefix, max_bytes)| QuotaUsage {
+                prefix,
+                used_bytes: 0,
+                max_bytes,
+            })
+            .collect();
+        return Quotas {
+            usage,
+            sizes: HashMap::new(),
+        };
+    }
+
+    fn counts(&self, key: &str) -> bool {
+        return self
+            .usage
+            .iter()
+            .any(|quota| key.starts_with(&quota.prefix));
+    }
+
+    /// Size of `key` after a record of `size` bytes replaces it or, with `append`, is added to
+    /// it, and its size before.
+    fn resize(&self, key: &str, size: u64, append: bool) -> (u64, u64) {
+        let old = self.sizes.get(key).copied().unwrap_or(0);
+        let new = if append { old + size } else { size };
+        return (old, new);
+    }
+
+    fn check(&self, key: &str, size: u64, append: bool) -> Result<()> {
+        let (old, new) = self.resize(key, size, append);
+        if new <= old {
+            return Ok(());
+        }
+        for quota in &self.usage {
+            if key.starts_with(&quota.prefix) && quota.used_bytes - old + new > quota.max_bytes {
+                return Err(Error::QuotaExceeded {
+                    prefix: quota.prefix.clone(),
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    fn wrote(&mut self, key: &str, size: u64, append: bool) {
+        if !self.counts(key) {
+            return;
+        }
+        let (old, new) = self.resize(key, size, append);
+        for quota in &mut self.usage {
+            if key.starts_with(&quota.prefix) {
+                quota.used_bytes = quota.used_bytes - old + new;
+            }
+        }
+        self.sizes.insert(key.to_string(), new);
+    }
+
+    fn removed(&mut self, key: &str) {
+        let Some(old) = self.sizes.remove(key) else {
+            return;
+        };
+        for quota in &mut self.usage {
+            if key.starts_with(&quota.prefix) {
+                quota.used_bytes -= old;
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.sizes.clear();
+        for quota in &mut self.usage {
+            quota.used_bytes = 0;
+        }
+    }
+}
+
+impl RCask {
+    /// Fails with `Error::QuotaExceeded` if writing a record of `size` bytes for `key` would
+    /// take a namespace over its quota, see `lru_wrote` for `append`.
+    pub(crate) fn admit_quota(&self, key: &str, size: u64, append: bool) -> Result<()> {
+        return match &self.quotas {
+            Some(quotas) => quotas.check(key, size, append),
+            None => Ok(()),
+        };
+    }
+
+    /// Measures the usage of every quota from the log, after the store was opened or
+    /// compacted.
+    pub(crate) fn measure_quotas(&mut self) -> Result<()> {
+        let Some(quotas) = &mut self.quotas else {
+            return Ok(());
+        };
+        quotas.clear();
+        let now = crate::now_millis();
+        let verify = self.verify_checksums;
+        for key in self.store.keys() {
+            if !quotas.counts(&key) {
+                continue;
+            }
+            let Some(offset) = self.store.offset(&key) else {
+                continue;
+            };
+            let Some((stored, attributes)) = self.store.get_value_bytes_at(&key, offset, verify)?
+            else {
+                continue;
+            };
+            if attributes.is_tombstone() || attributes.is_expired(now) {
+                continue;
+            }
+            quotas.wrote(&key, (key.len() + stored.len()) as u64, false);
+        }
+        return Ok(());
+    }
+
+    pub(crate) fn quotas_wrote(&mut self, key: &str, size: u64, append: bool) {
+        if let Some(quotas) = &mut self.quotas {
+            quotas.wrote(key, size, append);
+        }
+    }
+
+    pub(crate) fn quotas_removed(&mut self, key: &str) {
+        if let Some(quotas) = &mut self.quotas {
+            quotas.removed(key);
+        }
+    }
+
+    pub(crate) fn quotas_cleared(&mut self) {
+        if let Some(quotas) = &mut self.quotas {
+            quotas.clear();
+        }
+    }
+
+    /// Usage of every quota, for `stats`.
+    pub(crate) fn quota_usage(&self) -> Vec<QuotaUsage> {
+        return self
+            .quotas
+            .as_ref()
+            .map_or_else(Vec::new, |quotas| quotas.usage.clone());
+    }
+}
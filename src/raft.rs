@@ -0,0 +1,1124 @@
+//! Raft-replicated stores, with the `raft` feature.
+//!
+//! A `ReplicatedRCask` runs one member of a group of stores, usually three, that agree on
+//! every write through the Raft consensus protocol. Writes go to the leader, which appends
+//! them to a Raft log next to its store, `<pattern>.raft`, sends them to the other members
+//! and applies them to the store once a majority holds them. Every member applies the same
+//! writes in the same order, so their stores hold the same keys. When the leader fails, the
+//! others elect a new one among those holding every committed write, as long as a majority
+//! of the group is up.
+//!
+//! `set` and `delete` return once the write is applied on the leader, and `get` confirms
+//! with a majority that the member still leads before it reads, so a read sees every write
+//! that returned before it started. Members that do not lead answer them with
+//! `Error::NotLeader`, naming the leader they know of.
+//!
+//! Members talk over TCP in the frames of `ReplicationSource`, a type byte and a
+//! little-endian `u64` length followed by the payload: `VOTE` and `VOTED` for elections,
+//! `APPEND` and `APPENDED` for entries and heartbeats. Each member keeps its term, its vote
+//! and how far it has applied the log in `<pattern>.raftstate`. The Raft log is not
+//! compacted: it holds every write since the group started, and a member that joins late
+//! catches up by replaying it.
+//!
+//! The store must only be written to through its `ReplicatedRCask`, or it would no longer
+//! hold the same keys as the other members.
+
+use crate::checksum::Crc32;
+use crate::replication::{invalid, read_frame, read_u64, write_frame};
+use crate::{Error, RCask, Result};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const VOTE: u8 = 1;
+const VOTED: u8 = 2;
+const APPEND: u8 = 3;
+const APPENDED: u8 = 4;
+
+const NOOP: u8 = 0;
+const SET: u8 = 1;
+const DELETE: u8 = 2;
+
+/// Most bytes of entries in one `APPEND` frame, past its first entry.
+const BATCH: usize = 1 << 20;
+
+/// Most entries applied to the store at once.
+const APPLY_BATCH: usize = 1024;
+
+/// Options of one member of a Raft group, see `ReplicatedRCask::start`.
+#[derive(Debug, Clone)]
+pub struct RaftOptions {
+    /// The member's id, unique in the group.
+    pub id: u64,
+    /// The ids and addresses of the other members.
+    pub peers: Vec<(u64, SocketAddr)>,
+    /// How long a member waits to hear from a leader before it stands for election, drawn
+    /// anew between this and twice this every time. Defaults to 300 ms.
+    pub election_timeout: Duration,
+    /// How often the leader sends a heartbeat to members it has nothing else to send. Must
+    /// be well below `election_timeout`. Defaults to 50 ms.
+    pub heartbeat_interval: Duration,
+    /// How long a write or read waits for the group, and a member for the reply of
+    /// another, before giving up. Defaults to 5 s.
+    pub request_timeout: Duration,
+}
+
+impl RaftOptions {
+    /// Options for the member `id` of a group whose other members are `peers`.
+    pub fn new(id: u64, peers: Vec<(u64, SocketAddr)>) -> Self {
+        return RaftOptions {
+            id,
+            peers,
+            election_timeout: Duration::from_millis(300),
+            heartbeat_interval: Duration::from_millis(50),
+            request_timeout: Duration::from_secs(5),
+        };
+    }
+}
+
+/// One member of a Raft group of stores, see the module documentation.
+pub struct ReplicatedRCask {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    options: RaftOptions,
+    node: Mutex<Node>,
+    /// Signalled whenever the node changes, and on shutdown.
+    changed: Condvar,
+    store: Mutex<RCask>,
+    /// Address of the listener, connected to on shutdown to wake it.
+    address: SocketAddr,
+    stopped: AtomicBool,
+    /// Why the member stopped on its own, if it did.
+    failure: Mutex<Option<String>>,
+    /// Open connections, shut down on shutdown to wake the threads reading them.
+    connections: Mutex<HashMap<u64, TcpStream>>,
+    next_connection: AtomicU64,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+struct Node {
+    id: u64,
+    role: Role,
+    term: u64,
+    voted_for: Option<u64>,
+    leader: Option<u64>,
+    log: Log,
+    commit: u64,
+    applied: u64,
+    /// Members that voted for this one in the current term, while it is a candidate.
+    votes: HashSet<u64>,
+    peers: HashMap<u64, Peer>,
+    /// When the member stands for election unless it hears from a leader first.
+    deadline: Instant,
+    /// Number of reads that asked the other members to confirm the leadership.
+    round: u64,
+    /// Outcomes of applied writes that a caller waits for, by index.
+    waiting: HashMap<u64, Option<Error>>,
+    state_path: PathBuf,
+}
+
+/// What the leader knows of another member.
+struct Peer {
+    /// Index of the next entry to send.
+    next: u64,
+    /// Index of the last entry known to be in the member's log.
+    matched: u64,
+    /// Term in which the member was last asked for its vote.
+    asked: u64,
+    /// Last read round the member confirmed the leadership in.
+    confirmed: u64,
+    /// Read round when the last `APPEND` was sent.
+    sent_round: u64,
+    last_sent: Option<Instant>,
+}
+
+enum Request {
+    Vote {
+        term: u64,
+        last_index: u64,
+        last_term: u64,
+    },
+    Append {
+        term: u64,
+        prev_index: u64,
+        prev_term: u64,
+        commit: u64,
+        entries: Vec<(u64, Vec<u8>)>,
+        round: u64,
+    },
+}
+
+impl ReplicatedRCask {
+    /// Starts the member of `options` on `store`, taking requests of the other members on
+    /// `listener`. The member starts as a follower and resumes from its Raft log, if it ran
+    /// before.
+    pub fn start(
+        store: RCask,
+        listener: TcpListener,
+        options: RaftOptions,
+    ) -> Result<ReplicatedRCask> {
+        if options.peers.iter().any(|(id, _)| *id == options.id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the peers of a member must not include the member itself",
+            )
+            .into());
+        }
+        let directory = Path::new(&store.directory);
+        let log = Log::open(&directory.join(format!("{}.raft", store.pattern)))?;
+        let state_path = directory.join(format!("{}.raftstate", store.pattern));
+        let (term, voted_for, applied) = read_state(&state_path)?;
+        if applied > log.last_index() {
+            return Err(
+                invalid("the Raft log is shorter than the part applied to the store").into(),
+            );
+        }
+        let peers = options
+            .peers
+            .iter()
+            .map(|(id, _)| (*id, Peer::new(log.last_index())))
+            .collect();
+        let node = Node {
+            id: options.id,
+            role: Role::Follower,
+            term,
+            voted_for,
+            leader: None,
+            log,
+            commit: applied,
+            applied,
+            votes: HashSet::new(),
+            peers,
+            deadline: election_deadline(&options),
+            round: 0,
+            waiting: HashMap::new(),
+            state_path,
+        };
+        let shared = Arc::new(Shared {
+            address: listener.local_addr()?,
+            options,
+            node: Mutex::new(node),
+            changed: Condvar::new(),
+            store: Mutex::new(store),
+            stopped: AtomicBool::new(false),
+            failure: Mutex::new(None),
+            connections: Mutex::new(HashMap::new()),
+            next_connection: AtomicU64::new(0),
+            threads: Mutex::new(Vec::new()),
+        });
+
+        let mut threads = Vec::new();
+        let listening = shared.clone();
+        threads.push(thread::spawn(move || listening.listen(listener)));
+        let ticking = shared.clone();
+        threads.push(thread::spawn(move || ticking.tick()));
+        let applying = shared.clone();
+        threads.push(thread::spawn(move || applying.apply()));
+        for (id, address) in shared.options.peers.clone() {
+            let replicating = shared.clone();
+            threads.push(thread::spawn(move || replicating.replicate(id, address)));
+        }
+        shared.threads().extend(threads);
+        return Ok(ReplicatedRCask { shared });
+    }
+
+    /// Sets `key` to `value` in every store of the group. Returns once the write is applied
+    /// on this member, which must be the leader. If it loses the leadership first, this
+    /// returns `Error::NotLeader`, and the write may or may not take effect; so does a
+    /// write that times out.
+    pub fn set<V: AsRef<[u8]>>(&self, key: &str, value: V) -> Result<()> {
+        return self.propose(encode_command(SET, key, value.as_ref()));
+    }
+
+    /// Deletes `key` from every store of the group, like `set`.
+    pub fn delete(&self, key: &str) -> Result<()> {
+        return self.propose(encode_command(DELETE, key, &[]));
+    }
+
+    /// Reads `key` as of every write that returned before the call, after confirming with
+    /// a majority of the group that this member still leads it.
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        self.read_index()?;
+        return self.shared.store().get(key);
+    }
+
+    /// Reads `key` from this member's store without asking the group, also on followers.
+    /// The value may miss writes that are already committed.
+    pub fn get_local(&self, key: &str) -> Result<Option<String>> {
+        self.shared.check()?;
+        return self.shared.store().get(key);
+    }
+
+    /// Whether this member leads its group, as far as it knows.
+    pub fn is_leader(&self) -> bool {
+        return self.shared.node().role == Role::Leader;
+    }
+
+    /// The id of the member this one last heard from as the leader, or its own if it leads.
+    pub fn leader(&self) -> Option<u64> {
+        return self.shared.node().leader;
+    }
+
+    /// Stops the member: it stops answering the group and its threads exit. Writes and
+    /// reads waiting for the group fail. Dropping the member also stops it.
+    pub fn shutdown(&self) {
+        self.shared.stop();
+        loop {
+            let threads = mem::take(&mut *self.shared.threads());
+            if threads.is_empty() {
+                return;
+            }
+            for thread in threads {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    fn propose(&self, command: Vec<u8>) -> Result<()> {
+        let shared = &self.shared;
+        let mut node = shared.check_node()?;
+        if node.role != Role::Leader {
+            return Err(Error::NotLeader {
+                leader: node.leader,
+            });
+        }
+        let term = node.term;
+        node.log.push(term, command)?;
+        node.log.sync()?;
+        let index = node.log.last_index();
+        node.waiting.insert(index, None);
+        node.advance();
+        shared.changed.notify_all();
+
+        let deadline = Instant::now() + shared.options.request_timeout;
+        loop {
+            if node.applied >= index {
+                let outcome = node.waiting.remove(&index).flatten();
+                if node.log.term(index) != term {
+                    return Err(Error::NotLeader {
+                        leader: node.leader,
+                    });
+                }
+                return outcome.map_or(Ok(()), Err);
+            }
+            if let Err(err) = shared.wait_check(&node, term, deadline) {
+                node.waiting.remove(&index);
+                return Err(err);
+            }
+            node = shared.wait(node, deadline.saturating_duration_since(Instant::now()));
+        }
+    }
+
+    /// Waits until this member, as the leader, has applied every write committed before
+    /// the call, and a majority has confirmed it still leads.
+    fn read_index(&self) -> Result<()> {
+        let shared = &self.shared;
+        let mut node = shared.check_node()?;
+        if node.role != Role::Leader {
+            return Err(Error::NotLeader {
+                leader: node.leader,
+            });
+        }
+        let term = node.term;
+        let deadline = Instant::now() + shared.options.request_timeout;
+        // The leader only knows what is committed once an entry of its own term is.
+        while node.log.term(node.commit) != term {
+            shared.wait_check(&node, term, deadline)?;
+            node = shared.wait(node, deadline.saturating_duration_since(Instant::now()));
+        }
+        let index = node.commit;
+        node.round += 1;
+        let round = node.round;
+        shared.changed.notify_all();
+        loop {
+            let confirmed = node
+                .peers
+                .values()
+                .filter(|peer| peer.confirmed >= round)
+                .count();
+            if confirmed + 1 >= node.majority() && node.applied >= index {
+                return Ok(());
+            }
+            shared.wait_check(&node, term, deadline)?;
+            node = shared.wait(node, deadline.saturating_duration_since(Instant::now()));
+        }
+    }
+}
+
+impl Drop for ReplicatedRCask {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Shared {
+    fn node(&self) -> MutexGuard<'_, Node> {
+        return self.node.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    fn store(&self) -> MutexGuard<'_, RCask> {
+        return self.store.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    fn threads(&self) -> MutexGuard<'_, Vec<JoinHandle<()>>> {
+        return self.threads.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    fn is_stopped(&self) -> bool {
+        return self.stopped.load(Ordering::SeqCst);
+    }
+
+    /// Fails if the member has stopped.
+    fn check(&self) -> Result<()> {
+        if !self.is_stopped() {
+            return Ok(());
+        }
+        let message = match &*self.failure.lock().unwrap_or_else(|e| e.into_inner()) {
+            Some(failure) => format!("the Raft member stopped: {}", failure),
+            None => "the Raft member is shut down".to_string(),
+        };
+        return Err(io::Error::other(message).into());
+    }
+
+    fn check_node(&self) -> Result<MutexGuard<'_, Node>> {
+        self.check()?;
+        return Ok(self.node());
+    }
+
+    /// Fails a caller waiting in `term` if the member stopped, lost the leadership or
+    /// waited past `deadline`.
+    fn wait_check(&self, node: &Node, term: u64, deadline: Instant) -> Result<()> {
+        self.check()?;
+        if node.term != term || node.role != Role::Leader {
+            return Err(Error::NotLeader {
+                leader: node.leader,
+            });
+        }
+        if Instant::now() >= deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the group did not answer within the request timeout",
+            )
+            .into());
+        }
+        return Ok(());
+    }
+
+    fn wait<'a>(&self, node: MutexGuard<'a, Node>, timeout: Duration) -> MutexGuard<'a, Node> {
+        return self
+            .changed
+            .wait_timeout(node, timeout)
+            .unwrap_or_else(|e| e.into_inner())
+            .0;
+    }
+
+    /// Stops every thread of the member, without waiting for them.
+    fn stop(&self) {
+        if self.stopped.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // Taking the lock orders the flag before the wake-up of threads about to wait.
+        drop(self.node());
+        self.changed.notify_all();
+        let connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        for stream in connections.values() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        drop(connections);
+        let _ = TcpStream::connect_timeout(&self.address, self.options.request_timeout);
+    }
+
+    /// Stops the member after an error it cannot go on from, e.g. failing to sync its log.
+    fn fail(&self, err: impl ToString) {
+        let mut failure = self.failure.lock().unwrap_or_else(|e| e.into_inner());
+        failure.get_or_insert(err.to_string());
+        drop(failure);
+        self.stop();
+    }
+
+    fn listen(self: Arc<Self>, listener: TcpListener) {
+        for stream in listener.incoming() {
+            if self.is_stopped() {
+                return;
+            }
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let serving = self.clone();
+            let thread = thread::spawn(move || serving.serve(stream));
+            let mut threads = self.threads();
+            threads.retain(|thread| !thread.is_finished());
+            threads.push(thread);
+        }
+    }
+
+    /// Answers the requests of another member on `stream`.
+    fn serve(&self, stream: TcpStream) {
+        let Ok(mut connection) = Connection::new(self, stream) else {
+            return;
+        };
+        while let Ok((kind, payload)) = read_frame(&mut connection.stream) {
+            if self.is_stopped() {
+                return;
+            }
+            let answer = self.node().answer(kind, &payload, &self.options);
+            self.changed.notify_all();
+            let (kind, reply) = match answer {
+                Ok(answer) => answer,
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => return,
+                Err(err) => return self.fail(err),
+            };
+            if write_frame(&mut connection.stream, kind, &[&reply]).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Stands for election whenever the election timeout passes without a leader.
+    fn tick(&self) {
+        let mut node = self.node();
+        while !self.is_stopped() {
+            let now = Instant::now();
+            if node.role != Role::Leader && now >= node.deadline {
+                node.deadline = election_deadline(&self.options);
+                if let Err(err) = node.stand() {
+                    drop(node);
+                    return self.fail(err);
+                }
+                self.changed.notify_all();
+            }
+            let timeout = node.deadline.saturating_duration_since(now);
+            node = self.wait(node, timeout.min(self.options.heartbeat_interval));
+        }
+    }
+
+    /// Applies committed entries to the store, in order.
+    fn apply(&self) {
+        loop {
+            let mut node = self.node();
+            while node.applied >= node.commit && !self.is_stopped() {
+                node = self.wait(node, self.options.heartbeat_interval);
+            }
+            if self.is_stopped() {
+                return;
+            }
+            let first = node.applied + 1;
+            let last = node.commit.min(node.applied + APPLY_BATCH as u64);
+            let commands: Vec<Vec<u8>> = (first..=last)
+                .map(|index| node.log.entries[index as usize - 1].1.clone())
+                .collect();
+            drop(node);
+
+            let mut outcomes = Vec::with_capacity(commands.len());
+            let mut store = self.store();
+            for command in &commands {
+                loop {
+                    match apply_command(&mut store, command) {
+                        // Transient: the write is retried until the store takes it.
+                        Err(Error::Backpressure { .. }) if !self.is_stopped() => {
+                            thread::sleep(self.options.heartbeat_interval);
+                        }
+                        // The other members applied the write, so it cannot be skipped.
+                        Err(err @ (Error::Io(_) | Error::DiskFull(_))) => return self.fail(err),
+                        outcome => {
+                            outcomes.push(outcome.err());
+                            break;
+                        }
+                    }
+                }
+            }
+            if let Err(err) = store.sync() {
+                return self.fail(err);
+            }
+            drop(store);
+
+            let mut node = self.node();
+            for (index, outcome) in (first..=last).zip(outcomes) {
+                if let Some(waiting) = node.waiting.get_mut(&index) {
+                    *waiting = outcome;
+                }
+            }
+            node.applied = last;
+            if let Err(err) = node.save() {
+                drop(node);
+                return self.fail(err);
+            }
+            self.changed.notify_all();
+        }
+    }
+
+    /// Sends the requests for the member `id` at `address`: votes while this member stands
+    /// for election, entries and heartbeats while it leads.
+    fn replicate(&self, id: u64, address: SocketAddr) {
+        let mut connection: Option<Connection> = None;
+        loop {
+            let mut node = self.node();
+            let request = loop {
+                if self.is_stopped() {
+                    return;
+                }
+                if let Some(request) = node.request_for(id, self.options.heartbeat_interval) {
+                    break request;
+                }
+                node = self.wait(node, self.options.heartbeat_interval / 4);
+            };
+            drop(node);
+            let (kind, payload) = match self.exchange(&mut connection, address, &request) {
+                Ok(reply) => reply,
+                Err(_) => {
+                    connection = None;
+                    thread::sleep(self.options.heartbeat_interval);
+                    continue;
+                }
+            };
+            let handled = self.node().handle(id, &request, kind, &payload);
+            match handled {
+                Ok(()) => self.changed.notify_all(),
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => connection = None,
+                Err(err) => return self.fail(err),
+            }
+        }
+    }
+
+    fn exchange<'a>(
+        &'a self,
+        connection: &mut Option<Connection<'a>>,
+        address: SocketAddr,
+        request: &Request,
+    ) -> io::Result<(u8, Vec<u8>)> {
+        if connection.is_none() {
+            let timeout = self.options.request_timeout;
+            let stream = TcpStream::connect_timeout(&address, self.options.election_timeout)?;
+            stream.set_nodelay(true)?;
+            stream.set_read_timeout(Some(timeout))?;
+            stream.set_write_timeout(Some(timeout))?;
+            *connection = Some(Connection::new(self, stream)?);
+        }
+        let Some(connection) = connection else {
+            unreachable!();
+        };
+        let (kind, payload) = request.encode(self.options.id);
+        write_frame(&mut connection.stream, kind, &[&payload])?;
+        return read_frame(&mut connection.stream);
+    }
+}
+
+/// A stream registered with the member, so that shutting it down wakes the thread on it.
+struct Connection<'a> {
+    shared: &'a Shared,
+    id: u64,
+    stream: TcpStream,
+}
+
+impl<'a> Connection<'a> {
+    fn new(shared: &'a Shared, stream: TcpStream) -> io::Result<Connection<'a>> {
+        let id = shared.next_connection.fetch_add(1, Ordering::SeqCst);
+        let mut connections = shared.connections.lock().unwrap_or_else(|e| e.into_inner());
+        if shared.is_stopped() {
+            let _ = stream.shutdown(Shutdown::Both);
+            return Err(io::Error::from(io::ErrorKind::NotConnected));
+        }
+        connections.insert(id, stream.try_clone()?);
+        return Ok(Connection { shared, id, stream });
+    }
+}
+
+impl Drop for Connection<'_> {
+    fn drop(&mut self) {
+        let mut connections = self
+            .shared
+            .connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        connections.remove(&self.id);
+    }
+}
+
+impl Node {
+    fn majority(&self) -> usize {
+        let members = self.peers.len() + 1;
+        return members / 2 + 1;
+    }
+
+    fn save(&self) -> io::Result<()> {
+        return write_state(&self.state_path, self.term, self.voted_for, self.applied);
+    }
+
+    /// Moves to the newer `term` as a follower, when a message carries one.
+    fn observe_term(&mut self, term: u64) -> io::Result<()> {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.role = Role::Follower;
+            self.leader = None;
+            self.save()?;
+        }
+        return Ok(());
+    }
+
+    /// Stands for election in a new term, voting for itself.
+    fn stand(&mut self) -> io::Result<()> {
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.voted_for = Some(self.id);
+        self.leader = None;
+        self.votes = HashSet::from([self.id]);
+        self.save()?;
+        if self.votes.len() >= self.majority() {
+            self.lead()?;
+        }
+        return Ok(());
+    }
+
+    /// Takes the leadership won in the current term.
+    fn lead(&mut self) -> io::Result<()> {
+        self.role = Role::Leader;
+        self.leader = Some(self.id);
+        let last = self.log.last_index();
+        for peer in self.peers.values_mut() {
+            *peer = Peer::new(last);
+        }
+        // Committing an entry of its own term commits every entry before it.
+        self.log.push(self.term, vec![NOOP])?;
+        self.log.sync()?;
+        self.advance();
+        return Ok(());
+    }
+
+    /// Commits the entries of the current term that a majority holds, as the leader.
+    fn advance(&mut self) {
+        if self.role != Role::Leader {
+            return;
+        }
+        let mut matched: Vec<u64> = self.peers.values().map(|peer| peer.matched).collect();
+        matched.push(self.log.last_index());
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let index = matched[self.majority() - 1];
+        if index > self.commit && self.log.term(index) == self.term {
+            self.commit = index;
+        }
+    }
+
+    /// The next request for the member `id`, if one is due.
+    fn request_for(&mut self, id: u64, heartbeat: Duration) -> Option<Request> {
+        let last = self.log.last_index();
+        let peer = self.peers.get_mut(&id)?;
+        match self.role {
+            Role::Candidate if peer.asked < self.term => {
+                peer.asked = self.term;
+                return Some(Request::Vote {
+                    term: self.term,
+                    last_index: last,
+                    last_term: self.log.term(last),
+                });
+            }
+            Role::Leader => {
+                let due = peer.sent_round < self.round
+                    || peer.next <= last
+                    || peer
+                        .last_sent
+                        .is_none_or(|sent| sent.elapsed() >= heartbeat);
+                if !due {
+                    return None;
+                }
+                peer.sent_round = self.round;
+                peer.last_sent = Some(Instant::now());
+                let prev_index = peer.next - 1;
+                let mut entries = Vec::new();
+                let mut size = 0;
+                for (term, command) in &self.log.entries[prev_index as usize..] {
+                    if !entries.is_empty() && size + command.len() > BATCH {
+                        break;
+                    }
+                    size += command.len();
+                    entries.push((*term, command.clone()));
+                }
+                return Some(Request::Append {
+                    term: self.term,
+                    prev_index,
+                    prev_term: self.log.term(prev_index),
+                    commit: self.commit,
+                    entries,
+                    round: self.round,
+                });
+            }
+            _ => return None,
+        }
+    }
+
+    /// Handles the reply of the member `id` to `request`.
+    fn handle(&mut self, id: u64, request: &Request, kind: u8, payload: &[u8]) -> io::Result<()> {
+        let term = read_u64(payload, 0)?;
+        let accepted = read_u64(payload, 8)? != 0;
+        self.observe_term(term)?;
+        match request {
+            Request::Vote { term, .. } if kind == VOTED => {
+                if self.role == Role::Candidate && self.term == *term && accepted {
+                    self.votes.insert(id);
+                    if self.votes.len() >= self.majority() {
+                        self.lead()?;
+                    }
+                }
+            }
+            Request::Append { term, round, .. } if kind == APPENDED => {
+                if self.role != Role::Leader || self.term != *term {
+                    return Ok(());
+                }
+                let index = read_u64(payload, 16)?;
+                let last = self.log.last_index();
+                let Some(peer) = self.peers.get_mut(&id) else {
+                    return Ok(());
+                };
+                peer.confirmed = peer.confirmed.max(*round);
+                if accepted {
+                    peer.matched = peer.matched.max(index);
+                    peer.next = peer.matched + 1;
+                    self.advance();
+                } else {
+                    peer.next = index.clamp(peer.matched + 1, last + 1);
+                }
+            }
+            _ => return Err(invalid(&format!("unexpected frame type {}", kind))),
+        }
+        return Ok(());
+    }
+
+    /// Answers a request of another member.
+    fn answer(
+        &mut self,
+        kind: u8,
+        payload: &[u8],
+        options: &RaftOptions,
+    ) -> io::Result<(u8, Vec<u8>)> {
+        let term = read_u64(payload, 0)?;
+        let from = read_u64(payload, 8)?;
+        if !options.peers.iter().any(|(id, _)| *id == from) {
+            return Err(invalid(&format!(
+                "request from member {} of another group",
+                from
+            )));
+        }
+        self.observe_term(term)?;
+        match kind {
+            VOTE => {
+                let last_index = read_u64(payload, 16)?;
+                let last_term = read_u64(payload, 24)?;
+                let granted = term == self.term
+                    && self.voted_for.is_none_or(|vote| vote == from)
+                    && (last_term, last_index) >= (self.log.last_term(), self.log.last_index());
+                if granted {
+                    self.voted_for = Some(from);
+                    self.save()?;
+                    self.deadline = election_deadline(options);
+                }
+                return Ok((VOTED, encode_reply(self.term, granted, 0)));
+            }
+            APPEND => {
+                if term < self.term {
+                    return Ok((APPENDED, encode_reply(self.term, false, 0)));
+                }
+                self.role = Role::Follower;
+                self.leader = Some(from);
+                self.deadline = election_deadline(options);
+                let prev_index = read_u64(payload, 16)?;
+                let prev_term = read_u64(payload, 24)?;
+                let commit = read_u64(payload, 32)?;
+                let entries = decode_entries(payload, 40)?;
+                if prev_index > self.log.last_index() {
+                    let hint = self.log.last_index() + 1;
+                    return Ok((APPENDED, encode_reply(self.term, false, hint)));
+                }
+                let conflict = self.log.term(prev_index);
+                if conflict != prev_term {
+                    // Skips the whole term that differs, instead of one entry per round trip.
+                    let mut hint = prev_index;
+                    while hint > 1 && self.log.term(hint - 1) == conflict {
+                        hint -= 1;
+                    }
+                    return Ok((APPENDED, encode_reply(self.term, false, hint)));
+                }
+                let matched = prev_index + entries.len() as u64;
+                let mut appended = false;
+                for (index, (term, command)) in (prev_index + 1..).zip(entries) {
+                    if index <= self.log.last_index() {
+                        if self.log.term(index) == term {
+                            continue;
+                        }
+                        self.log.truncate(index)?;
+                    }
+                    self.log.push(term, command)?;
+                    appended = true;
+                }
+                if appended {
+                    self.log.sync()?;
+                }
+                self.commit = self.commit.max(commit.min(matched));
+                return Ok((APPENDED, encode_reply(self.term, true, matched)));
+            }
+            _ => return Err(invalid(&format!("unexpected frame type {}", kind))),
+        }
+    }
+}
+
+impl Peer {
+    fn new(last: u64) -> Self {
+        return Peer {
+            next: last + 1,
+            matched: 0,
+            asked: 0,
+            confirmed: 0,
+            sent_round: 0,
+            last_sent: None,
+        };
+    }
+}
+
+impl Request {
+    fn encode(&self, from: u64) -> (u8, Vec<u8>) {
+        let mut payload = Vec::new();
+        match self {
+            Request::Vote {
+                term,
+                last_index,
+                last_term,
+            } => {
+                for field in [*term, from, *last_index, *last_term] {
+                    payload.extend_from_slice(&field.to_le_bytes());
+                }
+                return (VOTE, payload);
+            }
+            Request::Append {
+                term,
+                prev_index,
+                prev_term,
+                commit,
+                entries,
+                ..
+            } => {
+                for field in [*term, from, *prev_index, *prev_term, *commit] {
+                    payload.extend_from_slice(&field.to_le_bytes());
+                }
+                for (term, command) in entries {
+                    payload.extend_from_slice(&term.to_le_bytes());
+                    payload.extend_from_slice(&(command.len() as u64).to_le_bytes());
+                    payload.extend_from_slice(command);
+                }
+                return (APPEND, payload);
+            }
+        }
+    }
+}
+
+fn encode_reply(term: u64, accepted: bool, index: u64) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(24);
+    for field in [term, accepted as u64, index] {
+        payload.extend_from_slice(&field.to_le_bytes());
+    }
+    return payload;
+}
+
+fn decode_entries(payload: &[u8], mut at: usize) -> io::Result<Vec<(u64, Vec<u8>)>> {
+    let mut entries = Vec::new();
+    while at < payload.len() {
+        let term = read_u64(payload, at)?;
+        let length = read_u64(payload, at + 8)? as usize;
+        let command = payload
+            .get(at + 16..(at + 16).saturating_add(length))
+            .ok_or_else(|| invalid("truncated frame"))?;
+        entries.push((term, command.to_vec()));
+        at += 16 + length;
+    }
+    return Ok(entries);
+}
+
+/// A command of the log: its type, the key's length and the key, then the value.
+fn encode_command(kind: u8, key: &str, value: &[u8]) -> Vec<u8> {
+    let mut command = Vec::with_capacity(9 + key.len() + value.len());
+    command.push(kind);
+    command.extend_from_slice(&(key.len() as u64).to_le_bytes());
+    command.extend_from_slice(key.as_bytes());
+    command.extend_from_slice(value);
+    return command;
+}
+
+fn apply_command(store: &mut RCask, command: &[u8]) -> Result<()> {
+    if command.first() == Some(&NOOP) {
+        return Ok(());
+    }
+    let length = read_u64(command, 1)? as usize;
+    let key = command
+        .get(9..9usize.saturating_add(length))
+        .ok_or_else(|| invalid("truncated Raft command"))?;
+    let value = &command[9 + length..];
+    let key = std::str::from_utf8(key).map_err(|_| Error::InvalidKey { key: key.to_vec() })?;
+    return match command[0] {
+        SET => store.set(key, value).map(drop),
+        DELETE => store.delete(key).map(drop),
+        kind => Err(invalid(&format!("unknown Raft command {}", kind)).into()),
+    };
+}
+
+fn election_deadline(options: &RaftOptions) -> Instant {
+    let timeout = options.election_timeout;
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(options.id);
+    let jitter = hasher.finish() % (timeout.as_micros().max(1) as u64);
+    return Instant::now() + timeout + Duration::from_micros(jitter);
+}
+
+/// Reads the term, vote and applied index of `<pattern>.raftstate`, all zero and no vote if
+/// the member never ran.
+fn read_state(path: &Path) -> io::Result<(u64, Option<u64>, u64)> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((0, None, 0)),
+        Err(e) => return Err(e),
+    };
+    let term = read_u64(&bytes, 0)?;
+    let voted = read_u64(&bytes, 8)? != 0;
+    let vote = read_u64(&bytes, 16)?;
+    let applied = read_u64(&bytes, 24)?;
+    return Ok((term, voted.then_some(vote), applied));
+}
+
+fn write_state(path: &Path, term: u64, voted_for: Option<u64>, applied: u64) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(32);
+    for field in [
+        term,
+        voted_for.is_some() as u64,
+        voted_for.unwrap_or(0),
+        applied,
+    ] {
+        bytes.extend_from_slice(&field.to_le_bytes());
+    }
+    let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+    let mut file = File::create(&tmp)?;
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+    return fs::rename(&tmp, path);
+}
+
+/// The Raft log: its entries in memory, and in `<pattern>.raft` as the term, the length of
+/// the command, the command and a CRC-32 of the three.
+struct Log {
+    file: File,
+    /// Term and command of every entry; entry `i` is at `i - 1`.
+    entries: Vec<(u64, Vec<u8>)>,
+    /// Where every entry starts in the file.
+    offsets: Vec<u64>,
+    length: u64,
+}
+
+impl Log {
+    fn open(path: &Path) -> io::Result<Log> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let mut entries = Vec::new();
+        let mut offsets = Vec::new();
+        let mut at = 0;
+        while let Some((term, command, next)) = decode_entry(&bytes, at) {
+            entries.push((term, command));
+            offsets.push(at as u64);
+            at = next;
+        }
+        // An entry torn by a crash was never synced, so no member counted it as held.
+        file.set_len(at as u64)?;
+        return Ok(Log {
+            file,
+            entries,
+            offsets,
+            length: at as u64,
+        });
+    }
+
+    fn last_index(&self) -> u64 {
+        return self.entries.len() as u64;
+    }
+
+    /// The term of entry `index`, 0 before the first.
+    fn term(&self, index: u64) -> u64 {
+        if index == 0 {
+            return 0;
+        }
+        return self
+            .entries
+            .get(index as usize - 1)
+            .map_or(0, |(term, _)| *term);
+    }
+
+    fn last_term(&self) -> u64 {
+        return self.term(self.last_index());
+    }
+
+    /// Appends an entry, which is only durable after `sync`.
+    fn push(&mut self, term: u64, command: Vec<u8>) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(20 + command.len());
+        bytes.extend_from_slice(&term.to_le_bytes());
+        bytes.extend_from_slice(&(command.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&command);
+        let mut crc = Crc32::new();
+        crc.update(&bytes);
+        bytes.extend_from_slice(&crc.finish().to_le_bytes());
+        self.file.seek(SeekFrom::Start(self.length))?;
+        self.file.write_all(&bytes)?;
+        self.offsets.push(self.length);
+        self.length += bytes.len() as u64;
+        self.entries.push((term, command));
+        return Ok(());
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        return self.file.sync_data();
+    }
+
+    /// Drops entry `index` and every entry after it.
+    fn truncate(&mut self, index: u64) -> io::Result<()> {
+        let Some(&offset) = self.offsets.get(index as usize - 1) else {
+            return Ok(());
+        };
+        self.file.set_len(offset)?;
+        self.length = offset;
+        self.entries.truncate(index as usize - 1);
+        self.offsets.truncate(index as usize - 1);
+        return Ok(());
+    }
+}
+
+/// Decodes the entry of the log file at `at`, and where the next one starts, unless it is
+/// torn or damaged.
+fn decode_entry(bytes: &[u8], at: usize) -> Option<(u64, Vec<u8>, usize)> {
+    let term = read_u64(bytes, at).ok()?;
+    let length = read_u64(bytes, at + 8).ok()? as usize;
+    let end = (at + 16).checked_add(length)?;
+    let checksum = bytes.get(end..end.checked_add(4)?)?;
+    let mut crc = Crc32::new();
+    crc.update(&bytes[at..end]);
+    if crc.finish().to_le_bytes() != checksum {
+        return None;
+    }
+    return Some((term, bytes[at + 16..end].to_vec(), end + 4));
+}
@@ -0,0 +1,520 @@
+//! Reading records through the value pipeline, and reader handles that do so concurrently
+//! with the writer.
+//!
+//! A `Reader` has its own file handle and its own copy of the index. The writer publishes the
+//! end of the log after every write and bumps a generation whenever compaction replaces the
+//! segment. Before each read a reader compares these with what it has indexed, reads any new
+//! records into its index or reopens the new segment, so a write that returned on the writer
+//! is visible to every read that starts afterwards. Reads never take a lock; only reopening
+//! after a compaction briefly locks the segment path.
+//!
+//! Other processes can read a live store too. The writer holds an exclusive lock on
+//! `<directory>/<pattern>.lock` while it is open, so a second writer fails with
+//! `Error::Locked`, and `Builder::open_reader` opens a reader without it. Such a reader
+//! cannot see what the writer publishes, so every `Builder::refresh_interval` it reads the
+//! records appended since and looks for a newer segment. Compaction builds a segment under
+//! another name and renames it into place once complete, so the newest segment in the
+//! directory is always whole. A reader keeps reading a superseded segment until then; on
+//! Unix its open handle outlives the writer deleting it. `RCask::follow` opens such a reader
+//! that tails the store every `FOLLOW_INTERVAL`, e.g. for a reporting process that wants
+//! near-real-time data without replication.
+
+use crate::blob::BlobStore;
+use crate::builder::Builder;
+use crate::cache::BlockCache;
+use crate::dedup::ContentStore;
+use crate::keycodec::{self, KeyCodec};
+use crate::kvstore::{Attributes, KVStore};
+use crate::notify::DirectoryEvents;
+use crate::value::ValueEncoding;
+use crate::vfs::FileSystem;
+use crate::{delta, frame, now_millis, segment_paths, Error, RCask, Result};
+use fs2::FileExt;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often a reader opened with `RCask::follow` reads what the writer appended.
+pub const FOLLOW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// State the writer publishes to its readers.
+pub(crate) struct Published {
+    /// End of the last complete record in the active segment.
+    pub(crate) end: AtomicU64,
+    /// Incremented every time compaction replaces the active segment.
+    pub(crate) generation: AtomicU64,
+    /// Path of the active segment.
+    pub(crate) segment: Mutex<String>,
+}
+
+impl Published {
+    pub(crate) fn new(store: &KVStore) -> Self {
+        return Published {
+            end: AtomicU64::new(store.end()),
+            generation: AtomicU64::new(0),
+            segment: Mutex::new(store.path.clone()),
+        };
+    }
+
+    /// Publishes records appended to the active segment.
+    pub(crate) fn appended(&self, store: &KVStore) {
+        self.end.store(store.end(), Ordering::Release);
+    }
+
+    /// Publishes a new active segment.
+    pub(crate) fn replaced(&self, store: &KVStore) {
+        *self.segment.lock().unwrap_or_else(|e| e.into_inner()) = store.path.clone();
+        self.end.store(store.end(), Ordering::Release);
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+}
+
+impl RCask {
+    /// Opens a read-only follower of the store `pattern` in `directory`, which a writer in
+    /// another process keeps writing to. The follower reads the records appended to the active
+    /// segment, and follows compaction to the new one, every `FOLLOW_INTERVAL`. Stores written
+    /// with value options need `Builder::open_reader` with the same options instead.
+    pub fn follow(directory: String, pattern: String) -> Result<Reader> {
+        return RCask::builder(directory, pattern)
+            .refresh_interval(FOLLOW_INTERVAL)
+            .open_reader();
+    }
+}
+
+/// Where a reader learns what the writer wrote.
+enum Watch {
+    /// From a writer in this process.
+    Writer {
+        published: Arc<Published>,
+        generation: u64,
+    },
+    /// From the directory of a writer in another process.
+    Directory {
+        directory: String,
+        pattern: String,
+        interval: Duration,
+        /// When the directory was last looked at, `None` to look at the next read.
+        checked: Option<Instant>,
+        /// Changes to the store's files, where the system reports them, see `notify`.
+        events: Option<DirectoryEvents>,
+    },
+}
+
+/// A read-only handle on a store, created by `RCask::reader`, or by `Builder::open_reader`
+/// in a process other than the writer's.
+///
+/// Readers can be moved to other threads and read while the writer keeps writing; every write
+/// acknowledged by the writer is visible to the reads that start after it, or with
+/// `Builder::open_reader` to those after the next refresh.
+pub struct Reader {
+    fs: Arc<dyn FileSystem>,
+    watch: Watch,
+    store: KVStore,
+    values: ValueEncoding,
+    verify_checksums: bool,
+    compress_index: bool,
+    block_cache: Option<Arc<BlockCache>>,
+    key_codec: Option<Arc<dyn KeyCodec>>,
+}
+
+impl Reader {
+    pub(crate) fn new(
+        fs: Arc<dyn FileSystem>,
+        published: Arc<Published>,
+        values: ValueEncoding,
+        verify_checksums: bool,
+        compress_index: bool,
+        block_cache: Option<Arc<BlockCache>>,
+        key_codec: Option<Arc<dyn KeyCodec>>,
+    ) -> Result<Self> {
+        let generation = published.generation.load(Ordering::Acquire);
+        let segment = published
+            .segment
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let mut reader = Reader {
+            store: KVStore::open_read_only(fs.as_ref(), Path::new(&segment))?,
+            fs,
+            watch: Watch::Writer {
+                published,
+                generation,
+            },
+            values,
+            verify_checksums,
+            compress_index,
+            block_cache,
+            key_codec,
+        };
+        reader.reopen(Path::new(&segment))?;
+        return Ok(reader);
+    }
+
+    /// Opens a reader on the store a `Builder` describes, see `Builder::open_reader`.
+    pub(crate) fn open(builder: Builder) -> Result<Self> {
+        let Builder {
+            directory,
+            pattern,
+            schema,
+            delta,
+            dedup,
+            blobs,
+            checksums,
+            fs,
+            compress_index,
+            block_cache,
+            refresh_interval,
+            key_codec,
+            #[cfg(feature = "zstd")]
+            compression,
+            ..
+        } = builder;
+        let Some(segment) = segment_paths(&directory, &pattern)?.pop() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no store {} in {}", pattern, directory),
+            )
+            .into());
+        };
+        // Never records a codec, which is the writer's to do.
+        keycodec::check(&directory, &pattern, key_codec.as_deref(), false)?;
+        let values = ValueEncoding {
+            schema: schema.map(Arc::new),
+            delta,
+            dedup: match dedup {
+                Some(options) => Some(ContentStore::open(&directory, &pattern, options)?),
+                None => None,
+            },
+            blobs: match blobs {
+                Some(options) => Some(BlobStore::open(&directory, &pattern, options)?),
+                None => None,
+            },
+            shared: HashSet::new(),
+            live_blobs: HashSet::new(),
+            #[cfg(feature = "zstd")]
+            compression,
+            #[cfg(feature = "zstd")]
+            dictionary: None,
+        };
+        values.check_format(&directory, &pattern, false)?;
+        let events = DirectoryEvents::watch(&directory, &pattern).ok();
+        let mut reader = Reader {
+            store: KVStore::open_read_only(fs.as_ref(), &segment)?,
+            fs,
+            watch: Watch::Directory {
+                directory,
+                pattern,
+                interval: refresh_interval,
+                checked: Some(Instant::now()),
+                events,
+            },
+            values,
+            verify_checksums: checksums.unwrap_or(false),
+            compress_index,
+            block_cache: block_cache.map(|capacity| Arc::new(BlockCache::new(capacity))),
+            key_codec,
+        };
+        reader.reopen(&segment)?;
+        return Ok(reader);
+    }
+
+    /// Retrieves the value associated with a given key in string format.
+    pub fn get(&mut self, key: &str) -> Result<Option<String>> {
+        return match self.get_bytes(key)? {
+            Some(bytes) => String::from_utf8(bytes)
+                .map(Some)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err).into()),
+            None => Ok(None),
+        };
+    }
+
+    /// Retrieves the raw value bytes associated with a given key.
+    pub fn get_bytes(&mut self, key: &str) -> Result<Option<Vec<u8>>> {
+        return self.read_value(key, Vec::new());
+    }
+
+    /// Like `get_bytes`, but reads the value into `buf`, see `RCask::get_into`.
+    pub fn get_into(&mut self, key: &str, buf: &mut Vec<u8>) -> Result<bool> {
+        return match self.read_value(key, std::mem::take(buf))? {
+            Some(value) => {
+                *buf = value;
+                Ok(true)
+            }
+            None => Ok(false),
+        };
+    }
+
+    fn read_value(&mut self, key: &str, buf: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.catch_up()?;
+        let key = match &self.key_codec {
+            Some(codec) => codec.encode(key),
+            None => Cow::Borrowed(key),
+        };
+        let key = key.as_ref();
+        let verify = self.verify_checksums;
+        let Some((framed, attributes)) =
+            read_latest_into(&mut self.store, &mut self.values, key, verify, buf)?
+        else {
+            return Ok(None);
+        };
+        expect_value(key, &attributes)?;
+        let offset = self.store.offset(key).unwrap_or_default();
+        let (plain, _) = resolve(
+            &mut self.store,
+            &mut self.values,
+            key,
+            offset,
+            framed,
+            verify,
+        )?;
+        return Ok(Some(self.values.untag_schema(plain)?));
+    }
+
+    /// Catches up with the writer now. Reads do so themselves, a reader opened with
+    /// `Builder::open_reader` once every refresh interval.
+    pub fn refresh(&mut self) -> Result<()> {
+        if let Watch::Directory { checked, .. } = &mut self.watch {
+            *checked = None;
+        }
+        return self.catch_up();
+    }
+
+    /// Waits until the writer in another process changes the store or `timeout` has passed,
+    /// then catches up, e.g. to tail a store without reading in a busy loop. Woken as soon as
+    /// a change lands where the system reports changes, see `notify`; otherwise returns at
+    /// the next refresh. A reader of a writer in this process catches up at once.
+    pub fn wait(&mut self, timeout: Duration) -> Result<()> {
+        if let Watch::Directory {
+            interval,
+            checked,
+            events,
+            ..
+        } = &mut self.watch
+        {
+            match events {
+                Some(events) => events.wait(timeout)?,
+                None => {
+                    let next = checked
+                        .map(|at| interval.saturating_sub(at.elapsed()))
+                        .unwrap_or_default();
+                    std::thread::sleep(next.min(timeout));
+                }
+            }
+        }
+        return self.catch_up();
+    }
+
+    /// Catches up with everything the writer has published, or what it wrote to the
+    /// directory if the refresh interval has passed.
+    fn catch_up(&mut self) -> Result<()> {
+        match &mut self.watch {
+            Watch::Writer {
+                published,
+                generation,
+            } => {
+                let current = published.generation.load(Ordering::Acquire);
+                if current != *generation {
+                    let segment = published
+                        .segment
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .clone();
+                    self.store = KVStore::open_read_only(self.fs.as_ref(), Path::new(&segment))?;
+                    *generation = current;
+                    return self.reopen(Path::new(&segment));
+                }
+                if published.end.load(Ordering::Acquire) > self.store.end() {
+                    self.store.load()?;
+                }
+            }
+            Watch::Directory {
+                directory,
+                pattern,
+                interval,
+                checked,
+                events,
+            } => {
+                let due = match (&mut *checked, events) {
+                    (None, _) => true,
+                    (Some(_), Some(events)) => events.changed()?,
+                    (Some(at), None) => at.elapsed() >= *interval,
+                };
+                if !due {
+                    return Ok(());
+                }
+                let latest = segment_paths(directory, pattern)?.pop();
+                let Some(segment) = latest.filter(|path| *path != Path::new(&self.store.path))
+                else {
+                    self.store.load()?;
+                    *checked = Some(Instant::now());
+                    return Ok(());
+                };
+                match KVStore::open_read_only(self.fs.as_ref(), &segment) {
+                    Ok(store) => self.store = store,
+                    // Superseded again before it was opened; the next read looks again.
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        *checked = None;
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+                *checked = Some(Instant::now());
+                return self.reopen(&segment);
+            }
+        }
+        return Ok(());
+    }
+
+    /// Sets up the segment just opened as `self.store` at `segment`.
+    fn reopen(&mut self, segment: &Path) -> Result<()> {
+        self.store.cache_blocks(self.block_cache.clone());
+        self.store.compress_index(self.compress_index);
+        return self.values.open_segment(segment);
+    }
+}
+
+/// Takes the exclusive lock on `<directory>/<pattern>.lock` that the writer of a store holds
+/// while it is open, see the module docs.
+pub(crate) fn lock_writer(directory: &str, pattern: &str) -> Result<fs::File> {
+    let path = Path::new(directory).join(format!("{}.lock", pattern));
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)?;
+    if let Err(e) = file.try_lock_exclusive() {
+        if e.kind() == fs2::lock_contended_error().kind() {
+            return Err(Error::Locked {
+                path: path.to_string_lossy().to_string(),
+            });
+        }
+        return Err(e.into());
+    }
+    return Ok(file);
+}
+
+/// Reads the record of `key` at `offset` and returns its plain (schema tagged) bytes,
+/// resolving delta chains and values stored outside the log, together with the number of
+/// deltas that were applied.
+pub(crate) fn read_plain(
+    store: &mut KVStore,
+    values: &mut ValueEncoding,
+    key: &str,
+    offset: u64,
+    verify: bool,
+) -> Result<Option<(Vec<u8>, u16)>> {
+    return match read_framed(store, values, key, offset, verify)? {
+        Some((_, attributes)) if attributes.collection => Ok(None),
+        Some((framed, _)) => Ok(Some(resolve(store, values, key, offset, framed, verify)?)),
+        None => Ok(None),
+    };
+}
+
+/// Resolves the framed value of the record of `key` at `offset` into plain bytes.
+pub(crate) fn resolve(
+    store: &mut KVStore,
+    values: &mut ValueEncoding,
+    key: &str,
+    offset: u64,
+    framed: Vec<u8>,
+    verify: bool,
+) -> Result<(Vec<u8>, u16)> {
+    if !values.is_framed() {
+        return Ok((framed, 0));
+    }
+
+    return match frame::parse(&framed)? {
+        frame::Frame::Full(plain) => Ok((plain.to_vec(), 0)),
+        frame::Frame::Shared { hash } => Ok((values.shared_value(hash)?, 0)),
+        frame::Frame::Blob { id } => Ok((values.blob_value(id)?, 0)),
+        frame::Frame::Delta { depth, base, ops } => {
+            // Bases always precede their deltas, which also rules out cycles.
+            if base >= offset {
+                return Err(corrupt_delta(key));
+            }
+            let Some((base_plain, _)) = read_plain(store, values, key, base, verify)? else {
+                return Err(corrupt_delta(key));
+            };
+            Ok((delta::apply(&base_plain, ops)?, depth))
+        }
+    };
+}
+
+/// Reads the record of `key` at `offset` and returns it before frames are resolved.
+pub(crate) fn read_framed(
+    store: &mut KVStore,
+    values: &mut ValueEncoding,
+    key: &str,
+    offset: u64,
+    verify: bool,
+) -> Result<Option<(Vec<u8>, Attributes)>> {
+    return read_framed_into(store, values, key, offset, verify, Vec::new());
+}
+
+/// Like `read_framed`, but reads into `buf`, see `KVStore::get_value_into`.
+fn read_framed_into(
+    store: &mut KVStore,
+    values: &mut ValueEncoding,
+    key: &str,
+    offset: u64,
+    verify: bool,
+    buf: Vec<u8>,
+) -> Result<Option<(Vec<u8>, Attributes)>> {
+    return match store.get_value_into(key, offset, verify, buf)? {
+        // Collection records bypass the value pipeline.
+        Some((stored, attributes)) if attributes.collection => Ok(Some((stored, attributes))),
+        Some((stored, attributes)) => Ok(Some((values.unseal(stored)?, attributes))),
+        None => Ok(None),
+    };
+}
+
+/// Fails with `Error::WrongType` if a record read as a value belongs to a collection.
+pub(crate) fn expect_value(key: &str, attributes: &Attributes) -> Result<()> {
+    if attributes.collection {
+        return Err(Error::WrongType {
+            key: key.to_string(),
+            expected: "value",
+        });
+    }
+    return Ok(());
+}
+
+/// Reads the latest record of `key`, treating expired records as missing.
+pub(crate) fn read_latest(
+    store: &mut KVStore,
+    values: &mut ValueEncoding,
+    key: &str,
+    verify: bool,
+) -> Result<Option<(Vec<u8>, Attributes)>> {
+    return read_latest_into(store, values, key, verify, Vec::new());
+}
+
+/// Like `read_latest`, but reads into `buf`, see `KVStore::get_value_into`.
+pub(crate) fn read_latest_into(
+    store: &mut KVStore,
+    values: &mut ValueEncoding,
+    key: &str,
+    verify: bool,
+    buf: Vec<u8>,
+) -> Result<Option<(Vec<u8>, Attributes)>> {
+    let Some(offset) = store.offset(key) else {
+        return Ok(None);
+    };
+    return match read_framed_into(store, values, key, offset, verify, buf)? {
+        Some((_, attributes)) if attributes.is_expired(now_millis()) => Ok(None),
+        record => Ok(record),
+    };
+}
+
+fn corrupt_delta(key: &str) -> Error {
+    return io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Data corruption: broken delta chain for key {}", key),
+    )
+    .into();
+}
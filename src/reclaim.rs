@@ -0,0 +1,73 @@
+//! Reclaiming dead regions of the log without compaction.
+//!
+//! Records of the active segment that no live key needs any more are dead. Large contiguous
+//! runs of them are marked as skippable and punched out of the file, which returns their disk
+//! space right away and defers the full rewrite of compaction.
+
+use crate::kvstore::{BATCH_HEADER_SIZE, HOLE_HEADER_SIZE};
+use crate::{collection, frame, reader, RCask, Result};
+
+impl RCask {
+    /// Punches every contiguous dead region of at least `min_region` bytes out of the active
+    /// segment and returns the number of bytes punched, which includes regions punched by
+    /// earlier calls. Regions still holding a live record, the base of a delta, an earlier
+    /// record of a list, set or hash or a version kept by `Builder::history` are kept, as are
+    /// expired records until compaction drops them. Frees nothing where the file system cannot
+    /// punch holes, such as outside Linux.
+    pub fn punch_holes(&mut self, min_region: u64) -> Result<u64> {
+        // 1. Find the extent of every record that is still needed.
+        let verify = self.verify_checksums;
+        let mut live = Vec::new();
+        for key in self.store.keys() {
+            let versions = self.retained_versions(&key, true)?;
+            for version in self.store.offset(&key).into_iter().chain(versions) {
+                let mut offset = Some(version);
+                while let Some(at) = offset {
+                    live.push((at, self.store.record_end(at)?));
+                    offset = self.record_base(&key, at, verify)?;
+                }
+            }
+        }
+        // Commit markers keep the records of their batch visible to loading.
+        for &at in self.store.commit_markers() {
+            live.push((at, at + BATCH_HEADER_SIZE));
+        }
+        live.sort_unstable();
+        live.dedup();
+
+        // 2. Punch the gaps between them.
+        // Every region needs room for its marker.
+        let min_region = min_region.max(HOLE_HEADER_SIZE);
+        let mut freed = 0;
+        let mut start = self.store.data_start();
+        let end = self.store.end();
+        for (record_start, record_end) in live.into_iter().chain([(end, end)]) {
+            if record_start >= start.saturating_add(min_region) {
+                freed += self.store.punch_hole(start, record_start)?;
+            }
+            start = start.max(record_end);
+        }
+        return Ok(freed);
+    }
+
+    /// Returns the offset of the earlier record that the record of `key` at `offset` is
+    /// stored against, if any.
+    fn record_base(&mut self, key: &str, offset: u64, verify: bool) -> Result<Option<u64>> {
+        let Some((framed, attributes)) =
+            reader::read_framed(&mut self.store, &mut self.values, key, offset, verify)?
+        else {
+            return Ok(None);
+        };
+        if attributes.collection {
+            return Ok(collection::chain_base(&framed)?);
+        }
+        // Tombstones hold no value.
+        if !self.values.is_framed() || attributes.is_tombstone() {
+            return Ok(None);
+        }
+        return match frame::parse(&framed)? {
+            frame::Frame::Delta { base, .. } => Ok(Some(base)),
+            _ => Ok(None),
+        };
+    }
+}
@@ -0,0 +1,837 @@
+//! Streaming replication to followers over TCP.
+//!
+//! A `ReplicationSource` serves the primary's log to followers, and a `ReplicationSink` applies
+//! it to a store of its own on another machine, which stays as far behind as the source's poll
+//! interval. Records are shipped as the bytes of the active segment, so the follower's log is
+//! a copy of the primary's and readers of the follower see whole groups of records at once,
+//! as on the primary.
+//!
+//! The protocol is a stream of frames, each a type byte and a little-endian `u64` length
+//! followed by the payload:
+//!
+//! 1. The follower sends `HELLO` with the primary's store id (see `RCask::store_id`), segment
+//!    and position it has applied up to, if it has followed that primary before, and the blob
+//!    files it holds.
+//! 2. The source answers `WELCOME` with its store id and heartbeat interval, and whether it
+//!    starts over with a full copy or resumes at the follower's position.
+//! 3. A full copy, and every compaction of the primary after it, starts with `SEGMENT`, then
+//!    the segment's zstd dictionary as `DICT`, and ends with `SWITCH` listing every live blob
+//!    file once the records written so far have been sent. The follower builds the segment
+//!    on the side and only switches to it then.
+//! 4. `BLOB` frames carry blob and content-store files before any record references them, in
+//!    pieces of up to a MiB, and `RECORDS` frames the bytes of the log from a position on.
+//!    When idle, the source sends a `HEARTBEAT` with its current position.
+//! 5. The follower acknowledges every `RECORDS` and `HEARTBEAT` frame with the position it
+//!    has applied, in an `ACK`.
+//!
+//! The follower must be opened with the same value options as the primary, e.g.
+//! `Builder::blob_files` or a zstd compression, and must not be written to: writes of its own
+//! would make its log differ from the primary's. Its caches are invalidated as records
+//! arrive, but bounded mode, quotas and expiry callbacks only see the state it was opened
+//! with.
+
+use crate::kvstore::KVStore;
+use crate::{segment_number, RCask, Result, Version};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const HELLO: u8 = 1;
+const WELCOME: u8 = 2;
+const SEGMENT: u8 = 3;
+const DICT: u8 = 4;
+const BLOB: u8 = 5;
+const RECORDS: u8 = 6;
+const SWITCH: u8 = 7;
+const HEARTBEAT: u8 = 8;
+const ACK: u8 = 9;
+
+/// First bytes of `HELLO`.
+const MAGIC: &[u8; 8] = b"RCASKREP";
+const PROTOCOL_VERSION: u32 = 2;
+/// Bytes of the log sent per `RECORDS` frame, and of a blob file per `BLOB` frame.
+const CHUNK: usize = 1 << 20;
+/// Largest frame accepted. Only lists of blob files, in `HELLO` and `SWITCH`, and
+/// dictionaries come near it.
+const MAX_FRAME: u64 = 64 << 20;
+/// Directories of blob and content-store files after the pattern, by their number in frames.
+const BLOB_DIRS: [&str; 2] = ["large", "blobs"];
+
+/// A blob or content-store file, by the number of its directory and its name.
+type BlobFile = (u8, String);
+
+/// Serves the log of a store to followers, see the module docs.
+#[derive(Clone)]
+pub struct ReplicationSource {
+    store: Arc<Mutex<RCask>>,
+    poll_interval: Duration,
+    heartbeat_interval: Duration,
+    followers: Arc<Mutex<HashMap<u64, (SocketAddr, Version)>>>,
+    next_follower: Arc<AtomicU64>,
+}
+
+impl ReplicationSource {
+    /// Creates a source for `store`, which checks for new records every 100 ms and sends a
+    /// heartbeat after a second without any.
+    pub fn new(store: Arc<Mutex<RCask>>) -> Self {
+        return ReplicationSource {
+            store,
+            poll_interval: Duration::from_millis(100),
+            heartbeat_interval: Duration::from_secs(1),
+            followers: Arc::new(Mutex::new(HashMap::new())),
+            next_follower: Arc::new(AtomicU64::new(0)),
+        };
+    }
+
+    /// Sets how often the log is checked for new records.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        return self;
+    }
+
+    /// Sets how long the source waits without records before it sends a heartbeat. Followers
+    /// give up on a source they have not heard from for four times as long.
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        return self;
+    }
+
+    /// Returns the address and acknowledged position of every connected follower.
+    pub fn followers(&self) -> Vec<(SocketAddr, Version)> {
+        let followers = self.followers.lock().unwrap_or_else(|e| e.into_inner());
+        let mut followers: Vec<_> = followers.values().copied().collect();
+        followers.sort();
+        return followers;
+    }
+
+    /// Accepts followers on `listener`, serving each on a thread of its own, until accepting
+    /// fails.
+    pub fn listen(&self, listener: TcpListener) -> Result<()> {
+        for stream in listener.incoming() {
+            let source = self.clone();
+            let stream = stream?;
+            thread::spawn(move || {
+                let _ = source.serve(stream);
+            });
+        }
+        return Ok(());
+    }
+
+    /// Serves one follower over `stream` until it disconnects.
+    pub fn serve(&self, stream: TcpStream) -> Result<()> {
+        let peer = stream.peer_addr()?;
+        let id = self.next_follower.fetch_add(1, Ordering::Relaxed);
+        let result = self.stream_log(stream, id, peer);
+        self.followers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&id);
+        return match result {
+            Err(e) if is_disconnect(&e) => Ok(()),
+            result => Ok(result?),
+        };
+    }
+
+    fn stream_log(&self, mut stream: TcpStream, id: u64, peer: SocketAddr) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let hello = Hello::decode(&expect_frame(&mut stream, HELLO)?)?;
+        let (store_id, directory, pattern, segment, end) = {
+            let mut store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+            let store_id = store.store_id().map_err(io::Error::from)?;
+            let (directory, pattern) = (store.directory.clone(), store.pattern.clone());
+            (
+                store_id,
+                directory,
+                pattern,
+                store.segment,
+                store.store.end(),
+            )
+        };
+        let resume = hello
+            .position
+            .filter(|&(id, position)| {
+                id == store_id && position.segment() == segment && position.offset() <= end
+            })
+            .map(|(_, position)| position);
+
+        let mut welcome = store_id.to_le_bytes().to_vec();
+        welcome.extend_from_slice(&(self.heartbeat_interval.as_millis() as u64).to_le_bytes());
+        welcome.push(resume.is_none() as u8);
+        write_frame(&mut stream, WELCOME, &[&welcome])?;
+
+        let mut acks = stream.try_clone()?;
+        let followers = self.followers.clone();
+        followers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(id, (peer, resume.unwrap_or(Version::new(segment, 0))));
+        thread::spawn(move || {
+            while let Ok(payload) = expect_frame(&mut acks, ACK) {
+                let Ok(position) = read_position(&payload) else {
+                    break;
+                };
+                let mut followers = followers.lock().unwrap_or_else(|e| e.into_inner());
+                if let Some(follower) = followers.get_mut(&id) {
+                    follower.1 = position;
+                }
+            }
+        });
+
+        let directory = PathBuf::from(directory);
+        let mut sent: HashSet<BlobFile> = hello.blobs;
+        let mut position = resume;
+        let mut log: Option<fs::File> = None;
+        let mut last_sent = Instant::now();
+        loop {
+            let (segment, path, end) = {
+                let store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+                (
+                    store.segment,
+                    PathBuf::from(&store.store.path),
+                    store.store.end(),
+                )
+            };
+            let switching = position.is_none_or(|position| position.segment() != segment);
+            if switching || log.is_none() {
+                log = match fs::File::open(&path) {
+                    Ok(file) => Some(file),
+                    // Compacted away since; the next round sees the new segment.
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e),
+                };
+            }
+            if switching {
+                write_frame(&mut stream, SEGMENT, &[&segment.to_le_bytes()])?;
+                match fs::read(path.with_extension("dict")) {
+                    Ok(dictionary) => write_frame(&mut stream, DICT, &[&dictionary])?,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+                position = Some(Version::new(segment, 0));
+            }
+
+            // Blob files are written before the records that reference them, so every one
+            // the records up to `end` need is there by now.
+            let blobs = blob_files(&directory, &pattern)?;
+            let mut idle = true;
+            for blob in &blobs {
+                if sent.contains(blob) {
+                    continue;
+                }
+                let path = blob_path(&directory, &pattern, blob);
+                let mut file = match fs::File::open(&path) {
+                    Ok(file) => file,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e),
+                };
+                let (name, length) = (encode_blob(blob), file.metadata()?.len());
+                let mut piece = vec![0; CHUNK];
+                let mut offset = 0;
+                // An empty file is sent as one empty piece.
+                loop {
+                    let size = CHUNK.min((length - offset) as usize);
+                    file.read_exact(&mut piece[..size])?;
+                    let header = [length.to_le_bytes(), offset.to_le_bytes()].concat();
+                    write_frame(&mut stream, BLOB, &[&name, &header, &piece[..size]])?;
+                    offset += size as u64;
+                    if offset == length {
+                        break;
+                    }
+                }
+                sent.insert(blob.clone());
+                idle = false;
+            }
+
+            let mut offset = position.map_or(0, Version::offset);
+            if let Some(file) = &mut log {
+                file.seek(SeekFrom::Start(offset))?;
+                let mut chunk = vec![0; CHUNK];
+                while offset < end {
+                    let length = CHUNK.min((end - offset) as usize);
+                    file.read_exact(&mut chunk[..length])?;
+                    write_frame(
+                        &mut stream,
+                        RECORDS,
+                        &[&offset.to_le_bytes(), &chunk[..length]],
+                    )?;
+                    offset += length as u64;
+                    idle = false;
+                }
+            }
+            position = Some(Version::new(segment, offset));
+
+            if switching {
+                let mut names = Vec::new();
+                for blob in &blobs {
+                    names.extend_from_slice(&encode_blob(blob));
+                }
+                write_frame(&mut stream, SWITCH, &[&names])?;
+                sent = blobs.into_iter().collect();
+            }
+            if idle && last_sent.elapsed() >= self.heartbeat_interval {
+                let heartbeat = Version::new(segment, end).as_u64().to_le_bytes();
+                write_frame(&mut stream, HEARTBEAT, &[&heartbeat])?;
+                last_sent = Instant::now();
+            }
+            if idle {
+                thread::sleep(self.poll_interval);
+            } else {
+                last_sent = Instant::now();
+            }
+        }
+    }
+}
+
+/// How far a `ReplicationSink` has caught up with its primary.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplicationStatus {
+    /// Position of the primary's log the follower has applied up to.
+    pub applied: Option<Version>,
+    /// Position of the primary's log at its last heartbeat or record.
+    pub primary: Option<Version>,
+    /// When the primary was last heard from.
+    pub last_heard: Option<Instant>,
+}
+
+impl ReplicationStatus {
+    /// Bytes of the primary's log the follower has yet to apply, as of the last heartbeat.
+    pub fn lag_bytes(&self) -> Option<u64> {
+        let (applied, primary) = (self.applied?, self.primary?);
+        if applied.segment() != primary.segment() {
+            return None;
+        }
+        return Some(primary.offset().saturating_sub(applied.offset()));
+    }
+}
+
+/// Applies the log a `ReplicationSource` serves to a follower store, see the module docs.
+#[derive(Clone)]
+pub struct ReplicationSink {
+    store: Arc<Mutex<RCask>>,
+    status: Arc<Mutex<ReplicationStatus>>,
+}
+
+/// A blob file a follower is receiving the pieces of.
+struct Receiving {
+    blob: BlobFile,
+    file: fs::File,
+    /// Where it is written.
+    tmp: PathBuf,
+    /// Where it goes once it is complete.
+    path: PathBuf,
+    written: u64,
+}
+
+/// A segment a full copy is building next to the follower's active one.
+struct Pending {
+    /// The primary's number of the segment.
+    segment: u64,
+    file: fs::File,
+    /// Where it is built.
+    path: PathBuf,
+    /// Where it goes once it is complete.
+    target: PathBuf,
+    end: u64,
+}
+
+impl ReplicationSink {
+    /// Creates a sink applying records to `store`, which must not be written otherwise.
+    pub fn new(store: Arc<Mutex<RCask>>) -> Self {
+        return ReplicationSink {
+            store,
+            status: Arc::new(Mutex::new(ReplicationStatus::default())),
+        };
+    }
+
+    /// Returns how far the follower has caught up.
+    pub fn status(&self) -> ReplicationStatus {
+        return *self.status.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    /// Connects to a source at `address` and follows it, see `follow`.
+    pub fn connect<A: ToSocketAddrs>(&self, address: A) -> Result<()> {
+        return self.follow(TcpStream::connect(address)?);
+    }
+
+    /// Follows the source at the other end of `stream` until it disconnects or falls silent,
+    /// after which calling it again with a new connection resumes where it stopped.
+    pub fn follow(&self, stream: TcpStream) -> Result<()> {
+        return match self.apply_stream(stream) {
+            Err(e) if is_disconnect(&e) => Ok(()),
+            result => Ok(result?),
+        };
+    }
+
+    fn apply_stream(&self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+        let (directory, pattern, hello) = {
+            let store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+            let directory = PathBuf::from(&store.directory);
+            let position = store.replica_position()?;
+            let blobs = blob_files(&directory, &store.pattern)?
+                .into_iter()
+                .collect();
+            (directory, store.pattern.clone(), Hello { position, blobs })
+        };
+        write_frame(&mut stream, HELLO, &[&hello.encode()])?;
+        let welcome = expect_frame(&mut stream, WELCOME)?;
+        if welcome.len() != 25 {
+            return Err(invalid("malformed WELCOME frame"));
+        }
+        let primary_id = u128::from_le_bytes(welcome[..16].try_into().unwrap_or_default());
+        let heartbeat = u64::from_le_bytes(welcome[16..24].try_into().unwrap_or_default());
+        stream.set_read_timeout(Some(Duration::from_millis(heartbeat.max(1) * 4)))?;
+        let mut applied = match welcome[24] {
+            0 => hello.position.map(|(_, position)| position),
+            _ => None,
+        };
+
+        let mut pending: Option<Pending> = None;
+        let mut receiving: Option<Receiving> = None;
+        loop {
+            let (kind, payload) = read_frame(&mut stream)?;
+            let mut status = self.status();
+            status.last_heard = Some(Instant::now());
+            match kind {
+                SEGMENT => {
+                    let segment = read_u64(&payload, 0)?;
+                    // A copy the source gave up on is started over.
+                    if let Some(previous) = pending.take() {
+                        drop(previous.file);
+                        remove_if_exists(&previous.path)?;
+                        remove_if_exists(&previous.target.with_extension("dict"))?;
+                    }
+                    let store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+                    let target = PathBuf::from(store.get_next_segment_path());
+                    let path = PathBuf::from(format!("{}.compacting", target.display()));
+                    let file = fs::File::create(&path)?;
+                    pending = Some(Pending {
+                        segment,
+                        file,
+                        path,
+                        target,
+                        end: 0,
+                    });
+                }
+                DICT => {
+                    let Some(pending) = &pending else {
+                        return Err(invalid("DICT frame outside of a full copy"));
+                    };
+                    write_file(&pending.target.with_extension("dict"), &payload)?;
+                }
+                BLOB => {
+                    let (blob, at) = decode_blob(&payload, 0)?;
+                    let (length, offset) = (read_u64(&payload, at)?, read_u64(&payload, at + 8)?);
+                    let piece = &payload[at + 16..];
+                    if offset == 0 {
+                        let path = blob_path(&directory, &pattern, &blob);
+                        if let Some(parent) = path.parent() {
+                            fs::create_dir_all(parent)?;
+                        }
+                        let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+                        let file = fs::File::create(&tmp)?;
+                        receiving = Some(Receiving {
+                            blob: blob.clone(),
+                            file,
+                            tmp,
+                            path,
+                            written: 0,
+                        });
+                    }
+                    let Some(file) = receiving.as_mut().filter(|file| file.blob == blob) else {
+                        return Err(invalid("BLOB frame out of order"));
+                    };
+                    if offset != file.written || offset + piece.len() as u64 > length {
+                        return Err(invalid("BLOB frame out of order"));
+                    }
+                    file.file.write_all(piece)?;
+                    file.written += piece.len() as u64;
+                    if file.written == length {
+                        file.file.sync_all()?;
+                        fs::rename(&file.tmp, &file.path)?;
+                        receiving = None;
+                    }
+                }
+                RECORDS => {
+                    let offset = read_u64(&payload, 0)?;
+                    let records = &payload[8..];
+                    let segment = match &mut pending {
+                        Some(pending) => {
+                            if offset != pending.end {
+                                return Err(out_of_order(offset, pending.end));
+                            }
+                            pending.file.write_all(records)?;
+                            pending.end += records.len() as u64;
+                            pending.segment
+                        }
+                        None => {
+                            let Some(position) = applied else {
+                                return Err(invalid("RECORDS frame before a full copy"));
+                            };
+                            let mut store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+                            store.apply_replicated(offset, records)?;
+                            position.segment()
+                        }
+                    };
+                    let position = Version::new(segment, offset + records.len() as u64);
+                    applied = Some(position);
+                    status.primary = status.primary.max(Some(position));
+                    write_frame(&mut stream, ACK, &[&position.as_u64().to_le_bytes()])?;
+                }
+                SWITCH => {
+                    let Some(pending) = pending.take() else {
+                        return Err(invalid("SWITCH frame outside of a full copy"));
+                    };
+                    let mut live = HashSet::new();
+                    let mut at = 0;
+                    while at < payload.len() {
+                        let (blob, next) = decode_blob(&payload, at)?;
+                        live.insert(blob);
+                        at = next;
+                    }
+                    let mut store = self.store.lock().unwrap_or_else(|e| e.into_inner());
+                    store.switch_replica(pending, primary_id, &live)?;
+                }
+                HEARTBEAT => {
+                    status.primary = Some(read_position(&payload)?);
+                    if let Some(position) = applied {
+                        write_frame(&mut stream, ACK, &[&position.as_u64().to_le_bytes()])?;
+                    }
+                }
+                kind => return Err(invalid(&format!("unexpected frame type {}", kind))),
+            }
+            status.applied = applied;
+            *self.status.lock().unwrap_or_else(|e| e.into_inner()) = status;
+        }
+    }
+}
+
+impl RCask {
+    /// Returns the id that tells this store apart from every other one, e.g. to a follower
+    /// that checks it still follows the same primary. The id is made up when first asked for
+    /// and kept in `<directory>/<pattern>.id`; a follower takes on its primary's.
+    pub fn store_id(&mut self) -> Result<u128> {
+        let path = self.id_path();
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                return u128::from_str_radix(text.trim(), 16).map_err(|_| {
+                    invalid(&format!("{} does not hold a store id", path.display())).into()
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        let id = random_id();
+        write_file(&path, format!("{:032x}\n", id).as_bytes())?;
+        return Ok(id);
+    }
+
+    fn id_path(&self) -> PathBuf {
+        return Path::new(&self.directory).join(format!("{}.id", self.pattern));
+    }
+
+    /// Path of the follower state: the primary's id, its number for the active segment and
+    /// ours.
+    fn replica_path(&self) -> PathBuf {
+        return Path::new(&self.directory).join(format!("{}.replica", self.pattern));
+    }
+
+    /// Returns the primary's id and the position of its log this follower has applied up to,
+    /// if it has followed one into its active segment.
+    fn replica_position(&self) -> io::Result<Option<(u128, Version)>> {
+        let text = match fs::read_to_string(self.replica_path()) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        let [id, segment, ours] = fields.as_slice() else {
+            return Ok(None);
+        };
+        let (Ok(id), Ok(segment), Ok(ours)) = (
+            u128::from_str_radix(id, 16),
+            segment.parse::<u64>(),
+            ours.parse::<u64>(),
+        ) else {
+            return Ok(None);
+        };
+        if ours != self.segment {
+            return Ok(None);
+        }
+        return Ok(Some((id, Version::new(segment, self.store.end()))));
+    }
+
+    /// Appends records of the primary's log that start at `offset` to the active segment.
+    fn apply_replicated(&mut self, offset: u64, records: &[u8]) -> io::Result<()> {
+        if offset != self.store.end() {
+            return Err(out_of_order(offset, self.store.end()));
+        }
+        let keys = self.store.append_raw(records)?;
+        for key in &keys {
+            self.invalidate(key);
+        }
+        self.published.appended(&self.store);
+        if self.store.end() != offset + records.len() as u64 {
+            return Err(invalid("replicated records end inside a record"));
+        }
+        return Ok(());
+    }
+
+    /// Makes the segment a full copy built the active one, keeping only the blob files in
+    /// `live`, and records whose copy it is.
+    fn switch_replica(
+        &mut self,
+        pending: Pending,
+        primary_id: u128,
+        live: &HashSet<BlobFile>,
+    ) -> Result<()> {
+        pending.file.sync_all()?;
+        drop(pending.file);
+        self.fs.rename(&pending.path, &pending.target)?;
+        let mut store = KVStore::new(self.fs.as_ref(), &pending.target, self.checksum)?;
+        store.compress_index(self.compress_index);
+        store.cache_blocks(self.block_cache.clone());
+        self.values.open_segment(&pending.target)?;
+
+        let old = std::mem::replace(&mut self.store, store).path;
+        self.segment = segment_number(&pending.target);
+        self.published.replaced(&self.store);
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
+        self.pins.invalidate_all();
+        self.retire_segment(PathBuf::from(&old))?;
+        match self.fs.remove_file(&Path::new(&old).with_extension("dict")) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let directory = PathBuf::from(&self.directory);
+        for blob in blob_files(&directory, &self.pattern)? {
+            if !live.contains(&blob) {
+                let _ = self
+                    .fs
+                    .remove_file(&blob_path(&directory, &self.pattern, &blob));
+            }
+        }
+
+        let state = format!("{:032x} {} {}\n", primary_id, pending.segment, self.segment);
+        write_file(&self.replica_path(), state.as_bytes())?;
+        write_file(&self.id_path(), format!("{:032x}\n", primary_id).as_bytes())?;
+        return self.measure_quotas();
+    }
+}
+
+/// The follower's side of the handshake.
+struct Hello {
+    /// The primary followed and the position of its log applied up to.
+    position: Option<(u128, Version)>,
+    blobs: HashSet<BlobFile>,
+}
+
+impl Hello {
+    fn encode(&self) -> Vec<u8> {
+        let mut payload = MAGIC.to_vec();
+        payload.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+        let (id, position) = self.position.unwrap_or((0, Version::new(0, 0)));
+        payload.push(self.position.is_some() as u8);
+        payload.extend_from_slice(&id.to_le_bytes());
+        payload.extend_from_slice(&position.as_u64().to_le_bytes());
+        for blob in &self.blobs {
+            payload.extend_from_slice(&encode_blob(blob));
+        }
+        return payload;
+    }
+
+    fn decode(payload: &[u8]) -> io::Result<Self> {
+        if payload.len() < 37 || &payload[..8] != MAGIC {
+            return Err(invalid("not an rcask replication handshake"));
+        }
+        let version = u32::from_le_bytes(payload[8..12].try_into().unwrap_or_default());
+        if version != PROTOCOL_VERSION {
+            return Err(invalid(&format!(
+                "unsupported protocol version {}",
+                version
+            )));
+        }
+        let id = u128::from_le_bytes(payload[13..29].try_into().unwrap_or_default());
+        let position = Version::from_u64(read_u64(payload, 29)?);
+        let mut blobs = HashSet::new();
+        let mut at = 37;
+        while at < payload.len() {
+            let (blob, next) = decode_blob(payload, at)?;
+            blobs.insert(blob);
+            at = next;
+        }
+        return Ok(Hello {
+            position: (payload[12] != 0).then_some((id, position)),
+            blobs,
+        });
+    }
+}
+
+pub(crate) fn write_frame(stream: &mut TcpStream, kind: u8, parts: &[&[u8]]) -> io::Result<()> {
+    let length: usize = parts.iter().map(|part| part.len()).sum();
+    let mut frame = Vec::with_capacity(9 + length);
+    frame.push(kind);
+    frame.extend_from_slice(&(length as u64).to_le_bytes());
+    for part in parts {
+        frame.extend_from_slice(part);
+    }
+    return stream.write_all(&frame);
+}
+
+pub(crate) fn read_frame(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 9];
+    stream.read_exact(&mut header)?;
+    let length = u64::from_le_bytes(header[1..].try_into().unwrap_or_default());
+    if length > MAX_FRAME {
+        return Err(invalid(&format!("frame of {} bytes is too large", length)));
+    }
+    // Memory only grows with the bytes that actually arrive.
+    let mut payload = Vec::new();
+    Read::by_ref(stream)
+        .take(length)
+        .read_to_end(&mut payload)?;
+    if (payload.len() as u64) < length {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    return Ok((header[0], payload));
+}
+
+fn expect_frame(stream: &mut TcpStream, expected: u8) -> io::Result<Vec<u8>> {
+    let (kind, payload) = read_frame(stream)?;
+    if kind != expected {
+        return Err(invalid(&format!(
+            "expected frame type {}, got {}",
+            expected, kind
+        )));
+    }
+    return Ok(payload);
+}
+
+pub(crate) fn read_u64(payload: &[u8], at: usize) -> io::Result<u64> {
+    let bytes = payload
+        .get(at..at + 8)
+        .ok_or_else(|| invalid("truncated frame"))?;
+    return Ok(u64::from_le_bytes(bytes.try_into().unwrap_or_default()));
+}
+
+fn read_position(payload: &[u8]) -> io::Result<Version> {
+    return Ok(Version::from_u64(read_u64(payload, 0)?));
+}
+
+fn encode_blob((dir, name): &BlobFile) -> Vec<u8> {
+    let mut encoded = vec![*dir];
+    encoded.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    encoded.extend_from_slice(name.as_bytes());
+    return encoded;
+}
+
+/// Decodes a blob file at `at` and returns it with the offset after it.
+fn decode_blob(payload: &[u8], at: usize) -> io::Result<(BlobFile, usize)> {
+    let header = payload
+        .get(at..at + 3)
+        .ok_or_else(|| invalid("truncated blob name"))?;
+    let length = u16::from_le_bytes([header[1], header[2]]) as usize;
+    let name = payload
+        .get(at + 3..at + 3 + length)
+        .and_then(|name| std::str::from_utf8(name).ok())
+        .ok_or_else(|| invalid("malformed blob name"))?;
+    // Only plain file names, so a source cannot write outside the blob directories.
+    let plain = name.ends_with(".blob") && !name.contains(['/', '\\']) && !name.starts_with('.');
+    if header[0] as usize >= BLOB_DIRS.len() || !plain {
+        return Err(invalid(&format!("unexpected blob file {:?}", name)));
+    }
+    return Ok(((header[0], name.to_string()), at + 3 + length));
+}
+
+fn blob_path(directory: &Path, pattern: &str, (dir, name): &BlobFile) -> PathBuf {
+    return directory
+        .join(format!("{}.{}", pattern, BLOB_DIRS[*dir as usize]))
+        .join(name);
+}
+
+/// Returns the blob and content-store files of the store `pattern` in `directory`.
+fn blob_files(directory: &Path, pattern: &str) -> io::Result<Vec<BlobFile>> {
+    let mut files = Vec::new();
+    for (dir, name) in BLOB_DIRS.iter().enumerate() {
+        let entries = match fs::read_dir(directory.join(format!("{}.{}", pattern, name))) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".blob") {
+                files.push((dir as u8, name));
+            }
+        }
+    }
+    files.sort();
+    return Ok(files);
+}
+
+/// Replaces the file at `path` with `contents` through a temporary file.
+fn write_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    return fs::rename(&tmp, path);
+}
+
+/// Removes the file at `path`, if there is one.
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    return match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    };
+}
+
+fn random_id() -> u128 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+    let mut halves = [0u64; 2];
+    for half in &mut halves {
+        // Every `RandomState` is seeded differently.
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(nanos);
+        hasher.write_u32(std::process::id());
+        *half = hasher.finish();
+    }
+    return (halves[0] as u128) << 64 | halves[1] as u128;
+}
+
+/// Whether `error` means the other end went away.
+fn is_disconnect(error: &io::Error) -> bool {
+    return matches!(
+        error.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::UnexpectedEof
+    );
+}
+
+fn out_of_order(offset: u64, end: u64) -> io::Error {
+    return invalid(&format!(
+        "replicated records start at {} but the log ends at {}",
+        offset, end
+    ));
+}
+
+pub(crate) fn invalid(message: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+}
@@ -0,0 +1,45 @@
+//! Store-level retention: dropping records once they are older than a duration.
+//!
+//! With `Builder::retention`, every record is stamped with its write time, as with history,
+//! and compaction drops the records written longer ago than the retention, whatever their key,
+//! e.g. to keep only the last week of an event log. Older versions kept by `HistoryOptions`
+//! and tombstones are dropped once they are that old too. When every record of the active
+//! segment is that old, compaction drops the whole segment as `clear` does, without reading a
+//! record.
+//!
+//! Records are only dropped by compaction, so reads still see them until it runs. Records
+//! without a write time, e.g. written by `bulk_load` or before retention was enabled, are
+//! kept, and a segment holding one, or packed blocks, is never dropped whole.
+
+use crate::kvstore::Attributes;
+use crate::{RCask, Result};
+
+impl RCask {
+    /// Write time before which records are dropped, if the store has a retention.
+    fn retention_cutoff(&self) -> Option<u64> {
+        return self
+            .retention
+            .map(|retention| crate::now_millis().saturating_sub(retention.as_millis() as u64));
+    }
+
+    /// Whether compaction drops a record with `attributes` for its age.
+    pub(crate) fn outlived(&self, attributes: &Attributes) -> bool {
+        return match (self.retention_cutoff(), attributes.written_at) {
+            (Some(cutoff), Some(written_at)) => written_at < cutoff,
+            _ => false,
+        };
+    }
+
+    /// Clears the store if every record in it is older than the retention, and returns
+    /// whether it did.
+    pub(crate) fn drop_outlived_segment(&mut self) -> Result<bool> {
+        let outlived = match (self.retention_cutoff(), self.store.newest_write()) {
+            (Some(cutoff), Some(newest)) => newest < cutoff,
+            _ => false,
+        };
+        if outlived {
+            self.clear()?;
+        }
+        return Ok(outlived);
+    }
+}
@@ -0,0 +1,224 @@
+//! A pool of background threads shared by stores.
+//!
+//! Stores never spawn threads of their own. Work that should happen in the background, such
+//! as advancing an incremental compaction or sweeping expired keys, is submitted to a
+//! `BackgroundRuntime` instead, which runs it on a fixed number of threads however many stores
+//! share it. Dropping the runtime stops its threads once the jobs already running are done.
+//!
+//! ```no_run
+//! use rcask::{BackgroundRuntime, RCask};
+//! use std::sync::{Arc, Mutex};
+//! use std::time::Duration;
+//!
+//! # fn main() -> rcask::Result<()> {
+//! let runtime = BackgroundRuntime::new(2)?;
+//! let store = RCask::builder("./".to_string(), "log".to_string())
+//!     .incremental_compaction(64)
+//!     .open()?;
+//! let store = Arc::new(Mutex::new(store));
+//! runtime.maintain(&store, Duration::from_millis(100));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::warm::WARM_BATCH;
+use crate::{RCask, Result};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+type Job = Box<dyn FnOnce() + Send>;
+type Task = Box<dyn FnMut() -> bool + Send>;
+
+/// A task that runs every `interval` for as long as it returns true.
+struct Timer {
+    due: Instant,
+    /// Breaks ties between timers due at the same time, oldest first.
+    sequence: u64,
+    interval: Duration,
+    task: Task,
+}
+
+impl Ord for Timer {
+    /// Reversed, so that the heap yields the timer due first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        return (other.due, other.sequence).cmp(&(self.due, self.sequence));
+    }
+}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+
+impl Eq for Timer {}
+
+#[derive(Default)]
+struct Queue {
+    jobs: VecDeque<Job>,
+    timers: BinaryHeap<Timer>,
+    sequence: u64,
+    shutdown: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    wake: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        return self.queue.lock().unwrap_or_else(|e| e.into_inner());
+    }
+}
+
+/// A fixed-size pool of threads that runs background work for any number of stores; see the
+/// module documentation.
+pub struct BackgroundRuntime {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl BackgroundRuntime {
+    /// Starts a runtime with `threads` threads (at least one). Fails if the operating system
+    /// cannot start them.
+    pub fn new(threads: usize) -> Result<Self> {
+        let mut runtime = BackgroundRuntime {
+            shared: Arc::new(Shared::default()),
+            threads: Vec::new(),
+        };
+        for i in 0..threads.max(1) {
+            let shared = runtime.shared.clone();
+            // On failure, dropping the runtime stops the threads already started.
+            let thread = thread::Builder::new()
+                .name(format!("rcask-background-{}", i))
+                .spawn(move || work(&shared))?;
+            runtime.threads.push(thread);
+        }
+        return Ok(runtime);
+    }
+
+    /// Number of threads the runtime runs work on.
+    pub fn threads(&self) -> usize {
+        return self.threads.len();
+    }
+
+    /// Runs `job` once, as soon as a thread is free.
+    pub fn spawn<F: FnOnce() + Send + 'static>(&self, job: F) {
+        self.shared.lock().jobs.push_back(Box::new(job));
+        self.shared.wake.notify_one();
+    }
+
+    /// Runs `task` every `interval`, starting one interval from now, until it returns false.
+    pub fn every<F: FnMut() -> bool + Send + 'static>(&self, interval: Duration, task: F) {
+        let mut queue = self.shared.lock();
+        queue.sequence += 1;
+        let timer = Timer {
+            due: Instant::now() + interval,
+            sequence: queue.sequence,
+            interval,
+            task: Box::new(task),
+        };
+        queue.timers.push(timer);
+        drop(queue);
+        self.shared.wake.notify_one();
+    }
+
+    /// Every `interval`, advances the store's incremental compaction by one step, syncs it if
+    /// its `Builder::sync_interval` has passed and reports its expired keys to its
+    /// `Builder::on_expire` callback, until the store is dropped.
+    /// Errors are left for the next run to retry; they show up in `RCask::health`.
+    pub fn maintain(&self, store: &Arc<Mutex<RCask>>, interval: Duration) {
+        let store = Arc::downgrade(store);
+        self.every(interval, move || {
+            let Some(store) = store.upgrade() else {
+                return false;
+            };
+            let mut store = store.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = store.compaction_tick();
+            let _ = store.sync_if_due();
+            if store.expiry.is_some() {
+                let _ = store.sweep_expired();
+            }
+            return true;
+        });
+    }
+
+    /// Reads `keys` into the caches of the store, like `RCask::warm`, on a background thread,
+    /// locking the store for a batch of keys at a time so that other requests can run in
+    /// between. Stops early if the store is dropped or a read fails.
+    pub fn warm(&self, store: &Arc<Mutex<RCask>>, keys: Vec<String>) {
+        let store = Arc::downgrade(store);
+        self.spawn(move || {
+            for batch in keys.chunks(WARM_BATCH) {
+                let Some(store) = store.upgrade() else {
+                    return;
+                };
+                let mut store = store.lock().unwrap_or_else(|e| e.into_inner());
+                if store.warm(batch).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+impl Drop for BackgroundRuntime {
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.wake.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Runs jobs and due timers until the runtime shuts down.
+fn work(shared: &Shared) {
+    let mut queue = shared.lock();
+    loop {
+        if queue.shutdown {
+            return;
+        }
+        if let Some(job) = queue.jobs.pop_front() {
+            drop(queue);
+            job();
+            queue = shared.lock();
+            continue;
+        }
+        let now = Instant::now();
+        let next = queue.timers.peek().map(|timer| timer.due);
+        match next {
+            Some(due) if due <= now => {
+                let Some(mut timer) = queue.timers.pop() else {
+                    continue;
+                };
+                drop(queue);
+                let again = (timer.task)();
+                queue = shared.lock();
+                if again {
+                    timer.due = Instant::now() + timer.interval;
+                    queue.timers.push(timer);
+                }
+            }
+            Some(due) => {
+                queue = shared
+                    .wake
+                    .wait_timeout(queue, due - now)
+                    .unwrap_or_else(|e| e.into_inner())
+                    .0;
+            }
+            None => queue = shared.wake.wait(queue).unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+}
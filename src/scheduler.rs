@@ -0,0 +1,126 @@
+//! A compaction scheduler shared by several stores.
+//!
+//! Every store compacts on the thread that writes to it. A process that opens many stores,
+//! e.g. one per tenant, can hand all of them the same `CompactionScheduler` through
+//! `Builder::compaction_scheduler`, which admits only a few compactions at a time and can
+//! limit the rate at which they write. A store holds its permit for one full compaction, or
+//! for one step of an incremental compaction, so stores driven from the same thread never
+//! wait on each other's permits.
+
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+struct State {
+    running: usize,
+    /// When the rate limit lets the next compaction start.
+    next_start: Instant,
+}
+
+/// Admits compactions of the stores that share it, see the module documentation.
+///
+/// ```no_run
+/// use rcask::{CompactionScheduler, RCask};
+/// use std::sync::Arc;
+///
+/// # fn main() -> rcask::Result<()> {
+/// let scheduler = Arc::new(CompactionScheduler::new(1).rate_limit(64 << 20));
+/// let mut tenants = Vec::new();
+/// for tenant in ["a", "b", "c"] {
+///     let store = RCask::builder(format!("./{}", tenant), "log".to_string())
+///         .compaction_scheduler(scheduler.clone())
+///         .open()?;
+///     tenants.push(store);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct CompactionScheduler {
+    max_concurrent: usize,
+    /// Bytes per second compactions may write, if limited.
+    rate: Option<u64>,
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+impl CompactionScheduler {
+    /// Creates a scheduler that runs at most `max_concurrent` compactions at a time (at least
+    /// one).
+    pub fn new(max_concurrent: usize) -> Self {
+        return CompactionScheduler {
+            max_concurrent: max_concurrent.max(1),
+            rate: None,
+            state: Mutex::new(State {
+                running: 0,
+                next_start: Instant::now(),
+            }),
+            released: Condvar::new(),
+        };
+    }
+
+    /// Delays compactions so that together they write at most about `bytes_per_second`.
+    pub fn rate_limit(mut self, bytes_per_second: u64) -> Self {
+        self.rate = Some(bytes_per_second.max(1));
+        return self;
+    }
+
+    /// Number of compactions running right now.
+    pub fn running(&self) -> usize {
+        return self.lock().running;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        return self.state.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    /// Waits until a compaction may start.
+    pub(crate) fn acquire(&self) -> Permit<'_> {
+        let mut state = self.lock();
+        loop {
+            let now = Instant::now();
+            if state.running < self.max_concurrent && state.next_start <= now {
+                break;
+            }
+            state = match state.running < self.max_concurrent {
+                true => {
+                    let wait = state.next_start - now;
+                    self.released
+                        .wait_timeout(state, wait)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                false => self.released.wait(state).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+        state.running += 1;
+        return Permit {
+            scheduler: self,
+            written: 0,
+        };
+    }
+}
+
+/// Lets one compaction run until it is dropped.
+pub(crate) struct Permit<'a> {
+    scheduler: &'a CompactionScheduler,
+    written: u64,
+}
+
+impl Permit<'_> {
+    /// Counts bytes the compaction wrote against the rate limit.
+    pub(crate) fn wrote(&mut self, bytes: u64) {
+        self.written += bytes;
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.lock();
+        state.running -= 1;
+        if let Some(rate) = self.scheduler.rate {
+            let delay = Duration::from_secs_f64(self.written as f64 / rate as f64);
+            state.next_start = state.next_start.max(Instant::now()) + delay;
+        }
+        drop(state);
+        self.scheduler.released.notify_all();
+    }
+}
@@ -0,0 +1,90 @@
+//! Versioned value schemas.
+//!
+//! When a store is opened with a `SchemaRegistry`, every value is prefixed with a one byte
+//! schema version. Values written under an older version are upgraded step by step on read,
+//! and rewritten at the current version during compaction, so old data stays readable after
+//! the serialized shape of a value changes.
+//!
+//! ```
+//! use rcask::SchemaRegistry;
+//!
+//! // Version 1 stored a bare name, version 2 stores "name;age".
+//! let schema = SchemaRegistry::new(2).upgrade(1, |mut value| {
+//!     value.extend_from_slice(b";0");
+//!     Ok(value)
+//! });
+//! # let _ = schema;
+//! ```
+
+use crate::{BoxError, Error, Result};
+use std::collections::HashMap;
+
+/// Converts a value from one schema version to the next.
+pub type Upgrade = Box<dyn Fn(Vec<u8>) -> std::result::Result<Vec<u8>, BoxError> + Send + Sync>;
+
+/// The current schema version plus the functions that upgrade older versions to it.
+pub struct SchemaRegistry {
+    current: u8,
+    upgrades: HashMap<u8, Upgrade>,
+}
+
+impl SchemaRegistry {
+    /// Creates a registry whose new values are written at version `current`.
+    pub fn new(current: u8) -> Self {
+        return SchemaRegistry {
+            current,
+            upgrades: HashMap::new(),
+        };
+    }
+
+    /// Registers the function that converts a value from version `from` to `from + 1`.
+    pub fn upgrade<F>(mut self, from: u8, upgrade: F) -> Self
+    where
+        F: Fn(Vec<u8>) -> std::result::Result<Vec<u8>, BoxError> + Send + Sync + 'static,
+    {
+        self.upgrades.insert(from, Box::new(upgrade));
+        return self;
+    }
+
+    /// The version new values are tagged with.
+    pub fn current(&self) -> u8 {
+        return self.current;
+    }
+
+    /// Prefixes a value with the current schema version.
+    pub(crate) fn encode(&self, value: &[u8]) -> Vec<u8> {
+        let mut stored = Vec::with_capacity(value.len() + 1);
+        stored.push(self.current);
+        stored.extend_from_slice(value);
+        return stored;
+    }
+
+    /// Strips the version byte from a stored value, upgrading it to the current version.
+    pub(crate) fn decode(&self, stored: Vec<u8>) -> Result<Vec<u8>> {
+        let Some((&version, _)) = stored.split_first() else {
+            return Err(schema_error("Stored value is missing its schema version"));
+        };
+        if version > self.current {
+            return Err(schema_error(&format!(
+                "Value has schema version {}, newer than the current version {}",
+                version, self.current
+            )));
+        }
+
+        let mut value = stored[1..].to_vec();
+        for from in version..self.current {
+            let upgrade = self.upgrades.get(&from).ok_or_else(|| {
+                schema_error(&format!(
+                    "No upgrade registered from schema version {}",
+                    from
+                ))
+            })?;
+            value = upgrade(value).map_err(Error::Codec)?;
+        }
+        return Ok(value);
+    }
+}
+
+fn schema_error(message: &str) -> Error {
+    return Error::Codec(message.into());
+}
@@ -0,0 +1,55 @@
+//! Settings a store must always be opened with, recorded in a small file next to it.
+//!
+//! A record is a header line naming what it records followed by the setting's id, an empty
+//! line standing for the setting being off. A store without a record was written with the
+//! setting off.
+
+use crate::{Error, Result};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Checks the setting `id` against the one recorded at `path` under `header`, recording it if
+/// the store is `empty` and has no record yet. `describe` names a setting in the error.
+pub(crate) fn check(
+    path: &Path,
+    header: &str,
+    id: Option<String>,
+    empty: bool,
+    describe: impl Fn(&Option<String>) -> String,
+) -> Result<()> {
+    let recorded = match fs::read_to_string(path) {
+        Ok(text) => {
+            let mut lines = text.lines();
+            if lines.next() != Some(header) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} does not start with `{}`", path.display(), header),
+                )
+                .into());
+            }
+            Some(lines.next().unwrap_or_default().to_string()).filter(|id| !id.is_empty())
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    if recorded == id {
+        return Ok(());
+    }
+    if recorded.is_none() && empty {
+        let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(format!("{}\n{}\n", header, id.unwrap_or_default()).as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        return Ok(());
+    }
+    return Err(Error::Io(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "the store was written with {} but is opened with {}",
+            describe(&recorded),
+            describe(&id)
+        ),
+    )));
+}
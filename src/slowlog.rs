@@ -0,0 +1,86 @@
+//! Slow-operation log.
+//!
+//! Operations that take longer than a threshold are kept in a fixed-size ring buffer, newest
+//! last, so tail latency can be diagnosed after the fact. See `Builder::slow_log`.
+
+use crate::RCask;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The kind of a logged operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Get,
+    Set,
+    Compaction,
+}
+
+/// An operation that exceeded the slow-log threshold, as returned by `RCask::slow_log`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowOperation {
+    pub operation: Operation,
+    /// Length of the key, or 0 for compactions.
+    pub key_len: usize,
+    /// Bytes of the value read or written, or of the compacted log.
+    pub bytes: u64,
+    pub duration: Duration,
+    /// What the operation waited on, if known, e.g. "blocked on compaction".
+    pub cause: Option<&'static str>,
+}
+
+pub(crate) struct SlowLog {
+    threshold: Duration,
+    capacity: usize,
+    entries: VecDeque<SlowOperation>,
+}
+
+impl SlowLog {
+    pub(crate) fn new(threshold: Duration, capacity: usize) -> Self {
+        return SlowLog {
+            threshold,
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        };
+    }
+
+    /// Logs an operation that began at `started` if it took longer than the threshold.
+    pub(crate) fn record(
+        &mut self,
+        operation: Operation,
+        key_len: usize,
+        bytes: u64,
+        started: Instant,
+        cause: Option<&'static str>,
+    ) {
+        let duration = started.elapsed();
+        if duration < self.threshold || self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(SlowOperation {
+            operation,
+            key_len,
+            bytes,
+            duration,
+            cause,
+        });
+    }
+
+    pub(crate) fn entries(&self) -> Vec<SlowOperation> {
+        return self.entries.iter().cloned().collect();
+    }
+}
+
+impl RCask {
+    /// Returns the logged slow operations, oldest first. Requires `Builder::slow_log`; empty
+    /// otherwise.
+    pub fn slow_log(&self) -> Vec<SlowOperation> {
+        return self
+            .slow_log
+            .as_ref()
+            .map(SlowLog::entries)
+            .unwrap_or_default();
+    }
+}
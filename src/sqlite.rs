@@ -0,0 +1,87 @@
+//! Import and export through a two-column SQLite table.
+//!
+//! The table has the shape `(key TEXT PRIMARY KEY, value BLOB NOT NULL)`, which most
+//! tools can query directly.
+
+use crate::{RCask, Result};
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+use std::io;
+
+pub use rusqlite;
+
+/// Writes every live key/value pair into `table`, creating the table if needed.
+/// Existing rows with the same key are replaced. Returns the number of rows written.
+pub fn export(store: &mut RCask, conn: &mut Connection, table: &str) -> Result<u64> {
+    let table = quote_identifier(table);
+    let entries = store.get_all_key_values()?;
+
+    // A single transaction keeps large exports from paying a journal sync per row.
+    let tx = conn.transaction().map_err(to_io)?;
+    tx.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} (key TEXT PRIMARY KEY, value BLOB NOT NULL)",
+            table
+        ),
+        [],
+    )
+    .map_err(to_io)?;
+
+    {
+        let mut insert = tx
+            .prepare(&format!(
+                "INSERT OR REPLACE INTO {} (key, value) VALUES (?1, ?2)",
+                table
+            ))
+            .map_err(to_io)?;
+        for (key, value) in &entries {
+            insert.execute((key, value)).map_err(to_io)?;
+        }
+    }
+
+    tx.commit().map_err(to_io)?;
+    return Ok(entries.len() as u64);
+}
+
+/// Reads every row of `table` and writes it into the store.
+/// The key and value columns may hold text, blobs or numbers. Returns the number of rows imported.
+pub fn import(conn: &Connection, table: &str, store: &mut RCask) -> Result<u64> {
+    let mut select = conn
+        .prepare(&format!(
+            "SELECT key, value FROM {}",
+            quote_identifier(table)
+        ))
+        .map_err(to_io)?;
+    let mut rows = select.query([]).map_err(to_io)?;
+
+    let mut imported = 0;
+    while let Some(row) = rows.next().map_err(to_io)? {
+        let key = column_bytes(row.get_ref(0).map_err(to_io)?)?;
+        let value = column_bytes(row.get_ref(1).map_err(to_io)?)?;
+        store.set(key, value)?;
+        imported += 1;
+    }
+    return Ok(imported);
+}
+
+/// Converts a dynamically typed SQLite value into bytes.
+fn column_bytes(value: ValueRef) -> io::Result<Vec<u8>> {
+    return match value {
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => Ok(bytes.to_vec()),
+        ValueRef::Integer(int) => Ok(int.to_string().into_bytes()),
+        ValueRef::Real(real) => Ok(real.to_string().into_bytes()),
+        ValueRef::Null => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "NULL key or value in SQLite table",
+        )),
+    };
+}
+
+/// Quotes a table name so it can be safely interpolated into SQL.
+fn quote_identifier(name: &str) -> String {
+    return format!("\"{}\"", name.replace('"', "\"\""));
+}
+
+fn to_io(err: rusqlite::Error) -> io::Error {
+    return io::Error::other(err);
+}
@@ -0,0 +1,116 @@
+//! Counters describing how a store is doing, collected into `Stats`.
+//!
+//! Write amplification compares the bytes of keys and values handed to the store with the
+//! bytes written to its log, including compaction rewrites. Space amplification compares the
+//! bytes of the records the index still points to with the disk space the log takes up.
+
+use crate::{CacheStats, QuotaUsage, RCask, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// A snapshot of a store's counters, returned by `RCask::stats`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// The block cache's counters, if `Builder::block_cache` is set. Reads through reader
+    /// handles count too.
+    pub block_cache: Option<CacheStats>,
+    /// Bytes of keys and values written through this handle since it was opened. Writes to
+    /// lists, sets and hashes count their encoded operations.
+    pub user_bytes_written: u64,
+    /// Bytes this handle has written to log segments since it was opened, including record
+    /// framing and compaction.
+    pub physical_bytes_written: u64,
+    /// Bytes of the records that the index still points to.
+    pub live_bytes: u64,
+    /// Disk space taken up by the log segments, without punched holes.
+    pub disk_bytes: u64,
+    /// Usage of every quota of `Builder::quota`.
+    pub quotas: Vec<QuotaUsage>,
+    /// Estimated heap memory of the in-memory index, see `RCask::index_memory_bytes`.
+    pub index_memory_bytes: u64,
+}
+
+impl Stats {
+    /// Physical bytes written per user byte, or 0 before the first write.
+    pub fn write_amplification(&self) -> f64 {
+        return ratio(self.physical_bytes_written, self.user_bytes_written);
+    }
+
+    /// Disk space taken up per live byte, or 0 for an empty store.
+    pub fn space_amplification(&self) -> f64 {
+        return ratio(self.disk_bytes, self.live_bytes);
+    }
+}
+
+fn ratio(numerator: u64, denominator: u64) -> f64 {
+    if denominator == 0 {
+        return 0.0;
+    }
+    return numerator as f64 / denominator as f64;
+}
+
+/// Bytes written through a handle, see `Stats`.
+#[derive(Debug, Default)]
+pub(crate) struct Traffic {
+    pub(crate) user_bytes: u64,
+    /// Bytes written to segments that compaction has since replaced.
+    pub(crate) retired_bytes: u64,
+}
+
+impl RCask {
+    /// Returns the store's counters. Finding the live bytes reads the header of every live
+    /// record, so this takes time proportional to the number of keys.
+    pub fn stats(&mut self) -> Result<Stats> {
+        let mut physical = self.traffic.retired_bytes + self.store.written();
+        let mut disk = disk_usage(Path::new(&self.store.path))?;
+        if let Some(migration) = &self.migration {
+            physical += migration.store.written();
+            disk += disk_usage(Path::new(&migration.store.path))?;
+        }
+
+        // Keys packed into the same block share its offset.
+        let offsets: BTreeSet<u64> = self
+            .store
+            .keys()
+            .iter()
+            .filter_map(|key| self.store.offset(key))
+            .collect();
+        let mut live = 0;
+        for offset in offsets {
+            live += self.store.record_end(offset)? - offset;
+        }
+
+        return Ok(Stats {
+            block_cache: self.block_cache.as_ref().map(|cache| cache.stats()),
+            user_bytes_written: self.traffic.user_bytes,
+            physical_bytes_written: physical,
+            live_bytes: live,
+            disk_bytes: disk,
+            quotas: self.quota_usage(),
+            index_memory_bytes: self.index_memory_bytes(),
+        });
+    }
+
+    /// Estimates the heap memory the in-memory index of this handle takes up, e.g. to plan how
+    /// much RAM a store needs or to alert before it runs out: the key bytes of every entry and
+    /// the hash table holding them, including the index of a compaction in progress. Reader
+    /// handles each keep a copy of their own.
+    pub fn index_memory_bytes(&self) -> u64 {
+        let pending = self.migration.as_ref();
+        return self.store.index_memory_bytes()
+            + pending.map_or(0, |migration| migration.store.index_memory_bytes());
+    }
+}
+
+/// Disk space taken up by a file, which with holes punched can be less than its length.
+fn disk_usage(path: &Path) -> Result<u64> {
+    let metadata = fs::metadata(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        return Ok(metadata.len().min(metadata.blocks() * 512));
+    }
+    #[cfg(not(unix))]
+    return Ok(metadata.len());
+}
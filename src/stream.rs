@@ -0,0 +1,96 @@
+//! Scanning the store as a `futures::Stream`.
+//!
+//! A stream reads through its own `Reader`, so it can be polled on any task while the writer
+//! keeps writing. It takes a snapshot of the keys when it is created and reads their latest
+//! values in batches of `STREAM_BATCH`: a batch is read when the previous one has been
+//! consumed, so a slow consumer holds back the reads, and the stream yields to the executor
+//! between batches so that a long scan does not starve other tasks. Keys deleted since the
+//! snapshot, and lists, sets and hashes, are skipped.
+
+use crate::{Error, RCask, Reader, Result};
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Keys read per batch.
+pub const STREAM_BATCH: usize = 256;
+
+/// A stream of key/value pairs, created by `RCask::stream` or `RCask::stream_prefix`.
+pub struct EntryStream {
+    reader: Reader,
+    keys: std::vec::IntoIter<String>,
+    batch: VecDeque<Result<(String, Vec<u8>)>>,
+    /// Whether the next empty batch is read in this poll rather than after yielding.
+    ready: bool,
+}
+
+impl RCask {
+    /// Returns a stream of every key and its value, in key order.
+    pub fn stream(&self) -> Result<EntryStream> {
+        return self.stream_prefix("");
+    }
+
+    /// Returns a stream of every key that starts with `prefix` and its value, in key order.
+    pub fn stream_prefix(&self, prefix: &str) -> Result<EntryStream> {
+        let mut keys = self.store.keys_with_prefix(&self.stored_key(prefix));
+        if let Some(codec) = &self.key_codec {
+            // The reader encodes them again.
+            for key in &mut keys {
+                *key = codec.decode(key).into_owned();
+            }
+        }
+        keys.sort_unstable();
+        return Ok(EntryStream {
+            reader: self.reader()?,
+            keys: keys.into_iter(),
+            batch: VecDeque::new(),
+            ready: true,
+        });
+    }
+}
+
+impl EntryStream {
+    fn read_batch(&mut self) {
+        for key in self.keys.by_ref().take(STREAM_BATCH) {
+            match self.reader.get_bytes(&key) {
+                Ok(Some(value)) => self.batch.push_back(Ok((key, value))),
+                Ok(None) | Err(Error::WrongType { .. }) => {}
+                Err(e) => self.batch.push_back(Err(e)),
+            }
+        }
+    }
+}
+
+impl Stream for EntryStream {
+    type Item = Result<(String, Vec<u8>)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some(entry) = this.batch.pop_front() {
+            return Poll::Ready(Some(entry));
+        }
+        if this.keys.len() == 0 {
+            return Poll::Ready(None);
+        }
+        if !this.ready {
+            this.ready = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        this.ready = false;
+        this.read_batch();
+        return match this.batch.pop_front() {
+            Some(entry) => Poll::Ready(Some(entry)),
+            None if this.keys.len() == 0 => Poll::Ready(None),
+            None => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        };
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        return (self.batch.len(), Some(self.batch.len() + self.keys.len()));
+    }
+}
@@ -0,0 +1,185 @@
+//! Anti-entropy: bringing two stores to the same data by transferring only what differs.
+//!
+//! `sync_with` compares the range digests of both stores (see `digest`) and looks at the keys
+//! of the ranges that differ, so stores that mostly agree exchange little more than their
+//! digests. For every key whose latest record differs, the newer write wins and is copied to
+//! the other store, deletions included, keeping its write time, expiry and metadata. Write
+//! times are only recorded with `Builder::history`; a record with one is newer than a record
+//! without, and where neither tells, a value beats a deletion. Two different values neither
+//! write time orders are left as they are and reported in `SyncReport::unresolved`.
+//!
+//! A key deleted on one side whose tombstone compaction has already dropped looks like a key
+//! the other side never had, so the other side's value comes back. Lists, sets and hashes are
+//! not synced.
+
+use crate::digest::key_range;
+use crate::kvstore::Attributes;
+use crate::{RCask, ReadOptions, Result};
+use std::collections::BTreeSet;
+
+/// Number of key ranges `sync_with` compares, as a depth of `range_hashes`.
+const SYNC_DEPTH: u32 = 8;
+
+/// What `RCask::sync_with` did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Key ranges whose digests differed.
+    pub differing_ranges: u64,
+    /// Keys of those ranges that were compared.
+    pub keys_compared: u64,
+    /// Records copied from this store to the other one.
+    pub sent: u64,
+    /// Records copied from the other store to this one.
+    pub received: u64,
+    /// Keys both stores hold different values of, that no write time tells which is newer,
+    /// e.g. because neither store keeps history. They are left as they are.
+    pub unresolved: Vec<String>,
+}
+
+/// The latest record of a key on one side of a sync.
+struct SyncRecord {
+    /// The value, or `None` if the key is deleted, expired or missing.
+    value: Option<Vec<u8>>,
+    attributes: Attributes,
+}
+
+impl SyncRecord {
+    /// Whether this record wins over `other`, or `None` if both hold a value and their write
+    /// times do not tell which is newer.
+    fn beats(&self, other: &SyncRecord) -> Option<bool> {
+        let (ours, theirs) = (self.attributes.written_at, other.attributes.written_at);
+        if ours != theirs {
+            return Some(ours > theirs);
+        }
+        return match (&self.value, &other.value) {
+            (Some(_), Some(_)) => None,
+            (ours, _) => Some(ours.is_some()),
+        };
+    }
+}
+
+impl RCask {
+    /// Makes this store and `other` hold the same data, e.g. to repair a replica or catch up
+    /// after a migration, and reports what was transferred. See the module docs for how
+    /// conflicting writes are resolved. Keys whose values differ but whose write times are
+    /// missing or equal are not changed on either side and are listed in
+    /// `SyncReport::unresolved`, so stores without `Builder::history` only agree after the
+    /// caller settles them. `other` can be any open store, including one of another
+    /// directory.
+    pub fn sync_with(&mut self, other: &mut RCask) -> Result<SyncReport> {
+        let mut report = SyncReport::default();
+        let ours = self.range_hashes(SYNC_DEPTH)?;
+        let theirs = other.range_hashes(SYNC_DEPTH)?;
+        let differing: Vec<bool> = ours.iter().zip(&theirs).map(|(a, b)| a != b).collect();
+        report.differing_ranges = differing.iter().filter(|&&differs| differs).count() as u64;
+        if report.differing_ranges == 0 {
+            return Ok(report);
+        }
+
+        let keys: BTreeSet<String> = self
+            .store
+            .keys()
+            .into_iter()
+            .chain(other.store.keys())
+            .filter(|key| differing[key_range(key, SYNC_DEPTH)])
+            .collect();
+        for key in keys {
+            let (Some(mine), Some(yours)) = (self.sync_record(&key)?, other.sync_record(&key)?)
+            else {
+                continue;
+            };
+            report.keys_compared += 1;
+            if mine.value == yours.value {
+                continue;
+            }
+            match yours.beats(&mine) {
+                Some(true) => {
+                    self.apply_sync_record(&key, yours)?;
+                    report.received += 1;
+                }
+                Some(false) => {
+                    other.apply_sync_record(&key, mine)?;
+                    report.sent += 1;
+                }
+                None => report.unresolved.push(key),
+            }
+        }
+        return Ok(report);
+    }
+
+    /// Reads the latest record of `key` for a sync, or `None` if it holds a collection.
+    fn sync_record(&mut self, key: &str) -> Result<Option<SyncRecord>> {
+        let verify = self.verify_checksums;
+        let attributes = match self.store.offset(key) {
+            Some(offset) => match self.store.get_value_bytes_at(key, offset, verify)? {
+                Some((_, attributes)) => attributes,
+                None => Attributes::default(),
+            },
+            None => Attributes::default(),
+        };
+        if attributes.collection {
+            return Ok(None);
+        }
+        let options = ReadOptions {
+            fill_cache: false,
+            ..Default::default()
+        };
+        let value = match attributes.is_tombstone() {
+            true => None,
+            false => self.get_opt_into(key, &options, Vec::new())?,
+        };
+        return Ok(Some(SyncRecord { value, attributes }));
+    }
+
+    /// Writes the winning record of a sync, keeping its write time.
+    fn apply_sync_record(&mut self, key: &str, record: SyncRecord) -> Result<()> {
+        let SyncRecord { value, attributes } = record;
+        if let Some(size) = value
+            .as_ref()
+            .and_then(|value| self.values.reference_size(value))
+        {
+            self.admit_quota(key, (key.len() + size) as u64, false)?;
+        }
+        let stored = match &value {
+            Some(value) => Some(self.encode_update(key.as_bytes(), value)?.into_owned()),
+            None => None,
+        };
+        if let Some(stored) = &stored {
+            self.admit_quota(key, (key.len() + stored.len()) as u64, false)?;
+        }
+        self.admit_write()?;
+        self.invalidate(key);
+        self.record_write(key);
+        match stored {
+            Some(stored) => {
+                let attributes = Attributes {
+                    expires_at: attributes.expires_at,
+                    meta: attributes.meta,
+                    written_at: attributes.written_at,
+                    ..Default::default()
+                };
+                let size = (key.len() + stored.len()) as u64;
+                let written = self.store.set(key, &stored, &attributes);
+                self.health.wrote(&written);
+                written?;
+                if let (Some(listener), Some(expires_at)) =
+                    (&mut self.expiry, attributes.expires_at)
+                {
+                    listener.schedule(key, expires_at);
+                }
+                self.lru_wrote([(key, size)], false)?;
+            }
+            None => {
+                let deletion = Attributes {
+                    written_at: attributes.written_at,
+                    ..Attributes::tombstone()
+                };
+                let written = self.store.set(key, [], &deletion);
+                self.health.wrote(&written);
+                written?;
+                self.lru_removed(key);
+            }
+        }
+        return self.finish_write(false);
+    }
+}
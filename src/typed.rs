@@ -0,0 +1,119 @@
+//! A typed facade over `RCask` that converts keys and values through codecs.
+//!
+//! ```no_run
+//! use rcask::typed::{Text, TypedRCask};
+//! use rcask::RCask;
+//!
+//! # fn main() -> rcask::Result<()> {
+//! let store = RCask::new("./".to_string(), "counters".to_string())?;
+//! let mut counters: TypedRCask<u64, i64, Text, Text> = TypedRCask::new(store);
+//! counters.set(&7, &-3)?;
+//! assert_eq!(counters.get(&7)?, Some(-3));
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{Error, RCask, Result, Version};
+use std::fmt::Display;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+/// Converts values of type `T` to and from the bytes stored in the log.
+pub trait Codec<T> {
+    fn encode(value: &T) -> Result<Vec<u8>>;
+    fn decode(bytes: &[u8]) -> Result<T>;
+}
+
+/// Stores values through their `Display` and `FromStr` implementations.
+/// Suitable for strings, numbers and any other type with a textual form.
+pub struct Text;
+
+impl<T> Codec<T> for Text
+where
+    T: Display + FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        return Ok(value.to_string().into_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T> {
+        let text = std::str::from_utf8(bytes).map_err(|err| Error::Codec(err.into()))?;
+        return text.parse().map_err(|err: T::Err| Error::Codec(err.into()));
+    }
+}
+
+/// Stores byte vectors unchanged.
+pub struct Raw;
+
+impl Codec<Vec<u8>> for Raw {
+    fn encode(value: &Vec<u8>) -> Result<Vec<u8>> {
+        return Ok(value.clone());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Vec<u8>> {
+        return Ok(bytes.to_vec());
+    }
+}
+
+/// Stores values as JSON using serde.
+#[cfg(feature = "json")]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for Json {
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        return serde_json::to_vec(value).map_err(|err| Error::Codec(err.into()));
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T> {
+        return serde_json::from_slice(bytes).map_err(|err| Error::Codec(err.into()));
+    }
+}
+
+/// An `RCask` store whose keys are `K` and values are `V`, encoded with the codecs `KC` and `VC`.
+/// Keys must encode to valid UTF-8, since the index is keyed by strings.
+pub struct TypedRCask<K, V, KC, VC> {
+    store: RCask,
+    _types: PhantomData<fn() -> (K, V)>,
+    _codecs: PhantomData<fn() -> (KC, VC)>,
+}
+
+impl<K, V, KC, VC> TypedRCask<K, V, KC, VC>
+where
+    KC: Codec<K>,
+    VC: Codec<V>,
+{
+    /// Wraps an existing store.
+    pub fn new(store: RCask) -> Self {
+        return TypedRCask {
+            store,
+            _types: PhantomData,
+            _codecs: PhantomData,
+        };
+    }
+
+    /// Encodes and stores a key-value pair, returning the version of the new record.
+    pub fn set(&mut self, key: &K, value: &V) -> Result<Version> {
+        let key = Self::encode_key(key)?;
+        return self.store.set(key, VC::encode(value)?);
+    }
+
+    /// Retrieves and decodes the value associated with a key.
+    pub fn get(&mut self, key: &K) -> Result<Option<V>> {
+        let key = Self::encode_key(key)?;
+        return match self.store.get_bytes(&key)? {
+            Some(bytes) => Ok(Some(VC::decode(&bytes)?)),
+            None => Ok(None),
+        };
+    }
+
+    /// Returns the underlying untyped store.
+    pub fn into_inner(self) -> RCask {
+        return self.store;
+    }
+
+    fn encode_key(key: &K) -> Result<String> {
+        return String::from_utf8(KC::encode(key)?).map_err(|err| Error::Codec(err.into()));
+    }
+}
@@ -0,0 +1,325 @@
+//! Transformations applied to values between the public API and the log.
+//!
+//! On the way in a value is tagged with its schema version ("plain" bytes), framed as a full
+//! value, a delta or a reference to a value stored outside the log, and finally compressed
+//! ("sealed");
+//! on the way out the steps run in reverse. Resolving delta frames needs access to the log, so
+//! that step is driven by `RCask`.
+//!
+//! Which of the steps are on is part of the stored values, so it is recorded in
+//! `<directory>/<pattern>.values` when a store is first opened with any of them, and opening
+//! fails if they differ from the recorded ones, including a store written without any.
+
+use crate::blob::BlobStore;
+#[cfg(feature = "zstd")]
+use crate::compression::{self, Dictionary, DictionaryOptions};
+use crate::dedup::ContentStore;
+use crate::delta::DeltaOptions;
+use crate::frame;
+use crate::schema::SchemaRegistry;
+use crate::{setting, Result};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// First line of a value format record.
+const FORMAT_HEADER: &str = "rcask-value-format 1";
+
+pub(crate) struct ValueEncoding {
+    pub(crate) schema: Option<Arc<SchemaRegistry>>,
+    pub(crate) delta: Option<DeltaOptions>,
+    pub(crate) dedup: Option<ContentStore>,
+    pub(crate) blobs: Option<BlobStore>,
+    /// Deduplicated values referenced by records written since the last `prepare_segment`.
+    pub(crate) shared: HashSet<u64>,
+    /// Blobs referenced by records written since the last `prepare_segment`.
+    pub(crate) live_blobs: HashSet<u64>,
+    #[cfg(feature = "zstd")]
+    pub(crate) compression: Option<DictionaryOptions>,
+    /// Dictionary of the active segment, once compaction has trained one.
+    #[cfg(feature = "zstd")]
+    pub(crate) dictionary: Option<Dictionary>,
+}
+
+impl ValueEncoding {
+    /// Returns an encoding with the same configuration for a reader handle.
+    /// Per-segment state is not copied; the reader loads it with `open_segment`.
+    pub(crate) fn for_reader(&self) -> ValueEncoding {
+        return ValueEncoding {
+            schema: self.schema.clone(),
+            delta: self.delta.clone(),
+            dedup: self.dedup.clone(),
+            blobs: self.blobs.clone(),
+            shared: HashSet::new(),
+            live_blobs: HashSet::new(),
+            #[cfg(feature = "zstd")]
+            compression: self.compression.clone(),
+            #[cfg(feature = "zstd")]
+            dictionary: None,
+        };
+    }
+
+    /// Names the steps that change how values are stored, e.g. `schema,blobs`, or `None` if
+    /// values are stored exactly as they were set.
+    fn format(&self) -> Option<String> {
+        let steps = [
+            ("schema", self.schema.is_some()),
+            ("delta", self.delta.is_some()),
+            ("dedup", self.dedup.is_some()),
+            ("blobs", self.blobs.is_some()),
+            #[cfg(feature = "zstd")]
+            ("zstd", self.compression.is_some()),
+        ];
+        let names: Vec<&str> = steps
+            .iter()
+            .filter(|(_, on)| *on)
+            .map(|(name, _)| *name)
+            .collect();
+        return (!names.is_empty()).then(|| names.join(","));
+    }
+
+    /// Checks the format against the one recorded for the store `pattern` in `directory`,
+    /// recording it if the store is `empty` and has no record yet.
+    pub(crate) fn check_format(&self, directory: &str, pattern: &str, empty: bool) -> Result<()> {
+        let path = format_path(Path::new(directory), pattern);
+        return setting::check(&path, FORMAT_HEADER, self.format(), empty, |format| {
+            return match format {
+                Some(format) => format!("value options {}", format),
+                None => "no value options".to_string(),
+            };
+        });
+    }
+
+    /// Whether values are stored exactly as they were set.
+    pub(crate) fn is_identity(&self) -> bool {
+        #[cfg(feature = "zstd")]
+        if self.compression.is_some() {
+            return false;
+        }
+        return self.schema.is_none() && !self.is_framed();
+    }
+
+    /// Whether plain values are wrapped in frames.
+    pub(crate) fn is_framed(&self) -> bool {
+        return self.delta.is_some() || self.dedup.is_some() || self.blobs.is_some();
+    }
+
+    /// Whether a plain value of `size` bytes is large enough to be deduplicated.
+    fn is_shared(&self, size: usize) -> bool {
+        return match &self.dedup {
+            Some(store) => size >= store.options.min_value_size,
+            None => false,
+        };
+    }
+
+    /// Whether a plain value of `size` bytes is large enough to get its own blob file.
+    fn is_blob(&self, size: usize) -> bool {
+        return match &self.blobs {
+            Some(store) => size >= store.options.min_value_size,
+            None => false,
+        };
+    }
+
+    /// Whether a value is stored outside the log.
+    pub(crate) fn is_external(&self, value: &[u8]) -> bool {
+        return self.is_shared(value.len()) || self.is_blob(value.len());
+    }
+
+    /// The size of the record `value` leaves in the log if `encode` stores it outside the log,
+    /// so that quotas can be checked before it is written out.
+    pub(crate) fn reference_size(&self, value: &[u8]) -> Option<usize> {
+        let plain = value.len() + usize::from(self.schema.is_some());
+        if !self.is_shared(plain) && !self.is_blob(plain) {
+            return None;
+        }
+        let framed = frame::blob(0).len();
+        // References are too short to compress and are sealed with just a tag.
+        #[cfg(feature = "zstd")]
+        if self.compression.is_some() {
+            return Some(framed + 1);
+        }
+        return Some(framed);
+    }
+
+    /// Converts a value into the bytes stored in the log, never as a delta.
+    pub(crate) fn encode<'a>(&mut self, value: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        let plain = self.tag_schema(value);
+        let framed = match self.is_framed() {
+            true => Cow::Owned(self.frame(&plain)?),
+            false => plain,
+        };
+        return self.seal(framed);
+    }
+
+    /// Frames a plain value, moving it to the content store or a blob file when it is large
+    /// enough.
+    pub(crate) fn frame(&mut self, plain: &[u8]) -> Result<Vec<u8>> {
+        if let Some(store) = self.dedup.as_ref().filter(|_| self.is_shared(plain.len())) {
+            if let Some(hash) = store.put(plain)? {
+                self.shared.insert(hash);
+                return Ok(frame::shared(hash));
+            }
+        }
+        if self.is_blob(plain.len()) {
+            if let Some(store) = &mut self.blobs {
+                let id = store.put(plain)?;
+                self.live_blobs.insert(id);
+                return Ok(frame::blob(id));
+            }
+        }
+        return Ok(frame::full(plain));
+    }
+
+    /// Whether a framed value refers to a value stored outside the log.
+    pub(crate) fn is_reference(&self, framed: &[u8]) -> bool {
+        return matches!(
+            frame::parse(framed),
+            Ok(frame::Frame::Shared { .. } | frame::Frame::Blob { .. })
+        );
+    }
+
+    /// Records that a reference carried over by compaction is still live.
+    pub(crate) fn retain_reference(&mut self, framed: &[u8]) -> Result<()> {
+        match frame::parse(framed)? {
+            frame::Frame::Shared { hash } => self.shared.insert(hash),
+            frame::Frame::Blob { id } => self.live_blobs.insert(id),
+            _ => false,
+        };
+        return Ok(());
+    }
+
+    /// Reads a deduplicated value.
+    pub(crate) fn shared_value(&self, hash: u64) -> Result<Vec<u8>> {
+        return match &self.dedup {
+            Some(store) => Ok(store.get(hash)?),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Value is deduplicated but deduplication is disabled",
+            )
+            .into()),
+        };
+    }
+
+    /// Reads a value stored in a blob file.
+    pub(crate) fn blob_value(&self, id: u64) -> Result<Vec<u8>> {
+        return match &self.blobs {
+            Some(store) => Ok(store.get(id)?),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Value is in a blob file but blob files are disabled",
+            )
+            .into()),
+        };
+    }
+
+    /// Tags a value with its schema version.
+    pub(crate) fn tag_schema<'a>(&self, value: &'a [u8]) -> Cow<'a, [u8]> {
+        return match &self.schema {
+            Some(schema) => Cow::Owned(schema.encode(value)),
+            None => Cow::Borrowed(value),
+        };
+    }
+
+    /// Strips the schema version from a plain value, upgrading it to the current version.
+    pub(crate) fn untag_schema(&self, plain: Vec<u8>) -> Result<Vec<u8>> {
+        return match &self.schema {
+            Some(schema) => schema.decode(plain),
+            None => Ok(plain),
+        };
+    }
+
+    /// Applies the final storage transformation (compression) to a framed value.
+    #[cfg_attr(not(feature = "zstd"), allow(clippy::unnecessary_wraps))]
+    pub(crate) fn seal<'a>(&mut self, framed: Cow<'a, [u8]>) -> Result<Cow<'a, [u8]>> {
+        #[cfg(feature = "zstd")]
+        if self.compression.is_some() {
+            return Ok(Cow::Owned(compression::encode(
+                self.dictionary.as_mut(),
+                &framed,
+            )?));
+        }
+        return Ok(framed);
+    }
+
+    /// Reverses `seal`.
+    #[cfg_attr(not(feature = "zstd"), allow(clippy::unnecessary_wraps))]
+    pub(crate) fn unseal(&mut self, stored: Vec<u8>) -> Result<Vec<u8>> {
+        #[cfg(feature = "zstd")]
+        if self.compression.is_some() {
+            return Ok(compression::decode(self.dictionary.as_mut(), &stored)?);
+        }
+        return Ok(stored);
+    }
+
+    /// Loads the per-segment state (such as the compression dictionary) of the active segment.
+    #[cfg_attr(not(feature = "zstd"), allow(unused_variables))]
+    pub(crate) fn open_segment(&mut self, segment: &Path) -> Result<()> {
+        #[cfg(feature = "zstd")]
+        if let Some(options) = &self.compression {
+            self.dictionary = Dictionary::load(segment, options)?;
+        }
+        return Ok(());
+    }
+
+    /// Prepares to rewrite the given live values into a new segment during compaction.
+    /// Must be called after every value of the old segment has been decoded.
+    #[cfg_attr(not(feature = "zstd"), allow(unused_variables))]
+    pub(crate) fn prepare_segment<'a, I>(&mut self, segment: &Path, values: I) -> Result<()>
+    where
+        I: Iterator<Item = &'a [u8]>,
+    {
+        self.shared.clear();
+        self.live_blobs.clear();
+        #[cfg(feature = "zstd")]
+        if let Some(options) = &self.compression {
+            let samples: Vec<&[u8]> = values.take(options.max_samples).collect();
+            self.dictionary = Dictionary::train(&samples, options)?;
+            if let Some(dictionary) = &self.dictionary {
+                dictionary.save(segment)?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Prepares to relocate values into a new segment a few at a time, keeping the dictionary
+    /// of the active segment. See `prepare_segment`.
+    pub(crate) fn begin_relocation(&mut self) {
+        self.shared.clear();
+        self.live_blobs.clear();
+    }
+
+    /// Saves the dictionary of the active segment for the segment that replaces it.
+    #[cfg_attr(
+        not(feature = "zstd"),
+        allow(unused_variables, clippy::unnecessary_wraps)
+    )]
+    pub(crate) fn keep_dictionary(&self, segment: &Path) -> Result<()> {
+        #[cfg(feature = "zstd")]
+        if let Some(dictionary) = &self.dictionary {
+            dictionary.save(segment)?;
+        }
+        return Ok(());
+    }
+
+    /// Removes the per-segment state of a segment that was deleted.
+    #[cfg_attr(not(feature = "zstd"), allow(unused_variables))]
+    pub(crate) fn remove_segment(&mut self, segment: &Path) -> Result<()> {
+        #[cfg(feature = "zstd")]
+        compression::remove(segment)?;
+        // Every live value has been rewritten since `prepare_segment`, so any deduplicated
+        // value or blob that was not referenced again belonged only to the removed segment.
+        if let Some(store) = &self.dedup {
+            store.retain(&self.shared)?;
+        }
+        if let Some(store) = &self.blobs {
+            store.retain(&self.live_blobs)?;
+        }
+        return Ok(());
+    }
+}
+
+/// Path of the value format record of the store `pattern` in `directory`.
+pub(crate) fn format_path(directory: &Path, pattern: &str) -> PathBuf {
+    return directory.join(format!("{}.values", pattern));
+}
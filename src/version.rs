@@ -0,0 +1,45 @@
+//! Version tokens for optimistic concurrency.
+
+use std::fmt;
+
+/// Bits of a version that hold the record's offset within its segment.
+const OFFSET_BITS: u32 = 40;
+
+/// Identifies one record of a key, as returned by `RCask::set` and `RCask::version`.
+///
+/// Versions are the record's position in the log: every write gets a version greater than any
+/// earlier one, so a version never comes back once the key has changed. Compaction moves
+/// records and therefore gives every key a new version, which makes a conditional write right
+/// after a compaction report a conflict even though the key did not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version(u64);
+
+impl Version {
+    pub(crate) fn new(segment: u64, offset: u64) -> Self {
+        return Version(segment << OFFSET_BITS | offset);
+    }
+
+    pub(crate) fn segment(self) -> u64 {
+        return self.0 >> OFFSET_BITS;
+    }
+
+    pub(crate) fn offset(self) -> u64 {
+        return self.0 & ((1 << OFFSET_BITS) - 1);
+    }
+
+    /// Returns the version as a plain number, e.g. to hand it to a client.
+    pub fn as_u64(self) -> u64 {
+        return self.0;
+    }
+
+    /// Reverses `as_u64`.
+    pub fn from_u64(version: u64) -> Self {
+        return Version(version);
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.0);
+    }
+}
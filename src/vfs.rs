@@ -0,0 +1,305 @@
+//! File system layer under the log files.
+//!
+//! Every log segment is opened through a `FileSystem`, by default `OsFileSystem`. Tests can
+//! substitute `FaultyFileSystem` through `Builder::file_system` to inject short writes,
+//! interrupted calls, full disks and failing syncs on demand, and exercise the paths that
+//! retry or recover from them deterministically. Content-store, blob and dictionary files are
+//! not affected.
+//!
+//! Segments are also renamed and deleted through the `FileSystem`. On Windows a file that
+//! another handle opened without sharing deletion cannot be renamed or deleted, so log files
+//! are opened shared for reading, writing and deletion, and a superseded segment that cannot
+//! be deleted yet is kept and deleted after a later compaction, or the next time the store is
+//! opened, instead of failing the one that replaced it. `Fault::FileInUse` exercises that path on any platform.
+//!
+//! ```no_run
+//! use rcask::vfs::{Fault, FaultyFileSystem};
+//! use rcask::RCask;
+//! use std::sync::Arc;
+//!
+//! # fn main() -> rcask::Result<()> {
+//! let fs = Arc::new(FaultyFileSystem::new());
+//! let mut store = RCask::builder("./".to_string(), "log".to_string())
+//!     .file_system(fs.clone())
+//!     .open()?;
+//! fs.inject(Fault::NoSpace, 1);
+//! assert!(store.set("key", "value").is_err());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// An open log file.
+pub trait LogFile: Read + Write + Seek + Send {
+    /// Flushes written data to the disk, like `File::sync_data`.
+    fn sync_data(&mut self) -> io::Result<()>;
+
+    /// Frees the disk space of `len` bytes at `offset` without changing the file's length;
+    /// the range reads back as zeros. Fails with `ErrorKind::Unsupported` where the file
+    /// system cannot do this.
+    fn punch_hole(&mut self, _offset: u64, _len: u64) -> io::Result<()> {
+        return Err(io::Error::from(io::ErrorKind::Unsupported));
+    }
+
+    /// Cuts the file down to `len` bytes, like `File::set_len`, to drop what a failed write
+    /// left behind. Fails with `ErrorKind::Unsupported` where the file cannot be shortened.
+    fn truncate(&mut self, _len: u64) -> io::Result<()> {
+        return Err(io::Error::from(io::ErrorKind::Unsupported));
+    }
+
+    /// Hints that the file is about to be read sequentially, so that the system reads ahead
+    /// more eagerly. Does nothing where there is no such hint.
+    fn advise_sequential(&mut self) {}
+}
+
+impl LogFile for File {
+    fn sync_data(&mut self) -> io::Result<()> {
+        return File::sync_data(self);
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        return self.set_len(len);
+    }
+
+    #[cfg(target_os = "linux")]
+    fn advise_sequential(&mut self) {
+        use std::os::fd::AsRawFd;
+
+        // SAFETY: the descriptor belongs to `self` and stays open for the call. The hint is
+        // only advice, so failing to give it changes nothing.
+        unsafe { libc::posix_fadvise(self.as_raw_fd(), 0, 0, libc::POSIX_FADV_SEQUENTIAL) };
+    }
+
+    #[cfg(target_os = "linux")]
+    fn punch_hole(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+        // SAFETY: the descriptor belongs to `self` and stays open for the call.
+        let result = unsafe { libc::fallocate(self.as_raw_fd(), mode, offset as i64, len as i64) };
+        if result != 0 {
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() == Some(libc::EOPNOTSUPP) {
+                return Err(io::Error::from(io::ErrorKind::Unsupported));
+            }
+            return Err(error);
+        }
+        return Ok(());
+    }
+}
+
+/// Opens, renames and deletes log files.
+pub trait FileSystem: Send + Sync {
+    /// Opens `path` for reading and appending, creating it if it does not exist.
+    fn open_writable(&self, path: &Path) -> io::Result<Box<dyn LogFile>>;
+
+    /// Opens an existing `path` for reading only.
+    fn open_read_only(&self, path: &Path) -> io::Result<Box<dyn LogFile>>;
+
+    /// Renames `from` to `to`, replacing `to` if it exists, like `fs::rename`.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        return fs::rename(from, to);
+    }
+
+    /// Deletes `path`, like `fs::remove_file`.
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        return fs::remove_file(path);
+    }
+}
+
+/// Options for opening a log file. On Windows the file is shared for reading, writing and
+/// deletion, so that compaction can rename and delete segments that readers have open.
+fn log_file_options(writable: bool) -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.read(true);
+    if writable {
+        options.write(true).create(true).truncate(false);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        // FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE
+        options.share_mode(0x1 | 0x2 | 0x4);
+    }
+    return options;
+}
+
+/// The operating system's file system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsFileSystem;
+
+impl FileSystem for OsFileSystem {
+    fn open_writable(&self, path: &Path) -> io::Result<Box<dyn LogFile>> {
+        return Ok(Box::new(log_file_options(true).open(path)?));
+    }
+
+    fn open_read_only(&self, path: &Path) -> io::Result<Box<dyn LogFile>> {
+        return Ok(Box::new(log_file_options(false).open(path)?));
+    }
+}
+
+/// A fault `FaultyFileSystem` can inject.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// A write writes only part of its buffer.
+    ShortWrite,
+    /// A write fails with `ErrorKind::Interrupted` (EINTR) without writing anything.
+    Interrupted,
+    /// A write fails with `ErrorKind::StorageFull` (ENOSPC) without writing anything.
+    NoSpace,
+    /// A sync fails with an I/O error.
+    SyncFailure,
+    /// A rename fails with an I/O error, leaving both paths as they were.
+    RenameFailure,
+    /// A deletion fails with `ErrorKind::PermissionDenied`, as on Windows while another
+    /// process has the file open.
+    FileInUse,
+}
+
+#[derive(Default)]
+struct Pending {
+    faults: Vec<(Fault, usize)>,
+}
+
+impl Pending {
+    /// Consumes one pending fault of the given kinds, if any.
+    fn take(&mut self, kinds: &[Fault]) -> Option<Fault> {
+        let (fault, remaining) = self
+            .faults
+            .iter_mut()
+            .find(|(fault, remaining)| kinds.contains(fault) && *remaining > 0)?;
+        *remaining -= 1;
+        return Some(*fault);
+    }
+}
+
+/// An `OsFileSystem` that injects faults into the writes and syncs of its files on demand.
+/// Clones share the same pending faults.
+#[derive(Clone, Default)]
+pub struct FaultyFileSystem {
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl FaultyFileSystem {
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Makes the next `count` writes (or syncs, for `Fault::SyncFailure`, renames, for
+    /// `Fault::RenameFailure`, or deletions, for `Fault::FileInUse`) of any file opened
+    /// through this file system fail with `fault`. Faults of different kinds queue up in the
+    /// order they were injected.
+    pub fn inject(&self, fault: Fault, count: usize) {
+        self.lock().faults.push((fault, count));
+    }
+
+    /// Drops every pending fault.
+    pub fn clear(&self) {
+        self.lock().faults.clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Pending> {
+        return self.pending.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    fn wrap(&self, file: File) -> Box<dyn LogFile> {
+        return Box::new(FaultyFile {
+            file,
+            faults: self.clone(),
+        });
+    }
+}
+
+impl FileSystem for FaultyFileSystem {
+    fn open_writable(&self, path: &Path) -> io::Result<Box<dyn LogFile>> {
+        return Ok(self.wrap(log_file_options(true).open(path)?));
+    }
+
+    fn open_read_only(&self, path: &Path) -> io::Result<Box<dyn LogFile>> {
+        return Ok(self.wrap(log_file_options(false).open(path)?));
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if self.lock().take(&[Fault::RenameFailure]).is_some() {
+            return Err(io::Error::other(format!(
+                "injected rename failure of {}",
+                from.display()
+            )));
+        }
+        return fs::rename(from, to);
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        if self.lock().take(&[Fault::FileInUse]).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("injected sharing violation on {}", path.display()),
+            ));
+        }
+        return fs::remove_file(path);
+    }
+}
+
+struct FaultyFile {
+    file: File,
+    faults: FaultyFileSystem,
+}
+
+impl Read for FaultyFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        return self.file.read(buf);
+    }
+}
+
+impl Seek for FaultyFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        return self.file.seek(pos);
+    }
+}
+
+impl Write for FaultyFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let kinds = [Fault::ShortWrite, Fault::Interrupted, Fault::NoSpace];
+        return match self.faults.lock().take(&kinds) {
+            Some(Fault::ShortWrite) if buf.len() > 1 => self.file.write(&buf[..buf.len() / 2]),
+            Some(Fault::Interrupted) => Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "injected interrupted write",
+            )),
+            Some(Fault::NoSpace) => Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                "injected no space left on device",
+            )),
+            _ => self.file.write(buf),
+        };
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.file.flush();
+    }
+}
+
+impl LogFile for FaultyFile {
+    fn sync_data(&mut self) -> io::Result<()> {
+        if self.faults.lock().take(&[Fault::SyncFailure]).is_some() {
+            return Err(io::Error::other("injected sync failure"));
+        }
+        return self.file.sync_data();
+    }
+
+    fn punch_hole(&mut self, offset: u64, len: u64) -> io::Result<()> {
+        return LogFile::punch_hole(&mut self.file, offset, len);
+    }
+
+    fn truncate(&mut self, len: u64) -> io::Result<()> {
+        return self.file.set_len(len);
+    }
+
+    fn advise_sequential(&mut self) {
+        LogFile::advise_sequential(&mut self.file);
+    }
+}
@@ -0,0 +1,49 @@
+//! Warming the caches after a restart.
+//!
+//! A freshly opened store serves every read from the disk. `RCask::warm` reads a list of
+//! keys ahead of the traffic that needs them, e.g. the keys of `RCask::top_keys` saved before
+//! the restart, filling the read cache and the block cache on the way, and the operating
+//! system's page cache whether or not the store has caches of its own.
+//! `BackgroundRuntime::warm` does the same on a background thread, a batch at a time, so
+//! that requests are served while the store warms up.
+
+use crate::{Error, RCask, ReadOptions, Result};
+
+/// Keys `BackgroundRuntime::warm` reads each time it holds the store's lock.
+pub(crate) const WARM_BATCH: usize = 256;
+
+impl RCask {
+    /// Reads `keys` into the caches and returns how many of them have a value. Keys holding
+    /// lists, sets or hashes are skipped.
+    pub fn warm<I, K>(&mut self, keys: I) -> Result<u64>
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<str>,
+    {
+        let keys = keys
+            .into_iter()
+            .map(|key| self.stored_key(key.as_ref()).into_owned());
+        let keys: Vec<String> = keys.collect();
+        return self.warm_stored(keys);
+    }
+
+    /// Reads every key that starts with `prefix` into the caches, like `warm`.
+    pub fn warm_prefix(&mut self, prefix: &str) -> Result<u64> {
+        let keys = self.store.keys_with_prefix(&self.stored_key(prefix));
+        return self.warm_stored(keys);
+    }
+
+    /// Like `warm`, with the keys as stored.
+    fn warm_stored(&mut self, keys: Vec<String>) -> Result<u64> {
+        let options = ReadOptions::default();
+        let mut warmed = 0;
+        for key in keys {
+            match self.get_opt_into(&key, &options, Vec::new()) {
+                Ok(Some(_)) => warmed += 1,
+                Ok(None) | Err(Error::WrongType { .. }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        return Ok(warmed);
+    }
+}
@@ -0,0 +1,95 @@
+//! A hierarchical timer wheel of key expiry times.
+//!
+//! Level 0 has one slot per second for the next 64 seconds, and every further level has
+//! slots 64 times as wide, so four levels cover about 194 days; later times wait in an
+//! overflow list. Advancing the wheel empties the level 0 slots that came due and, whenever a
+//! slot of a higher level comes into range, redistributes its keys into the levels below, so
+//! each key is touched at most once per level rather than on every sweep.
+
+use std::mem;
+
+/// Milliseconds per level 0 slot.
+const TICK_MS: u64 = 1000;
+/// log2 of the number of slots per level.
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const LEVELS: usize = 4;
+
+pub(crate) struct TimerWheel {
+    /// The last tick the wheel has advanced to.
+    now: u64,
+    levels: Vec<Vec<Vec<(String, u64)>>>,
+    /// Timers beyond the last level.
+    overflow: Vec<(String, u64)>,
+    /// Timers that came due and were not handed out yet.
+    due: Vec<(String, u64)>,
+}
+
+impl TimerWheel {
+    /// Returns an empty wheel starting at `now` (milliseconds since the Unix epoch).
+    pub(crate) fn new(now: u64) -> Self {
+        return TimerWheel {
+            now: now / TICK_MS,
+            levels: (0..LEVELS).map(|_| vec![Vec::new(); SLOTS]).collect(),
+            overflow: Vec::new(),
+            due: Vec::new(),
+        };
+    }
+
+    /// Schedules `key` to come due at `expires_at` (milliseconds since the Unix epoch).
+    pub(crate) fn insert(&mut self, key: String, expires_at: u64) {
+        let tick = expires_at / TICK_MS;
+        if tick <= self.now {
+            self.due.push((key, expires_at));
+            return;
+        }
+        let delta = tick - self.now;
+        let level = ((u64::BITS - 1 - delta.leading_zeros()) / SLOT_BITS) as usize;
+        if level >= LEVELS {
+            self.overflow.push((key, expires_at));
+            return;
+        }
+        let slot = (tick >> (SLOT_BITS * level as u32)) as usize & (SLOTS - 1);
+        self.levels[level][slot].push((key, expires_at));
+    }
+
+    /// Advances the wheel to `now` and returns every timer due by then, with its expiry time.
+    /// A key may be returned more than once if it was scheduled more than once.
+    pub(crate) fn advance(&mut self, now: u64) -> Vec<(String, u64)> {
+        let target = now / TICK_MS;
+        while self.now < target {
+            self.now += 1;
+            self.cascade();
+            let slot = self.now as usize & (SLOTS - 1);
+            let expired = mem::take(&mut self.levels[0][slot]);
+            self.due.extend(expired);
+        }
+        // The current slot may hold timers later in the same tick.
+        let (due, later) = mem::take(&mut self.due)
+            .into_iter()
+            .partition(|(_, expires_at)| *expires_at <= now);
+        self.due = later;
+        return due;
+    }
+
+    /// Moves the timers of every higher level slot that starts at the current tick into the
+    /// levels below it.
+    fn cascade(&mut self) {
+        for level in 1..=LEVELS {
+            let width = SLOT_BITS * level as u32;
+            if self.now & ((1 << width) - 1) != 0 {
+                break;
+            }
+            let timers = match self.levels.get_mut(level) {
+                Some(slots) => {
+                    let slot = (self.now >> width) as usize & (SLOTS - 1);
+                    mem::take(&mut slots[slot])
+                }
+                None => mem::take(&mut self.overflow),
+            };
+            for (key, expires_at) in timers {
+                self.insert(key, expires_at);
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+#![allow(clippy::needless_return)]
+
+use rcask::RCask;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-batch-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+fn compact(store: &mut RCask) -> rcask::Result<()> {
+    let mut task = store.compaction_task()?;
+    while !task.run_for(Duration::from_secs(1))? {}
+    return Ok(());
+}
+
+/// The only segment of the store in `directory`.
+fn segment(directory: &str) -> PathBuf {
+    let mut segments: Vec<_> = std::fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "log"))
+        .collect();
+    assert_eq!(segments.len(), 1, "{:?}", segments);
+    return segments.remove(0);
+}
+
+fn value(i: usize) -> String {
+    return format!("{:0>2048}", i);
+}
+
+/// Starts a batch that sets 10 MiB of values, more than two chunks, and deletes `deleted`.
+fn fill(store: &mut RCask) -> rcask::Result<rcask::LargeBatch<'_>> {
+    let mut batch = store.large_batch()?;
+    for i in 0..5000 {
+        batch.set(&format!("key:{}", i), value(i))?;
+    }
+    batch.delete("deleted")?;
+    return Ok(batch);
+}
+
+#[test]
+fn a_committed_batch_survives_reopening_and_compaction() -> rcask::Result<()> {
+    let directory = directory("committed");
+    {
+        let mut store = RCask::new(directory.clone(), "log".to_string())?;
+        store.set("deleted", "before the batch")?;
+        assert_eq!(fill(&mut store)?.commit()?, 5001);
+    }
+
+    let mut store = RCask::new(directory.clone(), "log".to_string())?;
+    assert_eq!(store.get("key:0")?, Some(value(0)));
+    assert_eq!(store.get("key:4999")?, Some(value(4999)));
+    assert_eq!(store.get("deleted")?, None);
+    compact(&mut store)?;
+    drop(store);
+
+    let mut store = RCask::new(directory, "log".to_string())?;
+    assert_eq!(store.get("key:2500")?, Some(value(2500)));
+    assert_eq!(store.get("deleted")?, None);
+    return Ok(());
+}
+
+#[test]
+fn an_abandoned_batch_is_not_visible_after_reopening() -> rcask::Result<()> {
+    let directory = directory("abandoned");
+    {
+        let mut store = RCask::new(directory.clone(), "log".to_string())?;
+        store.set("deleted", "before the batch")?;
+        fill(&mut store)?.abort();
+        store.set("after", "the batch")?;
+    }
+
+    let mut store = RCask::new(directory, "log".to_string())?;
+    assert_eq!(store.get("key:0")?, None);
+    assert_eq!(store.get("key:4999")?, None);
+    assert_eq!(store.get("deleted")?.as_deref(), Some("before the batch"));
+    assert_eq!(store.get("after")?.as_deref(), Some("the batch"));
+    return Ok(());
+}
+
+#[test]
+fn a_batch_with_a_torn_commit_marker_is_not_visible_after_reopening() -> rcask::Result<()> {
+    let directory = directory("torn");
+    {
+        let mut store = RCask::new(directory.clone(), "log".to_string())?;
+        store.set("deleted", "before the batch")?;
+        fill(&mut store)?.commit()?;
+    }
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(segment(&directory))?;
+    file.set_len(file.metadata()?.len() - 1)?;
+    drop(file);
+
+    let mut store = RCask::new(directory, "log".to_string())?;
+    assert_eq!(store.get("key:0")?, None);
+    assert_eq!(store.get("key:4999")?, None);
+    assert_eq!(store.get("deleted")?.as_deref(), Some("before the batch"));
+    return Ok(());
+}
@@ -0,0 +1,70 @@
+#![allow(clippy::needless_return)]
+
+use rcask::blob::BlobOptions;
+use rcask::RCask;
+use std::path::Path;
+use std::time::Duration;
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-blob-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+fn compact(store: &mut RCask) -> rcask::Result<()> {
+    let mut task = store.compaction_task()?;
+    while !task.run_for(Duration::from_secs(1))? {}
+    return Ok(());
+}
+
+fn blob_files(directory: &str) -> usize {
+    return std::fs::read_dir(Path::new(directory).join("log.large"))
+        .map_or(0, |entries| entries.count());
+}
+
+fn open(directory: &str) -> rcask::Result<RCask> {
+    return RCask::builder(directory.to_string(), "log".to_string())
+        .blob_files(BlobOptions {
+            min_value_size: 1 << 10,
+        })
+        .open();
+}
+
+#[test]
+fn large_values_read_back_from_blob_files_after_reopening() -> rcask::Result<()> {
+    let directory = directory("reopen");
+    let (first, second) = ("1".repeat(8 << 10), "2".repeat(8 << 10));
+    {
+        let mut store = open(&directory)?;
+        store.set("first", &first)?;
+        store.set("second", &second)?;
+        store.set("small", "in the log")?;
+        assert_eq!(blob_files(&directory), 2);
+    }
+
+    let mut store = open(&directory)?;
+    assert_eq!(store.get("first")?, Some(first));
+    assert_eq!(store.get("second")?, Some(second));
+    assert_eq!(store.get("small")?.as_deref(), Some("in the log"));
+    return Ok(());
+}
+
+#[test]
+fn compaction_removes_the_blob_files_of_replaced_values() -> rcask::Result<()> {
+    let directory = directory("compaction");
+    let replaced = "3".repeat(8 << 10);
+    {
+        let mut store = open(&directory)?;
+        store.set("key", "0".repeat(8 << 10))?;
+        store.set("key", &replaced)?;
+        store.set("gone", "4".repeat(8 << 10))?;
+        store.delete("gone")?;
+        compact(&mut store)?;
+        assert_eq!(blob_files(&directory), 1);
+    }
+
+    let mut store = open(&directory)?;
+    assert_eq!(store.get("key")?, Some(replaced));
+    assert_eq!(store.get("gone")?, None);
+    return Ok(());
+}
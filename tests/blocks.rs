@@ -0,0 +1,75 @@
+#![allow(clippy::needless_return)]
+
+use rcask::{Builder, RCask};
+use std::time::Duration;
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-blocks-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+fn compact(store: &mut RCask) -> rcask::Result<()> {
+    let mut task = store.compaction_task()?;
+    while !task.run_for(Duration::from_secs(1))? {}
+    return Ok(());
+}
+
+/// Writes 1,000 small keys, compacts them and returns the size of the log on disk.
+fn fill(builder: Builder) -> rcask::Result<u64> {
+    let mut store = builder.open()?;
+    for i in 0..1000 {
+        store.set(format!("key:{:04}", i), format!("value {}", i))?;
+    }
+    compact(&mut store)?;
+    return Ok(store.stats()?.disk_bytes);
+}
+
+#[test]
+fn packed_records_read_back_after_reopening() -> rcask::Result<()> {
+    let directory = directory("reopen");
+    let builder = || RCask::builder(directory.clone(), "log".to_string()).block_packing(4096);
+    let packed = fill(builder())?;
+    let unpacked = fill(RCask::builder(
+        self::directory("unpacked"),
+        "log".to_string(),
+    ))?;
+    assert!(
+        packed < unpacked,
+        "{} bytes packed, {} not",
+        packed,
+        unpacked
+    );
+
+    let mut store = builder().open()?;
+    for i in 0..1000 {
+        let value = store.get(&format!("key:{:04}", i))?;
+        assert_eq!(value, Some(format!("value {}", i)));
+    }
+    // Records after the blocks, and tombstones of packed ones.
+    store.set("key:0001", "updated")?;
+    store.delete("key:0002")?;
+    drop(store);
+
+    let mut store = builder().open()?;
+    assert_eq!(store.get("key:0001")?.as_deref(), Some("updated"));
+    assert_eq!(store.get("key:0002")?, None);
+    assert_eq!(store.get("key:0999")?.as_deref(), Some("value 999"));
+    compact(&mut store)?;
+    drop(store);
+
+    let mut store = builder().open()?;
+    assert_eq!(store.get_all_key_values()?.len(), 999);
+    assert_eq!(store.get("key:0001")?.as_deref(), Some("updated"));
+    assert_eq!(store.get("key:0002")?, None);
+    return Ok(());
+}
+
+#[test]
+fn packed_records_read_back_without_the_option() -> rcask::Result<()> {
+    let directory = directory("option");
+    fill(RCask::builder(directory.clone(), "log".to_string()).block_packing(4096))?;
+    let mut store = RCask::builder(directory, "log".to_string()).open()?;
+    assert_eq!(store.get("key:0500")?.as_deref(), Some("value 500"));
+    return Ok(());
+}
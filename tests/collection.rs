@@ -0,0 +1,34 @@
+#![allow(clippy::needless_return)]
+
+use rcask::RCask;
+
+fn directory(name: &str) -> String {
+    let path =
+        std::env::temp_dir().join(format!("rcask-collection-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+#[test]
+fn removing_from_a_missing_set_writes_nothing() -> rcask::Result<()> {
+    let mut store = RCask::builder(directory("srem"), "log".to_string()).open()?;
+    let before = store.sequence();
+    store.srem("missing", "member")?;
+    assert_eq!(store.version("missing"), None);
+    assert_eq!(store.sequence(), before);
+    store.set("missing", "value")?;
+    assert_eq!(store.get("missing")?.as_deref(), Some("value"));
+    return Ok(());
+}
+
+#[test]
+fn removing_from_a_missing_hash_writes_nothing() -> rcask::Result<()> {
+    let mut store = RCask::builder(directory("hdel"), "log".to_string()).open()?;
+    let before = store.sequence();
+    store.hdel("missing", "field")?;
+    assert_eq!(store.version("missing"), None);
+    assert_eq!(store.sequence(), before);
+    store.set("missing", "value")?;
+    assert_eq!(store.get("missing")?.as_deref(), Some("value"));
+    return Ok(());
+}
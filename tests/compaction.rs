@@ -0,0 +1,79 @@
+#![allow(clippy::needless_return)]
+
+use rcask::blob::BlobOptions;
+use rcask::{CompactionFilter, FilterDecision, RCask, SchemaRegistry};
+use std::time::Duration;
+
+fn directory(name: &str) -> String {
+    let path =
+        std::env::temp_dir().join(format!("rcask-compaction-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+fn compact(store: &mut RCask) -> rcask::Result<()> {
+    let mut task = store.compaction_task()?;
+    while !task.run_for(Duration::from_secs(1))? {}
+    return Ok(());
+}
+
+struct ReplaceOld;
+
+impl CompactionFilter for ReplaceOld {
+    fn filter(&mut self, key: &str, _value: &[u8], _meta: Option<&[u8]>) -> FilterDecision {
+        return match key {
+            "replaced" => FilterDecision::Replace(b"new".to_vec()),
+            "dropped" => FilterDecision::Drop,
+            _ => FilterDecision::Keep,
+        };
+    }
+}
+
+#[test]
+fn filtered_values_are_not_served_from_the_read_cache() -> rcask::Result<()> {
+    let mut store = RCask::builder(directory("filter-cache"), "log".to_string())
+        .read_cache(1 << 20)
+        .compaction_filter(ReplaceOld)
+        .open()?;
+    store.set("replaced", "old")?;
+    store.set("dropped", "old")?;
+    assert_eq!(store.get("replaced")?.as_deref(), Some("old"));
+    assert_eq!(store.get("dropped")?.as_deref(), Some("old"));
+    compact(&mut store)?;
+    assert_eq!(store.get("replaced")?.as_deref(), Some("new"));
+    assert_eq!(store.get("dropped")?, None);
+    return Ok(());
+}
+
+#[test]
+fn blob_values_are_rewritten_at_the_current_schema_version() -> rcask::Result<()> {
+    let directory = directory("schema-blobs");
+    let blobs = BlobOptions {
+        min_value_size: 1 << 10,
+    };
+    let open = |schema: SchemaRegistry| {
+        return RCask::builder(directory.clone(), "log".to_string())
+            .blob_files(blobs.clone())
+            .schema(schema)
+            .open();
+    };
+    let large = "x".repeat(4 << 10);
+    {
+        let mut store = open(SchemaRegistry::new(1))?;
+        store.set("large", &large)?;
+        store.set("small", "1")?;
+    }
+    {
+        let upgraded = SchemaRegistry::new(2).upgrade(1, |mut value| {
+            value.extend_from_slice(b";2");
+            return Ok(value);
+        });
+        let mut store = open(upgraded)?;
+        compact(&mut store)?;
+    }
+    // Without an upgrade from version 1, only values compaction rewrote can be read.
+    let mut store = open(SchemaRegistry::new(2))?;
+    assert_eq!(store.get("large")?, Some(format!("{};2", large)));
+    assert_eq!(store.get("small")?.as_deref(), Some("1;2"));
+    return Ok(());
+}
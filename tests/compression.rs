@@ -0,0 +1,72 @@
+#![cfg(feature = "zstd")]
+#![allow(clippy::needless_return)]
+
+use rcask::compression::DictionaryOptions;
+use rcask::RCask;
+
+fn directory(name: &str) -> String {
+    let path =
+        std::env::temp_dir().join(format!("rcask-compression-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+fn dictionaries(directory: &str) -> usize {
+    return std::fs::read_dir(directory)
+        .unwrap()
+        .filter(|entry| {
+            let name = entry.as_ref().unwrap().file_name();
+            return name.to_string_lossy().ends_with(".dict");
+        })
+        .count();
+}
+
+/// A small JSON-like value, much like the others.
+fn value(i: usize) -> String {
+    return format!(
+        r#"{{"id":{},"name":"user {}","email":"user{}@example.com","active":{}}}"#,
+        i,
+        i,
+        i,
+        i.is_multiple_of(2)
+    );
+}
+
+#[test]
+fn values_compressed_with_a_trained_dictionary_read_back_after_reopening() -> rcask::Result<()> {
+    let directory = directory("reopen");
+    // Training happens in the compaction that the 2,000th write starts.
+    let open = || {
+        return RCask::builder(directory.clone(), "log".to_string())
+            .max_writes(2000)
+            .zstd_dictionary(DictionaryOptions::default())
+            .open();
+    };
+    {
+        let mut store = open()?;
+        for i in 0..=2000 {
+            store.set(format!("user:{}", i), value(i))?;
+        }
+        assert_eq!(dictionaries(&directory), 1);
+        // Written after the compaction, next to the compressed values.
+        store.set("user:2001", value(2001))?;
+    }
+
+    let mut store = open()?;
+    for i in 0..=2001 {
+        assert_eq!(store.get(&format!("user:{}", i))?, Some(value(i)));
+    }
+    // Starts another compaction, which trains a dictionary of its own.
+    for i in 0..=2000 {
+        store.set(format!("user:{}", i), value(i + 1))?;
+    }
+    drop(store);
+
+    let mut store = open()?;
+    assert_eq!(dictionaries(&directory), 1);
+    for i in 0..=2000 {
+        assert_eq!(store.get(&format!("user:{}", i))?, Some(value(i + 1)));
+    }
+    assert_eq!(store.get("user:2001")?, Some(value(2001)));
+    return Ok(());
+}
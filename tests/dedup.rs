@@ -0,0 +1,78 @@
+#![allow(clippy::needless_return)]
+
+use rcask::dedup::DedupOptions;
+use rcask::RCask;
+use std::path::Path;
+use std::time::Duration;
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-dedup-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+fn compact(store: &mut RCask) -> rcask::Result<()> {
+    let mut task = store.compaction_task()?;
+    while !task.run_for(Duration::from_secs(1))? {}
+    return Ok(());
+}
+
+fn stored_values(directory: &str) -> usize {
+    return std::fs::read_dir(Path::new(directory).join("log.blobs"))
+        .map_or(0, |entries| entries.count());
+}
+
+#[test]
+fn a_shared_value_is_stored_once_and_read_back_after_reopening() -> rcask::Result<()> {
+    let directory = directory("reopen");
+    let open = || {
+        return RCask::builder(directory.clone(), "log".to_string())
+            .deduplication(DedupOptions { min_value_size: 64 })
+            .open();
+    };
+    let shared = "s".repeat(1 << 10);
+    {
+        let mut store = open()?;
+        for i in 0..10 {
+            store.set(format!("copy:{}", i), &shared)?;
+        }
+        store.set("small", "inline")?;
+        assert_eq!(stored_values(&directory), 1);
+    }
+
+    let mut store = open()?;
+    for i in 0..10 {
+        assert_eq!(store.get(&format!("copy:{}", i))?, Some(shared.clone()));
+    }
+    assert_eq!(store.get("small")?.as_deref(), Some("inline"));
+    store.set("copy:10", &shared)?;
+    assert_eq!(stored_values(&directory), 1);
+    return Ok(());
+}
+
+#[test]
+fn compaction_keeps_referenced_values_and_removes_the_rest() -> rcask::Result<()> {
+    let directory = directory("compaction");
+    let open = || {
+        return RCask::builder(directory.clone(), "log".to_string())
+            .deduplication(DedupOptions { min_value_size: 64 })
+            .open();
+    };
+    let (kept, dropped) = ("k".repeat(1 << 10), "d".repeat(1 << 10));
+    {
+        let mut store = open()?;
+        store.set("kept:1", &kept)?;
+        store.set("kept:2", &kept)?;
+        store.set("dropped", &dropped)?;
+        store.delete("kept:1")?;
+        store.delete("dropped")?;
+        compact(&mut store)?;
+        assert_eq!(stored_values(&directory), 1);
+    }
+
+    let mut store = open()?;
+    assert_eq!(store.get("kept:2")?, Some(kept));
+    assert_eq!(store.get("kept:1")?, None);
+    assert_eq!(store.get("dropped")?, None);
+    return Ok(());
+}
@@ -0,0 +1,83 @@
+#![allow(clippy::needless_return)]
+
+use rcask::delta::DeltaOptions;
+use rcask::RCask;
+use std::time::Duration;
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-delta-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+fn compact(store: &mut RCask) -> rcask::Result<()> {
+    let mut task = store.compaction_task()?;
+    while !task.run_for(Duration::from_secs(1))? {}
+    return Ok(());
+}
+
+/// The `version`th value of a document that changes a few bytes at a time.
+fn document(version: usize) -> String {
+    let mut document: Vec<u8> = (0..4096).map(|i| b'a' + (i % 26) as u8).collect();
+    document[version * 7 % 4096] = b'#';
+    document.extend_from_slice(version.to_string().as_bytes());
+    return String::from_utf8(document).unwrap();
+}
+
+#[test]
+fn updates_stored_as_deltas_read_back_after_reopening() -> rcask::Result<()> {
+    let directory = directory("reopen");
+    let open = || {
+        return RCask::builder(directory.clone(), "log".to_string())
+            .delta_encoding(DeltaOptions::default())
+            .open();
+    };
+    {
+        let mut store = open()?;
+        store.set("doc", document(0))?;
+        let before = store.stats()?.physical_bytes_written;
+        for version in 1..=20 {
+            store.set("doc", document(version))?;
+        }
+        // Twenty full copies would take 80 KiB.
+        let written = store.stats()?.physical_bytes_written - before;
+        assert!(written < 20 << 10, "{} bytes written", written);
+        assert_eq!(store.get("doc")?, Some(document(20)));
+    }
+
+    let mut store = open()?;
+    assert_eq!(store.get("doc")?, Some(document(20)));
+    store.set("doc", document(21))?;
+    compact(&mut store)?;
+    assert_eq!(store.get("doc")?, Some(document(21)));
+    drop(store);
+
+    let mut store = open()?;
+    assert_eq!(store.get("doc")?, Some(document(21)));
+    return Ok(());
+}
+
+#[test]
+fn chains_longer_than_the_limit_restart_from_a_full_value() -> rcask::Result<()> {
+    let directory = directory("chain");
+    let options = DeltaOptions {
+        max_chain: 2,
+        ..DeltaOptions::default()
+    };
+    let open = || {
+        return RCask::builder(directory.clone(), "log".to_string())
+            .delta_encoding(options.clone())
+            .open();
+    };
+    {
+        let mut store = open()?;
+        for version in 0..10 {
+            store.set("doc", document(version))?;
+            store.set("other", document(version + 100))?;
+        }
+    }
+    let mut store = open()?;
+    assert_eq!(store.get("doc")?, Some(document(9)));
+    assert_eq!(store.get("other")?, Some(document(109)));
+    return Ok(());
+}
@@ -0,0 +1,42 @@
+#![allow(clippy::needless_return)]
+
+use rcask::RCask;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-entry-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+#[test]
+fn update_does_not_wait_for_the_key_lock() -> rcask::Result<()> {
+    let store = RCask::new(directory("update"), "log".to_string())?;
+    let locks = store.key_locks();
+    let store = Arc::new(Mutex::new(store));
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let (store, locks) = (store.clone(), locks.clone());
+            thread::spawn(move || -> rcask::Result<()> {
+                for _ in 0..100 {
+                    // The key lock first, the store only around single operations.
+                    let _guard = locks.lock("counter");
+                    let mut store = store.lock().unwrap();
+                    store.update("counter", |value| {
+                        let count: u64 =
+                            value.map_or(0, |v| String::from_utf8_lossy(v).parse().unwrap());
+                        return Some((count + 1).to_string().into_bytes());
+                    })?;
+                }
+                return Ok(());
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap()?;
+    }
+    let value = store.lock().unwrap().get("counter")?;
+    assert_eq!(value.as_deref(), Some("400"));
+    return Ok(());
+}
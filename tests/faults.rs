@@ -0,0 +1,102 @@
+#![allow(clippy::needless_return)]
+
+use rcask::vfs::{Fault, FaultyFileSystem};
+use rcask::{RCask, WriteOptions};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-faults-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+fn open(directory: &str, fs: &FaultyFileSystem) -> rcask::Result<RCask> {
+    return RCask::builder(directory.to_string(), "log".to_string())
+        .file_system(Arc::new(fs.clone()))
+        .max_writes(u64::MAX)
+        .open();
+}
+
+fn kind(error: rcask::Error) -> io::ErrorKind {
+    return io::Error::from(error).kind();
+}
+
+#[test]
+fn short_and_interrupted_writes_are_retried() -> rcask::Result<()> {
+    let (dir, fs) = (directory("retried"), FaultyFileSystem::new());
+    let mut store = open(&dir, &fs)?;
+    fs.inject(Fault::ShortWrite, 1);
+    store.set("short", "value")?;
+    fs.inject(Fault::Interrupted, 1);
+    store.set("interrupted", "value")?;
+    drop(store);
+    let mut store = open(&dir, &fs)?;
+    assert_eq!(store.get("short")?.as_deref(), Some("value"));
+    assert_eq!(store.get("interrupted")?.as_deref(), Some("value"));
+    return Ok(());
+}
+
+#[test]
+fn a_write_to_a_full_disk_fails_and_leaves_no_record() -> rcask::Result<()> {
+    let (dir, fs) = (directory("full"), FaultyFileSystem::new());
+    let mut store = open(&dir, &fs)?;
+    store.set("before", "1")?;
+    fs.inject(Fault::NoSpace, 1);
+    let error = store.set("failed", "2").unwrap_err();
+    assert_eq!(kind(error), io::ErrorKind::StorageFull);
+    store.set("after", "3")?;
+    drop(store);
+    let mut store = open(&dir, &fs)?;
+    assert_eq!(store.get("before")?.as_deref(), Some("1"));
+    assert_eq!(store.get("failed")?, None);
+    assert_eq!(store.get("after")?.as_deref(), Some("3"));
+    return Ok(());
+}
+
+#[test]
+fn a_failed_sync_is_reported() -> rcask::Result<()> {
+    let (dir, fs) = (directory("sync"), FaultyFileSystem::new());
+    let mut store = open(&dir, &fs)?;
+    fs.inject(Fault::SyncFailure, 1);
+    let options = WriteOptions {
+        sync: true,
+        ..Default::default()
+    };
+    assert!(store.set_opt("key", "value", &options).is_err());
+    store.set_opt("key", "value", &options)?;
+    drop(store);
+    let mut store = open(&dir, &fs)?;
+    assert_eq!(store.get("key")?.as_deref(), Some("value"));
+    return Ok(());
+}
+
+#[test]
+fn a_failed_rename_leaves_the_store_as_it_was() -> rcask::Result<()> {
+    let (dir, fs) = (directory("rename"), FaultyFileSystem::new());
+    let mut store = open(&dir, &fs)?;
+    for i in 0..10 {
+        store.set(format!("key-{}", i % 3), format!("{}", i))?;
+    }
+    fs.inject(Fault::RenameFailure, 1);
+    let mut task = store.compaction_task()?;
+    let mut failed = false;
+    loop {
+        match task.run_for(Duration::from_secs(1)) {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(_) => failed = true,
+        }
+    }
+    assert!(failed);
+    for (key, value) in [("key-0", "9"), ("key-1", "7"), ("key-2", "8")] {
+        assert_eq!(store.get(key)?.as_deref(), Some(value));
+    }
+    drop(store);
+    let mut store = open(&dir, &fs)?;
+    for (key, value) in [("key-0", "9"), ("key-1", "7"), ("key-2", "8")] {
+        assert_eq!(store.get(key)?.as_deref(), Some(value));
+    }
+    return Ok(());
+}
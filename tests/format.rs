@@ -0,0 +1,43 @@
+#![allow(clippy::needless_return)]
+
+use rcask::blob::BlobOptions;
+use rcask::{RCask, SchemaRegistry};
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-format-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+#[test]
+fn a_store_only_opens_with_the_value_options_it_was_written_with() -> rcask::Result<()> {
+    let directory = directory("schema");
+    let builder = || RCask::builder(directory.clone(), "log".to_string());
+    {
+        let mut store = builder().schema(SchemaRegistry::new(1)).open()?;
+        store.set("key", "value")?;
+    }
+    assert!(builder().open().is_err());
+    let with_blobs = builder()
+        .schema(SchemaRegistry::new(1))
+        .blob_files(BlobOptions::default())
+        .open();
+    assert!(with_blobs.is_err());
+    let mut store = builder().schema(SchemaRegistry::new(1)).open()?;
+    assert_eq!(store.get("key")?.as_deref(), Some("value"));
+    return Ok(());
+}
+
+#[test]
+fn a_plain_store_does_not_open_with_value_options() -> rcask::Result<()> {
+    let directory = directory("plain");
+    let builder = || RCask::builder(directory.clone(), "log".to_string());
+    {
+        let mut store = builder().open()?;
+        store.set("key", "value")?;
+    }
+    assert!(builder().schema(SchemaRegistry::new(1)).open().is_err());
+    let mut store = builder().open()?;
+    assert_eq!(store.get("key")?.as_deref(), Some("value"));
+    return Ok(());
+}
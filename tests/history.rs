@@ -0,0 +1,92 @@
+#![allow(clippy::needless_return)]
+
+use rcask::{HistoryOptions, RCask};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-history-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+fn compact(store: &mut RCask) -> rcask::Result<()> {
+    let mut task = store.compaction_task()?;
+    while !task.run_for(Duration::from_secs(1))? {}
+    return Ok(());
+}
+
+/// The time between two writes, at a distance from both.
+fn pause() -> SystemTime {
+    thread::sleep(Duration::from_millis(20));
+    let now = SystemTime::now();
+    thread::sleep(Duration::from_millis(20));
+    return now;
+}
+
+fn values(store: &mut RCask, key: &str) -> rcask::Result<Vec<Option<Vec<u8>>>> {
+    let versions = store.get_versions(key)?;
+    return Ok(versions.into_iter().map(|version| version.value).collect());
+}
+
+#[test]
+fn versions_survive_reopening_and_compaction() -> rcask::Result<()> {
+    let directory = directory("reopen");
+    let open = || {
+        return RCask::builder(directory.clone(), "log".to_string())
+            .history(HistoryOptions::default())
+            .open();
+    };
+    let (first, second, deleted) = {
+        let mut store = open()?;
+        store.set("key", "1")?;
+        let first = pause();
+        store.set("key", "2")?;
+        let second = pause();
+        store.delete("key")?;
+        (first, second, pause())
+    };
+    let expected = vec![None, Some(b"2".to_vec()), Some(b"1".to_vec())];
+
+    let mut store = open()?;
+    assert_eq!(values(&mut store, "key")?, expected);
+    assert_eq!(store.get_as_of("key", first)?, Some(b"1".to_vec()));
+    assert_eq!(store.get_as_of("key", second)?, Some(b"2".to_vec()));
+    assert_eq!(store.get_as_of("key", deleted)?, None);
+    compact(&mut store)?;
+    drop(store);
+
+    let mut store = open()?;
+    assert_eq!(values(&mut store, "key")?, expected);
+    assert_eq!(store.get_as_of("key", first)?, Some(b"1".to_vec()));
+    assert!(store.undelete("key")?);
+    drop(store);
+
+    let mut store = open()?;
+    assert_eq!(store.get("key")?.as_deref(), Some("2"));
+    return Ok(());
+}
+
+#[test]
+fn compaction_keeps_the_configured_number_of_versions() -> rcask::Result<()> {
+    let directory = directory("versions");
+    let open = || {
+        return RCask::builder(directory.clone(), "log".to_string())
+            .history(HistoryOptions {
+                retention: Duration::ZERO,
+                max_versions: Some(2),
+            })
+            .open();
+    };
+    {
+        let mut store = open()?;
+        for version in 1..=5 {
+            store.set("key", version.to_string())?;
+        }
+        compact(&mut store)?;
+    }
+    let mut store = open()?;
+    let expected = vec![Some(b"5".to_vec()), Some(b"4".to_vec())];
+    assert_eq!(values(&mut store, "key")?, expected);
+    return Ok(());
+}
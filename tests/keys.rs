@@ -0,0 +1,210 @@
+#![allow(clippy::needless_return)]
+
+use rcask::import::rocksdb;
+use rcask::keycodec::CaseFold;
+use rcask::{BoxError, Error, KeyCodec, Observer, RCask};
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-keys-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+#[test]
+fn keys_that_are_not_utf8_are_rejected() -> rcask::Result<()> {
+    let dir = directory("set");
+    let mut store = RCask::new(dir.clone(), "log".to_string())?;
+    let error = store.set(b"\xff\xfe", "value").unwrap_err();
+    assert!(matches!(error, Error::InvalidKey { key } if key == b"\xff\xfe"));
+    store.set("valid", "value")?;
+    drop(store);
+    let mut store = RCask::new(dir, "log".to_string())?;
+    assert_eq!(store.get("valid")?.as_deref(), Some("value"));
+    return Ok(());
+}
+
+#[test]
+fn a_bulk_load_stops_at_a_key_that_is_not_utf8() -> rcask::Result<()> {
+    let dir = directory("bulk");
+    let mut store = RCask::new(dir.clone(), "log".to_string())?;
+    let entries: Vec<(&[u8], &[u8])> = vec![(b"a", b"1"), (b"\xc3", b"2"), (b"c", b"3")];
+    let error = store.bulk_load(entries).unwrap_err();
+    assert!(matches!(error, Error::InvalidKey { .. }));
+    drop(store);
+    let mut store = RCask::new(dir, "log".to_string())?;
+    assert_eq!(store.get("a")?.as_deref(), Some("1"));
+    assert_eq!(store.get("c")?, None);
+    return Ok(());
+}
+
+#[test]
+fn a_dump_with_binary_keys_leaves_the_store_readable() -> rcask::Result<()> {
+    let dir = directory("dump");
+    let mut store = RCask::new(dir.clone(), "log".to_string())?;
+    let dump = "plain ==> 1\n0xff00 ==> 2\n";
+    let error = rocksdb::import_dump(dump.as_bytes(), &mut store).unwrap_err();
+    assert!(matches!(error, Error::InvalidKey { .. }));
+    drop(store);
+    let mut store = RCask::new(dir, "log".to_string())?;
+    assert_eq!(store.get("plain")?.as_deref(), Some("1"));
+    return Ok(());
+}
+
+#[test]
+fn renaming_or_copying_a_key_to_itself_changes_nothing() -> rcask::Result<()> {
+    let mut store = RCask::builder(directory("itself"), "log".to_string()).open()?;
+    store.set("key", "value")?;
+    assert!(store.rename("key", "key", false)?);
+    assert!(store.copy("key", "key", false)?);
+    assert!(!store.rename("missing", "missing", false)?);
+    assert_eq!(store.get("key")?.as_deref(), Some("value"));
+    return Ok(());
+}
+
+/// Records what it observes as `set <key>=<value>` and `delete <key>`.
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl Observer for Recorder {
+    fn on_set(&mut self, key: &str, value: &[u8]) -> Result<(), BoxError> {
+        let value = String::from_utf8_lossy(value);
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("set {}={}", key, value));
+        return Ok(());
+    }
+
+    fn on_delete(&mut self, key: &str) {
+        self.0.lock().unwrap().push(format!("delete {}", key));
+    }
+}
+
+#[test]
+fn observers_see_a_rename_as_a_set_and_a_delete() -> rcask::Result<()> {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut store = RCask::builder(directory("observed-rename"), "log".to_string())
+        .observer(Recorder(events.clone()))
+        .open()?;
+    store.set("from", "value")?;
+    events.lock().unwrap().clear();
+    assert!(store.rename("from", "to", false)?);
+    assert_eq!(*events.lock().unwrap(), vec!["set to=value", "delete from"]);
+    return Ok(());
+}
+
+#[test]
+fn observers_see_a_copy_as_a_set() -> rcask::Result<()> {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut store = RCask::builder(directory("observed-copy"), "log".to_string())
+        .observer(Recorder(events.clone()))
+        .open()?;
+    store.set("from", "value")?;
+    events.lock().unwrap().clear();
+    assert!(store.copy("from", "to", false)?);
+    assert!(store.copy("to", "to", false)?);
+    assert_eq!(*events.lock().unwrap(), vec!["set to=value"]);
+    return Ok(());
+}
+
+/// Stores keys reversed, which keeps neither prefixes nor order.
+struct Reversed;
+
+impl KeyCodec for Reversed {
+    fn id(&self) -> String {
+        return "reversed".to_string();
+    }
+
+    fn encode<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        return Cow::Owned(key.chars().rev().collect());
+    }
+
+    fn decode<'a>(&self, stored: &'a str) -> Cow<'a, str> {
+        return Cow::Owned(stored.chars().rev().collect());
+    }
+}
+
+#[test]
+fn delete_prefix_matches_decoded_keys() -> rcask::Result<()> {
+    let mut store = RCask::builder(directory("prefix-codec"), "log".to_string())
+        .key_codec(Reversed)
+        .open()?;
+    for key in ["user:1", "user:2", "group:1", "1:user"] {
+        store.set(key, "value")?;
+    }
+    assert_eq!(store.delete_prefix("user:")?, 2);
+    assert_eq!(store.get("user:1")?, None);
+    assert_eq!(store.get("user:2")?, None);
+    assert_eq!(store.get("group:1")?.as_deref(), Some("value"));
+    assert_eq!(store.get("1:user")?.as_deref(), Some("value"));
+    return Ok(());
+}
+
+#[test]
+fn delete_prefix_folds_the_prefix_like_the_keys() -> rcask::Result<()> {
+    let mut store = RCask::builder(directory("prefix-fold"), "log".to_string())
+        .key_codec(CaseFold)
+        .open()?;
+    store.set("User:1", "value")?;
+    store.set("other", "value")?;
+    assert_eq!(store.delete_prefix("USER:")?, 1);
+    assert_eq!(store.get("user:1")?, None);
+    assert_eq!(store.get("other")?.as_deref(), Some("value"));
+    return Ok(());
+}
+
+/// The only segment of the store in `directory`.
+fn segment(directory: &str) -> std::path::PathBuf {
+    let mut segments: Vec<_> = std::fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "log"))
+        .collect();
+    assert_eq!(segments.len(), 1, "{:?}", segments);
+    return segments.remove(0);
+}
+
+#[test]
+fn renames_and_prefix_deletes_survive_reopening() -> rcask::Result<()> {
+    let dir = directory("groups-reopen");
+    {
+        let mut store = RCask::new(dir.clone(), "log".to_string())?;
+        store.set("from", "value")?;
+        store.set("user:1", "1")?;
+        store.set("user:2", "2")?;
+        store.set("other", "3")?;
+        assert!(store.rename("from", "to", false)?);
+        assert_eq!(store.delete_prefix("user:")?, 2);
+    }
+    let mut store = RCask::new(dir, "log".to_string())?;
+    assert_eq!(store.get("from")?, None);
+    assert_eq!(store.get("to")?.as_deref(), Some("value"));
+    assert_eq!(store.get("user:1")?, None);
+    assert_eq!(store.get("user:2")?, None);
+    assert_eq!(store.get("other")?.as_deref(), Some("3"));
+    return Ok(());
+}
+
+#[test]
+fn a_torn_rename_is_dropped_whole_on_reopening() -> rcask::Result<()> {
+    let dir = directory("groups-torn");
+    let (before, after) = {
+        let mut store = RCask::new(dir.clone(), "log".to_string())?;
+        store.set("from", "value")?;
+        let before = std::fs::metadata(segment(&dir))?.len();
+        store.rename("from", "to", false)?;
+        (before, std::fs::metadata(segment(&dir))?.len())
+    };
+    // Cut the group short of its last record, as a crash in the middle of the write would.
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(segment(&dir))?;
+    file.set_len(before + (after - before) * 3 / 4)?;
+    drop(file);
+
+    let mut store = RCask::new(dir, "log".to_string())?;
+    assert_eq!(store.get("from")?.as_deref(), Some("value"));
+    assert_eq!(store.get("to")?, None);
+    return Ok(());
+}
@@ -0,0 +1,84 @@
+#![allow(clippy::needless_return)]
+
+use rcask::blob::BlobOptions;
+use rcask::dedup::DedupOptions;
+use rcask::{Builder, Error, RCask};
+use std::path::Path;
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-quota-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+fn files_in(directory: &str, name: &str) -> usize {
+    return std::fs::read_dir(Path::new(directory).join(name)).map_or(0, |entries| entries.count());
+}
+
+fn assert_rejected_without_files(builder: Builder, directory: &str) -> rcask::Result<()> {
+    let mut store = builder.quota("user:", 8).open()?;
+    let result = store.set("user:1", "x".repeat(1 << 10));
+    assert!(matches!(result, Err(Error::QuotaExceeded { .. })));
+    assert_eq!(store.get("user:1")?, None);
+    assert_eq!(files_in(directory, "log.blobs"), 0);
+    assert_eq!(files_in(directory, "log.large"), 0);
+    return Ok(());
+}
+
+#[test]
+fn a_rejected_blob_value_leaves_no_blob_file() -> rcask::Result<()> {
+    let directory = directory("blob");
+    let builder = RCask::builder(directory.clone(), "log".to_string())
+        .blob_files(BlobOptions { min_value_size: 64 });
+    return assert_rejected_without_files(builder, &directory);
+}
+
+#[test]
+fn a_rejected_shared_value_leaves_no_content_file() -> rcask::Result<()> {
+    let directory = directory("dedup");
+    let builder = RCask::builder(directory.clone(), "log".to_string())
+        .deduplication(DedupOptions { min_value_size: 64 });
+    return assert_rejected_without_files(builder, &directory);
+}
+
+#[test]
+fn writes_within_the_quota_are_accepted_and_counted_after_reopening() -> rcask::Result<()> {
+    let directory = directory("reopen");
+    let builder = || RCask::builder(directory.clone(), "log".to_string()).quota("user:", 32);
+    {
+        let mut store = builder().open()?;
+        store.set("user:1", "0123456789")?;
+        store.set("other", "x".repeat(64))?;
+    }
+    let mut store = builder().open()?;
+    let result = store.set("user:2", "x".repeat(16));
+    assert!(matches!(result, Err(Error::QuotaExceeded { .. })));
+    store.set("user:1", "1")?;
+    store.set("user:2", "0123456789")?;
+    return Ok(());
+}
+
+#[test]
+fn deleted_keys_free_their_quota_after_reopening_and_compaction() -> rcask::Result<()> {
+    let directory = directory("freed");
+    let builder = || RCask::builder(directory.clone(), "log".to_string()).quota("user:", 64);
+    let used = |store: &mut RCask| -> rcask::Result<u64> {
+        return Ok(store.stats()?.quotas[0].used_bytes);
+    };
+    {
+        let mut store = builder().open()?;
+        store.set("user:1", "x".repeat(40))?;
+        store.delete("user:1")?;
+        store.set("user:2", "y".repeat(10))?;
+    }
+    let mut store = builder().open()?;
+    assert_eq!(used(&mut store)?, 16);
+    let mut task = store.compaction_task()?;
+    while !task.run_for(std::time::Duration::from_secs(1))? {}
+    drop(store);
+
+    let mut store = builder().open()?;
+    assert_eq!(used(&mut store)?, 16);
+    store.set("user:3", "z".repeat(40))?;
+    return Ok(());
+}
@@ -0,0 +1,160 @@
+#![cfg(feature = "raft")]
+#![allow(clippy::needless_return)]
+
+use rcask::raft::{RaftOptions, ReplicatedRCask};
+use rcask::{Error, RCask};
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-raft-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+fn options(id: u64, addresses: &[SocketAddr]) -> RaftOptions {
+    let peers = (0..addresses.len() as u64)
+        .filter(|peer| *peer != id)
+        .map(|peer| (peer, addresses[peer as usize]))
+        .collect();
+    let mut options = RaftOptions::new(id, peers);
+    options.election_timeout = Duration::from_millis(150);
+    options.heartbeat_interval = Duration::from_millis(20);
+    return options;
+}
+
+fn start(
+    directory: &str,
+    listener: TcpListener,
+    options: RaftOptions,
+) -> rcask::Result<ReplicatedRCask> {
+    let store = RCask::builder(directory.to_string(), "log".to_string()).open()?;
+    return ReplicatedRCask::start(store, listener, options);
+}
+
+/// Starts a group of three members in directories named after `name`.
+fn group(name: &str) -> rcask::Result<(Vec<String>, Vec<SocketAddr>, Vec<ReplicatedRCask>)> {
+    let listeners = (0..3)
+        .map(|_| TcpListener::bind("127.0.0.1:0"))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let addresses = listeners
+        .iter()
+        .map(|listener| listener.local_addr())
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let directories: Vec<String> = (0..3)
+        .map(|id| directory(&format!("{}-{}", name, id)))
+        .collect();
+    let mut members = Vec::new();
+    for (id, listener) in listeners.into_iter().enumerate() {
+        let options = options(id as u64, &addresses);
+        members.push(start(&directories[id], listener, options)?);
+    }
+    return Ok((directories, addresses, members));
+}
+
+/// Waits for one of `members` to lead, and returns its position.
+fn leader(members: &[&ReplicatedRCask]) -> usize {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    loop {
+        let leaders: Vec<usize> = (0..members.len())
+            .filter(|at| members[*at].is_leader())
+            .collect();
+        if let [leader] = leaders[..] {
+            return leader;
+        }
+        assert!(Instant::now() < deadline, "no leader was elected");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Waits until `member` has applied `key` as `value`.
+fn wait_for(member: &ReplicatedRCask, key: &str, value: Option<&str>) -> rcask::Result<()> {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while member.get_local(key)?.as_deref() != value {
+        assert!(Instant::now() < deadline, "{} was not replicated", key);
+        thread::sleep(Duration::from_millis(10));
+    }
+    return Ok(());
+}
+
+#[test]
+fn writes_to_the_leader_reach_every_member() -> rcask::Result<()> {
+    let (_, _, members) = group("replicated")?;
+    let all: Vec<&ReplicatedRCask> = members.iter().collect();
+    let leader = &members[leader(&all)];
+
+    leader.set("key", "value")?;
+    leader.set("gone", "soon")?;
+    leader.delete("gone")?;
+    assert_eq!(leader.get("key")?, Some("value".to_string()));
+    assert_eq!(leader.get("gone")?, None);
+    for member in &members {
+        wait_for(member, "key", Some("value"))?;
+        wait_for(member, "gone", None)?;
+    }
+    return Ok(());
+}
+
+#[test]
+fn followers_refuse_writes_and_reads() -> rcask::Result<()> {
+    let (_, _, members) = group("followers")?;
+    let all: Vec<&ReplicatedRCask> = members.iter().collect();
+    let leader = leader(&all);
+    let follower = &members[(leader + 1) % 3];
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while follower.leader().is_none() {
+        assert!(Instant::now() < deadline, "the follower heard of no leader");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let expected = Some(leader as u64);
+    match follower.set("key", "value") {
+        Err(Error::NotLeader { leader }) => assert_eq!(leader, expected),
+        other => panic!("{:?}", other),
+    }
+    assert!(matches!(follower.get("key"), Err(Error::NotLeader { .. })));
+    return Ok(());
+}
+
+#[test]
+fn a_new_leader_takes_over_and_the_old_one_catches_up() -> rcask::Result<()> {
+    let (directories, addresses, mut members) = group("failover")?;
+    let all: Vec<&ReplicatedRCask> = members.iter().collect();
+    let first = leader(&all);
+    members[first].set("before", "1")?;
+
+    // Drops the leader, closing its store.
+    let stopped = members.remove(first);
+    drop(stopped);
+    let rest: Vec<&ReplicatedRCask> = members.iter().collect();
+    let second = &members[leader(&rest)];
+    assert_eq!(second.get("before")?, Some("1".to_string()));
+    second.set("after", "2")?;
+
+    // The old leader comes back as a follower and replays what it missed.
+    let listener = TcpListener::bind(addresses[first])?;
+    let options = options(first as u64, &addresses);
+    let restarted = start(&directories[first], listener, options)?;
+    wait_for(&restarted, "after", Some("2"))?;
+    assert_eq!(restarted.get_local("before")?, Some("1".to_string()));
+    return Ok(());
+}
+
+#[test]
+fn a_single_member_resumes_from_its_log() -> rcask::Result<()> {
+    let directory = directory("single");
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let member = start(&directory, listener, RaftOptions::new(0, Vec::new()))?;
+    leader(&[&member]);
+    member.set("key", "value")?;
+    drop(member);
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let member = start(&directory, listener, RaftOptions::new(0, Vec::new()))?;
+    leader(&[&member]);
+    assert_eq!(member.get("key")?, Some("value".to_string()));
+    member.set("key", "other")?;
+    assert_eq!(member.get("key")?, Some("other".to_string()));
+    return Ok(());
+}
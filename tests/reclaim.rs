@@ -0,0 +1,68 @@
+#![allow(clippy::needless_return)]
+
+use rcask::delta::DeltaOptions;
+use rcask::RCask;
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-reclaim-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+fn value(key: usize, version: usize) -> String {
+    return format!("{}:{}:{}", key, version, "v".repeat(1 << 10));
+}
+
+#[test]
+fn punched_regions_are_skipped_after_reopening() -> rcask::Result<()> {
+    let directory = directory("reopen");
+    let punched = {
+        let mut store = RCask::new(directory.clone(), "log".to_string())?;
+        for version in 0..10 {
+            for key in 0..20 {
+                store.set(format!("key:{}", key), value(key, version))?;
+            }
+        }
+        store.set("gone", "x".repeat(8 << 10))?;
+        store.delete("gone")?;
+        store.punch_holes(4096)?
+    };
+    // Nine of the ten versions of every key are dead, and so is `gone`.
+    assert!(punched >= (9 * 20) << 10, "{} bytes punched", punched);
+
+    let mut store = RCask::new(directory.clone(), "log".to_string())?;
+    for key in 0..20 {
+        assert_eq!(store.get(&format!("key:{}", key))?, Some(value(key, 9)));
+    }
+    assert_eq!(store.get("gone")?, None);
+    assert_eq!(store.punch_holes(4096)?, punched);
+    store.set("key:0", value(0, 10))?;
+    drop(store);
+
+    let mut store = RCask::new(directory, "log".to_string())?;
+    assert_eq!(store.get("key:0")?, Some(value(0, 10)));
+    assert_eq!(store.get("key:19")?, Some(value(19, 9)));
+    return Ok(());
+}
+
+#[test]
+fn the_bases_of_deltas_are_kept() -> rcask::Result<()> {
+    let directory = directory("delta");
+    let open = || {
+        return RCask::builder(directory.clone(), "log".to_string())
+            .delta_encoding(DeltaOptions::default())
+            .open();
+    };
+    {
+        let mut store = open()?;
+        for version in 0..4 {
+            store.set("doc", value(0, version))?;
+            store.set(format!("filler:{}", version), "f".repeat(8 << 10))?;
+            store.delete(&format!("filler:{}", version))?;
+        }
+        store.punch_holes(4096)?;
+    }
+    let mut store = open()?;
+    assert_eq!(store.get("doc")?, Some(value(0, 3)));
+    return Ok(());
+}
@@ -0,0 +1,100 @@
+#![allow(clippy::needless_return)]
+
+use rcask::blob::BlobOptions;
+use rcask::{RCask, ReplicationSink, ReplicationSource};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn directory(name: &str) -> String {
+    let path =
+        std::env::temp_dir().join(format!("rcask-replication-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+#[test]
+fn blob_files_larger_than_a_frame_arrive_whole() -> rcask::Result<()> {
+    let options = BlobOptions {
+        min_value_size: 1 << 10,
+    };
+    let large = "x".repeat(3 << 20) + "end";
+    let primary = RCask::builder(directory("primary"), "log".to_string())
+        .blob_files(options.clone())
+        .open()?;
+    let primary = Arc::new(Mutex::new(primary));
+    primary.lock().unwrap().set("large", &large)?;
+    primary.lock().unwrap().set("small", "1")?;
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+    let source = ReplicationSource::new(primary.clone()).poll_interval(Duration::from_millis(10));
+    thread::spawn(move || source.listen(listener));
+
+    let follower = RCask::builder(directory("follower"), "log".to_string())
+        .blob_files(options)
+        .open()?;
+    let follower = Arc::new(Mutex::new(follower));
+    let sink = ReplicationSink::new(follower.clone());
+    thread::spawn(move || sink.connect(address));
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while follower.lock().unwrap().get("small")?.is_none() {
+        assert!(Instant::now() < deadline, "follower did not catch up");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(follower.lock().unwrap().get("large")?, Some(large));
+    return Ok(());
+}
+
+/// Writes a frame of the replication protocol: its type, length and payload.
+fn write_frame(stream: &mut TcpStream, kind: u8, payload: &[u8]) -> std::io::Result<()> {
+    stream.write_all(&[kind])?;
+    stream.write_all(&(payload.len() as u64).to_le_bytes())?;
+    return stream.write_all(payload);
+}
+
+#[test]
+fn a_full_copy_started_over_leaves_nothing_of_the_first() -> rcask::Result<()> {
+    const WELCOME: u8 = 2;
+    const SEGMENT: u8 = 3;
+    const DICT: u8 = 4;
+
+    let directory = directory("restarted");
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let address = listener.local_addr()?;
+    // A primary that starts a full copy, gives up on it and starts another.
+    let primary = thread::spawn(move || -> std::io::Result<()> {
+        let (mut stream, _) = listener.accept()?;
+        let mut header = [0; 9];
+        stream.read_exact(&mut header)?;
+        let length = u64::from_le_bytes(header[1..].try_into().unwrap());
+        std::io::copy(&mut (&mut stream).take(length), &mut std::io::sink())?;
+        let mut welcome = 7u128.to_le_bytes().to_vec();
+        welcome.extend_from_slice(&1000u64.to_le_bytes());
+        welcome.push(1);
+        write_frame(&mut stream, WELCOME, &welcome)?;
+        write_frame(&mut stream, SEGMENT, &1u64.to_le_bytes())?;
+        write_frame(&mut stream, DICT, b"dictionary of the first copy")?;
+        write_frame(&mut stream, SEGMENT, &2u64.to_le_bytes())?;
+        return Ok(());
+    });
+
+    let follower = RCask::builder(directory.clone(), "log".to_string()).open()?;
+    let sink = ReplicationSink::new(Arc::new(Mutex::new(follower)));
+    sink.connect(address)?;
+    primary.join().unwrap()?;
+    let names: Vec<String> = std::fs::read_dir(&directory)?
+        .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+        .collect::<std::io::Result<_>>()?;
+    assert!(
+        !names.iter().any(|name| name.ends_with(".dict")),
+        "{:?}",
+        names
+    );
+    let copies = names.iter().filter(|name| name.ends_with(".compacting"));
+    assert!(copies.count() <= 1, "{:?}", names);
+    return Ok(());
+}
@@ -0,0 +1,49 @@
+#![allow(clippy::needless_return)]
+
+use rcask::RCask;
+use std::thread;
+use std::time::Duration;
+
+fn directory(name: &str) -> String {
+    let path =
+        std::env::temp_dir().join(format!("rcask-retention-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+fn compact(store: &mut RCask) -> rcask::Result<()> {
+    let mut task = store.compaction_task()?;
+    while !task.run_for(Duration::from_secs(1))? {}
+    return Ok(());
+}
+
+#[test]
+fn outlived_records_are_not_served_from_the_read_cache() -> rcask::Result<()> {
+    let mut store = RCask::builder(directory("cache"), "log".to_string())
+        .read_cache(1 << 20)
+        .retention(Duration::from_millis(200))
+        .open()?;
+    store.set("old", "1")?;
+    assert_eq!(store.get("old")?.as_deref(), Some("1"));
+    thread::sleep(Duration::from_millis(300));
+    store.set("new", "2")?;
+    assert_eq!(store.get("new")?.as_deref(), Some("2"));
+    compact(&mut store)?;
+    assert_eq!(store.get("old")?, None);
+    assert_eq!(store.get("new")?.as_deref(), Some("2"));
+    return Ok(());
+}
+
+#[test]
+fn a_wholly_outlived_segment_is_dropped() -> rcask::Result<()> {
+    let mut store = RCask::builder(directory("segment"), "log".to_string())
+        .read_cache(1 << 20)
+        .retention(Duration::from_millis(200))
+        .open()?;
+    store.set("old", "1")?;
+    assert_eq!(store.get("old")?.as_deref(), Some("1"));
+    thread::sleep(Duration::from_millis(300));
+    compact(&mut store)?;
+    assert_eq!(store.get("old")?, None);
+    return Ok(());
+}
@@ -0,0 +1,48 @@
+#![allow(clippy::needless_return)]
+
+use rcask::{HistoryOptions, RCask};
+use std::thread;
+use std::time::Duration;
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-sync-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+#[test]
+fn conflicts_without_write_times_are_reported_not_resolved() -> rcask::Result<()> {
+    let mut ours = RCask::builder(directory("untimed-ours"), "log".to_string()).open()?;
+    let mut theirs = RCask::builder(directory("untimed-theirs"), "log".to_string()).open()?;
+    ours.set("both", "ours")?;
+    theirs.set("both", "theirs")?;
+    theirs.set("only", "theirs")?;
+
+    let report = ours.sync_with(&mut theirs)?;
+    assert_eq!(report.unresolved, vec!["both".to_string()]);
+    assert_eq!(report.received, 1);
+    assert_eq!(ours.get("both")?.as_deref(), Some("ours"));
+    assert_eq!(theirs.get("both")?.as_deref(), Some("theirs"));
+    assert_eq!(ours.get("only")?.as_deref(), Some("theirs"));
+    return Ok(());
+}
+
+#[test]
+fn the_newer_write_wins_with_history() -> rcask::Result<()> {
+    let history = HistoryOptions::default();
+    let mut ours = RCask::builder(directory("timed-ours"), "log".to_string())
+        .history(history.clone())
+        .open()?;
+    let mut theirs = RCask::builder(directory("timed-theirs"), "log".to_string())
+        .history(history)
+        .open()?;
+    ours.set("key", "older")?;
+    thread::sleep(Duration::from_millis(5));
+    theirs.set("key", "newer")?;
+
+    let report = ours.sync_with(&mut theirs)?;
+    assert!(report.unresolved.is_empty());
+    assert_eq!(report.received, 1);
+    assert_eq!(ours.get("key")?.as_deref(), Some("newer"));
+    return Ok(());
+}
@@ -0,0 +1,46 @@
+#![allow(clippy::needless_return)]
+
+use rcask::{Error, RCask};
+use std::time::Duration;
+
+fn directory(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("rcask-version-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    return path.to_string_lossy().into_owned();
+}
+
+fn compact(store: &mut RCask) -> rcask::Result<()> {
+    let mut task = store.compaction_task()?;
+    while !task.run_for(Duration::from_secs(1))? {}
+    return Ok(());
+}
+
+#[test]
+fn a_write_at_the_current_version_succeeds() -> rcask::Result<()> {
+    let mut store = RCask::builder(directory("current"), "log".to_string()).open()?;
+    let version = store.set("key", "1")?;
+    assert_eq!(store.version("key"), Some(version));
+    let next = store.set_if_version("key", "2", version)?;
+    assert!(matches!(
+        store.set_if_version("key", "3", version),
+        Err(Error::Conflict { actual, .. }) if actual == Some(next)
+    ));
+    assert_eq!(store.get("key")?.as_deref(), Some("2"));
+    return Ok(());
+}
+
+#[test]
+fn compaction_invalidates_every_version() -> rcask::Result<()> {
+    let mut store = RCask::builder(directory("compaction"), "log".to_string()).open()?;
+    let version = store.set("untouched", "1")?;
+    store.set("other", "1")?;
+    store.set("other", "2")?;
+    compact(&mut store)?;
+    let result = store.set_if_version("untouched", "2", version);
+    assert!(matches!(result, Err(Error::Conflict { .. })));
+    // Reading the version again after the conflict lets the write through.
+    let version = store.version("untouched").unwrap();
+    store.set_if_version("untouched", "2", version)?;
+    assert_eq!(store.get("untouched")?.as_deref(), Some("2"));
+    return Ok(());
+}